@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use crate::models::Image;
+
+/// Packs rectangles into a fixed size area in rows ("shelves"). Each rectangle goes on the
+/// shortest shelf it fits on, or starts a new one below the others. One pixel of padding is
+/// kept between rectangles so linear filtering doesn't bleed between neighbours.
+#[derive(Clone, Debug)]
+pub struct ShelfPacker {
+    width: u32,
+    height: u32,
+    /// Top, height and used width of each shelf, top to bottom.
+    shelves: Vec<(u32, u32, u32)>,
+}
+
+impl ShelfPacker {
+    pub fn new(width: u32, height: u32) -> Self {
+        return ShelfPacker { width, height, shelves: vec![] };
+    }
+
+    /// Top-left corner for a `w` x `h` rectangle, or None if it doesn't fit anywhere.
+    pub fn allocate(&mut self, w: u32, h: u32) -> Option<[u32; 2]> {
+        let (pw, ph) = (w + 1, h + 1);
+        let best = self
+            .shelves
+            .iter_mut()
+            .filter(|s| s.1 >= ph && s.2 + pw <= self.width + 1)
+            .min_by_key(|s| s.1);
+        if let Some(shelf) = best {
+            let x = shelf.2;
+            shelf.2 += pw;
+            return Some([x, shelf.0]);
+        }
+
+        let top = self.shelves.last().map_or(0, |s| s.0 + s.1);
+        if w > self.width || top + h > self.height {
+            return None;
+        }
+        self.shelves.push((top, ph, pw));
+        return Some([0, top]);
+    }
+}
+
+pub type IconId = usize;
+
+/// Where an icon is in the atlas.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IconInfo {
+    pub atlas_pos: [u32; 2],
+    /// Size in pixels, which is also the icon's size on screen at scale 1.
+    pub size: [u32; 2],
+}
+
+/// An RGBA texture atlas of named icons.
+pub struct IconAtlas {
+    pub width: u32,
+    pub height: u32,
+    /// 8 bit sRGB RGBA, not premultiplied.
+    pub pixels: Vec<u8>,
+    icons: Vec<IconInfo>,
+    names: HashMap<String, IconId>,
+    packer: ShelfPacker,
+    /// Set when icons were added since the last `take_dirty`.
+    dirty: bool,
+}
+
+impl IconAtlas {
+    pub fn new(width: u32, height: u32) -> Self {
+        return IconAtlas {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+            icons: vec![],
+            names: HashMap::new(),
+            packer: ShelfPacker::new(width, height),
+            dirty: false,
+        };
+    }
+
+    /// Add an icon under `name`. Adding a name twice returns the first icon.
+    pub fn add(&mut self, name: &str, image: &Image) -> anyhow::Result<IconId> {
+        if let Some(&id) = self.names.get(name) {
+            return Ok(id);
+        }
+        anyhow::ensure!(
+            image.rgba.len() == (image.width * image.height * 4) as usize,
+            "icon '{name}': {} bytes of pixels for {}x{}",
+            image.rgba.len(),
+            image.width,
+            image.height
+        );
+        let Some(pos) = self.packer.allocate(image.width, image.height) else {
+            anyhow::bail!("icon atlas is full, no room for '{name}' ({}x{})", image.width, image.height);
+        };
+
+        let row_bytes = image.width as usize * 4;
+        for y in 0..image.height as usize {
+            let at = ((pos[1] as usize + y) * self.width as usize + pos[0] as usize) * 4;
+            self.pixels[at..at + row_bytes].copy_from_slice(&image.rgba[y * row_bytes..(y + 1) * row_bytes]);
+        }
+        let id = self.icons.len();
+        self.icons.push(IconInfo { atlas_pos: pos, size: [image.width, image.height] });
+        self.names.insert(name.to_string(), id);
+        self.dirty = true;
+        return Ok(id);
+    }
+
+    /// Decode a PNG and add it; see `add`.
+    pub fn add_png(&mut self, name: &str, bytes: &[u8]) -> anyhow::Result<IconId> {
+        if let Some(&id) = self.names.get(name) {
+            return Ok(id);
+        }
+        return self.add(name, &super::decode_png(bytes)?);
+    }
+
+    pub fn id(&self, name: &str) -> Option<IconId> {
+        return self.names.get(name).copied();
+    }
+
+    pub fn icon(&self, id: IconId) -> Option<&IconInfo> {
+        return self.icons.get(id);
+    }
+
+    /// Texture coordinates of the icon's left, top, right and bottom edges.
+    pub fn uv(&self, id: IconId) -> Option<[f32; 4]> {
+        let i = self.icons.get(id)?;
+        let (w, h) = (self.width as f32, self.height as f32);
+        return Some([
+            i.atlas_pos[0] as f32 / w,
+            i.atlas_pos[1] as f32 / h,
+            (i.atlas_pos[0] + i.size[0]) as f32 / w,
+            (i.atlas_pos[1] + i.size[1]) as f32 / h,
+        ]);
+    }
+
+    /// Whether the atlas changed since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        return std::mem::take(&mut self.dirty);
+    }
+}
+
+#[cfg(test)]
+fn solid(width: u32, height: u32, rgba: [u8; 4]) -> Image {
+    return Image { width, height, rgba: rgba.repeat((width * height) as usize) };
+}
+
+#[test]
+fn packer_places_without_overlap() {
+    let mut packer = ShelfPacker::new(64, 64);
+    let sizes = [[20, 10], [30, 16], [10, 8], [40, 12], [12, 10], [8, 20], [60, 2]];
+    let rects: Vec<[u32; 4]> = sizes
+        .iter()
+        .map(|&[w, h]| {
+            let [x, y] = packer.allocate(w, h).unwrap();
+            [x, y, x + w, y + h]
+        })
+        .collect();
+
+    for (i, a) in rects.iter().enumerate() {
+        assert!(a[2] <= 64 && a[3] <= 64, "{a:?} outside");
+        for b in &rects[i + 1..] {
+            let apart = a[2] < b[0] || b[2] < a[0] || a[3] < b[1] || b[3] < a[1];
+            assert!(apart, "{a:?} and {b:?} overlap or touch");
+        }
+    }
+    // The short 10x8 went on the first shelf, next to the 20x10, rather than starting a new one.
+    assert_eq!(rects[2], [21, 0, 31, 8]);
+
+    assert_eq!(packer.allocate(65, 1), None);
+    assert_eq!(packer.allocate(10, 40), None);
+    // Small things still fit in gaps on existing shelves.
+    assert!(packer.allocate(4, 4).is_some());
+}
+
+#[test]
+fn atlas_copies_icons_and_dedups_names() {
+    let mut atlas = IconAtlas::new(32, 32);
+    let red = atlas.add("red", &solid(4, 2, [255, 0, 0, 255])).unwrap();
+    let blue = atlas.add("blue", &solid(2, 2, [0, 0, 255, 128])).unwrap();
+    assert_ne!(red, blue);
+    assert!(atlas.take_dirty());
+    assert_eq!(atlas.add("red", &solid(8, 8, [0; 4])).unwrap(), red);
+    assert!(!atlas.take_dirty());
+    assert_eq!(atlas.id("blue"), Some(blue));
+
+    let b = *atlas.icon(blue).unwrap();
+    let px = |x: u32, y: u32| {
+        let at = ((y * atlas.width + x) * 4) as usize;
+        <[u8; 4]>::try_from(&atlas.pixels[at..at + 4]).unwrap()
+    };
+    assert_eq!(px(b.atlas_pos[0] + 1, b.atlas_pos[1] + 1), [0, 0, 255, 128]);
+    assert_eq!(px(3, 1), [255, 0, 0, 255]);
+    assert_eq!(atlas.uv(red), Some([0., 0., 4. / 32., 2. / 32.]));
+
+    assert!(atlas.add("huge", &solid(40, 4, [0; 4])).is_err());
+    assert!(atlas.add("bad", &Image { width: 2, height: 2, rgba: vec![0; 3] }).is_err());
+}
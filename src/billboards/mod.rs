@@ -0,0 +1,148 @@
+mod atlas;
+
+pub use atlas::{IconAtlas, IconId, IconInfo, ShelfPacker};
+
+use std::path::Path;
+
+use base64::Engine;
+use nalgebra::Vector3;
+
+use crate::models::Image;
+
+/// A value that goes from `near_value` at `near` meters from the camera to `far_value` at `far`
+/// meters, constant outside that range (Cesium's NearFarScalar).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NearFarScalar {
+    pub near: f32,
+    pub near_value: f32,
+    pub far: f32,
+    pub far_value: f32,
+}
+
+impl NearFarScalar {
+    pub fn new(near: f32, near_value: f32, far: f32, far_value: f32) -> Self {
+        return NearFarScalar { near, near_value, far, far_value };
+    }
+
+    /// The same interpolation the billboard shader does.
+    pub fn value_at(&self, distance: f32) -> f32 {
+        if self.far <= self.near {
+            return self.near_value;
+        }
+        let t = ((distance - self.near) / (self.far - self.near)).clamp(0., 1.);
+        return self.near_value + (self.far_value - self.near_value) * t;
+    }
+}
+
+/// A screen-aligned icon anchored at a point on or above the globe.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Billboard {
+    /// ECEF meters.
+    pub position: Vector3<f64>,
+    pub icon: IconId,
+    /// Multiplies the icon's size in pixels.
+    pub scale: f32,
+    /// Radians, counter-clockwise on screen.
+    pub rotation: f32,
+    /// Multiplies the icon's colours.
+    pub color: [f32; 4],
+    /// Offset of the icon's centre from the projected position, in pixels (y down).
+    pub pixel_offset: [f32; 2],
+    /// Extra scale depending on the distance from the camera.
+    pub scale_by_distance: Option<NearFarScalar>,
+    pub show: bool,
+}
+
+impl Billboard {
+    pub fn new(position: Vector3<f64>, icon: IconId) -> Self {
+        return Billboard {
+            position,
+            icon,
+            scale: 1.,
+            rotation: 0.,
+            color: [1.; 4],
+            pixel_offset: [0.; 2],
+            scale_by_distance: None,
+            show: true,
+        };
+    }
+}
+
+/// Decode a PNG of any colour type to 8 bit RGBA.
+pub fn decode_png(bytes: &[u8]) -> anyhow::Result<Image> {
+    let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size().ok_or_else(|| anyhow::anyhow!("png is too large"))?];
+    let info = reader.next_frame(&mut buf)?;
+    let px = &buf[..info.buffer_size()];
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => px.to_vec(),
+        png::ColorType::Rgb => px.chunks_exact(3).flat_map(|c| [c[0], c[1], c[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => px.chunks_exact(2).flat_map(|c| [c[0], c[0], c[0], c[1]]).collect(),
+        png::ColorType::Grayscale => px.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => anyhow::bail!("png palette wasn't expanded"),
+    };
+    return Ok(Image { width: info.width, height: info.height, rgba });
+}
+
+/// Read the bytes an image URI points at: a base64 `data:` URI or a file path, relative to `dir`
+/// (usually the directory of the document that names it) unless absolute.
+pub fn read_image_uri(uri: &str, dir: &Path) -> anyhow::Result<Vec<u8>> {
+    if let Some(rest) = uri.strip_prefix("data:") {
+        let Some((_, data)) = rest.split_once(";base64,") else {
+            anyhow::bail!("only base64 data URIs are supported");
+        };
+        return Ok(base64::engine::general_purpose::STANDARD.decode(data.trim())?);
+    }
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    return Ok(std::fs::read(dir.join(path))?);
+}
+
+#[cfg(test)]
+fn encode_png(image: &Image) -> Vec<u8> {
+    let mut out = vec![];
+    let mut encoder = png::Encoder::new(&mut out, image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(&image.rgba).unwrap();
+    return out;
+}
+
+#[test]
+fn scale_by_distance() {
+    let s = NearFarScalar::new(1000., 2., 11_000., 0.5);
+    assert_eq!(s.value_at(0.), 2.);
+    assert_eq!(s.value_at(6000.), 1.25);
+    assert_eq!(s.value_at(1e9), 0.5);
+    assert_eq!(NearFarScalar::new(5., 3., 5., 1.).value_at(100.), 3.);
+}
+
+#[test]
+fn png_round_trip_through_data_uri() {
+    let image = Image { width: 3, height: 2, rgba: (0..24).collect() };
+    let png = encode_png(&image);
+    let uri = format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(&png));
+    let decoded = decode_png(&read_image_uri(&uri, Path::new("")).unwrap()).unwrap();
+    assert_eq!((decoded.width, decoded.height), (3, 2));
+    assert_eq!(decoded.rgba, image.rgba);
+
+    let mut atlas = IconAtlas::new(16, 16);
+    let id = atlas.add_png("ramp", &png).unwrap();
+    assert_eq!(atlas.icon(id).unwrap().size, [3, 2]);
+    assert!(decode_png(b"not a png").is_err());
+    assert!(read_image_uri("data:image/png,abc", Path::new("")).is_err());
+}
+
+#[test]
+fn image_paths_are_relative_to_the_document() {
+    let dir = std::env::temp_dir().join(format!("wglobe-images-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("icons")).unwrap();
+    std::fs::write(dir.join("icons/pin.png"), b"png").unwrap();
+    assert_eq!(read_image_uri("icons/pin.png", &dir).unwrap(), b"png");
+    let absolute = dir.join("icons/pin.png");
+    assert_eq!(read_image_uri(absolute.to_str().unwrap(), Path::new("elsewhere")).unwrap(), b"png");
+    assert_eq!(read_image_uri(&format!("file://{}", absolute.display()), Path::new("elsewhere")).unwrap(), b"png");
+    assert!(read_image_uri("icons/pin.png", Path::new("elsewhere")).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
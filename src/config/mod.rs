@@ -0,0 +1,419 @@
+//! Start-up options for the wglobe binary, from the command line and an optional TOML file.
+//!
+//! The file uses the same names as the long options, with the camera and window ones in their
+//! own tables:
+//!
+//! ```toml
+//! data = ["tracks/ride.gpx", "tiles/tileset.json"]
+//! log_level = "info"
+//! bookmarks = "bookmarks.json"
+//!
+//! [camera]
+//! lat = 46.55
+//! lon = 7.98
+//! height = 4000
+//! pitch = -20
+//!
+//! [window]
+//! width = 1920
+//! height = 1080
+//! present_mode = "mailbox"
+//! max_fps = 60
+//!
+//! [bindings]
+//! look = ["MouseRight"]
+//!
+//! [tracks.csv]
+//! time = "timestamp"
+//! time_format = "unix_seconds"
+//! id = 0
+//! ```
+//!
+//! A `view` (see `core::bookmarks`) is applied after the camera table, so it wins. In the file it is
+//! a view string or a table of `ViewState` fields; on the command line it can also be a JSON file.
+//! Bindings, described in `core::input`, and the CSV columns (`tracks::CsvSchema`) can only be set
+//! in the file. Options given on the command
+//! line win over the file, except data, which is opened from both.
+//! Relative paths in the file are relative to the file.
+
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::core::{Bindings, PresentMode, ViewState};
+use crate::tracks::CsvSchema;
+
+#[derive(Parser, Debug)]
+#[command(name = "wglobe", about = "A globe viewer for tracks, satellites, models, point clouds and 3D Tiles")]
+pub struct Cli {
+    /// Data files or tilesets to open, as layers in this order.
+    pub data: Vec<PathBuf>,
+
+    /// Read options from a TOML file.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Initial camera latitude in degrees.
+    #[arg(long, allow_negative_numbers = true)]
+    pub lat: Option<f64>,
+    /// Initial camera longitude in degrees.
+    #[arg(long, allow_negative_numbers = true)]
+    pub lon: Option<f64>,
+    /// Initial camera height above the ellipsoid in meters.
+    #[arg(long, allow_negative_numbers = true)]
+    pub height: Option<f64>,
+    /// Initial camera heading in degrees clockwise from north.
+    #[arg(long, allow_negative_numbers = true)]
+    pub heading: Option<f64>,
+    /// Initial camera pitch in degrees; -90 looks straight down.
+    #[arg(long, allow_negative_numbers = true)]
+    pub pitch: Option<f64>,
+
+    /// Start at a view: a string such as "ll=46.58,7.99&h=4000&hpr=200,-15", or a JSON file saved
+    /// from the View window.
+    #[arg(long, value_name = "VIEW", value_parser = ViewState::parse_or_load)]
+    pub view: Option<ViewState>,
+    /// Keep bookmarks in this JSON file.
+    #[arg(long, value_name = "FILE")]
+    pub bookmarks: Option<PathBuf>,
+
+    /// Imagery source, a URL template or path.
+    #[arg(long, value_name = "SOURCE")]
+    pub imagery: Option<String>,
+    /// Terrain source, a URL or path.
+    #[arg(long, value_name = "SOURCE")]
+    pub terrain: Option<String>,
+
+    /// Window size, e.g. 1280x720.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+    /// How frames are presented; falls back to what the display supports.
+    #[arg(long, value_enum)]
+    pub present_mode: Option<PresentMode>,
+    /// Draw at most this many frames per second.
+    #[arg(long, value_name = "FPS")]
+    pub max_fps: Option<f64>,
+    /// Only draw when something changes: input, a playing clock or loading content.
+    #[arg(long)]
+    pub on_demand: bool,
+
+    /// Level of wglobe's own messages: off, error, warn, info, debug or trace. RUST_LOG still picks
+    /// levels per module.
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<log::LevelFilter>,
+
+    /// Render without showing a window, then exit.
+    #[arg(long)]
+    pub headless: bool,
+    /// Save the last frame rendered as a PNG.
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+    /// Frames to render before saving the output or exiting when headless.
+    #[arg(long, value_name = "N")]
+    pub frames: Option<u32>,
+}
+
+fn deserialize_view<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<ViewState>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum View {
+        Text(String),
+        Fields(ViewState),
+    }
+    return match View::deserialize(d)? {
+        View::Text(s) => s.parse().map(Some).map_err(serde::de::Error::custom),
+        View::Fields(v) => Ok(Some(v)),
+    };
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let parse = |n: &str| n.trim().parse::<u32>().ok().filter(|&n| n > 0);
+    return match s.split_once(['x', 'X']) {
+        Some((w, h)) => parse(w).zip(parse(h)).ok_or_else(|| format!("bad size '{s}'")),
+        None => Err(format!("expected WIDTHxHEIGHT, got '{s}'")),
+    };
+}
+
+/// Where the camera starts, in degrees and meters.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraStart {
+    pub lat: f64,
+    pub lon: f64,
+    pub height: f64,
+    pub heading: f64,
+    pub pitch: f64,
+}
+
+impl Default for CameraStart {
+    /// Looking down from high enough to see a hemisphere.
+    fn default() -> Self {
+        return CameraStart { lat: 0., lon: 0., height: 2e7, heading: 0., pitch: -90. };
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    pub present_mode: PresentMode,
+    pub max_fps: Option<f64>,
+    pub on_demand: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        return WindowConfig { width: 1280, height: 800, present_mode: PresentMode::Fifo, max_fps: None, on_demand: false };
+    }
+}
+
+/// How track files are read.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracksConfig {
+    pub csv: CsvSchema,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub data: Vec<PathBuf>,
+    /// None leaves the camera where the scene puts it.
+    pub camera: Option<CameraStart>,
+    #[serde(deserialize_with = "deserialize_view")]
+    pub view: Option<ViewState>,
+    /// JSON file the bookmarks are read from and saved to.
+    pub bookmarks: Option<PathBuf>,
+    pub imagery: Option<String>,
+    pub terrain: Option<String>,
+    pub window: WindowConfig,
+    /// Level of wglobe's own messages. None uses RUST_LOG, or info without it.
+    #[serde(deserialize_with = "deserialize_level")]
+    pub log_level: Option<log::LevelFilter>,
+    pub headless: bool,
+    pub output: Option<PathBuf>,
+    pub frames: u32,
+    pub bindings: Bindings,
+    pub tracks: TracksConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config {
+            data: vec![],
+            camera: None,
+            view: None,
+            bookmarks: None,
+            imagery: None,
+            terrain: None,
+            window: Default::default(),
+            log_level: None,
+            headless: false,
+            output: None,
+            frames: 1,
+            bindings: Default::default(),
+            tracks: Default::default(),
+        };
+    }
+}
+
+fn deserialize_level<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<log::LevelFilter>, D::Error> {
+    let s = String::deserialize(d)?;
+    return s.parse().map(Some).map_err(|_| serde::de::Error::custom(format!("unknown log level '{s}'")));
+}
+
+impl Config {
+    /// Parse a config file's contents. Relative paths are taken relative to `base`.
+    pub fn from_toml(text: &str, base: &Path) -> anyhow::Result<Self> {
+        let mut config: Config = toml::from_str(text)?;
+        for p in config.data.iter_mut().chain(config.output.as_mut()).chain(config.bookmarks.as_mut()) {
+            if p.is_relative() {
+                *p = base.join(&*p);
+            }
+        }
+        return Ok(config);
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("can't read '{}': {e}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        return Self::from_toml(&text, base).map_err(|e| anyhow::anyhow!("in '{}': {e}", path.display()));
+    }
+
+    /// The config file named on the command line, if any, with the other options applied on top.
+    pub fn from_cli(cli: Cli) -> anyhow::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::load(path)?,
+            None => Config::default(),
+        };
+        config.apply(cli);
+        config.check()?;
+        return Ok(config);
+    }
+
+    fn apply(&mut self, cli: Cli) {
+        self.data.extend(cli.data);
+
+        let camera = [cli.lat, cli.lon, cli.height, cli.heading, cli.pitch];
+        if camera.iter().any(Option::is_some) {
+            let c = self.camera.get_or_insert_with(Default::default);
+            for (value, field) in camera.into_iter().zip([&mut c.lat, &mut c.lon, &mut c.height, &mut c.heading, &mut c.pitch]) {
+                if let Some(v) = value {
+                    *field = v;
+                }
+            }
+        }
+
+        self.view = cli.view.or(self.view.take());
+        self.bookmarks = cli.bookmarks.or(self.bookmarks.take());
+        self.imagery = cli.imagery.or(self.imagery.take());
+        self.terrain = cli.terrain.or(self.terrain.take());
+        if let Some((width, height)) = cli.size {
+            self.window.width = width;
+            self.window.height = height;
+        }
+        self.window.present_mode = cli.present_mode.unwrap_or(self.window.present_mode);
+        self.window.max_fps = cli.max_fps.or(self.window.max_fps);
+        self.window.on_demand |= cli.on_demand;
+        self.log_level = cli.log_level.or(self.log_level);
+        self.headless |= cli.headless;
+        self.output = cli.output.or(self.output.take());
+        self.frames = cli.frames.unwrap_or(self.frames);
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if let Some(c) = &self.camera {
+            anyhow::ensure!((-90. ..=90.).contains(&c.lat), "latitude {} is out of range", c.lat);
+            anyhow::ensure!((-90. ..=90.).contains(&c.pitch), "pitch {} is out of range", c.pitch);
+        }
+        anyhow::ensure!(self.window.width > 0 && self.window.height > 0, "the window size can't be 0");
+        if let Some(fps) = self.window.max_fps {
+            anyhow::ensure!(fps > 0., "the frame rate cap has to be above 0");
+        }
+        anyhow::ensure!(self.frames > 0, "at least one frame has to be rendered");
+        if let Some(out) = &self.output {
+            let png = out.extension().is_some_and(|e| e.eq_ignore_ascii_case("png"));
+            anyhow::ensure!(png, "output '{}' should be a .png file", out.display());
+        }
+        return Ok(());
+    }
+
+    /// Whether the app should stop once `frames` frames are drawn.
+    pub fn exits_after_frames(&self) -> bool {
+        return self.headless || self.output.is_some();
+    }
+}
+
+#[cfg(test)]
+fn parse(args: &[&str]) -> anyhow::Result<Config> {
+    let cli = Cli::try_parse_from(std::iter::once("wglobe").chain(args.iter().copied()))?;
+    return Config::from_cli(cli);
+}
+
+#[test]
+fn command_line() {
+    assert_eq!(parse(&[]).unwrap(), Config::default());
+
+    let c = parse(&["a.gpx", "tiles/tileset.json", "--lat", "-33.9", "--lon=151.2", "--pitch", "-45", "--size", "640x480",
+        "--present-mode", "immediate", "--max-fps", "30", "--on-demand", "--log-level", "debug", "--headless", "--output", "shot.png", "--frames", "30"]).unwrap();
+    assert_eq!(c.data, [PathBuf::from("a.gpx"), PathBuf::from("tiles/tileset.json")]);
+    assert_eq!(c.camera, Some(CameraStart { lat: -33.9, lon: 151.2, pitch: -45., ..Default::default() }));
+    let window = WindowConfig { width: 640, height: 480, present_mode: PresentMode::Immediate, max_fps: Some(30.), on_demand: true };
+    assert_eq!(c.window, window);
+    assert_eq!(c.log_level, Some(log::LevelFilter::Debug));
+    assert!(c.headless && c.exits_after_frames());
+    assert_eq!(c.output, Some(PathBuf::from("shot.png")));
+    assert_eq!(c.frames, 30);
+
+    assert!(parse(&["--size", "640"]).is_err());
+    assert!(parse(&["--size", "0x480"]).is_err());
+    assert!(parse(&["--log-level", "loud"]).is_err());
+    assert!(parse(&["--lat", "91"]).is_err());
+    assert!(parse(&["--output", "shot.jpg"]).is_err());
+    assert!(parse(&["--frames", "0"]).is_err());
+    assert!(parse(&["--max-fps", "0"]).is_err());
+    assert!(parse(&["--present-mode", "vsync"]).is_err());
+}
+
+#[test]
+fn config_file() {
+    let text = r#"
+        data = ["ride.gpx", "/abs/sats.tle"]
+        imagery = "https://tile.example.com/{z}/{x}/{y}.png"
+        log_level = "warn"
+        output = "out/frame.png"
+        view = "ll=46.58,7.99&h=4000&hpr=200,-15"
+        bookmarks = "marks.json"
+
+        [camera]
+        lat = 46.55
+        lon = 7.98
+        height = 4000
+
+        [window]
+        present_mode = "mailbox"
+        max_fps = 60
+
+        [bindings]
+        exit = ["KeyQ"]
+
+        [tracks.csv]
+        delimiter = ";"
+        time = "timestamp"
+        time_format = "unix_millis"
+        lat = 1
+        id = "vehicle"
+    "#;
+    let c = Config::from_toml(text, Path::new("configs")).unwrap();
+    assert_eq!(c.data, [PathBuf::from("configs/ride.gpx"), PathBuf::from("/abs/sats.tle")]);
+    assert_eq!(c.output, Some(PathBuf::from("configs/out/frame.png")));
+    assert_eq!(c.imagery.as_deref(), Some("https://tile.example.com/{z}/{x}/{y}.png"));
+    assert_eq!(c.terrain, None);
+    assert_eq!(c.log_level, Some(log::LevelFilter::Warn));
+    assert_eq!(c.camera, Some(CameraStart { lat: 46.55, lon: 7.98, height: 4000., ..Default::default() }));
+    assert_eq!(c.window, WindowConfig { present_mode: PresentMode::Mailbox, max_fps: Some(60.), ..Default::default() });
+    assert_eq!(c.view, Some(ViewState { lat: 46.58, lon: 7.99, height: 4000., heading: 200., pitch: -15., ..Default::default() }));
+    assert_eq!(c.bookmarks, Some(PathBuf::from("configs/marks.json")));
+    assert_eq!(c.bindings.triggers(crate::core::Action::Exit), [crate::core::input::Trigger::Key(winit::keyboard::KeyCode::KeyQ)]);
+    let csv = &c.tracks.csv;
+    assert_eq!((csv.delimiter, csv.time_format), (';', crate::tracks::CsvTimeFormat::UnixMillis));
+    assert_eq!((&csv.time, &csv.lat, &csv.lon), (&"timestamp".into(), &crate::tracks::CsvColumn::Index(1), &"lon".into()));
+    assert_eq!(csv.id, Some("vehicle".into()));
+
+    let table = Config::from_toml("[view]\nlat = 1\nlon = 2\nheight = 3\nheading = 4\npitch = 5", Path::new("")).unwrap();
+    assert_eq!(table.view, Some(ViewState { lat: 1., lon: 2., height: 3., heading: 4., pitch: 5., ..Default::default() }));
+
+    assert!(Config::from_toml("log_level = \"loud\"", Path::new("")).is_err());
+    assert!(Config::from_toml("view = \"h=100\"", Path::new("")).is_err());
+    assert!(Config::from_toml("[camera]\nlatitude = 1", Path::new("")).is_err());
+    assert!(Config::from_toml("fullscreen = true", Path::new("")).is_err());
+    assert!(Config::from_toml("[window]\npresent_mode = \"fast\"", Path::new("")).is_err());
+    assert!(Config::from_toml("[tracks.csv]\ntime_column = \"t\"", Path::new("")).is_err());
+}
+
+#[test]
+fn command_line_overrides_config_file() {
+    let dir = std::env::temp_dir().join(format!("wglobe-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("view.toml");
+    std::fs::write(&path, "data = [\"ride.gpx\"]\nframes = 5\n[camera]\nlat = 10\nlon = 20\n[window]\nwidth = 800\nheight = 600\n").unwrap();
+
+    let c = parse(&["--config", path.to_str().unwrap(), "extra.czml", "--lon", "30", "--size", "1024x768"]).unwrap();
+    assert_eq!(c.data, [dir.join("ride.gpx"), PathBuf::from("extra.czml")]);
+    assert_eq!(c.camera, Some(CameraStart { lat: 10., lon: 30., ..Default::default() }));
+    assert_eq!((c.window.width, c.window.height), (1024, 768));
+    assert_eq!(c.frames, 5);
+    assert!(!c.exits_after_frames());
+
+    // Views from the command line, as a string or a JSON file.
+    let view: ViewState = "ll=1,2&h=3".parse().unwrap();
+    view.save(&dir.join("view.json")).unwrap();
+    assert_eq!(parse(&["--view", "ll=1,2&h=3"]).unwrap().view, Some(view.clone()));
+    assert_eq!(parse(&["--view", dir.join("view.json").to_str().unwrap()]).unwrap().view, Some(view));
+    assert!(parse(&["--view", "ll=1"]).is_err());
+
+    assert!(parse(&["--config", dir.join("missing.toml").to_str().unwrap()]).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,14 +1,20 @@
 use std::sync::Arc;
+use std::time::Instant;
 
 use winit::{
     application::ApplicationHandler,
     event::*,
-    event_loop::ActiveEventLoop,
-    keyboard::{KeyCode, PhysicalKey},
-    window::Window,
+    event_loop::{ActiveEventLoop, ControlFlow},
+    window::{Window, WindowAttributes},
 };
 
 use super::AppObjects;
+use super::Bounds;
+use super::DrawStats;
+use super::Gui;
+use super::input::{Action, Input, InputState};
+use super::pacing::{FramePacer, NextFrame};
+use super::RenderSettings;
 use super::Scene;
 
 pub struct RenderState<'a> {
@@ -18,6 +24,10 @@ pub struct RenderState<'a> {
 
     pub surface_tex_view: Option<wgpu::TextureView>,
 
+    /// The colour view renderables draw into: the scene target when MSAA or FXAA needs one,
+    /// the surface otherwise.
+    pub target_view: wgpu::TextureView,
+
     pub scene: &'a Scene,
 
     // model: [f32; 16],
@@ -26,33 +36,81 @@ pub struct RenderState<'a> {
 }
 
 pub trait Renderable {
+    /// Called once per frame before any rendering, e.g. to follow the scene clock.
+    fn update(&mut self, _ao: &AppObjects, _scene: &Scene) {}
+
     fn render(self: &Self, rs: &mut RenderState);
 
-    fn handle_key(&mut self, _event_loop: &ActiveEventLoop, _key: KeyCode, _pressed: bool) {}
-    fn handle_mouse(&mut self, _event_loop: &ActiveEventLoop, _state: ElementState, _button: MouseButton) {}
+    /// Layer opacity in [0, 1]. Renderables that don't blend may ignore it.
+    fn set_opacity(&mut self, _opacity: f32) {}
+
+    /// The span of time this renderable has data for, if it is time-dynamic.
+    fn time_range(&self) -> Option<(f64, f64)> {
+        None
+    }
+
+    /// True while the renderable changes without any input, e.g. while content is loading, so
+    /// frames keep coming when only redrawing on demand.
+    fn animating(&self) -> bool {
+        false
+    }
+
+    /// What the last `render` drew, for the performance overlay.
+    fn stats(&self) -> DrawStats {
+        DrawStats::default()
+    }
+
+    /// Where the renderable draws, in ECEF, so it can be skipped when out of view. None (the
+    /// default) means it is always drawn.
+    fn bounds(&self) -> Option<Bounds> {
+        None
+    }
+
+    /// Called once per frame, before `update`, while the layer takes input.
+    fn handle_input(&mut self, _ao: &AppObjects, _input: &InputState) {}
 }
 
 pub trait UserApp: Default {
-    fn render(&mut self, ao: &AppObjects) -> Result<(), wgpu::SurfaceError>;
-    fn handle_key(&mut self, ao: &AppObjects, event_loop: &ActiveEventLoop, key: KeyCode, pressed: bool);
-    fn handle_mouse(&mut self, ao: &AppObjects, event_loop: &ActiveEventLoop, state: ElementState, button: MouseButton);
+    /// Draw a frame. The app builds its UI with `gui.run` and draws it with `gui.paint` after the scene.
+    /// `input` is what happened since the last frame.
+    fn render(&mut self, ao: &AppObjects, gui: &mut Gui, input: &InputState) -> Result<(), wgpu::SurfaceError>;
+
+    /// Checked after each frame; true ends the event loop.
+    fn wants_exit(&self) -> bool {
+        false
+    }
 }
 
 pub struct BaseApp<UApp : UserApp> {
     #[cfg(target_arch = "wasm32")]
     proxy: Option<winit::event_loop::EventLoopProxy<AppObjects>>,
     pub ao: Option<AppObjects>,
+    pub gui: Option<Gui>,
+    /// Used when the `AppObjects` are created; set it before the event loop starts.
+    pub render_settings: RenderSettings,
+    /// The window to create; set it before the event loop starts. The canvas is used on the web.
+    pub window_attributes: WindowAttributes,
+    /// Decides when frames are drawn.
+    pub pacer: FramePacer,
+    /// Input since the last frame; set its bindings before the event loop starts.
+    pub input: Input,
 
     pub uapp: UApp,
 }
 
 
 impl<UApp: UserApp> BaseApp<UApp> {
+    #[allow(clippy::new_without_default)]
     pub fn new(#[cfg(target_arch = "wasm32")] event_loop: &EventLoop<AppObjects>) -> Self {
         #[cfg(target_arch = "wasm32")]
         let proxy = Some(event_loop.create_proxy());
         Self {
             ao: None,
+            gui: None,
+            render_settings: Default::default(),
+            window_attributes: Window::default_attributes(),
+            pacer: Default::default(),
+            input: Default::default(),
             #[cfg(target_arch = "wasm32")]
             proxy,
             uapp: Default::default(),
@@ -63,7 +121,7 @@ impl<UApp: UserApp> BaseApp<UApp> {
 impl<UApp: UserApp> ApplicationHandler<AppObjects> for BaseApp<UApp> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         #[allow(unused_mut)]
-        let mut window_attributes = Window::default_attributes();
+        let mut window_attributes = self.window_attributes.clone();
 
         #[cfg(target_arch = "wasm32")]
         {
@@ -85,16 +143,22 @@ impl<UApp: UserApp> ApplicationHandler<AppObjects> for BaseApp<UApp> {
         {
             // If we are not on web we can use pollster to
             // await the
-            self.ao = Some(pollster::block_on(AppObjects::new(window)).unwrap());
+            let mut ao = pollster::block_on(AppObjects::new(window, self.render_settings)).unwrap();
+            // Hidden windows may never be sent a resize, so configure the surface now.
+            let size = ao.window.inner_size();
+            ao.resize(size.width, size.height);
+            self.gui = Some(Gui::new(&ao));
+            self.ao = Some(ao);
         }
 
         #[cfg(target_arch = "wasm32")]
         {
             if let Some(proxy) = self.proxy.take() {
+                let settings = self.render_settings;
                 wasm_bindgen_futures::spawn_local(async move {
                     assert!(proxy
                         .send_event(
-                            AppObjects::new(window)
+                            AppObjects::new(window, settings)
                                 .await
                                 .expect("Unable to create canvas!!!")
                         )
@@ -114,6 +178,7 @@ impl<UApp: UserApp> ApplicationHandler<AppObjects> for BaseApp<UApp> {
                 event.window.inner_size().height,
             );
         }
+        self.gui = Some(Gui::new(&event));
         self.ao = Some(event);
     }
 
@@ -123,16 +188,33 @@ impl<UApp: UserApp> ApplicationHandler<AppObjects> for BaseApp<UApp> {
         _window_id: winit::window::WindowId,
         event: WindowEvent,
     ) {
-        let ao = match &mut self.ao {
-            Some(canvas) => canvas,
-            None => return,
+        let (ao, gui) = match (&mut self.ao, &mut self.gui) {
+            (Some(canvas), Some(gui)) => (canvas, gui),
+            _ => return,
         };
 
+        // The overlay gets first look at input; what it uses doesn't reach the app.
+        let gui_consumed = gui.on_window_event(&ao.window, &event);
+        if !matches!(event, WindowEvent::RedrawRequested) {
+            self.pacer.request_redraw();
+            self.input.window_event(&event, gui_consumed);
+            if self.input.state().just_pressed(Action::Exit) {
+                event_loop.exit();
+            }
+        }
+
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
             WindowEvent::Resized(size) => ao.resize(size.width, size.height),
             WindowEvent::RedrawRequested => {
-                match self.uapp.render(ao) {
+                self.pacer.frame_started(Instant::now());
+                super::diagnostics::next_frame();
+                ao.prepare_frame();
+                let result = self.uapp.render(ao, gui, self.input.state());
+                self.input.end_frame();
+                ao.profiler.end_frame();
+                match result {
+                    Ok(_) if self.uapp.wants_exit() => event_loop.exit(),
                     Ok(_) => {}
                     // Reconfigure the surface if it's lost or outdated
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
@@ -144,24 +226,24 @@ impl<UApp: UserApp> ApplicationHandler<AppObjects> for BaseApp<UApp> {
                     }
                 }
             }
-            WindowEvent::MouseInput { device_id: _device_id, state: mstate, button } => {
-                self.uapp.handle_mouse(ao, event_loop, mstate, button);
-            },
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        physical_key: PhysicalKey::Code(code),
-                        state: key_state,
-                        ..
-                    },
-                ..
-            } => {
-                match (code, key_state.is_pressed()) {
-                    (KeyCode::Escape, true) => event_loop.exit(),
-                    _ => self.uapp.handle_key(ao, event_loop, code, key_state.is_pressed())
-                }
-            }
             _ => {}
         }
     }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(ao) = &self.ao else {
+            return;
+        };
+        if ao.take_redraw_request() {
+            self.pacer.request_redraw();
+        }
+        match self.pacer.next_frame(Instant::now()) {
+            NextFrame::Now => {
+                ao.window.request_redraw();
+                event_loop.set_control_flow(ControlFlow::Wait);
+            }
+            NextFrame::At(t) => event_loop.set_control_flow(ControlFlow::WaitUntil(t)),
+            NextFrame::Idle => event_loop.set_control_flow(ControlFlow::Wait),
+        }
+    }
 }
@@ -1,7 +1,13 @@
+use std::cell::Cell;
 use std::sync::Arc;
 
 use winit::window::Window;
 
+use super::pipelines::{PipelineCache, TargetState};
+use super::profiler::Profiler;
+use super::shaders::ShaderRegistry;
+use super::targets::{pick_scene_format, supported_sample_counts, RenderSettings, RenderTargets};
+
 
 pub struct AppObjects {
     pub surface: wgpu::Surface<'static>,
@@ -9,6 +15,20 @@ pub struct AppObjects {
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub is_surface_configured: bool,
+    /// What the scene is drawn into before it reaches the surface; also holds the validated
+    /// `RenderSettings`.
+    pub targets: RenderTargets,
+    pub shaders: ShaderRegistry,
+    pub pipelines: PipelineCache,
+    pub profiler: Profiler,
+    /// MSAA sample counts the scene format supports.
+    supported_samples: Vec<u32>,
+    /// Present modes the surface supports.
+    present_modes: Vec<wgpu::PresentMode>,
+    /// Set when something asks for another frame; see `request_redraw`.
+    redraw_requested: Cell<bool>,
+    /// Settings to switch to at the start of the next frame.
+    requested_settings: Cell<Option<RenderSettings>>,
 
     // NEW!
     // render_pipeline: wgpu::RenderPipeline,
@@ -21,7 +41,7 @@ pub struct AppObjects {
 }
 
 impl AppObjects {
-    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
+    pub async fn new(window: Arc<Window>, settings: RenderSettings) -> anyhow::Result<Self> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -54,7 +74,9 @@ impl AppObjects {
 
                 // required_features: wgpu::Features::empty(),
                 // required_features: wgpu::Features::all_webgpu_mask(),
-                required_features: wgpu::Features::empty()
+                // Lets MSAA use sample counts other than 4, and render passes be timed, where the
+                // adapter can.
+                required_features: adapter.features() & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES | wgpu::Features::TIMESTAMP_QUERY)
                     // | wgpu::Features::BUFFER_BINDING_ARRAY
                     ,
 
@@ -71,27 +93,36 @@ impl AppObjects {
             .await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an Srgb surface texture. Using a different
-        // one will result all the colors comming out darker. If you want to support non
-        // Srgb surfaces, you'll need to account for that when drawing to the frame.
+        // Prefer an sRGB surface. Renderables draw linear colours into the scene target either
+        // way; the tone mapping pass (and the GUI) encode for surfaces that don't.
         let surface_format = surface_caps
             .formats
             .iter()
             .copied()
             .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
+        // Copying out of the surface lets frames be saved.
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC);
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode: settings.present_mode.pick(&surface_caps.present_modes),
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
 
 
+        let scene_format = pick_scene_format(&adapter, surface_format);
+        let supported_samples = supported_sample_counts(&adapter, device.features(), scene_format);
+        let settings = settings.validated(&supported_samples);
+        let shaders = ShaderRegistry::new();
+        let pipelines = PipelineCache::default();
+        let targets = RenderTargets::new(&device, &shaders, &pipelines, surface_format, scene_format, settings);
+
+        let profiler = Profiler::new(&device, &queue);
         // let mut renderables = vec![];
 
         Ok(Self {
@@ -100,6 +131,14 @@ impl AppObjects {
             queue,
             config,
             is_surface_configured: false,
+            targets,
+            shaders,
+            pipelines,
+            profiler,
+            supported_samples,
+            present_modes: surface_caps.present_modes,
+            redraw_requested: Cell::new(false),
+            requested_settings: Cell::new(None),
             // renderables,
             window,
         })
@@ -110,10 +149,54 @@ impl AppObjects {
             self.config.width = width;
             self.config.height = height;
             self.surface.configure(&self.device, &self.config);
+            self.targets.resize(&self.device, width, height);
             self.is_surface_configured = true;
         }
     }
 
+    pub fn settings(&self) -> &RenderSettings {
+        return self.targets.settings();
+    }
+
+    pub fn supported_sample_counts(&self) -> &[u32] {
+        return &self.supported_samples;
+    }
+
+    /// Switch render settings from the next frame on.
+    pub fn request_render_settings(&self, settings: RenderSettings) {
+        self.requested_settings.set(Some(settings));
+    }
+
+    /// Draw another frame after this one, even when only redrawing on demand. Call it while
+    /// something is changing on its own, like a playing clock.
+    pub fn request_redraw(&self) {
+        self.redraw_requested.set(true);
+    }
+
+    pub fn take_redraw_request(&self) -> bool {
+        return self.redraw_requested.take();
+    }
+
+    /// Get ready for a frame: apply requested settings, pick up shader edits and rebuild the
+    /// pipelines that need it.
+    pub fn prepare_frame(&mut self) {
+        self.profiler.begin_frame(&self.device);
+        if let Some(settings) = self.requested_settings.take() {
+            let settings = settings.validated(&self.supported_samples);
+            if settings != *self.settings() {
+                log::info!("render settings changed to {settings:?}");
+                self.targets = RenderTargets::new(&self.device, &self.shaders, &self.pipelines, self.config.format, self.targets.format(), settings);
+                self.config.present_mode = settings.present_mode.pick(&self.present_modes);
+                if self.is_surface_configured {
+                    self.surface.configure(&self.device, &self.config);
+                    self.targets.resize(&self.device, self.config.width, self.config.height);
+                }
+            }
+        }
+        self.shaders.poll();
+        self.pipelines.refresh(&self.device, &self.shaders, TargetState::of(&self.targets));
+    }
+
 
     /*
     pub fn handle_mouse(&mut self, event_loop: &ActiveEventLoop, state: ElementState, button: MouseButton) {
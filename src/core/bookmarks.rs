@@ -0,0 +1,351 @@
+//! Saving what the camera looks at, to share it or come back to it: a view state that goes to
+//! JSON or a short string, and named bookmarks of them.
+//!
+//! The string is a URL query, optionally after a `?`:
+//!
+//! ```text
+//! ll=46.5775,7.9854&h=4000&hpr=200,-15,0&fov=60&t=2024-07-01T12:00:00.000Z&layers=ride.gpx,tiles
+//! ```
+//!
+//! `ll` (lat,lon in degrees) is required. `h` is meters, `hpr` heading,pitch[,roll] in degrees,
+//! `t` the clock time and `layers` the visible layers; a missing one is left alone when the view is
+//! applied, except that `h` and `hpr` default to the ground and looking north along it.
+
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+use super::clock::{format_iso8601, parse_iso8601};
+use super::{CameraPose, Geodetic, LayerManager, Scene};
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ViewState {
+    pub lat: f64,
+    pub lon: f64,
+    pub height: f64,
+    pub heading: f64,
+    pub pitch: f64,
+    #[serde(default)]
+    pub roll: f64,
+    /// Vertical field of view in degrees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fov: Option<f64>,
+    /// Clock time, in seconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<f64>,
+    /// Names of the visible layers; the others are hidden.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layers: Option<Vec<String>>,
+}
+
+impl ViewState {
+    pub fn capture(scene: &Scene, layers: &LayerManager) -> Self {
+        let (at, heading, pitch, roll) = camera_hpr(&scene.cam);
+        return ViewState {
+            lat: at.lat,
+            lon: at.lon,
+            height: at.height,
+            heading,
+            pitch,
+            roll,
+            fov: Some(scene.cam.intrin.fovy()),
+            time: Some(scene.clock.current),
+            layers: Some(layers.visible().map(|l| l.name.clone()).collect()),
+        };
+    }
+
+    pub fn apply(&self, scene: &mut Scene, layers: &mut LayerManager) {
+        scene.cam.set_hpr(&self.at(), self.heading, self.pitch, self.roll);
+        if let Some(fov) = self.fov {
+            scene.cam.intrin.set_fovy(fov);
+        }
+        if let Some(t) = self.time {
+            scene.clock.seek(t);
+        }
+        if let Some(names) = &self.layers {
+            for l in layers.iter_mut() {
+                l.visible = names.contains(&l.name);
+            }
+            for name in names.iter().filter(|n| layers.find(n).is_none()) {
+                log::warn!("the view shows layer '{name}', which isn't open");
+            }
+        }
+    }
+
+    pub fn at(&self) -> Geodetic {
+        return Geodetic::new(self.lat, self.lon, self.height);
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        return read_json(path);
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        return write_json(path, self);
+    }
+
+    /// Load `s` if it names a JSON file, otherwise parse it as a view string.
+    pub fn parse_or_load(s: &str) -> anyhow::Result<Self> {
+        let path = Path::new(s.trim());
+        if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+            return ViewState::load(path);
+        }
+        return s.parse();
+    }
+}
+
+/// The view as a query string, rounded to a tenth of a millimeter and a millionth of a degree.
+impl std::fmt::Display for ViewState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // 1e-9 degrees is about 0.1 mm on the ground.
+        write!(f, "ll={},{}&h={}", number(self.lat, 9), number(self.lon, 9), number(self.height, 4))?;
+        write!(f, "&hpr={},{}", number(self.heading, 6), number(self.pitch, 6))?;
+        if self.roll != 0. {
+            write!(f, ",{}", number(self.roll, 6))?;
+        }
+        if let Some(fov) = self.fov {
+            write!(f, "&fov={}", number(fov, 6))?;
+        }
+        if let Some(t) = self.time {
+            write!(f, "&t={}", format_iso8601(t))?;
+        }
+        if let Some(layers) = &self.layers {
+            let names: Vec<String> = layers.iter().map(|n| escape(n)).collect();
+            write!(f, "&layers={}", names.join(","))?;
+        }
+        return Ok(());
+    }
+}
+
+impl FromStr for ViewState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let query = s.trim().split_once('?').map_or(s.trim(), |(_, q)| q);
+        let mut view = ViewState::default();
+        let mut has_position = false;
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').with_context(|| format!("'{pair}' in the view should be key=value"))?;
+            let numbers = || -> anyhow::Result<Vec<f64>> {
+                return value.split(',').map(|v| v.parse().with_context(|| format!("bad number '{v}' in {key}"))).collect();
+            };
+            match key {
+                "ll" => {
+                    let [lat, lon] = numbers()?[..] else {
+                        anyhow::bail!("ll should be lat,lon");
+                    };
+                    (view.lat, view.lon) = (lat, lon);
+                    has_position = true;
+                }
+                "h" => view.height = value.parse().with_context(|| format!("bad height '{value}'"))?,
+                "hpr" => match numbers()?[..] {
+                    [h, p] => (view.heading, view.pitch) = (h, p),
+                    [h, p, r] => (view.heading, view.pitch, view.roll) = (h, p, r),
+                    _ => anyhow::bail!("hpr should be heading,pitch or heading,pitch,roll"),
+                },
+                "fov" => view.fov = Some(value.parse().with_context(|| format!("bad field of view '{value}'"))?),
+                "t" => view.time = Some(parse_iso8601(value)?),
+                "layers" => view.layers = Some(value.split(',').filter(|n| !n.is_empty()).map(unescape).collect::<anyhow::Result<_>>()?),
+                _ => anyhow::bail!("unknown '{key}' in the view"),
+            }
+        }
+        anyhow::ensure!(has_position, "the view needs a position, as ll=lat,lon");
+        anyhow::ensure!((-90. ..=90.).contains(&view.lat), "latitude {} is out of range", view.lat);
+        return Ok(view);
+    }
+}
+
+/// Named views, kept in the order they were added.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Bookmarks {
+    entries: Vec<Bookmark>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    #[serde(flatten)]
+    pub view: ViewState,
+}
+
+impl Bookmarks {
+    /// Load bookmarks from a JSON file, or start with none if it doesn't exist yet.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Bookmarks::default());
+        }
+        return read_json(path);
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        return write_json(path, self);
+    }
+
+    /// Add a bookmark, replacing one with the same name.
+    pub fn add(&mut self, name: &str, view: ViewState) {
+        match self.entries.iter_mut().find(|b| b.name == name) {
+            Some(b) => b.view = view,
+            None => self.entries.push(Bookmark { name: name.to_string(), view }),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<ViewState> {
+        let i = self.entries.iter().position(|b| b.name == name)?;
+        return Some(self.entries.remove(i).view);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ViewState> {
+        return self.entries.iter().find(|b| b.name == name).map(|b| &b.view);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Bookmark> {
+        return self.entries.iter();
+    }
+}
+
+/// `CameraPose::geodetic_hpr`, except that looking straight up or down, where heading and roll turn
+/// about the same axis and can't be told apart, it is all heading.
+fn camera_hpr(cam: &CameraPose) -> (Geodetic, f64, f64, f64) {
+    let (at, heading, pitch, roll) = cam.geodetic_hpr();
+    if pitch.abs() > 89.9 {
+        return (at, cam.viewpoint().heading, pitch, 0.);
+    }
+    return (at, heading, pitch, roll);
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("can't read '{}'", path.display()))?;
+    return serde_json::from_str(&text).with_context(|| format!("can't parse '{}'", path.display()));
+}
+
+fn write_json<T: serde::Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
+    let text = serde_json::to_string_pretty(value)?;
+    return std::fs::write(path, text + "\n").with_context(|| format!("can't write '{}'", path.display()));
+}
+
+/// `x` with at most `decimals` decimals and no trailing zeros.
+fn number(x: f64, decimals: usize) -> String {
+    let s = format!("{x:.decimals$}");
+    let s = if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.') } else { &s };
+    return if s == "-0" { "0".to_string() } else { s.to_string() };
+}
+
+/// Percent-encode everything but unreserved URL characters.
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(b as char);
+        } else {
+            write!(out, "%{b:02X}").unwrap();
+        }
+    }
+    return out;
+}
+
+fn unescape(s: &str) -> anyhow::Result<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = tail.get(..2).and_then(|h| std::str::from_utf8(h).ok()).with_context(|| format!("bad escape in '{s}'"))?;
+            bytes.push(u8::from_str_radix(hex, 16).with_context(|| format!("bad escape in '{s}'"))?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    return String::from_utf8(bytes).with_context(|| format!("'{s}' isn't UTF-8"));
+}
+
+#[cfg(test)]
+fn eiger() -> ViewState {
+    return ViewState {
+        lat: 46.5775123456789,
+        lon: 7.9854123456789,
+        height: 4012.3456789,
+        heading: 201.23456789,
+        pitch: -15.43210987,
+        roll: 2.5,
+        fov: Some(60.),
+        time: Some(1719835200.25),
+        layers: Some(vec!["ride.gpx".to_string(), "tiles, 3D & more".to_string()]),
+    };
+}
+
+#[cfg(test)]
+fn assert_same_pose(a: &ViewState, b: &ViewState) {
+    let apart = (a.at().to_ecef() - b.at().to_ecef()).norm();
+    assert!(apart < 1e-3, "{apart} m apart: {a:?} vs {b:?}");
+    for (x, y) in [(a.heading, b.heading), (a.pitch, b.pitch), (a.roll, b.roll)] {
+        assert!((x - y).abs() < 1e-6, "{a:?} vs {b:?}");
+    }
+}
+
+#[test]
+fn views_round_trip_through_strings() {
+    let view = eiger();
+    let s = view.to_string();
+    assert_eq!(
+        s,
+        "ll=46.577512346,7.985412346&h=4012.3457&hpr=201.234568,-15.43211,2.5&fov=60\
+         &t=2024-07-01T12:00:00.250Z&layers=ride.gpx,tiles%2C%203D%20%26%20more"
+    );
+    let back: ViewState = s.parse().unwrap();
+    assert_same_pose(&view, &back);
+    assert_eq!((back.fov, back.time, &back.layers), (view.fov, view.time, &view.layers));
+
+    // Only the position is needed, and a URL in front is skipped.
+    let short: ViewState = "wglobe://view?ll=-33.9,151.2".parse().unwrap();
+    assert_eq!(short, ViewState { lat: -33.9, lon: 151.2, ..Default::default() });
+    assert_eq!(short.to_string(), "ll=-33.9,151.2&h=0&hpr=0,0");
+
+    for bad in ["h=100", "ll=1", "ll=1,2&hpr=1", "ll=1,2&zoom=3", "ll=95,0", "ll=1,2&layers=%zz", "ll=1,x"] {
+        assert!(bad.parse::<ViewState>().is_err(), "{bad}");
+    }
+}
+
+#[test]
+fn views_and_bookmarks_round_trip_through_json() {
+    let dir = std::env::temp_dir().join(format!("wglobe-bookmarks-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let view = eiger();
+    view.save(&dir.join("view.json")).unwrap();
+    let back = ViewState::load(&dir.join("view.json")).unwrap();
+    assert_same_pose(&view, &back);
+    assert_eq!(back, view);
+
+    let path = dir.join("bookmarks.json");
+    let mut bookmarks = Bookmarks::load(&path).unwrap();
+    assert_eq!(bookmarks.iter().count(), 0);
+    bookmarks.add("eiger", view.clone());
+    bookmarks.add("sydney", "ll=-33.9,151.2&h=500".parse().unwrap());
+    bookmarks.add("eiger", ViewState { height: 5000., ..view.clone() });
+    bookmarks.save(&path).unwrap();
+
+    let loaded = Bookmarks::load(&path).unwrap();
+    assert_eq!(loaded, bookmarks);
+    assert_eq!(loaded.iter().map(|b| b.name.as_str()).collect::<Vec<_>>(), ["eiger", "sydney"]);
+    assert_eq!(loaded.get("eiger").unwrap().height, 5000.);
+    bookmarks.remove("eiger");
+    assert!(bookmarks.get("eiger").is_none());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn views_round_trip_through_the_camera() {
+    for view in [eiger(), ViewState { lat: 80., lon: -120., height: 1e7, heading: 75., pitch: -90., ..Default::default() }] {
+        let mut cam = CameraPose::default();
+        cam.set_hpr(&view.at(), view.heading, view.pitch, view.roll);
+        let (at, h, p, r) = camera_hpr(&cam);
+        assert!((at.to_ecef() - view.at().to_ecef()).norm() < 1e-3, "{at:?}");
+        assert!((h - view.heading).abs() < 0.01 && (p - view.pitch).abs() < 0.01 && (r - view.roll).abs() < 0.01, "{h} {p} {r}");
+    }
+}
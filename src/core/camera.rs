@@ -1,11 +1,17 @@
-use nalgebra::{Isometry3,Point3,Vector3};
+use nalgebra::{Isometry3,Matrix3,Point3,Rotation3,Translation3,UnitQuaternion,Vector3};
 use wgpu::util::DeviceExt;
 
 use super::AppObjects;
+use super::Clock;
+use super::Geodetic;
+use super::pipelines::CachedLayout;
+use super::ToneMapping;
+use super::geo::{ecef_to_hpr, hpr_to_ecef};
+use super::shaders::uniforms::uniform_struct;
 
-type Isometry3f = Isometry3<f32>;
-type Point3f = Point3<f32>;
-type Vector3f = Vector3<f32>;
+/// While set, `CameraIntrin::to_matrix` is the identity rather than a perspective projection,
+/// and nothing can be culled against the frustum.
+pub(crate) const PROJECTION_IS_IDENTITY: bool = true;
 
 pub struct CameraIntrin {
     tlbr: [f32; 4],
@@ -15,24 +21,58 @@ pub struct CameraIntrin {
 
 pub struct CameraPose {
     pub intrin: CameraIntrin,
-    pub pose: Isometry3f,
+    /// World (ECEF) to view. In f64 so the eye keeps its precision far from the earth's centre;
+    /// it is cast to f32 only for the shaders.
+    pub pose: Isometry3<f64>,
+}
+
+/// Where a camera is and which way it looks, in degrees and meters, without roll.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Viewpoint {
+    pub at: Geodetic,
+    /// Clockwise from north.
+    pub heading: f64,
+    /// Up from the horizon, in [-90, 90].
+    pub pitch: f64,
 }
 
 pub struct Scene {
     pub cam: CameraPose,
+    pub clock: Clock,
     pub time: f32,
+    /// How the HDR scene is brought to the screen.
+    pub tone_mapping: ToneMapping,
 
-    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group_layout: CachedLayout,
     pub bind_group: wgpu::BindGroup,
     pub buffer: wgpu::Buffer,
 }
 
 impl CameraIntrin {
+    /// 2 tan(fovy / 2): the frustum height at unit distance.
+    pub fn sse_denominator(&self) -> f64 {
+        return ((self.tlbr[1] - self.tlbr[3]).abs() / self.zn) as f64;
+    }
+
+    /// Vertical field of view in degrees.
+    pub fn fovy(&self) -> f64 {
+        return 2. * (self.sse_denominator() / 2.).atan().to_degrees();
+    }
+
+    /// Change the vertical field of view, keeping the aspect ratio.
+    pub fn set_fovy(&mut self, degrees: f64) {
+        let scale = (degrees.clamp(1e-3, 179.9).to_radians() / 2.).tan() / (self.fovy().to_radians() / 2.).tan();
+        self.tlbr = self.tlbr.map(|x| x * scale as f32);
+    }
+
+    #[allow(clippy::identity_op, clippy::erasing_op)]
     fn to_matrix(&self) -> nalgebra::Matrix4<f32> {
         let mut out = nalgebra::Matrix4::<f32>::identity();
 
-        log::warn!("proj is identity for now");
-        return out;
+        if PROJECTION_IS_IDENTITY {
+            super::diagnostics::log_once!(log::Level::Warn, "proj is identity for now");
+            return out;
+        }
 
         let left = self.tlbr[0];
         let rght = self.tlbr[2];
@@ -53,12 +93,80 @@ impl Default for CameraPose {
     fn default() -> Self {
         return CameraPose {
             intrin: CameraIntrin { tlbr: [-1.,-1.,1.,1.], zn: 0.001, zf: 100. },
-            pose: Isometry3f::look_at_lh(&Point3f::new(0.,0.,-1.), &Vector3f::zeros().into(), &Vector3f::y())
+            pose: Isometry3::look_at_lh(&Point3::new(0.,0.,-1.), &Vector3::zeros().into(), &Vector3::y())
             // pose: Isometry3f::identity(),
         }
     }
 }
 
+impl CameraPose {
+    /// Camera position in world (ECEF) coordinates.
+    pub fn position(&self) -> Vector3<f64> {
+        return self.pose.inverse().translation.vector;
+    }
+
+    /// World (ECEF) to clip space, the same transform the shaders apply (`proj * mv`).
+    pub fn view_projection(&self) -> nalgebra::Matrix4<f64> {
+        return self.intrin.to_matrix().cast::<f64>() * self.pose.to_matrix();
+    }
+
+    /// Where the camera is and which way it looks, as a geodetic position and heading/pitch/roll
+    /// in degrees (see `geo::hpr_to_ecef`).
+    pub fn geodetic_hpr(&self) -> (Geodetic, f64, f64, f64) {
+        let at = Geodetic::from_ecef(&self.position());
+        // View space is left handed: +z looks forward, +y is up.
+        let inv = self.pose.rotation.inverse().to_rotation_matrix().into_inner();
+        let fwd = inv.column(2).into_owned();
+        let up = inv.column(1).into_owned();
+        let body = Matrix3::from_columns(&[fwd, up.cross(&fwd), up]);
+        let q = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(body));
+        let (h, p, r) = ecef_to_hpr(&at, &q);
+        return (at, h, p, r);
+    }
+
+    /// Put the camera at `at`, looking along `heading` and `pitch` (degrees, no roll). The
+    /// inverse of `geodetic_hpr`.
+    pub fn look_from(&mut self, at: &Geodetic, heading: f64, pitch: f64) {
+        let enu = at.enu_to_ecef();
+        let (h, p) = (heading.to_radians(), pitch.to_radians());
+        let dir = enu * Vector3::new(h.sin() * p.cos(), h.cos() * p.cos(), p.sin());
+        // Perpendicular to the view direction, so looking straight down still has an up.
+        let up = enu * Vector3::new(-h.sin() * p.sin(), -h.cos() * p.sin(), p.cos());
+        let eye = Point3::from(at.to_ecef());
+        self.pose = Isometry3::look_at_lh(&eye, &(eye + dir), &up);
+    }
+
+    /// Like `geodetic_hpr`, but keeps the heading when looking straight up or down by taking it
+    /// from the camera's up vector, as `look_from` sets it. Assumes there is no roll.
+    pub fn viewpoint(&self) -> Viewpoint {
+        let at = Geodetic::from_ecef(&self.position());
+        let to_enu = at.enu_to_ecef().transpose();
+        let inv = self.pose.rotation.inverse().to_rotation_matrix().into_inner();
+        let fwd = to_enu * inv.column(2);
+        let up = to_enu * inv.column(1);
+        let pitch = fwd.z.clamp(-1., 1.).asin();
+        // Horizontal with length one whatever the pitch.
+        let level = fwd * pitch.cos() - up * pitch.sin();
+        let heading = level.x.atan2(level.y).to_degrees().rem_euclid(360.);
+        return Viewpoint { at, heading, pitch: pitch.to_degrees() };
+    }
+
+    pub fn set_viewpoint(&mut self, v: &Viewpoint) {
+        self.look_from(&v.at, v.heading, v.pitch);
+    }
+
+    /// Put the camera at `at` with heading, pitch and roll in degrees. The inverse of
+    /// `geodetic_hpr`.
+    pub fn set_hpr(&mut self, at: &Geodetic, heading: f64, pitch: f64, roll: f64) {
+        let q = hpr_to_ecef(at, heading, pitch, roll);
+        // View x is the body's left, y its up and z its forward.
+        let view_to_ecef = Matrix3::from_columns(&[q * Vector3::y(), q * Vector3::z(), q * Vector3::x()]);
+        let rotation = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(view_to_ecef)).inverse();
+        let translation = Translation3::from(-(rotation * at.to_ecef()));
+        self.pose = Isometry3::from_parts(translation, rotation);
+    }
+}
+
 impl Scene {
     pub fn new(ao: &AppObjects) -> Self {
         let cam = Default::default();
@@ -69,9 +177,7 @@ impl Scene {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
         });
 
-        let bind_group_layout = ao.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("cameraBgl"),
-            entries: &[
+        let bind_group_layout = ao.pipelines.get_or_create_bgl(&ao.device, &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
@@ -83,8 +189,7 @@ impl Scene {
                     // count: 1u32.try_into().ok(),
                     count: None,
                 },
-            ],
-        });
+            ]);
 
         let bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("cameraBg"),
@@ -97,13 +202,20 @@ impl Scene {
 
         Scene {
             cam,
+            clock: Clock::default(),
             time: 0.,
+            tone_mapping: Default::default(),
             buffer,
             bind_group_layout,
             bind_group
         }
     }
 
+    pub fn tick(&mut self) {
+        self.clock.tick();
+        self.time = self.clock.elapsed() as f32;
+    }
+
     pub fn update_buffer(&self, ao: &AppObjects) {
         let lowered_scene: LoweredScene = self.into();
         ao.queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&lowered_scene));
@@ -118,28 +230,26 @@ where T: Default + Copy
     return out;
 }
 
-impl Into<LoweredScene> for &Scene {
-    fn into(self) -> LoweredScene {
-        log::info!("mv:\n{}", self.cam.pose.to_matrix());
+impl From<&Scene> for LoweredScene {
+    fn from(scene: &Scene) -> LoweredScene {
         LoweredScene {
-            mv: slice_to_array(self.cam.pose.to_matrix().as_slice()),
-            proj: slice_to_array(self.cam.intrin.to_matrix().as_slice()),
-            time: self.time,
+            mv: slice_to_array(scene.cam.pose.to_matrix().cast::<f32>().as_slice()),
+            proj: slice_to_array(scene.cam.intrin.to_matrix().as_slice()),
+            time: scene.time,
             ..Default::default()
         }
     }
 }
 
-#[repr(C)]
-#[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone, Debug, Default)]
-pub struct LoweredScene {
-    mv: [f32; 16],
-    proj: [f32; 16],
-
-    time: f32,
-    pad1: f32,
-    pad2: f32,
-    pad3: f32,
+uniform_struct! {
+    pub struct LoweredScene {
+        mv: [f32; 16],
+        proj: [f32; 16],
+        time: f32,
+        pad1: f32,
+        pad2: f32,
+        pad3: f32,
+    }
 }
 
 #[test]
@@ -156,7 +266,7 @@ fn check_bytemuck_stuff() {
     fn my_bytes_of<T>(t: &T) -> &[u8] {
         unsafe {
             let tt : *const T = t;
-            let ttt = tt as (*const u8);
+            let ttt = tt as *const u8;
             return &*std::ptr::slice_from_raw_parts(ttt, std::mem::size_of::<T>());
         }
     }
@@ -165,3 +275,61 @@ fn check_bytemuck_stuff() {
     println!("lscene bytes: {:?}", my_bytes_of(&lscene));
 }
 
+
+#[test]
+fn camera_readout_matches_look_at() {
+    // 1 km above 45N 10E, looking north and 30 degrees down.
+    let at = Geodetic::new(45., 10., 1000.);
+    let enu = at.enu_to_ecef();
+    let eye = at.to_ecef();
+    let dir = enu * Vector3::new(0., 30f64.to_radians().cos(), -30f64.to_radians().sin());
+    let up = enu * Vector3::z();
+    let cam = CameraPose {
+        pose: Isometry3::look_at_lh(&Point3::from(eye), &Point3::from(eye + dir * 100.), &up),
+        ..Default::default()
+    };
+
+    let (g, h, p, r) = cam.geodetic_hpr();
+    assert!((g.lat - 45.).abs() < 1e-4 && (g.lon - 10.).abs() < 1e-4, "{g:?}");
+    assert!((g.height - 1000.).abs() < 1e-3, "{g:?}");
+    assert!(h.rem_euclid(360.).min(360. - h.rem_euclid(360.)) < 0.1, "heading {h}");
+    assert!((p + 30.).abs() < 0.1, "pitch {p}");
+    assert!(r.abs() < 0.1, "roll {r}");
+}
+
+#[test]
+fn set_hpr_reads_back() {
+    let at = Geodetic::new(-33.9, 151.2, 5e5);
+    let mut cam = CameraPose::default();
+    for (heading, pitch, roll) in [(120., -60., 15.), (10., 20., -40.), (300., -5., 170.)] {
+        cam.set_hpr(&at, heading, pitch, roll);
+        let (_, h, p, r) = cam.geodetic_hpr();
+        assert!((h - heading).abs() < 0.01 && (p - pitch).abs() < 0.01 && (r - roll).abs() < 0.01, "{h} {p} {r}");
+    }
+}
+
+#[test]
+fn look_from_reads_back() {
+    for (lat, lon, height, heading, pitch) in [(45., 10., 1000., 0., -30.), (-33.9, 151.2, 5e5, 120., -60.), (80., 0., 1e7, 45., -90.), (0., -70., 200., 270., 10.)] {
+        let mut cam = CameraPose::default();
+        cam.look_from(&Geodetic::new(lat, lon, height), heading, pitch);
+        let (g, h, p, r) = cam.geodetic_hpr();
+        assert!((g.lat - lat).abs() < 1e-4 && (g.lon - lon).abs() < 1e-4, "{g:?}");
+        assert!((g.height - height).abs() < 1e-3, "{g:?}");
+        assert!((p - pitch).abs() < 0.1, "pitch {p}");
+        // Straight down, heading and roll are the same rotation.
+        if pitch > -90. {
+            assert!(((h - heading + 180.).rem_euclid(360.) - 180.).abs() < 0.1, "heading {h}");
+            assert!(r.abs() < 0.1, "roll {r}");
+        }
+        let mut rolled = CameraPose::default();
+        rolled.set_hpr(&Geodetic::new(lat, lon, height), heading, pitch, 0.);
+        assert!((rolled.pose.rotation.angle_to(&cam.pose.rotation)).abs() < 1e-4);
+        assert!((rolled.position() - cam.position()).norm() < 1e-3);
+
+        // The viewpoint keeps the heading even then.
+        let v = cam.viewpoint();
+        assert!(((v.heading - heading + 180.).rem_euclid(360.) - 180.).abs() < 0.1, "heading {}", v.heading);
+        assert!((v.pitch - pitch).abs() < 0.1, "pitch {}", v.pitch);
+    }
+}
@@ -0,0 +1,91 @@
+//! Reading a rendered frame back from the GPU, e.g. to save a screenshot.
+
+use std::path::Path;
+
+use super::AppObjects;
+
+/// A copy of a frame on its way to the CPU. Record it with `copy` before the frame's commands are
+/// submitted, then `save` it.
+pub struct FrameCapture {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_row: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl FrameCapture {
+    /// Copy `texture` (which needs `COPY_SRC` usage) into a readback buffer.
+    pub fn copy(ao: &AppObjects, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) -> Self {
+        let (width, height) = (texture.width(), texture.height());
+        let bytes_per_pixel = texture.format().block_copy_size(None).unwrap_or(4);
+        // Rows of a buffer copy have to start at multiples of 256 bytes.
+        let padded_row = (width * bytes_per_pixel).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frameCapture"),
+            size: (padded_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout { offset: 0, bytes_per_row: Some(padded_row), rows_per_image: Some(height) },
+            },
+            texture.size(),
+        );
+        return FrameCapture { buffer, width, height, padded_row, format: texture.format() };
+    }
+
+    /// Wait for the copy and return the frame as tightly packed 8 bit RGBA.
+    pub fn read(self, ao: &AppObjects) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+        let bgra = match self.format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            f => anyhow::bail!("can't read back frames in {f:?}"),
+        };
+        let slice = self.buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |r| tx.send(r).unwrap());
+        ao.device.poll(wgpu::PollType::Wait)?;
+        rx.recv()??;
+        let rgba = unpad_rows(&slice.get_mapped_range(), self.width, self.height, self.padded_row, bgra);
+        self.buffer.unmap();
+        return Ok((self.width, self.height, rgba));
+    }
+
+    pub fn save(self, ao: &AppObjects, path: &Path) -> anyhow::Result<()> {
+        let (width, height, rgba) = self.read(ao)?;
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&rgba)?;
+        log::info!("saved a {width}x{height} frame to '{}'", path.display());
+        return Ok(());
+    }
+}
+
+/// Drop the padding at the end of each row of 4 byte pixels, swapping to RGBA if they are BGRA.
+fn unpad_rows(data: &[u8], width: u32, height: u32, padded_row: u32, bgra: bool) -> Vec<u8> {
+    let row = width as usize * 4;
+    let mut out = Vec::with_capacity(row * height as usize);
+    for r in data.chunks(padded_row as usize).take(height as usize) {
+        out.extend_from_slice(&r[..row]);
+    }
+    if bgra {
+        out.chunks_exact_mut(4).for_each(|px| px.swap(0, 2));
+    }
+    return out;
+}
+
+#[test]
+fn rows_are_unpadded() {
+    // Two rows of two pixels, padded to 12 bytes.
+    let data = [1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, 9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0];
+    assert_eq!(unpad_rows(&data, 2, 2, 12, false), [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+    assert_eq!(unpad_rows(&data, 2, 2, 12, true), [3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 16]);
+    // The last row needn't be padded.
+    assert_eq!(unpad_rows(&data[..20], 2, 2, 12, false).len(), 16);
+}
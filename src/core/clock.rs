@@ -0,0 +1,226 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Julian date of the unix epoch (1970-01-01T00:00:00Z).
+pub const JD_UNIX_EPOCH: f64 = 2440587.5;
+
+/// Scene time. All absolute times in wglobe are UTC seconds since the unix epoch stored as `f64`
+/// (leap seconds are ignored).
+#[derive(Clone, Debug)]
+pub struct Clock {
+    pub start: f64,
+    pub stop: f64,
+    pub current: f64,
+
+    /// Scene seconds per wall-clock second.
+    pub rate: f64,
+    pub playing: bool,
+
+    last_tick: Option<Instant>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.);
+        return Clock {
+            start: now,
+            stop: now + 86400.,
+            current: now,
+            rate: 1.,
+            playing: true,
+            last_tick: None,
+        };
+    }
+}
+
+impl Clock {
+    pub fn new(start: f64, stop: f64) -> Self {
+        return Clock {
+            start,
+            stop,
+            current: start,
+            ..Default::default()
+        };
+    }
+
+    /// Change the clock's span, moving `current` to `start`.
+    pub fn set_range(&mut self, start: f64, stop: f64) {
+        self.start = start;
+        self.stop = stop;
+        self.current = start;
+    }
+
+    /// Advance by the wall-clock time since the previous call.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let dt = self.last_tick.map(|t| (now - t).as_secs_f64()).unwrap_or(0.);
+        self.last_tick = Some(now);
+        self.advance(dt);
+    }
+
+    pub fn advance(&mut self, wall_dt: f64) {
+        if self.playing {
+            self.current += wall_dt * self.rate;
+        }
+    }
+
+    /// Jump to `t`, clamped to the clock's span.
+    pub fn seek(&mut self, t: f64) {
+        self.current = t.clamp(self.start, self.stop.max(self.start));
+    }
+
+    /// Seconds since `start`. This is what ends up in `LoweredScene::time`.
+    pub fn elapsed(&self) -> f64 {
+        return self.current - self.start;
+    }
+
+    pub fn julian_date(&self) -> f64 {
+        return unix_to_jd(self.current);
+    }
+}
+
+pub fn unix_to_jd(t: f64) -> f64 {
+    return t / 86400. + JD_UNIX_EPOCH;
+}
+
+pub fn jd_to_unix(jd: f64) -> f64 {
+    return (jd - JD_UNIX_EPOCH) * 86400.;
+}
+
+// Howard Hinnant's days_from_civil.
+pub fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = m as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    return era * 146097 + doe - 719468;
+}
+
+pub fn civil_to_unix(y: i64, mo: u32, d: u32, h: u32, mi: u32, s: f64) -> f64 {
+    return days_from_civil(y, mo, d) as f64 * 86400. + (h * 3600 + mi * 60) as f64 + s;
+}
+
+/// Parse an ISO 8601 date or date-time such as `2012-03-15T10:00:00Z`,
+/// `2012-03-15T10:00:00.25+02:00` or `2012-03-15`. A missing zone is taken as UTC.
+pub fn parse_iso8601(s: &str) -> anyhow::Result<f64> {
+    let s = s.trim();
+    let bad = || anyhow::anyhow!("bad ISO 8601 time '{s}'");
+    let num = |t: &str| -> anyhow::Result<u32> { t.parse::<u32>().map_err(|_| bad()) };
+
+    let (date, time) = match s.find(['T', 't', ' ']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+
+    let dparts: Vec<&str> = date.split('-').collect();
+    if dparts.len() != 3 {
+        return Err(bad());
+    }
+    let y: i64 = dparts[0].parse().map_err(|_| bad())?;
+    let mo = num(dparts[1])?;
+    let d = num(dparts[2])?;
+    if !(1..=12).contains(&mo) || !(1..=31).contains(&d) {
+        return Err(bad());
+    }
+
+    let Some(time) = time else {
+        return Ok(civil_to_unix(y, mo, d, 0, 0, 0.));
+    };
+
+    let (clock, offset) = if let Some(t) = time.strip_suffix(['Z', 'z']) {
+        (t, 0.)
+    } else if let Some(i) = time.rfind(['+', '-']) {
+        let (t, z) = time.split_at(i);
+        let sign = if z.starts_with('-') { -1. } else { 1. };
+        let z = &z[1..];
+        let (zh, zm) = match z.split_once(':') {
+            Some((a, b)) => (num(a)?, num(b)?),
+            None if z.len() == 4 => (num(&z[..2])?, num(&z[2..])?),
+            None => (num(z)?, 0),
+        };
+        (t, sign * (zh * 3600 + zm * 60) as f64)
+    } else {
+        (time, 0.)
+    };
+
+    let tparts: Vec<&str> = clock.split(':').collect();
+    if tparts.len() < 2 || tparts.len() > 3 {
+        return Err(bad());
+    }
+    let h = num(tparts[0])?;
+    let mi = num(tparts[1])?;
+    let sec: f64 = match tparts.get(2) {
+        Some(t) => t.parse().map_err(|_| bad())?,
+        None => 0.,
+    };
+
+    return Ok(civil_to_unix(y, mo, d, h, mi, sec) - offset);
+}
+
+/// Inverse of `parse_iso8601`, always UTC with millisecond precision.
+pub fn format_iso8601(t: f64) -> String {
+    let ms = (t * 1000.).round() as i64;
+    let days = ms.div_euclid(86_400_000);
+    let rem = ms.rem_euclid(86_400_000);
+
+    // civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+
+    return format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        y,
+        m,
+        d,
+        rem / 3_600_000,
+        rem / 60_000 % 60,
+        rem / 1000 % 60,
+        rem % 1000
+    );
+}
+
+#[test]
+fn iso8601_parsing() {
+    assert_eq!(parse_iso8601("1970-01-01T00:00:00Z").unwrap(), 0.);
+    assert_eq!(parse_iso8601("2000-01-01T12:00:00Z").unwrap(), 946728000.);
+    assert_eq!(parse_iso8601("2000-01-01T14:00:00+02:00").unwrap(), 946728000.);
+    assert_eq!(parse_iso8601("2000-01-01T07:30-0430").unwrap(), 946728000.);
+    assert_eq!(parse_iso8601("2000-01-01").unwrap(), 946684800.);
+    assert_eq!(parse_iso8601("2012-03-15T10:00:00.25").unwrap(), 1331805600.25);
+    assert!(parse_iso8601("2012-13-15T10:00:00Z").is_err());
+    assert!(parse_iso8601("yesterday").is_err());
+
+    assert_eq!(unix_to_jd(946728000.), 2451545.0);
+    assert_eq!(format_iso8601(1331805600.25), "2012-03-15T10:00:00.250Z");
+    assert_eq!(format_iso8601(-1.), "1969-12-31T23:59:59.000Z");
+}
+
+#[test]
+fn clock_advances_only_while_playing() {
+    let mut c = Clock::new(100., 200.);
+    c.rate = 10.;
+    c.advance(0.5);
+    assert_eq!(c.current, 105.);
+    c.playing = false;
+    c.advance(0.5);
+    assert_eq!(c.elapsed(), 5.);
+}
+
+#[test]
+fn seek_is_clamped_to_span() {
+    let mut c = Clock::new(100., 200.);
+    c.seek(150.);
+    assert_eq!(c.current, 150.);
+    c.seek(250.);
+    assert_eq!(c.current, 200.);
+    c.seek(0.);
+    assert_eq!(c.current, 100.);
+}
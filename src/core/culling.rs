@@ -0,0 +1,360 @@
+use nalgebra::{Matrix3, Matrix4, Vector3};
+
+use super::geo::{WGS84_A, WGS84_B};
+use super::camera::PROJECTION_IS_IDENTITY;
+use super::CameraPose;
+
+/// Where a volume is relative to the frustum.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Intersection {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+/// `normal . p + d = 0`, with the normal pointing into the frustum.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f64>,
+    pub d: f64,
+}
+
+impl Plane {
+    fn from_row(r: nalgebra::RowVector4<f64>) -> Self {
+        let normal = Vector3::new(r[0], r[1], r[2]);
+        let len = normal.norm();
+        return Plane { normal: normal / len, d: r[3] / len };
+    }
+
+    pub fn distance(&self, p: &Vector3<f64>) -> f64 {
+        return self.normal.dot(p) + self.d;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vector3<f64>,
+    pub radius: f64,
+}
+
+impl BoundingSphere {
+    /// A sphere around the points' bounding box; None without points.
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vector3<f64>> + Clone) -> Option<Self> {
+        let aabb = Aabb::from_points(points.clone())?;
+        let center = aabb.center();
+        let radius = points.into_iter().map(|p| (p - center).norm()).fold(0., f64::max);
+        return Some(BoundingSphere { center, radius });
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+}
+
+impl Aabb {
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vector3<f64>>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = *points.next()?;
+        let (mut min, mut max) = (first, first);
+        for p in points {
+            min = min.inf(p);
+            max = max.sup(p);
+        }
+        return Some(Aabb { min, max });
+    }
+
+    pub fn center(&self) -> Vector3<f64> {
+        return (self.min + self.max) / 2.;
+    }
+}
+
+/// An oriented box: `center` plus any combination of the columns of `half_axes` scaled by at
+/// most one (3D Tiles' `box` bounding volume).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Obb {
+    pub center: Vector3<f64>,
+    pub half_axes: Matrix3<f64>,
+}
+
+impl From<&Aabb> for Obb {
+    fn from(b: &Aabb) -> Self {
+        return Obb { center: b.center(), half_axes: Matrix3::from_diagonal(&((b.max - b.min) / 2.)) };
+    }
+}
+
+/// The six planes of a view frustum: left, right, bottom, top, near, far.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Planes of a world to clip space transform, for wgpu's clip volume (-w..w in x and y, 0..w
+    /// in z) (Gribb & Hartmann).
+    pub fn from_view_projection(m: &Matrix4<f64>) -> Self {
+        let r = |i: usize| m.row(i).into_owned();
+        return Frustum {
+            planes: [
+                Plane::from_row(r(3) + r(0)),
+                Plane::from_row(r(3) - r(0)),
+                Plane::from_row(r(3) + r(1)),
+                Plane::from_row(r(3) - r(1)),
+                Plane::from_row(r(2)),
+                Plane::from_row(r(3) - r(2)),
+            ],
+        };
+    }
+
+    /// Classify a volume that reaches `radius(plane normal)` from `center` towards each plane.
+    fn classify(&self, center: &Vector3<f64>, radius: impl Fn(&Vector3<f64>) -> f64) -> Intersection {
+        let mut out = Intersection::Inside;
+        for plane in &self.planes {
+            let (s, r) = (plane.distance(center), radius(&plane.normal));
+            if s < -r {
+                return Intersection::Outside;
+            }
+            if s < r {
+                out = Intersection::Intersecting;
+            }
+        }
+        return out;
+    }
+
+    pub fn point(&self, p: &Vector3<f64>) -> Intersection {
+        return self.classify(p, |_| 0.);
+    }
+
+    pub fn sphere(&self, s: &BoundingSphere) -> Intersection {
+        return self.classify(&s.center, |_| s.radius);
+    }
+
+    pub fn aabb(&self, b: &Aabb) -> Intersection {
+        let half = (b.max - b.min) / 2.;
+        return self.classify(&b.center(), |n| n.abs().dot(&half));
+    }
+
+    pub fn obb(&self, b: &Obb) -> Intersection {
+        return self.classify(&b.center, |n| (b.half_axes.transpose() * n).abs().sum());
+    }
+}
+
+/// Hides what is behind the curve of the WGS84 ellipsoid as seen from a camera.
+///
+/// Tests happen in "scaled space", where the ellipsoid is the unit sphere: a point is hidden
+/// when it is past the plane of the horizon circle and inside the cone from the eye that grazes
+/// the sphere.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EllipsoidalOccluder {
+    /// Camera position in scaled space.
+    eye: Vector3<f64>,
+    /// Squared distance from the eye to the horizon in scaled space; negative inside.
+    horizon2: f64,
+}
+
+fn to_scaled(p: &Vector3<f64>) -> Vector3<f64> {
+    return Vector3::new(p.x / WGS84_A, p.y / WGS84_A, p.z / WGS84_B);
+}
+
+impl EllipsoidalOccluder {
+    pub fn new(eye: &Vector3<f64>) -> Self {
+        let eye = to_scaled(eye);
+        return EllipsoidalOccluder { eye, horizon2: eye.norm_squared() - 1. };
+    }
+
+    /// Whether a point given in scaled space can be seen. Nothing is hidden with the eye inside
+    /// the ellipsoid.
+    pub fn is_scaled_point_visible(&self, p: &Vector3<f64>) -> bool {
+        if self.horizon2 <= 0. {
+            return true;
+        }
+        let vt = p - self.eye;
+        let vt_dot_vc = -vt.dot(&self.eye);
+        let occluded = vt_dot_vc > self.horizon2 && vt_dot_vc * vt_dot_vc / vt.norm_squared() > self.horizon2;
+        return !occluded;
+    }
+
+    /// Whether an ECEF point can be seen.
+    pub fn is_point_visible(&self, p: &Vector3<f64>) -> bool {
+        return self.is_scaled_point_visible(&to_scaled(p));
+    }
+}
+
+/// The "occludee point" of a set of ECEF points (Cesium's horizon culling point): a point in
+/// scaled space, along `direction`, that is hidden by the ellipsoid only when all the points
+/// are. None when there is no such point, e.g. when the points wrap too far around the globe.
+pub fn horizon_culling_point<'a>(direction: &Vector3<f64>, points: impl IntoIterator<Item = &'a Vector3<f64>>) -> Option<Vector3<f64>> {
+    let dir = to_scaled(direction).try_normalize(0.)?;
+    let mut magnitude = 0f64;
+    for p in points {
+        let scaled = to_scaled(p);
+        let mag2 = scaled.norm_squared();
+        let to_p = scaled / mag2.sqrt();
+        // Points under the surface count as being on it.
+        let (mag2, mag) = (mag2.max(1.), mag2.sqrt().max(1.));
+        let cos_alpha = to_p.dot(&dir);
+        let sin_alpha = to_p.cross(&dir).norm();
+        let cos_beta = 1. / mag;
+        let sin_beta = (mag2 - 1.).sqrt() * cos_beta;
+        let denominator = cos_alpha * cos_beta - sin_alpha * sin_beta;
+        if denominator <= 0. || !denominator.is_finite() {
+            return None;
+        }
+        magnitude = magnitude.max(1. / denominator);
+    }
+    return (magnitude > 0.).then(|| dir * magnitude);
+}
+
+/// What a renderable occupies, for culling.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounds {
+    pub sphere: BoundingSphere,
+    /// See `horizon_culling_point`; None to skip horizon culling.
+    pub occludee: Option<Vector3<f64>>,
+}
+
+impl Bounds {
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vector3<f64>> + Clone) -> Option<Self> {
+        let sphere = BoundingSphere::from_points(points.clone())?;
+        return Some(Bounds { sphere, occludee: horizon_culling_point(&sphere.center, points) });
+    }
+
+    /// Bounds of a sphere, hidden by the horizon only when the whole sphere is.
+    pub fn from_sphere(sphere: BoundingSphere) -> Self {
+        // The corners of the box around the sphere.
+        let r = sphere.radius;
+        let corners: Vec<Vector3<f64>> = [-r, r]
+            .iter()
+            .flat_map(|&x| [-r, r].into_iter().flat_map(move |y| [-r, r].into_iter().map(move |z| Vector3::new(x, y, z))))
+            .map(|o| sphere.center + o)
+            .collect();
+        return Bounds { sphere, occludee: horizon_culling_point(&sphere.center, &corners) };
+    }
+}
+
+/// The view frustum and horizon of one frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CullingVolume {
+    /// None when the camera has no real projection yet, leaving only the horizon to cull.
+    pub frustum: Option<Frustum>,
+    pub occluder: EllipsoidalOccluder,
+}
+
+impl CullingVolume {
+    pub fn new(view_proj: &Matrix4<f64>, eye: &Vector3<f64>) -> Self {
+        return CullingVolume { frustum: Some(Frustum::from_view_projection(view_proj)), occluder: EllipsoidalOccluder::new(eye) };
+    }
+
+    pub fn from_camera(cam: &CameraPose) -> Self {
+        if PROJECTION_IS_IDENTITY {
+            return CullingVolume { frustum: None, occluder: EllipsoidalOccluder::new(&cam.position()) };
+        }
+        return Self::new(&cam.view_projection(), &cam.position());
+    }
+
+    pub fn is_visible(&self, bounds: &Bounds) -> bool {
+        if self.frustum.is_some_and(|f| f.sphere(&bounds.sphere) == Intersection::Outside) {
+            return false;
+        }
+        return bounds.occludee.is_none_or(|p| self.occluder.is_scaled_point_visible(&p));
+    }
+}
+
+#[cfg(test)]
+fn test_frustum() -> Frustum {
+    // 90 degree perspective looking down -z from the origin (right handed view space), near 1,
+    // far 100, depth mapped to 0..1.
+    let (n, f) = (1., 100.);
+    #[rustfmt::skip]
+    let proj = Matrix4::new(
+        1., 0., 0., 0.,
+        0., 1., 0., 0.,
+        0., 0., f / (n - f), n * f / (n - f),
+        0., 0., -1., 0.,
+    );
+    return Frustum::from_view_projection(&proj);
+}
+
+#[test]
+fn frustum_planes_and_volumes() {
+    let fr = test_frustum();
+    let p = |x: f64, y: f64, z: f64| Vector3::new(x, y, z);
+    // Near plane at z = -1 facing -z, far plane at z = -100 facing +z.
+    assert!((fr.planes[4].distance(&p(0., 0., -1.))).abs() < 1e-9);
+    assert!((fr.planes[4].normal - p(0., 0., -1.)).norm() < 1e-9);
+    assert!((fr.planes[5].distance(&p(0., 0., -100.))).abs() < 1e-9);
+    // Side planes at 45 degrees.
+    assert!((fr.planes[0].normal - p(1., 0., -1.).normalize()).norm() < 1e-9);
+
+    assert_eq!(fr.point(&p(0., 0., -10.)), Intersection::Inside);
+    assert_eq!(fr.point(&p(0., 0., 10.)), Intersection::Outside);
+    assert_eq!(fr.point(&p(11., 0., -10.)), Intersection::Outside);
+
+    let sphere = |c: Vector3<f64>, radius: f64| BoundingSphere { center: c, radius };
+    assert_eq!(fr.sphere(&sphere(p(0., 0., -50.), 5.)), Intersection::Inside);
+    assert_eq!(fr.sphere(&sphere(p(0., 0., -0.5), 1.)), Intersection::Intersecting);
+    assert_eq!(fr.sphere(&sphere(p(0., 0., 5.), 1.)), Intersection::Outside);
+    // Next to the left plane: 1.5 away from it.
+    assert_eq!(fr.sphere(&sphere(p(-10. - 1.5 * 2f64.sqrt(), 0., -10.), 1.)), Intersection::Outside);
+    assert_eq!(fr.sphere(&sphere(p(-10. - 1.5 * 2f64.sqrt(), 0., -10.), 2.)), Intersection::Intersecting);
+
+    let aabb = |min: Vector3<f64>, max: Vector3<f64>| Aabb { min, max };
+    assert_eq!(fr.aabb(&aabb(p(-1., -1., -20.), p(1., 1., -10.))), Intersection::Inside);
+    assert_eq!(fr.aabb(&aabb(p(-1., -1., -120.), p(1., 1., -90.))), Intersection::Intersecting);
+    assert_eq!(fr.aabb(&aabb(p(12., -1., -11.), p(14., 1., -10.))), Intersection::Outside);
+
+    // A long thin diagonal box off to the right: its tip pokes in, then it is outside although
+    // its bounding sphere still reaches in.
+    let rot = nalgebra::Rotation3::from_axis_angle(&Vector3::z_axis(), std::f64::consts::FRAC_PI_4);
+    let thin = |c| Obb { center: c, half_axes: rot.matrix() * Matrix3::from_diagonal(&p(10., 0.1, 0.1)) };
+    assert_eq!(fr.obb(&thin(p(16., 0., -10.))), Intersection::Intersecting);
+    assert_eq!(fr.obb(&thin(p(19., 0., -10.))), Intersection::Outside);
+    assert_eq!(fr.sphere(&sphere(p(19., 0., -10.), 10.)), Intersection::Intersecting);
+    assert_eq!(fr.obb(&Obb::from(&aabb(p(-1., -1., -20.), p(1., 1., -10.)))), Intersection::Inside);
+}
+
+#[test]
+fn horizon_hides_far_side() {
+    use super::Geodetic;
+    let eye = Geodetic::new(0., 0., 1_000_000.).to_ecef();
+    let occluder = EllipsoidalOccluder::new(&eye);
+    assert!(occluder.is_point_visible(&Geodetic::new(5., 5., 0.).to_ecef()));
+    assert!(!occluder.is_point_visible(&Geodetic::new(0., 40., 0.).to_ecef()));
+    assert!(occluder.is_point_visible(&Geodetic::new(0., 40., 5_000_000.).to_ecef()));
+    assert!(EllipsoidalOccluder::new(&Vector3::zeros()).is_point_visible(&Geodetic::new(0., 180., 0.).to_ecef()));
+
+    // A patch of ground: visible when any of it is.
+    let patch = |lat: f64, lon: f64| -> Vec<Vector3<f64>> {
+        [(0., 0.), (0., 1.), (1., 0.), (1., 1.)].iter().map(|(a, b)| Geodetic::new(lat + a, lon + b, 0.).to_ecef()).collect()
+    };
+    let occludee = |pts: &Vec<Vector3<f64>>| Bounds::from_points(pts).unwrap().occludee.unwrap();
+    assert!(occluder.is_scaled_point_visible(&occludee(&patch(0., 5.))));
+    assert!(!occluder.is_scaled_point_visible(&occludee(&patch(0., 60.))));
+    // The horizon is about 30 degrees away; this patch straddles it.
+    let straddling = patch(0., 29.5);
+    assert!(straddling.iter().any(|p| occluder.is_point_visible(p)));
+    assert!(occluder.is_scaled_point_visible(&occludee(&straddling)));
+    // Points all around the globe have no occludee point.
+    let around = [Geodetic::new(0., 0., 0.).to_ecef(), Geodetic::new(0., 180., 0.).to_ecef()];
+    assert_eq!(Bounds::from_points(&around).unwrap().occludee, None);
+
+    // A tall sphere on the far side peeks over the horizon.
+    let far = Geodetic::new(0., 40., 0.).to_ecef();
+    assert!(!occluder.is_scaled_point_visible(&Bounds::from_sphere(BoundingSphere { center: far, radius: 1000. }).occludee.unwrap()));
+    assert!(occluder.is_scaled_point_visible(&Bounds::from_sphere(BoundingSphere { center: far, radius: 3_000_000. }).occludee.unwrap()));
+}
+
+#[test]
+fn the_camera_sees_the_globe() {
+    use super::Geodetic;
+    let mut cam = CameraPose::default();
+    cam.look_from(&Geodetic::new(0., 0., 1_000_000.), 0., -90.);
+    let volume = CullingVolume::from_camera(&cam);
+    let globe = Bounds::from_sphere(BoundingSphere { center: Vector3::zeros(), radius: 6_378_137. });
+    assert!(volume.is_visible(&globe));
+    let below = Bounds::from_sphere(BoundingSphere { center: Geodetic::new(0., 0., 0.).to_ecef(), radius: 100. });
+    assert!(volume.is_visible(&below));
+    let far_side = Bounds::from_sphere(BoundingSphere { center: Geodetic::new(0., 180., 0.).to_ecef(), radius: 100. });
+    assert!(!volume.is_visible(&far_side));
+}
@@ -0,0 +1,120 @@
+//! Logging setup and helpers for messages that would otherwise repeat every frame.
+//!
+//! Log targets are module paths, so `RUST_LOG=wglobe::tiles=debug` turns on one subsystem. Each
+//! line carries the number of the frame being drawn when it was logged.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Used when RUST_LOG isn't set: our messages at info, the GPU stack's only when something is wrong.
+pub const DEFAULT_FILTER: &str = "info,wgpu_core=warn,wgpu_hal=warn,naga=warn";
+
+static FRAME: AtomicU64 = AtomicU64::new(0);
+
+/// The frame being drawn, counting from 1; 0 before the first.
+pub fn frame() -> u64 {
+    return FRAME.load(Ordering::Relaxed);
+}
+
+/// Called by the event loop when a frame starts.
+pub fn next_frame() {
+    FRAME.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Set up logging from RUST_LOG, or `DEFAULT_FILTER` without it. `level`, e.g. from the command
+/// line, replaces the level of wglobe's own messages.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn init_logging(level: Option<log::LevelFilter>) {
+    use std::io::Write;
+
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(DEFAULT_FILTER));
+    if let Some(level) = level {
+        builder.filter_module(env!("CARGO_PKG_NAME"), level);
+    }
+    builder.format(|buf, record| {
+        let ts = buf.timestamp_millis();
+        let style = buf.default_level_style(record.level());
+        writeln!(buf, "[{ts} {style}{:<5}{style:#} {}] {}{}", record.level(), record.target(), frame_prefix(frame()), record.args())
+    });
+    builder.init();
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn init_logging(level: Option<log::LevelFilter>) {
+    let level = level.and_then(|l| l.to_level()).unwrap_or(log::Level::Info);
+    console_log::init_with_level(level).expect("logging was set up twice");
+}
+
+fn frame_prefix(frame: u64) -> String {
+    return if frame == 0 { String::new() } else { format!("frame {frame}: ") };
+}
+
+/// Counts how often a log statement was reached; see `log_once!` and `log_every_n!`.
+pub struct RateLimit {
+    calls: AtomicU64,
+}
+
+impl RateLimit {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        return RateLimit { calls: AtomicU64::new(0) };
+    }
+
+    /// True the first time only.
+    pub fn once(&self) -> bool {
+        return self.calls.fetch_add(1, Ordering::Relaxed) == 0;
+    }
+
+    /// Some on the first call and every `n`th after it, with the number of calls skipped since
+    /// the last one.
+    pub fn every(&self, n: u64) -> Option<u64> {
+        let n = n.max(1);
+        let call = self.calls.fetch_add(1, Ordering::Relaxed);
+        return match call {
+            0 => Some(0),
+            _ if call.is_multiple_of(n) => Some(n - 1),
+            _ => None,
+        };
+    }
+}
+
+/// Log the first time this statement is reached, and never again.
+macro_rules! log_once {
+    ($level:expr, $($arg:tt)+) => {{
+        static LIMIT: $crate::core::diagnostics::RateLimit = $crate::core::diagnostics::RateLimit::new();
+        if LIMIT.once() {
+            log::log!($level, $($arg)+);
+        }
+    }};
+}
+pub(crate) use log_once;
+
+/// Log the first time this statement is reached and every `n`th time after, with how many
+/// were skipped.
+macro_rules! log_every_n {
+    ($n:expr, $level:expr, $($arg:tt)+) => {{
+        static LIMIT: $crate::core::diagnostics::RateLimit = $crate::core::diagnostics::RateLimit::new();
+        match LIMIT.every($n) {
+            Some(0) => log::log!($level, $($arg)+),
+            Some(skipped) => log::log!($level, "{} ({skipped} more like this)", format_args!($($arg)+)),
+            None => {}
+        }
+    }};
+}
+pub(crate) use log_every_n;
+
+#[test]
+fn rate_limits() {
+    let once = RateLimit::new();
+    assert_eq!((0..5).map(|_| once.once()).collect::<Vec<_>>(), [true, false, false, false, false]);
+
+    let every = RateLimit::new();
+    let logged: Vec<(usize, u64)> = (0..10).filter_map(|i| every.every(4).map(|s| (i, s))).collect();
+    assert_eq!(logged, [(0, 0), (4, 3), (8, 3)]);
+    assert_eq!(RateLimit::new().every(0), Some(0));
+}
+
+#[test]
+fn lines_say_which_frame() {
+    assert_eq!(frame_prefix(0), "");
+    assert_eq!(frame_prefix(1234), "frame 1234: ");
+}
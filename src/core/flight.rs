@@ -0,0 +1,285 @@
+//! Moving the camera: free flight driven by input, and animated flights to a viewpoint.
+
+use nalgebra::{UnitQuaternion, Unit, Vector3};
+
+use super::camera::{CameraPose, Viewpoint};
+use super::geo::{Geodetic, WGS84_A};
+use super::input::{Action, InputState};
+
+/// The camera never goes lower than this above the ellipsoid, in meters.
+pub const MIN_HEIGHT: f64 = 1.;
+/// Flights climb to this fraction of the distance they cover, if that is higher than both ends.
+const CRUISE_PER_DISTANCE: f64 = 0.3;
+const MAX_CRUISE: f64 = 1.5e7;
+
+/// First person movement in the local east/north/up frame: forward and sideways along the
+/// heading, up and down along the vertical, looking around while `Action::Look` is held.
+#[derive(Copy, Clone, Debug)]
+pub struct FreeFlight {
+    /// Speed in meters per second per meter of height, so the ground below seems to move at the
+    /// same rate at any height.
+    pub speed: f64,
+    /// Speed is multiplied by this while shift is held.
+    pub boost: f64,
+    /// Degrees turned per pixel of mouse movement.
+    pub look_sensitivity: f64,
+}
+
+impl Default for FreeFlight {
+    fn default() -> Self {
+        return FreeFlight { speed: 0.5, boost: 5., look_sensitivity: 0.2 };
+    }
+}
+
+impl FreeFlight {
+    /// True while the input moves the camera.
+    pub fn active(&self, input: &InputState) -> bool {
+        let moves = [Action::MoveForward, Action::MoveBack, Action::MoveLeft, Action::MoveRight, Action::MoveUp, Action::MoveDown];
+        return moves.into_iter().any(|a| input.is_down(a)) || input.is_down(Action::Look);
+    }
+
+    /// Move the camera for `dt` seconds of input.
+    pub fn update(&self, cam: &mut CameraPose, input: &InputState, dt: f64) {
+        if !self.active(input) {
+            return;
+        }
+        let mut v = cam.viewpoint();
+        if input.is_down(Action::Look) {
+            let [dx, dy] = input.cursor_delta;
+            v.heading = (v.heading + dx * self.look_sensitivity).rem_euclid(360.);
+            v.pitch = (v.pitch - dy * self.look_sensitivity).clamp(-90., 90.);
+        }
+
+        let (sh, ch) = v.heading.to_radians().sin_cos();
+        let right = input.axis(Action::MoveLeft, Action::MoveRight) as f64;
+        let forward = input.axis(Action::MoveBack, Action::MoveForward) as f64;
+        let up = input.axis(Action::MoveDown, Action::MoveUp) as f64;
+        let dir = Vector3::new(sh, ch, 0.) * forward + Vector3::new(ch, -sh, 0.) * right + Vector3::z() * up;
+        if let Some(dir) = dir.try_normalize(1e-9) {
+            let boost = if input.modifiers.shift_key() { self.boost } else { 1. };
+            let step = self.speed * boost * v.at.height.max(MIN_HEIGHT) * dt;
+            let mut at = Geodetic::from_ecef(&(v.at.to_ecef() + v.at.enu_to_ecef() * dir * step));
+            at.height = at.height.max(MIN_HEIGHT);
+            v.at = at;
+        }
+        cam.set_viewpoint(&v);
+    }
+}
+
+/// An animated flight between two viewpoints. The position follows the great circle between the
+/// two, the height climbs and descends smoothly, and heading and pitch turn the short way.
+#[derive(Clone, Debug)]
+pub struct Flight {
+    from: Viewpoint,
+    to: Viewpoint,
+    /// Seconds.
+    duration: f64,
+    elapsed: f64,
+    /// Height added at the middle of the flight.
+    climb: f64,
+}
+
+impl Flight {
+    pub fn new(from: Viewpoint, to: Viewpoint, duration: f64) -> Self {
+        let distance = arc_angle(&from.at, &to.at) * WGS84_A;
+        let top = from.at.height.max(to.at.height);
+        let climb = ((distance * CRUISE_PER_DISTANCE).min(MAX_CRUISE) - top).max(0.);
+        return Flight { from, to, duration: duration.max(0.), elapsed: 0., climb };
+    }
+
+    /// The viewpoint at `t` from 0 (start) to 1 (end).
+    pub fn at(&self, t: f64) -> Viewpoint {
+        let t = t.clamp(0., 1.);
+        // Start and stop gently.
+        let s = t * t * (3. - 2. * t);
+        let (n0, n1) = (self.from.at.enu_to_ecef().column(2).into_owned(), self.to.at.enu_to_ecef().column(2).into_owned());
+        let n = slerp(&n0, &n1, s);
+        let hump = (std::f64::consts::PI * s).sin().powi(2);
+        let height = lerp(self.from.at.height, self.to.at.height, s) + self.climb * hump;
+        let at = Geodetic::new(n.z.clamp(-1., 1.).asin().to_degrees(), n.y.atan2(n.x).to_degrees(), height);
+        let turn = (self.to.heading - self.from.heading + 180.).rem_euclid(360.) - 180.;
+        let heading = (self.from.heading + turn * s).rem_euclid(360.);
+        return Viewpoint { at, heading, pitch: lerp(self.from.pitch, self.to.pitch, s) };
+    }
+
+    pub fn finished(&self) -> bool {
+        return self.elapsed >= self.duration;
+    }
+
+    /// Move on by `dt` seconds and put the camera where the flight is.
+    pub fn advance(&mut self, cam: &mut CameraPose, dt: f64) {
+        self.elapsed += dt;
+        let t = if self.duration > 0. { self.elapsed / self.duration } else { 1. };
+        cam.set_viewpoint(&self.at(t));
+    }
+}
+
+/// Free flight, interrupted by flights started with `fly_to`.
+#[derive(Clone, Debug, Default)]
+pub struct CameraController {
+    pub free: FreeFlight,
+    flight: Option<Flight>,
+}
+
+impl CameraController {
+    /// Fly from where the camera is to `lat`, `lon` and `height`, ending up looking along
+    /// `heading` and `pitch`, over `duration` seconds.
+    #[allow(clippy::too_many_arguments)]
+    pub fn fly_to(&mut self, cam: &CameraPose, lat: f64, lon: f64, height: f64, heading: f64, pitch: f64, duration: f64) {
+        let to = Viewpoint { at: Geodetic::new(lat, lon, height.max(MIN_HEIGHT)), heading, pitch: pitch.clamp(-90., 90.) };
+        self.flight = Some(Flight::new(cam.viewpoint(), to, duration));
+    }
+
+    pub fn flying(&self) -> bool {
+        return self.flight.is_some();
+    }
+
+    /// True while the camera moves, so frames keep coming.
+    pub fn animating(&self, input: &InputState) -> bool {
+        return self.flying() || self.free.active(input);
+    }
+
+    /// Move the camera for the `dt` seconds since the last frame. Moving it by hand ends a flight.
+    pub fn update(&mut self, cam: &mut CameraPose, input: &InputState, dt: f64) {
+        if self.free.active(input) {
+            self.flight = None;
+        }
+        match &mut self.flight {
+            Some(flight) => {
+                flight.advance(cam, dt);
+                if flight.finished() {
+                    self.flight = None;
+                }
+            }
+            None => self.free.update(cam, input, dt),
+        }
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    return a + (b - a) * t;
+}
+
+/// Angle between the surface normals at two points, in radians.
+fn arc_angle(a: &Geodetic, b: &Geodetic) -> f64 {
+    let (na, nb) = (a.enu_to_ecef().column(2).into_owned(), b.enu_to_ecef().column(2).into_owned());
+    return angle_between(&na, &nb);
+}
+
+/// Stays accurate for small angles, unlike the arc cosine of the dot product.
+fn angle_between(a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+    return a.cross(b).norm().atan2(a.dot(b));
+}
+
+/// Rotate unit vector `a` towards `b` by the fraction `t` of the angle between them. Opposite
+/// vectors go by way of the poles, or the equator if they are the poles.
+pub(crate) fn slerp(a: &Vector3<f64>, b: &Vector3<f64>, t: f64) -> Vector3<f64> {
+    let angle = angle_between(a, b);
+    let axis = a.cross(b).try_normalize(1e-12).unwrap_or_else(|| {
+        let across = if a.z.abs() < 0.9 { Vector3::z() } else { Vector3::x() };
+        a.cross(&across).normalize()
+    });
+    return UnitQuaternion::from_axis_angle(&Unit::new_unchecked(axis), angle * t) * a;
+}
+
+#[cfg(test)]
+fn test_flights() -> Vec<Flight> {
+    let v = |lat, lon, height, heading, pitch| Viewpoint { at: Geodetic::new(lat, lon, height), heading, pitch };
+    return vec![
+        // Across Europe, low at both ends.
+        Flight::new(v(48.86, 2.35, 500., 0., -30.), v(41.9, 12.5, 200., 90., -10.), 5.),
+        // Half way round the world from high up to the ground.
+        Flight::new(v(0., 0., 2e7, 0., -90.), v(0., 180., 10., 350., 0.), 8.),
+        // Pole to pole.
+        Flight::new(v(90., 0., 1000., 10., -45.), v(-90., 0., 1000., 20., -45.), 8.),
+        // Straight up.
+        Flight::new(v(10., 10., 100., 0., 0.), v(10., 10., 1e5, 0., -90.), 2.),
+    ];
+}
+
+#[test]
+fn flights_start_and_end_at_their_viewpoints() {
+    for f in test_flights() {
+        for (t, v) in [(0., f.from), (1., f.to)] {
+            let p = f.at(t);
+            assert!((p.at.to_ecef() - v.at.to_ecef()).norm() < 1e-3, "{t}: {p:?} vs {v:?}");
+            assert!(((p.heading - v.heading + 180.).rem_euclid(360.) - 180.).abs() < 1e-9, "{t}: {p:?} vs {v:?}");
+            assert!((p.pitch - v.pitch).abs() < 1e-9, "{t}: {p:?} vs {v:?}");
+        }
+    }
+}
+
+#[test]
+fn flights_progress_and_stay_above_ground() {
+    for f in test_flights() {
+        let lowest = f.from.at.height.min(f.to.at.height);
+        let mut last = (0., arc_angle(&f.from.at, &f.to.at));
+        for i in 1..=200 {
+            let p = f.at(i as f64 / 200.);
+            let progress = (arc_angle(&f.from.at, &p.at), arc_angle(&p.at, &f.to.at));
+            assert!(progress.0 >= last.0 - 1e-9 && progress.1 <= last.1 + 1e-9, "{i}: {p:?}");
+            assert!(p.at.height >= lowest - 1e-6 && p.at.height >= MIN_HEIGHT, "{i}: {p:?}");
+            last = progress;
+        }
+    }
+
+    // Paris to Rome climbs to about 300 km; heading turns the short way.
+    assert!(test_flights()[0].at(0.5).at.height > 3e5);
+    let f = &test_flights()[1];
+    assert!((0..=10).map(|i| f.at(i as f64 / 10.).heading).all(|h| h <= 1e-9 || h >= 350.));
+}
+
+#[test]
+fn flights_run_for_their_duration() {
+    let mut cam = CameraPose::default();
+    let mut controller = CameraController::default();
+    let input = InputState::default();
+    cam.look_from(&Geodetic::new(0., 0., 1e6), 0., -90.);
+    controller.fly_to(&cam, 45., 10., 1000., 30., -20., 2.);
+    for _ in 0..19 {
+        controller.update(&mut cam, &input, 0.1);
+        assert!(controller.flying());
+    }
+    controller.update(&mut cam, &input, 0.1);
+    assert!(!controller.flying());
+    let v = cam.viewpoint();
+    assert!((v.at.lat - 45.).abs() < 1e-4 && (v.at.lon - 10.).abs() < 1e-4 && (v.at.height - 1000.).abs() < 2., "{v:?}");
+    assert!((v.heading - 30.).abs() < 0.1 && (v.pitch + 20.).abs() < 0.1, "{v:?}");
+}
+
+#[test]
+fn free_flight_moves_in_the_local_frame() {
+    use super::input::{Input, Trigger};
+    use winit::keyboard::KeyCode;
+
+    let free = FreeFlight::default();
+    let mut cam = CameraPose::default();
+    let mut input = Input::default();
+    cam.look_from(&Geodetic::new(0., 0., 1000.), 90., -90.);
+
+    // Forward follows the heading even looking straight down: east, at 500 m/s at 1 km.
+    input.trigger(Trigger::Key(KeyCode::KeyW), true);
+    free.update(&mut cam, input.state(), 1.);
+    let v = cam.viewpoint();
+    let east = v.at.lon.to_radians() * WGS84_A;
+    assert!((east - 500.).abs() < 1., "{v:?}");
+    // Straight ahead along the tangent rises 500² / 2R, about 2 cm, above the start height.
+    assert!(v.at.lat.abs() < 1e-9 && (v.at.height - 1000.).abs() < 0.05, "{v:?}");
+    input.trigger(Trigger::Key(KeyCode::KeyW), false);
+
+    // Descending slows down near the ground and stops above it.
+    input.trigger(Trigger::Key(KeyCode::KeyQ), true);
+    for _ in 0..100 {
+        free.update(&mut cam, input.state(), 1.);
+    }
+    assert!((cam.viewpoint().at.height - MIN_HEIGHT).abs() < 1., "{:?}", cam.viewpoint());
+    input.trigger(Trigger::Key(KeyCode::KeyQ), false);
+
+    // Dragging turns and tilts the camera.
+    input.trigger(Trigger::Button(winit::event::MouseButton::Left), true);
+    input.cursor_moved([0., 0.]);
+    input.cursor_moved([50., -100.]);
+    free.update(&mut cam, input.state(), 0.1);
+    let v = cam.viewpoint();
+    assert!((v.heading - 100.).abs() < 0.1 && (v.pitch + 70.).abs() < 0.1, "{v:?}");
+}
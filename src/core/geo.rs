@@ -0,0 +1,157 @@
+use nalgebra::{Matrix3, Rotation3, UnitQuaternion, Vector3};
+
+// WGS84 ellipsoid.
+pub const WGS84_A: f64 = 6378137.0;
+pub const WGS84_F: f64 = 1.0 / 298.257223563;
+pub const WGS84_B: f64 = WGS84_A * (1.0 - WGS84_F);
+pub const WGS84_E2: f64 = WGS84_F * (2.0 - WGS84_F);
+
+/// A point given as geodetic latitude/longitude in degrees and height above the ellipsoid in
+/// meters.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Geodetic {
+    pub lat: f64,
+    pub lon: f64,
+    pub height: f64,
+}
+
+impl Geodetic {
+    pub fn new(lat: f64, lon: f64, height: f64) -> Self {
+        return Geodetic { lat, lon, height };
+    }
+
+    pub fn to_ecef(&self) -> Vector3<f64> {
+        let (slat, clat) = self.lat.to_radians().sin_cos();
+        let (slon, clon) = self.lon.to_radians().sin_cos();
+        let n = WGS84_A / (1.0 - WGS84_E2 * slat * slat).sqrt();
+        return Vector3::new(
+            (n + self.height) * clat * clon,
+            (n + self.height) * clat * slon,
+            (n * (1.0 - WGS84_E2) + self.height) * slat,
+        );
+    }
+
+    // Bowring's method with a couple of refinement steps; good to well under a millimeter from
+    // the center of the earth out past geostationary orbit.
+    pub fn from_ecef(p: &Vector3<f64>) -> Self {
+        let lon = p.y.atan2(p.x);
+        let r = (p.x * p.x + p.y * p.y).sqrt();
+        if r < 1e-9 {
+            let lat = if p.z >= 0.0 { 90.0 } else { -90.0 };
+            return Geodetic { lat, lon: 0.0, height: p.z.abs() - WGS84_B };
+        }
+
+        let ep2 = WGS84_E2 / (1.0 - WGS84_E2);
+        let mut beta = (WGS84_A * p.z).atan2(WGS84_B * r);
+        let mut lat = 0.0;
+        for _ in 0..4 {
+            let (sb, cb) = beta.sin_cos();
+            lat = (p.z + ep2 * WGS84_B * sb * sb * sb).atan2(r - WGS84_E2 * WGS84_A * cb * cb * cb);
+            beta = ((1.0 - WGS84_F) * lat.sin()).atan2(lat.cos());
+        }
+
+        let (slat, clat) = f64::sin_cos(lat);
+        let n = WGS84_A / (1.0 - WGS84_E2 * slat * slat).sqrt();
+        let height = if clat.abs() > 1e-10 {
+            r / clat - n
+        } else {
+            p.z.abs() / slat.abs() - n * (1.0 - WGS84_E2)
+        };
+
+        return Geodetic { lat: lat.to_degrees(), lon: lon.to_degrees(), height };
+    }
+
+    /// Rotation whose columns are the east, north and up axes expressed in ECEF.
+    pub fn enu_to_ecef(&self) -> Matrix3<f64> {
+        return enu_to_ecef(self.lat, self.lon);
+    }
+}
+
+/// Orientation of a body frame (x forward, y left, z up) in ECEF, given heading (clockwise from
+/// north), pitch (nose up) and roll (right wing down) in degrees relative to the local ENU frame at
+/// `at`.
+pub fn hpr_to_ecef(at: &Geodetic, heading: f64, pitch: f64, roll: f64) -> UnitQuaternion<f64> {
+    let enu = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(at.enu_to_ecef()));
+    let body_to_enu = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), (90.0 - heading).to_radians())
+        * UnitQuaternion::from_axis_angle(&Vector3::y_axis(), -pitch.to_radians())
+        * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), roll.to_radians());
+    return enu * body_to_enu;
+}
+
+/// Inverse of `hpr_to_ecef`, returning (heading, pitch, roll) in degrees. Heading is in [0, 360).
+pub fn ecef_to_hpr(at: &Geodetic, q: &UnitQuaternion<f64>) -> (f64, f64, f64) {
+    let to_enu = at.enu_to_ecef().transpose();
+    let fwd = to_enu * (q * Vector3::x());
+    let left = to_enu * (q * Vector3::y());
+
+    let heading = fwd.x.atan2(fwd.y).to_degrees().rem_euclid(360.0);
+    let pitch = fwd.z.clamp(-1.0, 1.0).asin().to_degrees();
+
+    // Roll is the angle of the left axis out of the horizontal plane, measured about forward.
+    let level_left = Vector3::z().cross(&fwd);
+    let roll = if level_left.norm() < 1e-12 {
+        0.0
+    } else {
+        let level_left = level_left.normalize();
+        let level_up = fwd.cross(&level_left);
+        left.dot(&level_up).atan2(left.dot(&level_left)).to_degrees()
+    };
+    return (heading, pitch, roll);
+}
+
+pub fn enu_to_ecef(lat_deg: f64, lon_deg: f64) -> Matrix3<f64> {
+    let (slat, clat) = lat_deg.to_radians().sin_cos();
+    let (slon, clon) = lon_deg.to_radians().sin_cos();
+    let e = Vector3::new(-slon, clon, 0.0);
+    let n = Vector3::new(-slat * clon, -slat * slon, clat);
+    let u = Vector3::new(clat * clon, clat * slon, slat);
+    return Matrix3::from_columns(&[e, n, u]);
+}
+
+#[test]
+fn geodetic_round_trip() {
+    for &(lat, lon, h) in &[
+        (0.0, 0.0, 0.0),
+        (45.0, -120.0, 1500.0),
+        (-33.9, 151.2, -30.0),
+        (89.999, 10.0, 100.0),
+        (12.0, 179.9, 35_786_000.0),
+    ] {
+        let g = Geodetic::new(lat, lon, h);
+        let back = Geodetic::from_ecef(&g.to_ecef());
+        assert!((back.lat - lat).abs() < 1e-9, "{g:?} -> {back:?}");
+        assert!((back.lon - lon).abs() < 1e-9, "{g:?} -> {back:?}");
+        assert!((back.height - h).abs() < 1e-4, "{g:?} -> {back:?}");
+    }
+
+    let pole = Geodetic::from_ecef(&Vector3::new(0.0, 0.0, WGS84_B + 10.0));
+    assert!((pole.lat - 90.0).abs() < 1e-12 && (pole.height - 10.0).abs() < 1e-6);
+}
+
+#[test]
+fn enu_frame_is_orthonormal_and_up_is_normal() {
+    let g = Geodetic::new(37.0, -122.0, 0.0);
+    let r = g.enu_to_ecef();
+    assert!((r.transpose() * r - Matrix3::identity()).norm() < 1e-12);
+    assert!((r.determinant() - 1.0).abs() < 1e-12);
+
+    // Moving along "up" only changes the height.
+    let up = g.to_ecef() + r.column(2) * 100.0;
+    let g2 = Geodetic::from_ecef(&up);
+    assert!((g2.lat - g.lat).abs() < 1e-9 && (g2.height - 100.0).abs() < 1e-6);
+}
+
+#[test]
+fn hpr_round_trip() {
+    let g = Geodetic::new(-20.0, 140.0, 0.0);
+    let enu = g.enu_to_ecef();
+
+    // Heading 90 points the nose east.
+    let q = hpr_to_ecef(&g, 90.0, 0.0, 0.0);
+    assert!((q * Vector3::x() - enu.column(0)).norm() < 1e-12);
+
+    for &(h, p, r) in &[(0.0, 0.0, 0.0), (45.0, 10.0, -5.0), (300.0, -60.0, 30.0), (180.0, 89.0, 0.0)] {
+        let (h2, p2, r2) = ecef_to_hpr(&g, &hpr_to_ecef(&g, h, p, r));
+        assert!((h2 - h).abs() < 1e-9 && (p2 - p).abs() < 1e-9 && (r2 - r).abs() < 1e-9, "{h2} {p2} {r2}");
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use wgpu::util::DeviceExt;
+use winit::{event::WindowEvent, window::Window};
+
+use super::{AppObjects, CachedLayout, PipelineBuilder, PipelineId};
+use super::pipelines::TargetState;
+use super::shaders::uniforms::uniform_struct;
+
+uniform_struct! {
+    pub(super) struct GuiUniforms {
+        screen_size_points: [f32; 2],
+        srgb_target: u32,
+        pad: u32,
+    }
+}
+
+/// egui integration: input comes in through `on_window_event`, a frame of UI is built with `run`
+/// and drawn on top of whatever is in the target with `paint`.
+///
+/// This is our own small take on egui-wgpu, which doesn't have a release for the wgpu we use.
+pub struct Gui {
+    pub ctx: egui::Context,
+    state: egui_winit::State,
+
+    pipeline: PipelineId,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    texture_bgl: CachedLayout,
+    textures: HashMap<egui::TextureId, (wgpu::Texture, wgpu::BindGroup)>,
+    vertex_buffer: Option<wgpu::Buffer>,
+    index_buffer: Option<wgpu::Buffer>,
+
+    pending: Option<egui::FullOutput>,
+    /// egui asked for another frame straight away, e.g. to animate a window opening.
+    repaint: bool,
+}
+
+impl Gui {
+    pub fn new(ao: &AppObjects) -> Self {
+        let ctx = egui::Context::default();
+        let state = egui_winit::State::new(
+            ctx.clone(),
+            egui::ViewportId::ROOT,
+            &ao.window,
+            Some(ao.window.scale_factor() as f32),
+            None,
+            Some(ao.device.limits().max_texture_dimension_2d as usize),
+        );
+
+        let uniform_buffer = ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("guiUniforms"),
+            contents: bytemuck::bytes_of(&GuiUniforms::default()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }]);
+        let uniform_bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("guiUniformBg"),
+            layout: &uniform_bgl,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let texture_bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]);
+
+        const ATTRS: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Uint32];
+        // Drawn straight onto the surface, after tone mapping.
+        let pipeline = PipelineBuilder::new("guiPipeline", "gui.wgsl")
+            .bind_groups(&[&uniform_bgl, &texture_bgl])
+            .vertex_buffer(wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<egui::epaint::Vertex>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &ATTRS,
+            })
+            .blend(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING)
+            .draws_into(TargetState::single(ao.config.format))
+            .build(ao);
+
+        return Gui {
+            ctx,
+            state,
+            pipeline,
+            uniform_buffer,
+            uniform_bind_group,
+            texture_bgl,
+            textures: HashMap::new(),
+            vertex_buffer: None,
+            index_buffer: None,
+            pending: None,
+            repaint: false,
+        };
+    }
+
+    /// Returns true if egui wants this event for itself (e.g. a click on a panel).
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        return self.state.on_window_event(window, event).consumed;
+    }
+
+    pub fn wants_pointer(&self) -> bool {
+        return self.ctx.wants_pointer_input();
+    }
+
+    pub fn wants_keyboard(&self) -> bool {
+        return self.ctx.wants_keyboard_input();
+    }
+
+    /// Build this frame's UI. The result is kept until `paint`.
+    pub fn run(&mut self, window: &Window, build_ui: impl FnMut(&egui::Context)) {
+        let input = self.state.take_egui_input(window);
+        let mut output = self.ctx.run(input, build_ui);
+        self.state.handle_platform_output(window, std::mem::take(&mut output.platform_output));
+        self.repaint = output.viewport_output.get(&egui::ViewportId::ROOT).is_some_and(|v| v.repaint_delay.is_zero());
+        self.pending = Some(output);
+    }
+
+    /// Whether the UI built by the last `run` is still changing.
+    pub fn needs_repaint(&self) -> bool {
+        return self.repaint;
+    }
+
+    fn update_texture(&mut self, ao: &AppObjects, id: egui::TextureId, delta: &egui::epaint::ImageDelta) {
+        let egui::ImageData::Color(image) = &delta.image;
+        let [w, h] = image.size;
+        let size = wgpu::Extent3d { width: w as u32, height: h as u32, depth_or_array_layers: 1 };
+
+        if delta.pos.is_none() {
+            let texture = ao.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("guiTexture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+            let filter = |f: egui::TextureFilter| match f {
+                egui::TextureFilter::Nearest => wgpu::FilterMode::Nearest,
+                egui::TextureFilter::Linear => wgpu::FilterMode::Linear,
+            };
+            let address_mode = match delta.options.wrap_mode {
+                egui::TextureWrapMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+                egui::TextureWrapMode::Repeat => wgpu::AddressMode::Repeat,
+                egui::TextureWrapMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+            };
+            let sampler = ao.device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("guiSampler"),
+                mag_filter: filter(delta.options.magnification),
+                min_filter: filter(delta.options.minification),
+                address_mode_u: address_mode,
+                address_mode_v: address_mode,
+                ..Default::default()
+            });
+            let bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("guiTextureBg"),
+                layout: &self.texture_bgl,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(
+                            &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+            });
+            self.textures.insert(id, (texture, bind_group));
+        }
+
+        let Some((texture, _)) = self.textures.get(&id) else {
+            super::diagnostics::log_every_n!(100, log::Level::Warn, "egui: partial update of unknown texture {id:?}");
+            return;
+        };
+        let [x, y] = delta.pos.unwrap_or([0, 0]);
+        ao.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: x as u32, y: y as u32, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&image.pixels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * w as u32),
+                rows_per_image: Some(h as u32),
+            },
+            size,
+        );
+    }
+
+    /// Draw the UI built by the last `run` into `view`, on top of its current contents.
+    pub fn paint(&mut self, ao: &AppObjects, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let Some(output) = self.pending.take() else {
+            return;
+        };
+
+        for (id, delta) in &output.textures_delta.set {
+            self.update_texture(ao, *id, delta);
+        }
+
+        let ppp = output.pixels_per_point;
+        let primitives = self.ctx.tessellate(output.shapes, ppp);
+        let (width, height) = (ao.config.width, ao.config.height);
+
+        let uniforms = GuiUniforms {
+            screen_size_points: [width as f32 / ppp, height as f32 / ppp],
+            srgb_target: ao.config.format.is_srgb() as u32,
+            pad: 0,
+        };
+        ao.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+
+        // Pack all meshes into one vertex and one index buffer.
+        let mut vertices: Vec<egui::epaint::Vertex> = vec![];
+        let mut indices: Vec<u32> = vec![];
+        let mut draws = vec![];
+        for p in &primitives {
+            let egui::epaint::Primitive::Mesh(mesh) = &p.primitive else {
+                continue;
+            };
+            let (base_vertex, first_index) = (vertices.len() as i32, indices.len() as u32);
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+            draws.push((p.clip_rect, mesh.texture_id, first_index..indices.len() as u32, base_vertex));
+        }
+
+        fn upload(ao: &AppObjects, buf: &mut Option<wgpu::Buffer>, data: &[u8], usage: wgpu::BufferUsages) {
+            // Keep to a multiple of 4 bytes for write_buffer.
+            let size = (data.len() as u64).next_power_of_two().max(1024);
+            if buf.as_ref().is_none_or(|b| b.size() < size) {
+                *buf = Some(ao.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("guiMeshes"),
+                    size,
+                    usage: usage | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }));
+            }
+            ao.queue.write_buffer(buf.as_ref().unwrap(), 0, data);
+        }
+        if !draws.is_empty() {
+            upload(ao, &mut self.vertex_buffer, bytemuck::cast_slice(&vertices), wgpu::BufferUsages::VERTEX);
+            upload(ao, &mut self.index_buffer, bytemuck::cast_slice(&indices), wgpu::BufferUsages::INDEX);
+        }
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("guiPass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: ao.profiler.timestamp_writes(),
+            });
+
+            if let (Some(vb), Some(ib)) = (&self.vertex_buffer, &self.index_buffer) {
+                render_pass.set_pipeline(&ao.pipelines.get(&self.pipeline));
+                render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, vb.slice(..));
+                render_pass.set_index_buffer(ib.slice(..), wgpu::IndexFormat::Uint32);
+
+                for (clip, texture_id, range, base_vertex) in draws {
+                    let Some((_, bind_group)) = self.textures.get(&texture_id) else {
+                        continue;
+                    };
+                    // Clip rect in points -> scissor rect in pixels, clamped to the target.
+                    let x0 = ((clip.min.x * ppp).round() as u32).min(width);
+                    let y0 = ((clip.min.y * ppp).round() as u32).min(height);
+                    let x1 = ((clip.max.x * ppp).round() as u32).clamp(x0, width);
+                    let y1 = ((clip.max.y * ppp).round() as u32).clamp(y0, height);
+                    if x1 == x0 || y1 == y0 {
+                        continue;
+                    }
+                    render_pass.set_scissor_rect(x0, y0, x1 - x0, y1 - y0);
+                    render_pass.set_bind_group(1, bind_group, &[]);
+                    render_pass.draw_indexed(range, base_vertex, 0..1);
+                }
+            }
+        }
+
+        for id in &output.textures_delta.free {
+            self.textures.remove(id);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// egui meshes. Positions are in points, colors are premultiplied sRGB.
+
+struct VertexInput {
+    @location(0) pos: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct GuiUniforms {
+    screen_size_points: vec2<f32>,
+    // 1 if the render target does the linear -> sRGB conversion itself.
+    srgb_target: u32,
+    pad: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> u: GuiUniforms;
+
+@group(1) @binding(0)
+var t: texture_2d<f32>;
+@group(1) @binding(1)
+var s: sampler;
+
+fn linear_from_gamma(srgb: vec3<f32>) -> vec3<f32> {
+    let cutoff = srgb < vec3<f32>(0.04045);
+    let lower = srgb / vec3<f32>(12.92);
+    let higher = pow((srgb + vec3<f32>(0.055)) / vec3<f32>(1.055), vec3<f32>(2.4));
+    return select(higher, lower, cutoff);
+}
+
+fn gamma_from_linear(rgb: vec3<f32>) -> vec3<f32> {
+    let cutoff = rgb < vec3<f32>(0.0031308);
+    let lower = rgb * vec3<f32>(12.92);
+    let higher = vec3<f32>(1.055) * pow(rgb, vec3<f32>(1.0 / 2.4)) - vec3<f32>(0.055);
+    return select(higher, lower, cutoff);
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(
+        2.0 * in.pos.x / u.screen_size_points.x - 1.0,
+        1.0 - 2.0 * in.pos.y / u.screen_size_points.y,
+        0.0,
+        1.0,
+    );
+    out.uv = in.uv;
+    let c = unpack4x8unorm(in.color);
+    out.color = vec4<f32>(linear_from_gamma(c.rgb), c.a);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Textures are Rgba8UnormSrgb, so this sample is already linear.
+    let c = in.color * textureSample(t, s, in.uv);
+    if (u.srgb_target == 1u) {
+        return c;
+    }
+    return vec4<f32>(gamma_from_linear(c.rgb), c.a);
+}
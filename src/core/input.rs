@@ -0,0 +1,380 @@
+//! Keyboard, mouse and touch input, collected between frames and mapped to named actions.
+//!
+//! Bindings come from the `[bindings]` table of the config file, one list of triggers per action.
+//! Keys use winit's `KeyCode` names and mouse buttons a `Mouse` prefix:
+//!
+//! ```toml
+//! [bindings]
+//! move-forward = ["KeyW", "ArrowUp"]
+//! look = ["MouseRight"]
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use winit::{
+    event::{ElementState, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+};
+
+/// Wheel movement in pixels that counts as one line.
+const PIXELS_PER_LINE: f64 = 40.;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Exit,
+    /// Start or stop the scene clock.
+    TogglePlay,
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    /// Held while dragging to turn the camera.
+    Look,
+    /// Switch to the next way of colouring point clouds.
+    CycleColours,
+}
+
+/// A key or mouse button an action can be bound to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum Trigger {
+    Key(KeyCode),
+    Button(MouseButton),
+}
+
+impl TryFrom<String> for Trigger {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        use serde::de::{Deserialize, IntoDeserializer, value::{Error, StrDeserializer}};
+
+        let button = match name.as_str() {
+            "MouseLeft" => Some(MouseButton::Left),
+            "MouseRight" => Some(MouseButton::Right),
+            "MouseMiddle" => Some(MouseButton::Middle),
+            "MouseBack" => Some(MouseButton::Back),
+            "MouseForward" => Some(MouseButton::Forward),
+            _ => None,
+        };
+        if let Some(button) = button {
+            return Ok(Trigger::Button(button));
+        }
+        let de: StrDeserializer<Error> = name.as_str().into_deserializer();
+        return KeyCode::deserialize(de).map(Trigger::Key).map_err(|_| format!("unknown key or mouse button '{name}'"));
+    }
+}
+
+/// Which triggers start each action. Actions a config file names replace their defaults; the
+/// others keep them.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(from = "HashMap<Action, Vec<Trigger>>")]
+pub struct Bindings {
+    triggers: HashMap<Action, Vec<Trigger>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use KeyCode as K;
+        use Trigger::{Button, Key};
+        let defaults = [
+            (Action::Exit, vec![Key(K::Escape)]),
+            (Action::TogglePlay, vec![Key(K::Space)]),
+            (Action::MoveForward, vec![Key(K::KeyW), Key(K::ArrowUp)]),
+            (Action::MoveBack, vec![Key(K::KeyS), Key(K::ArrowDown)]),
+            (Action::MoveLeft, vec![Key(K::KeyA), Key(K::ArrowLeft)]),
+            (Action::MoveRight, vec![Key(K::KeyD), Key(K::ArrowRight)]),
+            (Action::MoveUp, vec![Key(K::KeyE), Key(K::PageUp)]),
+            (Action::MoveDown, vec![Key(K::KeyQ), Key(K::PageDown)]),
+            (Action::Look, vec![Button(MouseButton::Left)]),
+            (Action::CycleColours, vec![Key(K::KeyC)]),
+        ];
+        return Bindings { triggers: defaults.into_iter().collect() };
+    }
+}
+
+impl From<HashMap<Action, Vec<Trigger>>> for Bindings {
+    fn from(overrides: HashMap<Action, Vec<Trigger>>) -> Self {
+        let mut bindings = Bindings::default();
+        bindings.triggers.extend(overrides);
+        return bindings;
+    }
+}
+
+impl Bindings {
+    pub fn triggers(&self, action: Action) -> &[Trigger] {
+        return self.triggers.get(&action).map_or(&[], |t| t.as_slice());
+    }
+
+    fn actions(&self, trigger: Trigger) -> impl Iterator<Item = Action> + '_ {
+        return self.triggers.iter().filter(move |(_, t)| t.contains(&trigger)).map(|(a, _)| *a);
+    }
+}
+
+/// Input as of the frame being drawn. Movement and presses are what happened since the last frame.
+#[derive(Clone, Debug, Default)]
+pub struct InputState {
+    /// Cursor position in physical pixels, if it is over the window.
+    pub cursor: Option<[f64; 2]>,
+    /// How far the cursor moved, in physical pixels.
+    pub cursor_delta: [f64; 2],
+    /// Lines scrolled, positive away from the user.
+    pub wheel: f64,
+    pub modifiers: ModifiersState,
+    /// Positions of the fingers on the screen, by touch id.
+    pub touches: HashMap<u64, [f64; 2]>,
+    down: HashSet<Trigger>,
+    held: HashSet<Action>,
+    pressed: HashSet<Action>,
+    released: HashSet<Action>,
+}
+
+impl InputState {
+    pub fn key_down(&self, key: KeyCode) -> bool {
+        return self.down.contains(&Trigger::Key(key));
+    }
+
+    pub fn button_down(&self, button: MouseButton) -> bool {
+        return self.down.contains(&Trigger::Button(button));
+    }
+
+    /// True while any trigger of the action is held.
+    pub fn is_down(&self, action: Action) -> bool {
+        return self.held.contains(&action);
+    }
+
+    /// True in the frame the action started.
+    pub fn just_pressed(&self, action: Action) -> bool {
+        return self.pressed.contains(&action);
+    }
+
+    /// True in the frame the action ended.
+    pub fn just_released(&self, action: Action) -> bool {
+        return self.released.contains(&action);
+    }
+
+    /// -1, 0 or 1 from a pair of opposing actions.
+    pub fn axis(&self, negative: Action, positive: Action) -> f32 {
+        return self.is_down(positive) as i32 as f32 - self.is_down(negative) as i32 as f32;
+    }
+}
+
+/// Collects window events into an `InputState`. The event loop feeds it events, hands the state
+/// to the app each frame, then calls `end_frame`.
+#[derive(Clone, Debug, Default)]
+pub struct Input {
+    pub bindings: Bindings,
+    state: InputState,
+    /// The touch standing in for the left mouse button.
+    primary_touch: Option<u64>,
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Self {
+        return Input { bindings, ..Default::default() };
+    }
+
+    pub fn state(&self) -> &InputState {
+        return &self.state;
+    }
+
+    /// Take in a window event. Presses the overlay consumed are dropped, but releases always count
+    /// so nothing stays held.
+    pub fn window_event(&mut self, event: &WindowEvent, gui_consumed: bool) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let PhysicalKey::Code(code) = event.physical_key {
+                    let pressed = event.state.is_pressed();
+                    if !(pressed && gui_consumed) {
+                        self.trigger(Trigger::Key(code), pressed);
+                    }
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = *state == ElementState::Pressed;
+                if !(pressed && gui_consumed) {
+                    self.trigger(Trigger::Button(*button), pressed);
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => self.cursor_moved([position.x, position.y]),
+            WindowEvent::CursorLeft { .. } => self.state.cursor = None,
+            WindowEvent::MouseWheel { delta, .. } if !gui_consumed => {
+                self.wheel(match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y as f64,
+                    MouseScrollDelta::PixelDelta(p) => p.y / PIXELS_PER_LINE,
+                });
+            }
+            WindowEvent::ModifiersChanged(m) => self.state.modifiers = m.state(),
+            WindowEvent::Touch(t) if !(gui_consumed && t.phase == TouchPhase::Started) => {
+                self.touch(t.id, t.phase, [t.location.x, t.location.y]);
+            }
+            WindowEvent::Focused(false) => self.release_all(),
+            _ => {}
+        }
+    }
+
+    /// A key or button went down or up.
+    pub fn trigger(&mut self, trigger: Trigger, pressed: bool) {
+        let s = &mut self.state;
+        if pressed {
+            // Key repeat doesn't press again.
+            if s.down.insert(trigger) {
+                for action in self.bindings.actions(trigger) {
+                    if s.held.insert(action) {
+                        s.pressed.insert(action);
+                    }
+                }
+            }
+        } else if s.down.remove(&trigger) {
+            for action in self.bindings.actions(trigger) {
+                let still_held = self.bindings.triggers(action).iter().any(|t| s.down.contains(t));
+                if !still_held && s.held.remove(&action) {
+                    s.released.insert(action);
+                }
+            }
+        }
+    }
+
+    pub fn cursor_moved(&mut self, position: [f64; 2]) {
+        let s = &mut self.state;
+        if let Some(last) = s.cursor {
+            s.cursor_delta[0] += position[0] - last[0];
+            s.cursor_delta[1] += position[1] - last[1];
+        }
+        s.cursor = Some(position);
+    }
+
+    pub fn wheel(&mut self, lines: f64) {
+        self.state.wheel += lines;
+    }
+
+    /// The first finger down acts as the left mouse button and moves the cursor.
+    pub fn touch(&mut self, id: u64, phase: TouchPhase, position: [f64; 2]) {
+        match phase {
+            TouchPhase::Started => {
+                self.state.touches.insert(id, position);
+                if self.primary_touch.is_none() {
+                    self.primary_touch = Some(id);
+                    self.state.cursor = Some(position);
+                    self.trigger(Trigger::Button(MouseButton::Left), true);
+                }
+            }
+            TouchPhase::Moved => {
+                if let Some(p) = self.state.touches.get_mut(&id) {
+                    *p = position;
+                }
+                if self.primary_touch == Some(id) {
+                    self.cursor_moved(position);
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.state.touches.remove(&id);
+                if self.primary_touch == Some(id) {
+                    self.primary_touch = None;
+                    self.trigger(Trigger::Button(MouseButton::Left), false);
+                }
+            }
+        }
+    }
+
+    /// Let go of everything, e.g. when the window loses focus and won't see the releases.
+    pub fn release_all(&mut self) {
+        let down: Vec<Trigger> = self.state.down.iter().copied().collect();
+        for t in down {
+            self.trigger(t, false);
+        }
+        self.state.touches.clear();
+        self.primary_touch = None;
+    }
+
+    /// Forget this frame's movement and presses.
+    pub fn end_frame(&mut self) {
+        let s = &mut self.state;
+        s.cursor_delta = [0., 0.];
+        s.wheel = 0.;
+        s.pressed.clear();
+        s.released.clear();
+    }
+}
+
+#[test]
+fn actions_follow_their_triggers() {
+    let mut input = Input::default();
+    input.trigger(Trigger::Key(KeyCode::KeyW), true);
+    assert!(input.state().just_pressed(Action::MoveForward));
+    assert!(input.state().key_down(KeyCode::KeyW));
+    assert_eq!(input.state().axis(Action::MoveBack, Action::MoveForward), 1.);
+    input.end_frame();
+
+    // A second trigger of a held action, and key repeat, don't press it again.
+    input.trigger(Trigger::Key(KeyCode::ArrowUp), true);
+    input.trigger(Trigger::Key(KeyCode::KeyW), true);
+    assert!(!input.state().just_pressed(Action::MoveForward));
+    input.trigger(Trigger::Key(KeyCode::KeyW), false);
+    assert!(input.state().is_down(Action::MoveForward));
+    assert!(!input.state().just_released(Action::MoveForward));
+    input.trigger(Trigger::Key(KeyCode::ArrowUp), false);
+    assert!(!input.state().is_down(Action::MoveForward));
+    assert!(input.state().just_released(Action::MoveForward));
+    input.end_frame();
+    assert!(!input.state().just_released(Action::MoveForward));
+
+    // Unbound keys are still tracked.
+    input.trigger(Trigger::Key(KeyCode::F7), true);
+    assert!(input.state().key_down(KeyCode::F7));
+    input.release_all();
+    assert!(!input.state().key_down(KeyCode::F7));
+}
+
+#[test]
+fn cursor_wheel_and_touch() {
+    let mut input = Input::default();
+    input.cursor_moved([10., 10.]);
+    assert_eq!(input.state().cursor_delta, [0., 0.]);
+    input.cursor_moved([15., 8.]);
+    input.cursor_moved([20., 8.]);
+    input.wheel(1.);
+    input.wheel(0.5);
+    assert_eq!(input.state().cursor_delta, [10., -2.]);
+    assert_eq!(input.state().wheel, 1.5);
+    input.end_frame();
+    assert_eq!(input.state().cursor_delta, [0., 0.]);
+    assert_eq!(input.state().wheel, 0.);
+
+    // The first finger drags like the left button; a second one is only tracked.
+    input.touch(1, TouchPhase::Started, [100., 100.]);
+    input.touch(2, TouchPhase::Started, [200., 200.]);
+    assert!(input.state().just_pressed(Action::Look));
+    input.touch(2, TouchPhase::Moved, [210., 200.]);
+    input.touch(1, TouchPhase::Moved, [105., 100.]);
+    assert_eq!(input.state().cursor_delta, [5., 0.]);
+    assert_eq!(input.state().touches.len(), 2);
+    input.touch(1, TouchPhase::Ended, [105., 100.]);
+    assert!(input.state().just_released(Action::Look));
+    assert_eq!(input.state().touches.len(), 1);
+}
+
+#[test]
+fn bindings_from_toml() {
+    #[derive(serde::Deserialize)]
+    struct File {
+        bindings: Bindings,
+    }
+    let file: File = toml::from_str("[bindings]\nlook = [\"MouseRight\"]\nexit = [\"KeyX\", \"Escape\"]").unwrap();
+    let b = file.bindings;
+    assert_eq!(b.triggers(Action::Look), [Trigger::Button(MouseButton::Right)]);
+    assert_eq!(b.triggers(Action::Exit), [Trigger::Key(KeyCode::KeyX), Trigger::Key(KeyCode::Escape)]);
+    // Actions the file doesn't name keep their defaults.
+    assert_eq!(b.triggers(Action::MoveForward), Bindings::default().triggers(Action::MoveForward));
+
+    let mut input = Input::new(b);
+    input.trigger(Trigger::Button(MouseButton::Left), true);
+    assert!(!input.state().is_down(Action::Look));
+    input.trigger(Trigger::Button(MouseButton::Right), true);
+    assert!(input.state().is_down(Action::Look));
+
+    assert!(toml::from_str::<File>("[bindings]\nlook = [\"MouseSideways\"]").is_err());
+    assert!(toml::from_str::<File>("[bindings]\nteleport = [\"KeyT\"]").is_err());
+}
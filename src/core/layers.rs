@@ -0,0 +1,293 @@
+use std::time::Instant;
+
+use super::{AppObjects, CullingVolume, DrawStats, InputState, RenderState, Renderable, Scene};
+
+/// What shows where no layer draws.
+pub const BACKGROUND: wgpu::Color = wgpu::Color { r: 0.1, g: 0.2, b: 0.3, a: 1.0 };
+
+/// Identifies a layer for as long as it lives. IDs are never reused within a `LayerManager`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LayerId(u64);
+
+pub struct Layer {
+    id: LayerId,
+    pub name: String,
+    pub visible: bool,
+    /// In [0, 1], passed on to the renderable every frame.
+    pub opacity: f32,
+    /// Whether the renderable is handed input each frame.
+    pub input_enabled: bool,
+    pub renderable: Box<dyn Renderable>,
+}
+
+impl Layer {
+    pub fn id(&self) -> LayerId {
+        return self.id;
+    }
+}
+
+/// What is on the globe: an ordered list of named layers.
+///
+/// Layers are drawn in list order, so the last layer is on top; input goes the other way, topmost
+/// first. Hidden layers are neither updated, drawn nor given input. Layers out of view are still
+/// updated but not drawn.
+#[derive(Default)]
+pub struct LayerManager {
+    layers: Vec<Layer>,
+    next_id: u64,
+}
+
+impl LayerManager {
+    pub fn new() -> Self {
+        return Default::default();
+    }
+
+    /// Add a layer on top of the others.
+    pub fn add(&mut self, name: &str, renderable: Box<dyn Renderable>) -> LayerId {
+        let id = LayerId(self.next_id);
+        self.next_id += 1;
+        self.layers.push(Layer {
+            id,
+            name: name.to_string(),
+            visible: true,
+            opacity: 1.,
+            input_enabled: true,
+            renderable,
+        });
+        return id;
+    }
+
+    pub fn remove(&mut self, id: LayerId) -> Option<Layer> {
+        let i = self.index_of(id)?;
+        return Some(self.layers.remove(i));
+    }
+
+    pub fn len(&self) -> usize {
+        return self.layers.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.layers.is_empty();
+    }
+
+    pub fn get(&self, id: LayerId) -> Option<&Layer> {
+        return self.layers.iter().find(|l| l.id == id);
+    }
+
+    pub fn get_mut(&mut self, id: LayerId) -> Option<&mut Layer> {
+        return self.layers.iter_mut().find(|l| l.id == id);
+    }
+
+    /// First layer with this name.
+    pub fn find(&self, name: &str) -> Option<LayerId> {
+        return self.layers.iter().find(|l| l.name == name).map(|l| l.id);
+    }
+
+    /// Position in draw order, 0 being the bottom.
+    pub fn index_of(&self, id: LayerId) -> Option<usize> {
+        return self.layers.iter().position(|l| l.id == id);
+    }
+
+    /// Move a layer to `index` in draw order (clamped), shifting the others.
+    pub fn move_to(&mut self, id: LayerId, index: usize) {
+        if let Some(i) = self.index_of(id) {
+            let layer = self.layers.remove(i);
+            self.layers.insert(index.min(self.layers.len()), layer);
+        }
+    }
+
+    /// Move a layer one step towards the top.
+    pub fn raise(&mut self, id: LayerId) {
+        if let Some(i) = self.index_of(id) {
+            self.move_to(id, i + 1);
+        }
+    }
+
+    /// Move a layer one step towards the bottom.
+    pub fn lower(&mut self, id: LayerId) {
+        if let Some(i) = self.index_of(id) {
+            self.move_to(id, i.saturating_sub(1));
+        }
+    }
+
+    /// All layers in draw order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Layer> {
+        return self.layers.iter();
+    }
+
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut Layer> {
+        return self.layers.iter_mut();
+    }
+
+    /// Visible layers in draw order.
+    pub fn visible(&self) -> impl DoubleEndedIterator<Item = &Layer> {
+        return self.layers.iter().filter(|l| l.visible);
+    }
+
+    /// Visible layers that may be in view of the culling volume, in draw order.
+    pub fn visible_in<'a>(&'a self, volume: &'a CullingVolume) -> impl Iterator<Item = &'a Layer> {
+        return self.visible().filter(|l| l.renderable.bounds().is_none_or(|b| volume.is_visible(&b)));
+    }
+
+    /// Layers that should see input, topmost first.
+    pub fn input_targets(&mut self) -> impl Iterator<Item = &mut Layer> {
+        return self.layers.iter_mut().rev().filter(|l| l.visible && l.input_enabled);
+    }
+
+    /// Union of the time ranges of all layers, visible or not.
+    pub fn time_range(&self) -> Option<(f64, f64)> {
+        return self
+            .layers
+            .iter()
+            .filter_map(|l| l.renderable.time_range())
+            .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)));
+    }
+
+    pub fn animating(&self) -> bool {
+        return self.visible().any(|l| l.renderable.animating());
+    }
+
+    pub fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        for l in self.layers.iter_mut().filter(|l| l.visible) {
+            let start = Instant::now();
+            l.renderable.set_opacity(l.opacity.clamp(0., 1.));
+            l.renderable.update(ao, scene);
+            ao.profiler.record(&l.name, start.elapsed(), DrawStats::default());
+        }
+    }
+
+    /// Clear to the background, then render the visible layers, timing each under its name.
+    /// Layers load what is there; those with a depth buffer clear their own.
+    pub fn render(&self, rs: &mut RenderState) {
+        rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("clear"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Clear(BACKGROUND), store: wgpu::StoreOp::Store },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        let volume = CullingVolume::from_camera(&rs.scene.cam);
+        for l in self.visible_in(&volume) {
+            let start = Instant::now();
+            rs.ao.profiler.set_scope(&l.name);
+            l.renderable.render(rs);
+            rs.ao.profiler.record(&l.name, start.elapsed(), l.renderable.stats());
+        }
+    }
+
+    /// Hand this frame's input to the layers that take it, topmost first.
+    pub fn handle_input(&mut self, ao: &AppObjects, input: &InputState) {
+        for l in self.input_targets() {
+            l.renderable.handle_input(ao, input);
+        }
+    }
+}
+
+#[cfg(test)]
+struct MockRenderable(Option<(f64, f64)>);
+
+#[cfg(test)]
+impl Renderable for MockRenderable {
+    fn render(self: &Self, _rs: &mut RenderState) {}
+
+    fn time_range(&self) -> Option<(f64, f64)> {
+        return self.0;
+    }
+}
+
+#[cfg(test)]
+struct MockBounded(super::culling::BoundingSphere);
+
+#[cfg(test)]
+impl Renderable for MockBounded {
+    fn render(self: &Self, _rs: &mut RenderState) {}
+
+    fn bounds(&self) -> Option<super::Bounds> {
+        return Some(super::Bounds::from_sphere(self.0));
+    }
+}
+
+#[cfg(test)]
+fn names(lm: &LayerManager) -> Vec<&str> {
+    return lm.iter().map(|l| l.name.as_str()).collect();
+}
+
+#[test]
+fn ids_are_stable_and_not_reused() {
+    let mut lm = LayerManager::new();
+    let a = lm.add("a", Box::new(MockRenderable(None)));
+    let b = lm.add("b", Box::new(MockRenderable(None)));
+    assert_eq!(lm.remove(a).map(|l| l.name), Some("a".to_string()));
+    assert!(lm.remove(a).is_none());
+    let c = lm.add("c", Box::new(MockRenderable(None)));
+    assert_ne!(c, a);
+    assert_eq!(lm.get(b).unwrap().name, "b");
+    assert_eq!(lm.find("c"), Some(c));
+    assert_eq!(lm.len(), 2);
+}
+
+#[test]
+fn z_order_moves() {
+    let mut lm = LayerManager::new();
+    let a = lm.add("a", Box::new(MockRenderable(None)));
+    let _b = lm.add("b", Box::new(MockRenderable(None)));
+    let c = lm.add("c", Box::new(MockRenderable(None)));
+    assert_eq!(names(&lm), ["a", "b", "c"]);
+    lm.raise(a);
+    assert_eq!(names(&lm), ["b", "a", "c"]);
+    lm.raise(c);
+    lm.lower(c);
+    assert_eq!(names(&lm), ["b", "c", "a"]);
+    lm.move_to(a, 0);
+    assert_eq!(names(&lm), ["a", "b", "c"]);
+    lm.move_to(a, 99);
+    assert_eq!(lm.index_of(a), Some(2));
+}
+
+#[test]
+fn visibility_and_input_filtering() {
+    let mut lm = LayerManager::new();
+    let a = lm.add("a", Box::new(MockRenderable(Some((10., 20.)))));
+    let b = lm.add("b", Box::new(MockRenderable(Some((0., 15.)))));
+    let c = lm.add("c", Box::new(MockRenderable(None)));
+    lm.get_mut(b).unwrap().visible = false;
+    lm.get_mut(c).unwrap().input_enabled = false;
+
+    assert_eq!(lm.visible().map(|l| l.id()).collect::<Vec<_>>(), [a, c]);
+    assert_eq!(lm.input_targets().map(|l| l.id()).collect::<Vec<_>>(), [a]);
+    lm.get_mut(c).unwrap().input_enabled = true;
+    assert_eq!(lm.input_targets().map(|l| l.id()).collect::<Vec<_>>(), [c, a]);
+    // Hidden layers still count towards the timeline.
+    assert_eq!(lm.time_range(), Some((0., 20.)));
+}
+
+#[test]
+fn layers_out_of_view_are_culled() {
+    use super::culling::BoundingSphere;
+    use super::Geodetic;
+    use nalgebra::Matrix4;
+
+    let mut lm = LayerManager::new();
+    let always = lm.add("always", Box::new(MockRenderable(None)));
+    let near = lm.add("near", Box::new(MockBounded(BoundingSphere { center: Geodetic::new(0., 1., 0.).to_ecef(), radius: 100. })));
+    let _far = lm.add("far", Box::new(MockBounded(BoundingSphere { center: Geodetic::new(0., 90., 0.).to_ecef(), radius: 100. })));
+    // A transform that squeezes the whole globe into the clip volume, so that the horizon alone
+    // decides, then the identity, whose clip volume is a 2 m box at the centre of the earth.
+    let eye = Geodetic::new(0., 0., 1_000_000.).to_ecef();
+    #[rustfmt::skip]
+    let squeeze = Matrix4::new(
+        1e-9, 0., 0., 0.,
+        0., 1e-9, 0., 0.,
+        0., 0., 1e-9, 0.5,
+        0., 0., 0., 1.,
+    );
+    let open = CullingVolume::new(&squeeze, &eye);
+    assert_eq!(lm.visible_in(&open).map(|l| l.id()).collect::<Vec<_>>(), [always, near]);
+    let closed = CullingVolume::new(&Matrix4::identity(), &eye);
+    assert_eq!(lm.visible_in(&closed).map(|l| l.id()).collect::<Vec<_>>(), [always]);
+}
@@ -1,6 +1,22 @@
 pub mod app;
 pub mod appobjects;
+pub mod bookmarks;
 pub mod camera;
+pub mod capture;
+pub mod clock;
+pub mod culling;
+pub mod diagnostics;
+pub mod flight;
+pub mod geo;
+pub mod gui;
+pub mod input;
+pub mod layers;
+pub mod pacing;
+pub mod pipelines;
+pub mod profiler;
+pub mod shaders;
+pub mod targets;
+pub mod tonemap;
 
 pub use appobjects::AppObjects;
 pub use app::RenderState;
@@ -8,4 +24,19 @@ pub use app::Renderable;
 pub use app::UserApp;
 pub use app::BaseApp;
 
-pub use camera::{CameraIntrin, CameraPose, Scene, LoweredScene};
+pub use bookmarks::{Bookmarks, ViewState};
+pub use camera::{CameraIntrin, CameraPose, Scene, LoweredScene, Viewpoint};
+pub use capture::FrameCapture;
+pub use clock::Clock;
+pub use culling::{Bounds, CullingVolume};
+pub use flight::{CameraController, Flight, FreeFlight};
+pub use geo::Geodetic;
+pub use gui::Gui;
+pub use input::{Action, Bindings, Input, InputState};
+pub use layers::{Layer, LayerId, LayerManager};
+pub use pacing::{FramePacer, PresentMode, RedrawMode};
+pub use pipelines::{CachedLayout, PipelineBuilder, PipelineCache, PipelineId};
+pub use profiler::{DrawStats, Profiler};
+pub use shaders::ShaderRegistry;
+pub use targets::{RenderSettings, RenderTargets, DEPTH_FORMAT};
+pub use tonemap::{ToneMapOperator, ToneMapping};
@@ -0,0 +1,139 @@
+//! When to draw the next frame: how frames are presented, an optional frame rate cap, and an
+//! on-demand mode that leaves the GPU idle while nothing changes.
+
+use std::time::{Duration, Instant};
+
+/// How frames reach the screen. Each falls back to what the surface supports, and Fifo is always
+/// supported.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentMode {
+    /// Wait for vertical blank; frames queue up behind it.
+    #[default]
+    Fifo,
+    /// Wait for vertical blank, replacing a queued frame with a newer one. Falls back to Fifo.
+    Mailbox,
+    /// Present straight away and allow tearing. Falls back to Mailbox, then Fifo.
+    Immediate,
+}
+
+impl PresentMode {
+    /// The wgpu mode to configure the surface with, given the modes it supports.
+    pub fn pick(self, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        let preferred: &[wgpu::PresentMode] = match self {
+            PresentMode::Fifo => &[],
+            PresentMode::Mailbox => &[wgpu::PresentMode::Mailbox],
+            PresentMode::Immediate => &[wgpu::PresentMode::Immediate, wgpu::PresentMode::Mailbox],
+        };
+        let mode = preferred.iter().copied().find(|m| supported.contains(m)).unwrap_or(wgpu::PresentMode::Fifo);
+        if preferred.first().is_some_and(|&p| p != mode) {
+            log::warn!("{self:?} presentation isn't supported, using {mode:?}");
+        }
+        return mode;
+    }
+}
+
+/// Whether frames are drawn all the time or only when something changed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RedrawMode {
+    #[default]
+    Continuous,
+    /// Draw after input, or when the app asks for another frame (e.g. while the clock plays).
+    OnDemand,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NextFrame {
+    Now,
+    At(Instant),
+    /// Nothing to draw until something changes.
+    Idle,
+}
+
+/// Decides when the next frame is due. The event loop asks `next_frame` whenever it is about to
+/// wait.
+#[derive(Clone, Debug)]
+pub struct FramePacer {
+    pub mode: RedrawMode,
+    /// Frames per second to stay under, if any.
+    pub max_fps: Option<f64>,
+    last_frame: Option<Instant>,
+    dirty: bool,
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        return FramePacer::new(RedrawMode::Continuous, None);
+    }
+}
+
+impl FramePacer {
+    pub fn new(mode: RedrawMode, max_fps: Option<f64>) -> Self {
+        return FramePacer { mode, max_fps: max_fps.filter(|&f| f > 0.), last_frame: None, dirty: true };
+    }
+
+    /// Something changed, so draw another frame even in on-demand mode.
+    pub fn request_redraw(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn frame_started(&mut self, now: Instant) {
+        self.last_frame = Some(now);
+        self.dirty = false;
+    }
+
+    pub fn next_frame(&self, now: Instant) -> NextFrame {
+        if self.mode == RedrawMode::OnDemand && !self.dirty {
+            return NextFrame::Idle;
+        }
+        let due = self.last_frame.zip(self.max_fps).map(|(last, fps)| last + Duration::from_secs_f64(1. / fps));
+        return match due {
+            Some(due) if due > now => NextFrame::At(due),
+            _ => NextFrame::Now,
+        };
+    }
+}
+
+#[test]
+fn present_modes_fall_back() {
+    use wgpu::PresentMode as P;
+    let all = [P::Fifo, P::FifoRelaxed, P::Mailbox, P::Immediate];
+    assert_eq!(PresentMode::Fifo.pick(&all), P::Fifo);
+    assert_eq!(PresentMode::Mailbox.pick(&all), P::Mailbox);
+    assert_eq!(PresentMode::Immediate.pick(&all), P::Immediate);
+
+    assert_eq!(PresentMode::Immediate.pick(&[P::Fifo, P::Mailbox]), P::Mailbox);
+    assert_eq!(PresentMode::Immediate.pick(&[P::Fifo]), P::Fifo);
+    assert_eq!(PresentMode::Mailbox.pick(&[P::Fifo, P::Immediate]), P::Fifo);
+    // Fifo even if the surface doesn't list it.
+    assert_eq!(PresentMode::Fifo.pick(&[]), P::Fifo);
+}
+
+#[test]
+fn frames_are_paced() {
+    let t0 = Instant::now();
+    let ms = |n: u64| t0 + Duration::from_millis(n);
+
+    let mut continuous = FramePacer::new(RedrawMode::Continuous, None);
+    assert_eq!(continuous.next_frame(t0), NextFrame::Now);
+    continuous.frame_started(t0);
+    assert_eq!(continuous.next_frame(ms(1)), NextFrame::Now);
+
+    let mut capped = FramePacer::new(RedrawMode::Continuous, Some(50.));
+    assert_eq!(capped.next_frame(t0), NextFrame::Now);
+    capped.frame_started(t0);
+    assert_eq!(capped.next_frame(ms(5)), NextFrame::At(ms(20)));
+    assert_eq!(capped.next_frame(ms(20)), NextFrame::Now);
+    assert_eq!(FramePacer::new(RedrawMode::Continuous, Some(0.)).max_fps, None);
+
+    // The first frame is always drawn, then only after requests.
+    let mut on_demand = FramePacer::new(RedrawMode::OnDemand, Some(50.));
+    assert_eq!(on_demand.next_frame(t0), NextFrame::Now);
+    on_demand.frame_started(t0);
+    assert_eq!(on_demand.next_frame(ms(100)), NextFrame::Idle);
+    on_demand.request_redraw();
+    assert_eq!(on_demand.next_frame(ms(100)), NextFrame::Now);
+    on_demand.frame_started(ms(100));
+    on_demand.request_redraw();
+    assert_eq!(on_demand.next_frame(ms(110)), NextFrame::At(ms(120)));
+}
@@ -0,0 +1,459 @@
+//! Render pipelines shared between renderables.
+//!
+//! A renderable gets its bind group layouts with `get_or_create_bgl`, describes its pipeline with
+//! a `PipelineBuilder` and keeps the `PipelineId` it gets back; identical layouts and descriptions
+//! are shared. The colour format and sample count come from the render targets, so the cache
+//! rebuilds every pipeline when those change, and rebuilds a pipeline when one of its shader files
+//! is edited. Passes that draw into something else, like the surface, fix their target with
+//! `PipelineBuilder::draws_into`. Layouts and pipelines nobody holds anymore are dropped at the next `refresh`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use super::shaders::{ShaderError, ShaderRegistry};
+use super::targets::{RenderTargets, DEPTH_FORMAT};
+use super::AppObjects;
+
+/// An owned `wgpu::VertexBufferLayout`, so pipeline descriptions can be hashed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    pub stride: wgpu::BufferAddress,
+    pub step_mode: wgpu::VertexStepMode,
+    pub attributes: Vec<wgpu::VertexAttribute>,
+}
+
+impl From<wgpu::VertexBufferLayout<'_>> for VertexLayout {
+    fn from(l: wgpu::VertexBufferLayout) -> Self {
+        return VertexLayout { stride: l.array_stride, step_mode: l.step_mode, attributes: l.attributes.to_vec() };
+    }
+}
+
+impl VertexLayout {
+    fn as_wgpu(&self) -> wgpu::VertexBufferLayout<'_> {
+        return wgpu::VertexBufferLayout { array_stride: self.stride, step_mode: self.step_mode, attributes: &self.attributes };
+    }
+}
+
+struct LayoutEntry {
+    id: u64,
+    layout: wgpu::BindGroupLayout,
+}
+
+/// A bind group layout from the `PipelineCache`. Layouts with the same entries are the same
+/// layout, and compare and hash by that, unlike `wgpu::BindGroupLayout`.
+#[derive(Clone)]
+pub struct CachedLayout(Rc<LayoutEntry>);
+
+impl std::ops::Deref for CachedLayout {
+    type Target = wgpu::BindGroupLayout;
+
+    fn deref(&self) -> &wgpu::BindGroupLayout {
+        return &self.0.layout;
+    }
+}
+
+impl PartialEq for CachedLayout {
+    fn eq(&self, other: &Self) -> bool {
+        return self.0.id == other.0.id;
+    }
+}
+
+impl Eq for CachedLayout {}
+
+impl std::hash::Hash for CachedLayout {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.id.hash(state);
+    }
+}
+
+impl std::fmt::Debug for CachedLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "CachedLayout({})", self.0.id);
+    }
+}
+
+/// Everything about a pipeline except what it draws into. Shaders have `vs_main` and `fs_main`
+/// entry points and a single colour target.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineDesc {
+    pub label: String,
+    pub shader: String,
+    /// `#define`s picking the shader variant.
+    pub defines: Vec<(String, String)>,
+    pub bind_group_layouts: Vec<CachedLayout>,
+    pub vertex_buffers: Vec<VertexLayout>,
+    pub primitive: wgpu::PrimitiveState,
+    pub blend: Option<wgpu::BlendState>,
+    pub depth_stencil: Option<wgpu::DepthStencilState>,
+    /// Draws into this instead of the scene target.
+    pub target: Option<TargetState>,
+}
+
+/// What a pipeline draws into: the scene target's colour format and sample count.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TargetState {
+    pub format: wgpu::TextureFormat,
+    pub samples: u32,
+}
+
+impl TargetState {
+    pub fn of(targets: &RenderTargets) -> Self {
+        return TargetState { format: targets.format(), samples: targets.settings().msaa_samples };
+    }
+
+    /// A single sampled `format` target, like the surface.
+    pub fn single(format: wgpu::TextureFormat) -> Self {
+        return TargetState { format, samples: 1 };
+    }
+}
+
+/// Handle to a pipeline in the `PipelineCache`. The pipeline stays in the cache while a handle
+/// to it is alive.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineId(Rc<usize>);
+
+struct Entry {
+    handle: Weak<usize>,
+    desc: PipelineDesc,
+    pipeline: wgpu::RenderPipeline,
+    target: TargetState,
+    /// Shader files the pipeline was built from, and the registry generation at the time.
+    files: Vec<String>,
+    generation: u64,
+}
+
+/// Pipelines by description and bind group layouts by their entries. Lives on `AppObjects`, so
+/// it is filled through a shared reference.
+#[derive(Default)]
+pub struct PipelineCache {
+    /// Slots are reused once their pipeline is dropped.
+    entries: RefCell<Vec<Option<Entry>>>,
+    ids: RefCell<HashMap<PipelineDesc, usize>>,
+    layouts: RefCell<HashMap<Vec<wgpu::BindGroupLayoutEntry>, Weak<LayoutEntry>>>,
+    next_layout: Cell<u64>,
+}
+
+/// Build from the shader files as they are now, or as the app shipped them with `shipped`.
+fn build(device: &wgpu::Device, shaders: &ShaderRegistry, desc: &PipelineDesc, target: TargetState, shipped: bool) -> Result<(wgpu::RenderPipeline, Vec<String>), ShaderError> {
+    let defines: Vec<(&str, &str)> = desc.defines.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let (module, pre) = match shipped {
+        true => shaders.compile_shipped(device, &desc.shader, &defines)?,
+        false => shaders.compile(device, &desc.shader, &defines)?,
+    };
+    let bind_group_layouts: Vec<&wgpu::BindGroupLayout> = desc.bind_group_layouts.iter().map(|l| &**l).collect();
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(&desc.label),
+        bind_group_layouts: &bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+    let buffers: Vec<wgpu::VertexBufferLayout> = desc.vertex_buffers.iter().map(VertexLayout::as_wgpu).collect();
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&desc.label),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &module,
+            entry_point: Some("vs_main"),
+            buffers: &buffers,
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: Some("fs_main"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target.format,
+                blend: desc.blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: desc.primitive,
+        depth_stencil: desc.depth_stencil.clone(),
+        multisample: wgpu::MultisampleState { count: target.samples, ..Default::default() },
+        multiview: None,
+        cache: None,
+    });
+    return Ok((pipeline, pre.files));
+}
+
+/// Like `build`, but turns validation errors (a shader that doesn't fit the pipeline's bindings
+/// or vertex layout) into an error instead of a panic.
+fn rebuild(device: &wgpu::Device, shaders: &ShaderRegistry, desc: &PipelineDesc, target: TargetState) -> Result<(wgpu::RenderPipeline, Vec<String>), ShaderError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let built = build(device, shaders, desc, target, false);
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+        return Err(ShaderError { file: desc.shader.clone(), line: 0, message: e.to_string() });
+    }
+    return built;
+}
+
+impl PipelineCache {
+    /// The pipeline for `desc`, built now if there isn't one yet. If an edited shader doesn't
+    /// compile, it is built from the shipped one instead until the next edit; those are checked
+    /// by tests.
+    pub fn get_or_create(&self, device: &wgpu::Device, shaders: &ShaderRegistry, target: TargetState, desc: PipelineDesc) -> PipelineId {
+        let target = desc.target.unwrap_or(target);
+        let mut entries = self.entries.borrow_mut();
+        if let Some(&slot) = self.ids.borrow().get(&desc)
+            && let Some(handle) = entries[slot].as_ref().and_then(|e| e.handle.upgrade())
+        {
+            return PipelineId(handle);
+        }
+        let (pipeline, files) = rebuild(device, shaders, &desc, target).unwrap_or_else(|e| {
+            log::error!("building the {} pipeline from the shipped shader: {e}", desc.label);
+            build(device, shaders, &desc, target, true).unwrap_or_else(|e| panic!("{e}"))
+        });
+        let slot = match self.ids.borrow().get(&desc) {
+            // Dropped but not evicted yet.
+            Some(&slot) => slot,
+            None => entries.iter().position(Option::is_none).unwrap_or(entries.len()),
+        };
+        if slot == entries.len() {
+            entries.push(None);
+        }
+        let handle = Rc::new(slot);
+        self.ids.borrow_mut().insert(desc.clone(), slot);
+        entries[slot] = Some(Entry { handle: Rc::downgrade(&handle), desc, pipeline, target, files, generation: shaders.generation() });
+        return PipelineId(handle);
+    }
+
+    pub fn get(&self, id: &PipelineId) -> wgpu::RenderPipeline {
+        return self.entries.borrow()[*id.0].as_ref().expect("pipelines live as long as their ids").pipeline.clone();
+    }
+
+    /// The bind group layout with these entries, created now if there isn't one yet.
+    pub fn get_or_create_bgl(&self, device: &wgpu::Device, entries: &[wgpu::BindGroupLayoutEntry]) -> CachedLayout {
+        let mut layouts = self.layouts.borrow_mut();
+        if let Some(entry) = layouts.get(entries).and_then(Weak::upgrade) {
+            return CachedLayout(entry);
+        }
+        let id = self.next_layout.replace(self.next_layout.get() + 1);
+        let label = format!("cachedBgl{id}");
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label: Some(&label), entries });
+        let entry = Rc::new(LayoutEntry { id, layout });
+        layouts.insert(entries.to_vec(), Rc::downgrade(&entry));
+        return CachedLayout(entry);
+    }
+
+    /// Number of pipelines and bind group layouts in the cache.
+    pub fn len(&self) -> (usize, usize) {
+        return (self.entries.borrow().iter().flatten().count(), self.layouts.borrow().len());
+    }
+
+    /// Drop pipelines and layouts nobody holds anymore.
+    fn evict(&self) {
+        let mut ids = self.ids.borrow_mut();
+        for slot in self.entries.borrow_mut().iter_mut() {
+            if slot.as_ref().is_some_and(|e| e.handle.strong_count() == 0) {
+                let e = slot.take().unwrap();
+                log::debug!("dropping pipeline {}", e.desc.label);
+                ids.remove(&e.desc);
+            }
+        }
+        self.layouts.borrow_mut().retain(|_, l| l.strong_count() > 0);
+    }
+
+    /// Drop what is no longer used, then rebuild pipelines whose shaders changed or that were
+    /// built for other targets. A pipeline that fails to rebuild keeps its previous version.
+    pub fn refresh(&self, device: &wgpu::Device, shaders: &ShaderRegistry, target: TargetState) {
+        self.evict();
+        for e in self.entries.borrow_mut().iter_mut().flatten() {
+            let target = e.desc.target.unwrap_or(target);
+            if e.target == target && !shaders.changed_since(&e.files, e.generation) {
+                continue;
+            }
+            e.generation = shaders.generation();
+            match rebuild(device, shaders, &e.desc, target) {
+                Ok((pipeline, files)) => {
+                    log::info!("rebuilt pipeline {}", e.desc.label);
+                    e.pipeline = pipeline;
+                    e.files = files;
+                    e.target = target;
+                }
+                Err(err) => log::error!("keeping the previous {} pipeline: {err}", e.desc.label),
+            }
+        }
+    }
+}
+
+/// Describes a pipeline in a few lines. Defaults to a triangle list with alpha blending and no
+/// depth buffer.
+pub struct PipelineBuilder {
+    desc: PipelineDesc,
+}
+
+impl PipelineBuilder {
+    /// `shader` is a name in the `ShaderRegistry`.
+    pub fn new(label: &str, shader: &str) -> Self {
+        return PipelineBuilder {
+            desc: PipelineDesc {
+                label: label.to_string(),
+                shader: shader.to_string(),
+                defines: vec![],
+                bind_group_layouts: vec![],
+                vertex_buffers: vec![],
+                primitive: Default::default(),
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                depth_stencil: None,
+                target: None,
+            },
+        };
+    }
+
+    /// Pick a shader variant: `#define name value` in front of the shader.
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.desc.defines.push((name.to_string(), value.to_string()));
+        return self;
+    }
+
+    /// Bind group layouts in group order, usually starting with the scene's.
+    pub fn bind_groups(mut self, layouts: &[&CachedLayout]) -> Self {
+        self.desc.bind_group_layouts = layouts.iter().map(|&l| l.clone()).collect();
+        return self;
+    }
+
+    pub fn vertex_buffer(mut self, layout: wgpu::VertexBufferLayout) -> Self {
+        self.desc.vertex_buffers.push(layout.into());
+        return self;
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.desc.primitive.topology = topology;
+        return self;
+    }
+
+    pub fn cull_back_faces(mut self) -> Self {
+        self.desc.primitive.cull_mode = Some(wgpu::Face::Back);
+        return self;
+    }
+
+    pub fn blend(mut self, blend: wgpu::BlendState) -> Self {
+        self.desc.blend = Some(blend);
+        return self;
+    }
+
+    /// Test against (and write) a `DEPTH_FORMAT` depth buffer, nearer fragments winning.
+    pub fn depth(mut self) -> Self {
+        self.desc.depth_stencil = Some(wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        });
+        return self;
+    }
+
+    /// Draw into `target` rather than the scene target, e.g. the surface in post processing.
+    pub fn draws_into(mut self, target: TargetState) -> Self {
+        self.desc.target = Some(target);
+        return self;
+    }
+
+    /// Get the pipeline from the app's cache, building it if this is the first time it's asked for.
+    pub fn build(self, ao: &AppObjects) -> PipelineId {
+        return ao.pipelines.get_or_create(&ao.device, &ao.shaders, TargetState::of(&ao.targets), self.desc);
+    }
+
+    /// Like `build`, for a pipeline with a fixed target made before there are `AppObjects`.
+    pub fn build_in(self, device: &wgpu::Device, shaders: &ShaderRegistry, cache: &PipelineCache) -> PipelineId {
+        let target = self.desc.target.expect("pipelines built without AppObjects fix their target");
+        return cache.get_or_create(device, shaders, target, self.desc);
+    }
+}
+
+#[test]
+fn descriptions_compare_by_content() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let hash = |d: &PipelineDesc| {
+        let mut h = DefaultHasher::new();
+        d.hash(&mut h);
+        h.finish()
+    };
+    let attrs = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+    let layout = || wgpu::VertexBufferLayout { array_stride: 28, step_mode: wgpu::VertexStepMode::Vertex, attributes: &attrs };
+    let points = || PipelineBuilder::new("points", "tracks.wgsl").vertex_buffer(layout()).topology(wgpu::PrimitiveTopology::PointList);
+
+    // Built separately, e.g. by two layers of the same kind.
+    let (a, b) = (points(), points());
+    assert_eq!(a.desc, b.desc);
+    assert_eq!(hash(&a.desc), hash(&b.desc));
+    assert_eq!(a.desc.vertex_buffers[0].as_wgpu(), layout());
+
+    assert_ne!(points().desc, points().topology(wgpu::PrimitiveTopology::LineList).desc);
+    assert_ne!(points().desc, points().depth().desc);
+    assert_ne!(points().desc, points().blend(wgpu::BlendState::REPLACE).desc);
+    assert_ne!(points().desc, points().define("SIZE", "2.0").desc);
+    let instanced = PipelineBuilder::new("points", "tracks.wgsl")
+        .vertex_buffer(wgpu::VertexBufferLayout { step_mode: wgpu::VertexStepMode::Instance, ..layout() })
+        .topology(wgpu::PrimitiveTopology::PointList);
+    assert_ne!(points().desc, instanced.desc);
+}
+
+#[test]
+fn equal_layouts_share_pipelines() {
+    let (device, _) = wgpu::Device::noop(&Default::default());
+    let (cache, shaders) = (PipelineCache::default(), ShaderRegistry::new());
+    let target = TargetState { format: wgpu::TextureFormat::Rgba16Float, samples: 1 };
+    let uniform = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+        count: None,
+    };
+
+    let vertex = wgpu::VertexBufferLayout {
+        array_stride: 24,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3],
+    };
+
+    // Two layers of the same kind each ask for their layouts and pipeline.
+    let layer = || {
+        let scene = cache.get_or_create_bgl(&device, &[uniform(0)]);
+        let own = cache.get_or_create_bgl(&device, &[uniform(0), uniform(1)]);
+        let desc = PipelineBuilder::new("shape", "simple_shape.wgsl").bind_groups(&[&scene, &own]).vertex_buffer(vertex.clone()).desc;
+        return cache.get_or_create(&device, &shaders, target, desc);
+    };
+    let (a, b) = (layer(), layer());
+    assert_eq!(a, b);
+    assert_eq!(cache.len(), (1, 2));
+    assert_ne!(cache.get_or_create_bgl(&device, &[uniform(0)]), cache.get_or_create_bgl(&device, &[uniform(1)]));
+
+    // Kept while either layer holds it, dropped after.
+    drop(a);
+    cache.refresh(&device, &shaders, target);
+    assert_eq!(cache.len(), (1, 2));
+    drop(b);
+    cache.refresh(&device, &shaders, target);
+    assert_eq!(cache.len(), (0, 0));
+    let c = layer();
+    assert_eq!(*c.0, 0);
+}
+
+#[test]
+fn broken_edits_fall_back_to_the_shipped_shader() {
+    let (device, _) = wgpu::Device::noop(&Default::default());
+    let (cache, mut shaders) = (PipelineCache::default(), ShaderRegistry::new());
+    let target = TargetState { format: wgpu::TextureFormat::Rgba16Float, samples: 1 };
+    let vertex = wgpu::VertexBufferLayout {
+        array_stride: 24,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3],
+    };
+    let scene = cache.get_or_create_bgl(&device, &[wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+        count: None,
+    }]);
+
+    shaders.edit("simple_shape.wgsl", "fn vs_main( {");
+    let desc = PipelineBuilder::new("shape", "simple_shape.wgsl").bind_groups(&[&scene, &scene]).vertex_buffer(vertex).desc;
+    let _id = cache.get_or_create(&device, &shaders, target, desc);
+    assert_eq!(cache.len().0, 1);
+}
@@ -0,0 +1,463 @@
+//! Frame timing: CPU time per frame and per layer, GPU time per render pass from timestamp
+//! queries where the adapter has them, and rolling averages and 95th percentiles of both.
+//!
+//! The frame is split into named scopes, one per layer plus the passes after the layers. Passes
+//! are timed by passing `Profiler::timestamp_writes` as their `timestamp_writes`; the GPU time of
+//! a scope is the sum of its passes. GPU results arrive a few frames late.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// What a renderable drew in a frame, for the performance overlay.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draw_calls: u32,
+    pub triangles: u64,
+    /// Tiles drawn, for tiled renderables.
+    pub tiles: Option<u32>,
+}
+
+impl std::ops::Add for DrawStats {
+    type Output = DrawStats;
+    fn add(self, o: DrawStats) -> DrawStats {
+        let tiles = match (self.tiles, o.tiles) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        return DrawStats { draw_calls: self.draw_calls + o.draw_calls, triangles: self.triangles + o.triangles, tiles };
+    }
+}
+
+impl std::ops::AddAssign for DrawStats {
+    fn add_assign(&mut self, o: DrawStats) {
+        *self = *self + o;
+    }
+}
+
+/// The last `capacity` samples of something measured once a frame.
+#[derive(Clone, Debug)]
+pub struct RollingStats {
+    samples: VecDeque<f64>,
+    capacity: usize,
+}
+
+impl RollingStats {
+    pub fn new(capacity: usize) -> Self {
+        return RollingStats { samples: VecDeque::with_capacity(capacity), capacity: capacity.max(1) };
+    }
+
+    pub fn push(&mut self, x: f64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(x);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.samples.is_empty();
+    }
+
+    pub fn last(&self) -> Option<f64> {
+        return self.samples.back().copied();
+    }
+
+    pub fn avg(&self) -> Option<f64> {
+        return (!self.is_empty()).then(|| self.samples.iter().sum::<f64>() / self.samples.len() as f64);
+    }
+
+    /// The nearest-rank 95th percentile.
+    pub fn p95(&self) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<f64> = self.samples.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let rank = (0.95 * sorted.len() as f64).ceil() as usize;
+        return Some(sorted[rank.clamp(1, sorted.len()) - 1]);
+    }
+}
+
+/// Frames the statistics are taken over.
+const WINDOW: usize = 120;
+/// Scopes not seen for this many frames (e.g. removed layers) are dropped.
+const SCOPE_TIMEOUT: u64 = 2 * WINDOW as u64;
+/// Timestamp queries per frame, two per pass.
+const MAX_QUERIES: u32 = 256;
+/// Frames of GPU results that can be waiting for readback at once.
+const READBACK_FRAMES: usize = 3;
+
+/// Timings of one scope, in milliseconds.
+#[derive(Clone, Debug)]
+pub struct ScopeTimings {
+    pub name: String,
+    pub cpu: RollingStats,
+    pub gpu: RollingStats,
+    /// What was drawn in the scope last frame.
+    pub stats: DrawStats,
+    last_seen: u64,
+}
+
+/// Timings of the whole frame, in milliseconds, and of its scopes in the order they first ran.
+#[derive(Clone, Debug)]
+pub struct FrameTimings {
+    /// From the start of one frame to the start of the next.
+    pub interval: RollingStats,
+    /// CPU time spent preparing and encoding the frame, without waiting for the surface.
+    pub cpu: RollingStats,
+    /// From the first timed pass starting to the last one ending.
+    pub gpu: RollingStats,
+    pub scopes: Vec<ScopeTimings>,
+    pub frame: u64,
+}
+
+impl FrameTimings {
+    fn new() -> Self {
+        return FrameTimings {
+            interval: RollingStats::new(WINDOW),
+            cpu: RollingStats::new(WINDOW),
+            gpu: RollingStats::new(WINDOW),
+            scopes: vec![],
+            frame: 0,
+        };
+    }
+
+    fn scope(&mut self, name: &str) -> &mut ScopeTimings {
+        let i = match self.scopes.iter().position(|s| s.name == name) {
+            Some(i) => i,
+            None => {
+                self.scopes.push(ScopeTimings {
+                    name: name.to_string(),
+                    cpu: RollingStats::new(WINDOW),
+                    gpu: RollingStats::new(WINDOW),
+                    stats: Default::default(),
+                    last_seen: self.frame,
+                });
+                self.scopes.len() - 1
+            }
+        };
+        return &mut self.scopes[i];
+    }
+
+    /// The per-scope lines of a report, e.g. for the log when there is no overlay.
+    pub fn summary(&self) -> String {
+        let ms = |s: &RollingStats| match (s.avg(), s.p95()) {
+            (Some(avg), Some(p95)) => format!("{avg:.2} ms (p95 {p95:.2})"),
+            _ => "-".to_string(),
+        };
+        let mut out = format!("frame {}, cpu {}, gpu {}", ms(&self.interval), ms(&self.cpu), ms(&self.gpu));
+        for s in &self.scopes {
+            out += &format!("\n  {}: cpu {}, gpu {}, {} draws, {} triangles", s.name, ms(&s.cpu), ms(&s.gpu), s.stats.draw_calls, s.stats.triangles);
+            if let Some(tiles) = s.stats.tiles {
+                out += &format!(", {tiles} tiles");
+            }
+        }
+        return out;
+    }
+}
+
+/// Scopes of a frame and the query indices of their passes, waiting for the GPU.
+struct Readback {
+    buffer: wgpu::Buffer,
+    /// Scope and the first query of each timed pass; each pass has two queries.
+    passes: Vec<(String, u32)>,
+    queries: u32,
+    mapped: Arc<AtomicBool>,
+    in_flight: bool,
+}
+
+struct GpuQueries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick.
+    period: f64,
+}
+
+/// Collects timings while a frame is built. Lives on `AppObjects`.
+pub struct Profiler {
+    gpu: Option<GpuQueries>,
+    readbacks: RefCell<Vec<Readback>>,
+    /// The readback this frame's timestamps will go to, if one is free.
+    current_readback: Cell<Option<usize>>,
+    next_query: Cell<u32>,
+    passes: RefCell<Vec<(String, u32)>>,
+
+    scope: RefCell<String>,
+    /// CPU time and draw stats per scope this frame.
+    frame_scopes: RefCell<Vec<(String, Duration, DrawStats)>>,
+    frame_start: Cell<Option<Instant>>,
+    /// Time this frame spent blocked rather than working, left out of its CPU time.
+    waited: Cell<Duration>,
+    timings: RefCell<FrameTimings>,
+}
+
+impl Profiler {
+    /// GPU timing is on if the device has `TIMESTAMP_QUERY`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let gpu = device.features().contains(wgpu::Features::TIMESTAMP_QUERY).then(|| GpuQueries {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("passTimestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: MAX_QUERIES,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("timestampResolve"),
+                size: MAX_QUERIES as u64 * 8,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            period: queue.get_timestamp_period() as f64,
+        });
+        let readbacks = match &gpu {
+            Some(_) => (0..READBACK_FRAMES)
+                .map(|_| Readback {
+                    buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("timestampReadback"),
+                        size: MAX_QUERIES as u64 * 8,
+                        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                        mapped_at_creation: false,
+                    }),
+                    passes: vec![],
+                    queries: 0,
+                    mapped: Arc::new(AtomicBool::new(false)),
+                    in_flight: false,
+                })
+                .collect(),
+            None => vec![],
+        };
+        return Profiler {
+            gpu,
+            readbacks: RefCell::new(readbacks),
+            current_readback: Cell::new(None),
+            next_query: Cell::new(0),
+            passes: RefCell::new(vec![]),
+            scope: RefCell::new(String::new()),
+            frame_scopes: RefCell::new(vec![]),
+            frame_start: Cell::new(None),
+            waited: Cell::new(Duration::ZERO),
+            timings: RefCell::new(FrameTimings::new()),
+        };
+    }
+
+    pub fn has_gpu_timing(&self) -> bool {
+        return self.gpu.is_some();
+    }
+
+    /// Start timing a frame, and pick up GPU results of earlier frames that have arrived.
+    pub fn begin_frame(&self, device: &wgpu::Device) {
+        let now = Instant::now();
+        let mut timings = self.timings.borrow_mut();
+        if let Some(prev) = self.frame_start.replace(Some(now)) {
+            timings.interval.push((now - prev).as_secs_f64() * 1e3);
+        }
+        timings.frame += 1;
+        drop(timings);
+
+        if self.gpu.is_some() {
+            let _ = device.poll(wgpu::PollType::Poll);
+            self.read_back();
+        }
+        let free = self.readbacks.borrow().iter().position(|r| !r.in_flight);
+        self.current_readback.set(free);
+        self.next_query.set(0);
+        self.passes.borrow_mut().clear();
+        self.frame_scopes.borrow_mut().clear();
+        self.waited.set(Duration::ZERO);
+        self.set_scope("frame");
+    }
+
+    /// Attribute the passes and CPU time that follow to `name`.
+    pub fn set_scope(&self, name: &str) {
+        name.clone_into(&mut self.scope.borrow_mut());
+    }
+
+    /// Add CPU time and what was drawn to a scope of this frame.
+    pub fn record(&self, name: &str, cpu: Duration, stats: DrawStats) {
+        let mut scopes = self.frame_scopes.borrow_mut();
+        match scopes.iter_mut().find(|s| s.0 == name) {
+            Some(s) => {
+                s.1 += cpu;
+                s.2 += stats;
+            }
+            None => scopes.push((name.to_string(), cpu, stats)),
+        }
+    }
+
+    /// Like `record`, for time spent waiting, e.g. for the next surface texture or in present.
+    /// It shows up in its scope but not in the frame's CPU time.
+    pub fn record_wait(&self, name: &str, waited: Duration) {
+        self.record(name, waited, DrawStats::default());
+        self.waited.set(self.waited.get() + waited);
+    }
+
+    /// Timestamp writes for a render pass in the current scope, or None if passes can't be timed.
+    pub fn timestamp_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        let gpu = self.gpu.as_ref()?;
+        self.current_readback.get()?;
+        let first = self.next_query.get();
+        if first + 2 > MAX_QUERIES {
+            return None;
+        }
+        self.next_query.set(first + 2);
+        self.passes.borrow_mut().push((self.scope.borrow().clone(), first));
+        return Some(wgpu::RenderPassTimestampWrites {
+            query_set: &gpu.query_set,
+            beginning_of_pass_write_index: Some(first),
+            end_of_pass_write_index: Some(first + 1),
+        });
+    }
+
+    /// Copy this frame's timestamps out for reading back. Call before submitting `encoder`.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        let (Some(gpu), Some(i)) = (&self.gpu, self.current_readback.get()) else {
+            return;
+        };
+        let queries = self.next_query.get();
+        if queries == 0 {
+            return;
+        }
+        let mut readbacks = self.readbacks.borrow_mut();
+        let r = &mut readbacks[i];
+        encoder.resolve_query_set(&gpu.query_set, 0..queries, &gpu.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&gpu.resolve_buffer, 0, &r.buffer, 0, queries as u64 * 8);
+        r.passes = std::mem::take(&mut self.passes.borrow_mut());
+        r.queries = queries;
+        r.in_flight = true;
+    }
+
+    /// Finish timing the frame. Call after the frame's commands are submitted.
+    pub fn end_frame(&self) {
+        if let Some(i) = self.current_readback.take() {
+            let readbacks = self.readbacks.borrow();
+            let r = &readbacks[i];
+            if r.in_flight {
+                let mapped = r.mapped.clone();
+                r.buffer.slice(..r.queries as u64 * 8).map_async(wgpu::MapMode::Read, move |res| mapped.store(res.is_ok(), Ordering::Release));
+            }
+        }
+
+        let mut timings = self.timings.borrow_mut();
+        if let Some(start) = self.frame_start.get() {
+            timings.cpu.push(start.elapsed().saturating_sub(self.waited.get()).as_secs_f64() * 1e3);
+        }
+        let frame = timings.frame;
+        for (name, cpu, stats) in self.frame_scopes.borrow().iter() {
+            let s = timings.scope(name);
+            s.cpu.push(cpu.as_secs_f64() * 1e3);
+            s.stats = *stats;
+            s.last_seen = frame;
+        }
+        timings.scopes.retain(|s| frame - s.last_seen < SCOPE_TIMEOUT);
+    }
+
+    fn read_back(&self) {
+        let Some(gpu) = &self.gpu else {
+            return;
+        };
+        for r in self.readbacks.borrow_mut().iter_mut().filter(|r| r.in_flight && r.mapped.load(Ordering::Acquire)) {
+            let ticks: Vec<u64> = bytemuck::pod_collect_to_vec(&r.buffer.slice(..r.queries as u64 * 8).get_mapped_range());
+            r.buffer.unmap();
+            r.mapped.store(false, Ordering::Release);
+            r.in_flight = false;
+
+            let (scopes, frame_ms) = gpu_times(&ticks, &r.passes, gpu.period);
+            let mut timings = self.timings.borrow_mut();
+            for (name, ms) in scopes {
+                // Scopes show up on the CPU side first, so a missing one was dropped since.
+                if let Some(s) = timings.scopes.iter_mut().find(|s| s.name == name) {
+                    s.gpu.push(ms);
+                }
+            }
+            if let Some(ms) = frame_ms {
+                timings.gpu.push(ms);
+            }
+        }
+    }
+
+    pub fn timings(&self) -> std::cell::Ref<'_, FrameTimings> {
+        return self.timings.borrow();
+    }
+}
+
+/// Milliseconds per scope (its passes summed, in first-seen order) and from the first pass
+/// starting to the last one ending, from resolved timestamps.
+fn gpu_times(ticks: &[u64], passes: &[(String, u32)], period_ns: f64) -> (Vec<(String, f64)>, Option<f64>) {
+    let ms = |t: u64| t as f64 * period_ns * 1e-6;
+    let mut scopes: Vec<(String, f64)> = vec![];
+    let mut span: Option<(u64, u64)> = None;
+    for (name, first) in passes {
+        let (Some(&begin), Some(&end)) = (ticks.get(*first as usize), ticks.get(*first as usize + 1)) else {
+            continue;
+        };
+        let t = ms(end.saturating_sub(begin));
+        match scopes.iter_mut().find(|s| s.0 == *name) {
+            Some(s) => s.1 += t,
+            None => scopes.push((name.clone(), t)),
+        }
+        span = Some(span.map_or((begin, end), |(b, e)| (b.min(begin), e.max(end))));
+    }
+    return (scopes, span.map(|(b, e)| ms(e.saturating_sub(b))));
+}
+
+#[test]
+fn rolling_stats() {
+    let mut s = RollingStats::new(20);
+    assert_eq!((s.avg(), s.p95(), s.last()), (None, None, None));
+    for x in 1..=40 {
+        s.push(x as f64);
+    }
+    // Only the last 20 samples, 21 to 40, count.
+    assert_eq!(s.avg(), Some(30.5));
+    assert_eq!(s.p95(), Some(39.));
+    assert_eq!(s.last(), Some(40.));
+
+    let mut one = RollingStats::new(20);
+    one.push(2.5);
+    assert_eq!((one.avg(), one.p95()), (Some(2.5), Some(2.5)));
+}
+
+#[test]
+fn gpu_times_are_summed_per_scope() {
+    let passes = [("tiles".to_string(), 0), ("tiles".to_string(), 2), ("post".to_string(), 4), ("gui".to_string(), 6)];
+    // Timestamps in 10 ns ticks.
+    let ticks = [1000, 101_000, 150_000, 200_000, 200_000, 250_000, 260_000, 255_000];
+    let (scopes, frame) = gpu_times(&ticks, &passes, 10.);
+    assert_eq!(scopes, [("tiles".to_string(), 1.5), ("post".to_string(), 0.5), ("gui".to_string(), 0.)]);
+    assert_eq!(frame, Some(2.54));
+
+    let (scopes, frame) = gpu_times(&ticks[..3], &passes, 10.);
+    assert_eq!((scopes.len(), frame), (1, Some(1.)));
+    assert_eq!(gpu_times(&[], &[], 1.), (vec![], None));
+}
+
+#[test]
+fn scopes_are_reported_in_order() {
+    let mut t = FrameTimings::new();
+    t.scope("tiles").cpu.push(1.);
+    t.scope("gui").cpu.push(0.5);
+    t.scope("tiles").stats = DrawStats { draw_calls: 12, triangles: 3400, tiles: Some(6) };
+    t.interval.push(16.);
+    let summary = t.summary();
+    assert_eq!(summary.lines().next(), Some("frame 16.00 ms (p95 16.00), cpu -, gpu -"));
+    assert_eq!(summary.lines().nth(1), Some("  tiles: cpu 1.00 ms (p95 1.00), gpu -, 12 draws, 3400 triangles, 6 tiles"));
+    assert_eq!(summary.lines().nth(2), Some("  gui: cpu 0.50 ms (p95 0.50), gpu -, 0 draws, 0 triangles"));
+
+    let sum = DrawStats { draw_calls: 1, triangles: 2, tiles: None } + DrawStats { draw_calls: 3, triangles: 4, tiles: Some(5) };
+    assert_eq!(sum, DrawStats { draw_calls: 4, triangles: 6, tiles: Some(5) });
+}
+
+#[test]
+fn waiting_is_not_cpu_time() {
+    let (device, queue) = wgpu::Device::noop(&Default::default());
+    let profiler = Profiler::new(&device, &queue);
+    profiler.begin_frame(&device);
+    profiler.record_wait("surface", Duration::from_millis(30));
+    profiler.record_wait("surface", Duration::from_millis(20));
+    profiler.end_frame();
+
+    let t = profiler.timings();
+    assert!(t.cpu.last().unwrap() < 50., "{:?}", t.cpu.last());
+    assert_eq!(t.scopes.iter().find(|s| s.name == "surface").unwrap().cpu.last(), Some(50.));
+}
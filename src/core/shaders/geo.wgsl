@@ -0,0 +1,18 @@
+// The WGS84 ellipsoid.
+
+const WGS84_A: f32 = 6378137.0;
+const WGS84_B: f32 = 6356752.314245;
+
+// Whether `p` can be seen from `eye` past the curve of the ellipsoid. Same test as
+// `EllipsoidalOccluder::is_point_visible`.
+fn above_horizon(eye: vec3<f32>, p: vec3<f32>) -> bool {
+    let scale = vec3<f32>(1.0 / WGS84_A, 1.0 / WGS84_A, 1.0 / WGS84_B);
+    let c = eye * scale;
+    let vh2 = dot(c, c) - 1.0;
+    if (vh2 <= 0.0) {
+        return true;
+    }
+    let vt = p * scale - c;
+    let vt_dot_vc = -dot(vt, c);
+    return !(vt_dot_vc > vh2 && vt_dot_vc * vt_dot_vc / dot(vt, vt) > vh2);
+}
@@ -0,0 +1,7 @@
+// Simple directional lighting for meshes.
+
+// Lambert with some ambient. Double sided, so back faces are lit as if they faced us.
+fn shade(color: vec3<f32>, normal: vec3<f32>, light_dir: vec3<f32>) -> vec3<f32> {
+    let lambert = abs(dot(normalize(normal), light_dir));
+    return color * (0.35 + 0.65 * lambert);
+}
@@ -0,0 +1,466 @@
+//! WGSL sources, composed with a small preprocessor and reloaded from disk in debug builds.
+//!
+//! Shaders may use, on lines of their own:
+//! - `#include "name.wgsl"`: paste another registered file, once per shader;
+//! - `#define NAME [value]`: set a flag, or a value that replaces the identifier `NAME` in the
+//!   lines that follow;
+//! - `#ifdef NAME`, `#ifndef NAME`, `#else`, `#endif`: keep lines only for some variants.
+//!
+//! Variants are picked by passing defines along with the shader name. Uniform structs shared with
+//! Rust can be included as `"<Struct>.wgsl"`, generated from their Rust declaration.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::LoweredScene;
+use uniforms::Uniform;
+
+pub mod uniforms;
+
+macro_rules! builtin_shaders {
+    ($($path:literal),* $(,)?) => {
+        &[$(($path, include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/", $path)))),*]
+    };
+}
+
+/// Every shader the app ships, by path under `src/`. Files are looked up by their file name.
+const BUILTIN: &[(&str, &str)] = builtin_shaders![
+    "core/shaders/scene.wgsl",
+    "core/shaders/geo.wgsl",
+    "core/shaders/lighting.wgsl",
+    "core/fxaa.wgsl",
+    "core/gui.wgsl",
+    "core/tonemap.wgsl",
+    "renderables/billboards.wgsl",
+    "renderables/instanced_mesh.wgsl",
+    "renderables/labels.wgsl",
+    "renderables/model.wgsl",
+    "renderables/point_cloud.wgsl",
+    "renderables/polylines.wgsl",
+    "renderables/simple_shape.wgsl",
+    "renderables/tracks.wgsl",
+];
+
+/// A problem with a shader, pointing into the file it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderError {
+    pub file: String,
+    /// 1-based; 0 when there is no line to blame.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}:{}: {}", self.file, self.line, self.message);
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+/// A shader with its includes and conditionals resolved.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Preprocessed {
+    pub source: String,
+    /// Every file that went into `source`, starting with the shader itself.
+    pub files: Vec<String>,
+    /// For each line of `source`, the file (index into `files`) and 1-based line it came from.
+    pub lines: Vec<(usize, usize)>,
+}
+
+impl Preprocessed {
+    /// Blame a 1-based line of `source` on the file it came from.
+    pub fn error_at(&self, line: usize, message: String) -> ShaderError {
+        let (file, line) = match self.lines.get(line.wrapping_sub(1)) {
+            Some(&(f, l)) => (self.files[f].clone(), l),
+            None => (self.files.first().cloned().unwrap_or_default(), 0),
+        };
+        return ShaderError { file, line, message };
+    }
+}
+
+struct Preprocessor<'a> {
+    load: &'a dyn Fn(&str) -> Option<String>,
+    defines: HashMap<String, String>,
+    out: Preprocessed,
+    included: HashSet<String>,
+}
+
+/// One `#ifdef` being read: whether its lines are kept, and whether `#else` was seen.
+struct Conditional {
+    active: bool,
+    parent_active: bool,
+    seen_else: bool,
+}
+
+fn is_ident_char(c: char) -> bool {
+    return c.is_ascii_alphanumeric() || c == '_';
+}
+
+impl Preprocessor<'_> {
+    fn file(&mut self, name: &str) -> Result<(), ShaderError> {
+        if !self.included.insert(name.to_string()) {
+            return Ok(());
+        }
+        let err = |line: usize, message: String| ShaderError { file: name.to_string(), line, message };
+        let text = (self.load)(name).ok_or_else(|| err(0, format!("no shader named '{name}'")))?;
+        self.out.files.push(name.to_string());
+        let file_index = self.out.files.len() - 1;
+
+        let mut stack: Vec<Conditional> = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let active = stack.last().is_none_or(|c| c.active);
+            let trimmed = line.trim();
+            let Some(directive) = trimmed.strip_prefix('#') else {
+                if active {
+                    let line = self.substitute(line);
+                    self.out.source.push_str(&line);
+                    self.out.source.push('\n');
+                    self.out.lines.push((file_index, line_no));
+                }
+                continue;
+            };
+            let (keyword, arg) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+            let arg = arg.trim();
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let set = self.defines.contains_key(arg);
+                    let cond = if keyword == "ifdef" { set } else { !set };
+                    stack.push(Conditional { active: active && cond, parent_active: active, seen_else: false });
+                }
+                "else" => {
+                    let c = stack.last_mut().ok_or_else(|| err(line_no, "#else without #ifdef".into()))?;
+                    if c.seen_else {
+                        return Err(err(line_no, "second #else".into()));
+                    }
+                    c.seen_else = true;
+                    c.active = c.parent_active && !c.active;
+                }
+                "endif" => {
+                    stack.pop().ok_or_else(|| err(line_no, "#endif without #ifdef".into()))?;
+                }
+                _ if !active => {}
+                "define" => {
+                    let (name, value) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+                    if name.is_empty() || !name.chars().all(is_ident_char) {
+                        return Err(err(line_no, format!("bad #define '{arg}'")));
+                    }
+                    self.defines.insert(name.to_string(), value.trim().to_string());
+                }
+                "include" => {
+                    let Some(target) = arg.strip_prefix('"').and_then(|a| a.strip_suffix('"')) else {
+                        return Err(err(line_no, format!("#include needs a quoted name, got '{arg}'")));
+                    };
+                    self.file(target).map_err(|mut e| {
+                        if e.line == 0 && e.file == target {
+                            // Missing file: blame the include.
+                            e = err(line_no, e.message);
+                        }
+                        e
+                    })?;
+                }
+                _ => return Err(err(line_no, format!("unknown directive '#{keyword}'"))),
+            }
+        }
+        if !stack.is_empty() {
+            return Err(err(text.lines().count(), "#ifdef without #endif".into()));
+        }
+        return Ok(());
+    }
+
+    /// Replace identifiers that have a defined value.
+    fn substitute(&self, line: &str) -> String {
+        if self.defines.values().all(String::is_empty) {
+            return line.to_string();
+        }
+        let mut out = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            let (before, from) = rest.split_at(start);
+            out.push_str(before);
+            let end = from.find(|c: char| !is_ident_char(c)).unwrap_or(from.len());
+            let ident = &from[..end];
+            // Part of a number like 1e5 or 0x1f: leave it.
+            let in_number = before.chars().last().is_some_and(|c| c.is_ascii_digit() || c == '.');
+            match self.defines.get(ident) {
+                Some(value) if !value.is_empty() && !in_number => out.push_str(value),
+                _ => out.push_str(ident),
+            }
+            rest = &from[end..];
+        }
+        out.push_str(rest);
+        return out;
+    }
+}
+
+/// Resolve `name` with `defines` (name, value; empty for flags) set up front. `load` gives the
+/// text of a file by name.
+pub fn preprocess(name: &str, defines: &[(&str, &str)], load: &dyn Fn(&str) -> Option<String>) -> Result<Preprocessed, ShaderError> {
+    let mut p = Preprocessor {
+        load,
+        defines: defines.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        out: Default::default(),
+        included: HashSet::new(),
+    };
+    p.file(name)?;
+    return Ok(p.out);
+}
+
+/// Parse and validate preprocessed WGSL, blaming errors on the original files.
+pub fn validate(pre: &Preprocessed) -> Result<naga::Module, ShaderError> {
+    let module = naga::front::wgsl::parse_str(&pre.source).map_err(|e| {
+        let line = e.location(&pre.source).map_or(0, |l| l.line_number as usize);
+        pre.error_at(line, e.message().to_string())
+    })?;
+    let mut validator = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::default());
+    validator.validate(&module).map_err(|e| {
+        let line = e.location(&pre.source).map_or(0, |l| l.line_number as usize);
+        pre.error_at(line, e.as_inner().to_string())
+    })?;
+    return Ok(module);
+}
+
+struct ShaderFile {
+    /// Under `src/`; None for generated files.
+    path: Option<&'static str>,
+    text: String,
+    /// What the app was built with, to fall back on when an edit doesn't compile.
+    shipped: String,
+    /// Registry generation in which the text last changed.
+    changed: u64,
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    modified: Option<std::time::SystemTime>,
+}
+
+/// All the app's shaders by file name. Debug builds on native watch the files in the source
+/// tree and pick up edits, which the `PipelineCache` then rebuilds from.
+pub struct ShaderRegistry {
+    files: HashMap<&'static str, ShaderFile>,
+    generation: u64,
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    last_poll: Option<std::time::Instant>,
+}
+
+fn file_name(path: &str) -> &str {
+    return path.rsplit('/').next().unwrap_or(path);
+}
+
+impl Default for ShaderRegistry {
+    fn default() -> Self {
+        // Generated from Rust declarations.
+        let generated = [("LoweredScene.wgsl", None, LoweredScene::wgsl_struct())];
+        let files = BUILTIN
+            .iter()
+            .map(|&(path, text)| (file_name(path), Some(path), text.to_string()))
+            .chain(generated)
+            .map(|(name, path, text)| {
+                let file = ShaderFile {
+                    path,
+                    shipped: text.clone(),
+                    text,
+                    changed: 0,
+                    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+                    modified: None,
+                };
+                (name, file)
+            })
+            .collect();
+        return ShaderRegistry {
+            files,
+            generation: 0,
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            last_poll: None,
+        };
+    }
+}
+
+impl ShaderRegistry {
+    pub fn new() -> Self {
+        return Default::default();
+    }
+
+    pub fn source(&self, name: &str) -> Option<&str> {
+        return self.files.get(name).map(|f| f.text.as_str());
+    }
+
+    pub fn preprocess(&self, name: &str, defines: &[(&str, &str)]) -> Result<Preprocessed, ShaderError> {
+        return preprocess(name, defines, &|n| self.source(n).map(str::to_string));
+    }
+
+    /// Preprocess and validate a shader, then hand it to the device.
+    pub fn compile(&self, device: &wgpu::Device, name: &str, defines: &[(&str, &str)]) -> Result<(wgpu::ShaderModule, Preprocessed), ShaderError> {
+        let pre = self.preprocess(name, defines)?;
+        return self.create_module(device, name, pre);
+    }
+
+    /// Like `compile`, but from the files as the app shipped them, ignoring edits on disk.
+    pub fn compile_shipped(&self, device: &wgpu::Device, name: &str, defines: &[(&str, &str)]) -> Result<(wgpu::ShaderModule, Preprocessed), ShaderError> {
+        let pre = preprocess(name, defines, &|n| self.files.get(n).map(|f| f.shipped.clone()))?;
+        return self.create_module(device, name, pre);
+    }
+
+    fn create_module(&self, device: &wgpu::Device, name: &str, pre: Preprocessed) -> Result<(wgpu::ShaderModule, Preprocessed), ShaderError> {
+        validate(&pre)?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(pre.source.as_str().into()),
+        });
+        return Ok((module, pre));
+    }
+
+    /// Bumped whenever a file changes.
+    pub fn generation(&self) -> u64 {
+        return self.generation;
+    }
+
+    /// Whether any of `files` changed after `generation`.
+    pub fn changed_since(&self, files: &[String], generation: u64) -> bool {
+        return files.iter().any(|f| self.files.get(f.as_str()).is_some_and(|f| f.changed > generation));
+    }
+
+    /// Replace a file's text as if it had been edited on disk.
+    #[cfg(test)]
+    pub fn edit(&mut self, name: &str, text: &str) {
+        self.generation += 1;
+        let file = self.files.get_mut(name).expect("only registered files can be edited");
+        file.text = text.to_string();
+        file.changed = self.generation;
+    }
+
+    /// Look for edited shader files, at most a few times a second. Only does anything in debug
+    /// builds on native, where the source tree is around.
+    pub fn poll(&mut self) {
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        {
+            use std::time::{Duration, Instant};
+            if self.last_poll.is_some_and(|t| t.elapsed() < Duration::from_millis(250)) {
+                return;
+            }
+            self.last_poll = Some(Instant::now());
+            let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+            let mut changed = vec![];
+            for (name, file) in &mut self.files {
+                let Some(path) = file.path.map(|p| root.join(p)) else {
+                    continue;
+                };
+                let Ok(modified) = std::fs::metadata(&path).and_then(|m| m.modified()) else {
+                    continue;
+                };
+                if file.modified.replace(modified).is_none_or(|m| m == modified) {
+                    continue;
+                }
+                match std::fs::read_to_string(&path) {
+                    Ok(text) if text != file.text => {
+                        file.text = text;
+                        changed.push(*name);
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("can't reload {}: {e}", path.display()),
+                }
+            }
+            if !changed.is_empty() {
+                self.generation += 1;
+                for name in changed {
+                    log::info!("shader {name} changed");
+                    self.files.get_mut(name).unwrap().changed = self.generation;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_files(files: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> + use<> {
+    let files: HashMap<String, String> = files.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    return move |name| files.get(name).cloned();
+}
+
+#[test]
+fn includes_are_pasted_once_and_lines_map_back() {
+    let load = test_files(&[
+        ("main.wgsl", "#include \"a.wgsl\"\n#include \"b.wgsl\"\nfn main() {}\n"),
+        ("a.wgsl", "#include \"b.wgsl\"\nconst A: f32 = B;\n"),
+        ("b.wgsl", "#include \"a.wgsl\"\nconst B: f32 = 1.0;\n"),
+    ]);
+    let pre = preprocess("main.wgsl", &[], &load).unwrap();
+    assert_eq!(pre.source, "const B: f32 = 1.0;\nconst A: f32 = B;\nfn main() {}\n");
+    assert_eq!(pre.files, ["main.wgsl", "a.wgsl", "b.wgsl"]);
+    let err = pre.error_at(2, "oops".into());
+    assert_eq!(err.to_string(), "a.wgsl:2: oops");
+    assert_eq!(pre.error_at(3, String::new()).file, "main.wgsl");
+
+    let missing = preprocess("main.wgsl", &[], &test_files(&[("main.wgsl", "\n  #include \"nope.wgsl\"\n")]));
+    assert_eq!(missing.unwrap_err().to_string(), "main.wgsl:2: no shader named 'nope.wgsl'");
+}
+
+#[test]
+fn conditionals_and_defines() {
+    let src = "\
+#ifdef FANCY
+fancy
+#ifndef PLAIN
+not plain
+#else
+plain
+#endif
+#else
+boring
+#define PLAIN
+#endif
+#ifdef PLAIN
+size SIZE
+#endif
+";
+    let load = test_files(&[("s.wgsl", src)]);
+    let run = |defines: &[(&str, &str)]| preprocess("s.wgsl", defines, &load).unwrap().source;
+    assert_eq!(run(&[]), "boring\nsize SIZE\n");
+    assert_eq!(run(&[("FANCY", "")]), "fancy\nnot plain\n");
+    assert_eq!(run(&[("FANCY", ""), ("PLAIN", "")]), "fancy\nplain\nsize SIZE\n");
+    // Values replace whole identifiers only.
+    assert_eq!(run(&[("SIZE", "4.0")]), "boring\nsize 4.0\n");
+    let load = test_files(&[("d.wgsl", "#define N 3\nvar<private> a: array<f32, N>; // N_MAX 1e5N\n")]);
+    let pre = preprocess("d.wgsl", &[], &load).unwrap();
+    assert_eq!(pre.source, "var<private> a: array<f32, 3>; // N_MAX 1e5N\n");
+
+    let fails = |src: &str| preprocess("x.wgsl", &[], &test_files(&[("x.wgsl", src)])).unwrap_err().to_string();
+    assert_eq!(fails("#ifdef A\n"), "x.wgsl:1: #ifdef without #endif");
+    assert_eq!(fails("a\n#endif\n"), "x.wgsl:2: #endif without #ifdef");
+    assert_eq!(fails("#ifdef A\n#else\n#else\n#endif\n"), "x.wgsl:3: second #else");
+    assert_eq!(fails("#pragma once\n"), "x.wgsl:1: unknown directive '#pragma'");
+    // Unknown directives in skipped code don't matter.
+    assert!(preprocess("y.wgsl", &[], &test_files(&[("y.wgsl", "#ifdef A\n#pragma\n#endif\n")])).is_ok());
+}
+
+#[test]
+fn compile_errors_point_into_included_files() {
+    let load = test_files(&[
+        ("main.wgsl", "#include \"lib.wgsl\"\n@fragment\nfn fs_main() -> @location(0) vec4<f32> { return f(); }\n"),
+        ("lib.wgsl", "fn f() -> vec4<f32> {\n    return vec4<f32>(1.0, 2.0);\n}\n"),
+    ]);
+    let err = validate(&preprocess("main.wgsl", &[], &load).unwrap()).unwrap_err();
+    assert_eq!((err.file.as_str(), err.line), ("lib.wgsl", 2), "{err}");
+}
+
+#[test]
+fn builtin_shaders_compile() {
+    let registry = ShaderRegistry::new();
+    for (path, _) in BUILTIN {
+        let name = file_name(path);
+        // Snippets are only checked as part of the shaders that use them.
+        if path.starts_with("core/shaders/") {
+            continue;
+        }
+        let pre = registry.preprocess(name, &[]).unwrap();
+        if let Err(e) = validate(&pre) {
+            panic!("{e}");
+        }
+    }    // Variants the renderables pick with defines.
+    const VARIANTS: &[(&str, &[(&str, &str)])] = &[("tracks.wgsl", &[("RELATIVE", "")])];
+    for &(name, defines) in VARIANTS {
+        let pre = registry.preprocess(name, defines).unwrap();
+        if let Err(e) = validate(&pre) {
+            panic!("{name} {defines:?}: {e}");
+        }
+    }
+}
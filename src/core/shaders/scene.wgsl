@@ -0,0 +1,7 @@
+// The scene uniforms every renderable gets in group 0. The struct is generated from `LoweredScene`
+// in camera.rs.
+
+#include "LoweredScene.wgsl"
+
+@group(0) @binding(0)
+var<uniform> scene: LoweredScene;
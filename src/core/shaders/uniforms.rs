@@ -0,0 +1,234 @@
+//! Uniform structs shared between Rust and WGSL.
+//!
+//! Declare them with `uniform_struct!`, which adds `repr(C)` and the bytemuck derives and
+//! describes the fields, so the WGSL layout can be worked out, compared with the Rust one, and
+//! written out as WGSL. Field types map to WGSL as `f32`, `u32` and `i32`, `[f32; N]` to
+//! `vecN<f32>` and `[f32; 16]` to `mat4x4<f32>`.
+
+use std::fmt::Write;
+
+/// A Rust type usable as a uniform struct field, with its WGSL name and layout.
+pub trait WgslType {
+    const WGSL: &'static str;
+    const SIZE: usize;
+    /// Alignment in the uniform address space.
+    const ALIGN: usize;
+}
+
+macro_rules! wgsl_types {
+    ($($ty:ty => $wgsl:literal, $size:literal, $align:literal;)*) => {
+        $(impl WgslType for $ty {
+            const WGSL: &'static str = $wgsl;
+            const SIZE: usize = $size;
+            const ALIGN: usize = $align;
+        })*
+    };
+}
+
+wgsl_types! {
+    f32 => "f32", 4, 4;
+    u32 => "u32", 4, 4;
+    i32 => "i32", 4, 4;
+    [f32; 2] => "vec2<f32>", 8, 8;
+    [f32; 3] => "vec3<f32>", 12, 16;
+    [f32; 4] => "vec4<f32>", 16, 16;
+    [u32; 2] => "vec2<u32>", 8, 8;
+    [u32; 4] => "vec4<u32>", 16, 16;
+    [f32; 16] => "mat4x4<f32>", 64, 16;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UniformField {
+    pub name: &'static str,
+    pub wgsl: &'static str,
+    /// Where the field is in the Rust struct.
+    pub offset: usize,
+    pub size: usize,
+    pub align: usize,
+}
+
+impl UniformField {
+    pub fn new<T: WgslType>(name: &'static str, offset: usize) -> Self {
+        return UniformField { name, wgsl: T::WGSL, offset, size: T::SIZE, align: T::ALIGN };
+    }
+}
+
+/// Field offsets and size of a struct as WGSL lays it out in the uniform address space.
+pub fn wgsl_layout(fields: &[UniformField]) -> (Vec<usize>, usize) {
+    let round_up = |n: usize, align: usize| n.div_ceil(align) * align;
+    let mut offsets = vec![];
+    let mut end = 0;
+    for f in fields {
+        let offset = round_up(end, f.align);
+        offsets.push(offset);
+        end = offset + f.size;
+    }
+    // Uniform structs are aligned to 16 bytes.
+    let align = fields.iter().map(|f| f.align).max().unwrap_or(1).max(16);
+    return (offsets, round_up(end, align));
+}
+
+/// A struct that goes into a uniform buffer as is. Implemented by `uniform_struct!`.
+pub trait Uniform: bytemuck::Pod {
+    const NAME: &'static str;
+
+    fn fields() -> Vec<UniformField>;
+
+    /// The struct's WGSL declaration.
+    fn wgsl_struct() -> String {
+        let mut s = format!("struct {} {{\n", Self::NAME);
+        for f in Self::fields() {
+            writeln!(s, "    {}: {},", f.name, f.wgsl).unwrap();
+        }
+        s.push_str("};\n");
+        return s;
+    }
+
+    /// Check that the Rust struct has the layout WGSL expects, so padding is explicit and in the
+    /// right places.
+    fn check_layout() -> Result<(), String> {
+        let fields = Self::fields();
+        let (offsets, size) = wgsl_layout(&fields);
+        for (f, offset) in fields.iter().zip(offsets) {
+            if f.offset != offset {
+                return Err(format!("{}.{} is at {} in Rust but {} in WGSL", Self::NAME, f.name, f.offset, offset));
+            }
+        }
+        let rust_size = std::mem::size_of::<Self>();
+        if rust_size != size {
+            return Err(format!("{} is {rust_size} bytes in Rust but {size} in WGSL", Self::NAME));
+        }
+        return Ok(());
+    }
+}
+
+/// Declare a uniform struct: adds `repr(C)`, the usual derives and a `Uniform` impl.
+macro_rules! uniform_struct {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_attr:meta])* $field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[repr(C)]
+        #[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+        $vis struct $name {
+            $($(#[$field_attr])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::core::shaders::uniforms::Uniform for $name {
+            const NAME: &'static str = stringify!($name);
+
+            fn fields() -> Vec<$crate::core::shaders::uniforms::UniformField> {
+                return vec![$(
+                    $crate::core::shaders::uniforms::UniformField::new::<$ty>(
+                        stringify!($field),
+                        std::mem::offset_of!($name, $field),
+                    )
+                ),*];
+            }
+        }
+    };
+}
+pub(crate) use uniform_struct;
+
+#[cfg(test)]
+fn find_struct<'a>(module: &'a naga::Module, name: &str) -> Option<(&'a [naga::StructMember], u32)> {
+    return module.types.iter().find_map(|(_, ty)| match &ty.inner {
+        naga::TypeInner::Struct { members, span } if ty.name.as_deref() == Some(name) => Some((members.as_slice(), *span)),
+        _ => None,
+    });
+}
+
+/// Compare `T` with the struct of the same name in a shader, as naga reflects it: field names,
+/// types and offsets, and the size.
+#[cfg(test)]
+pub fn check_against_module<T: Uniform>(module: &naga::Module) -> Result<(), String> {
+    T::check_layout()?;
+    let expected_module = naga::front::wgsl::parse_str(&T::wgsl_struct()).map_err(|e| e.message().to_string())?;
+    let (expected, expected_span) = find_struct(&expected_module, T::NAME).unwrap();
+    let (members, span) = find_struct(module, T::NAME).ok_or_else(|| format!("the shader has no struct {}", T::NAME))?;
+
+    let names = |m: &[naga::StructMember]| m.iter().map(|m| m.name.clone().unwrap_or_default()).collect::<Vec<_>>();
+    if names(members) != names(expected) {
+        return Err(format!("{} has fields {:?} in the shader but {:?} in Rust", T::NAME, names(members), names(expected)));
+    }
+    for ((m, e), f) in members.iter().zip(expected).zip(T::fields()) {
+        if module.types[m.ty].inner != expected_module.types[e.ty].inner {
+            return Err(format!("{}.{} isn't a {} in the shader", T::NAME, f.name, f.wgsl));
+        }
+        if m.offset as usize != f.offset {
+            return Err(format!("{}.{} is at {} in the shader but {} in Rust", T::NAME, f.name, m.offset, f.offset));
+        }
+    }
+    if span != expected_span || span as usize != std::mem::size_of::<T>() {
+        return Err(format!("{} is {span} bytes in the shader but {} in Rust", T::NAME, std::mem::size_of::<T>()));
+    }
+    return Ok(());
+}
+
+/// Panic unless `T` matches its declaration in one of the registered shaders.
+#[cfg(test)]
+pub fn assert_matches_shader<T: Uniform>(shader: &str) {
+    let pre = super::ShaderRegistry::new().preprocess(shader, &[]).unwrap();
+    let module = super::validate(&pre).unwrap_or_else(|e| panic!("{e}"));
+    if let Err(e) = check_against_module::<T>(&module) {
+        panic!("{shader}: {e}");
+    }
+}
+
+#[cfg(test)]
+uniform_struct! {
+    struct Padded {
+        viewport: [f32; 2],
+        opacity: f32,
+        pad: f32,
+        eye: [f32; 4],
+    }
+}
+
+#[cfg(test)]
+uniform_struct! {
+    struct Unpadded {
+        opacity: f32,
+        light: [f32; 4],
+    }
+}
+
+#[test]
+fn layouts_follow_wgsl_rules() {
+    assert_eq!(Padded::check_layout(), Ok(()));
+    assert_eq!(Padded::wgsl_struct(), "struct Padded {\n    viewport: vec2<f32>,\n    opacity: f32,\n    pad: f32,\n    eye: vec4<f32>,\n};\n");
+    // The vec4 is 16-byte aligned in WGSL but follows straight on in Rust.
+    assert_eq!(Unpadded::check_layout(), Err("Unpadded.light is at 4 in Rust but 16 in WGSL".into()));
+
+    let fields = [UniformField::new::<[f32; 3]>("a", 0), UniformField::new::<f32>("b", 12), UniformField::new::<[f32; 2]>("c", 16)];
+    assert_eq!(wgsl_layout(&fields), (vec![0, 12, 16], 32));
+}
+
+#[test]
+fn shader_structs_are_compared_field_by_field() {
+    let check = |src: &str| check_against_module::<Padded>(&naga::front::wgsl::parse_str(src).unwrap());
+    assert_eq!(check(&Padded::wgsl_struct()), Ok(()));
+    assert_eq!(
+        check("struct Padded { viewport: vec2<f32>, opacity: f32, pad1: f32, eye: vec4<f32> };"),
+        Err("Padded has fields [\"viewport\", \"opacity\", \"pad1\", \"eye\"] in the shader but [\"viewport\", \"opacity\", \"pad\", \"eye\"] in Rust".into())
+    );
+    assert_eq!(
+        check("struct Padded { viewport: vec2<f32>, opacity: f32, pad: u32, eye: vec4<f32> };"),
+        Err("Padded.pad isn't a f32 in the shader".into())
+    );
+    assert_eq!(
+        check("struct Padded { viewport: vec2<f32>, opacity: f32, pad: f32, @align(32) eye: vec4<f32> };"),
+        Err("Padded.eye is at 32 in the shader but 16 in Rust".into())
+    );
+    assert!(check("struct Other { a: f32 };").is_err());
+}
+
+#[test]
+fn core_uniforms_match_shaders() {
+    assert_matches_shader::<crate::core::LoweredScene>("simple_shape.wgsl");
+    assert_matches_shader::<crate::core::gui::GuiUniforms>("gui.wgsl");
+    assert_matches_shader::<crate::core::targets::ToneMapUniforms>("tonemap.wgsl");
+}
@@ -0,0 +1,409 @@
+use super::shaders::uniforms::uniform_struct;
+use super::shaders::ShaderRegistry;
+use super::pacing::PresentMode;
+use super::pipelines::{CachedLayout, PipelineBuilder, PipelineCache, PipelineId, TargetState};
+use super::profiler::Profiler;
+use super::tonemap::ToneMapping;
+
+/// Depth format of every depth buffer renderables draw with.
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Render quality knobs. Set when the `AppObjects` are created, and changed later with
+/// `AppObjects::request_render_settings`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RenderSettings {
+    /// Samples per pixel for the scene; 1 turns MSAA off.
+    pub msaa_samples: u32,
+    /// Smooth edges with an FXAA pass instead when MSAA is off, e.g. because the adapter can't
+    /// do it.
+    pub fxaa: bool,
+    pub present_mode: PresentMode,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        return RenderSettings { msaa_samples: 4, fxaa: true, present_mode: PresentMode::Fifo };
+    }
+}
+
+impl RenderSettings {
+    /// These settings cut down to what the adapter supports: the largest supported sample count
+    /// up to the one asked for.
+    pub fn validated(self, supported_samples: &[u32]) -> Self {
+        let msaa_samples = supported_samples
+            .iter()
+            .copied()
+            .filter(|&n| n <= self.msaa_samples.max(1))
+            .max()
+            .unwrap_or(1);
+        if msaa_samples != self.msaa_samples {
+            log::warn!("{}x MSAA isn't supported, using {msaa_samples}x", self.msaa_samples);
+        }
+        return RenderSettings { msaa_samples, ..self };
+    }
+
+    pub fn uses_fxaa(&self) -> bool {
+        return self.fxaa && self.msaa_samples <= 1;
+    }
+
+    /// Multisample state for pipelines that draw into the scene target.
+    pub fn multisample(&self) -> wgpu::MultisampleState {
+        return wgpu::MultisampleState { count: self.msaa_samples, ..Default::default() };
+    }
+}
+
+/// Format of the scene target: half floats, so lighting can go past 1 until tone mapping.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// `HDR_FORMAT` if the adapter can render and blend into it, otherwise the surface format (and
+/// tone mapping works on clipped colours).
+pub fn pick_scene_format(adapter: &wgpu::Adapter, surface_format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+    let features = adapter.get_texture_format_features(HDR_FORMAT);
+    if features.allowed_usages.contains(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+        && features.flags.contains(wgpu::TextureFormatFeatureFlags::BLENDABLE)
+    {
+        return HDR_FORMAT;
+    }
+    log::warn!("{HDR_FORMAT:?} can't be rendered to, drawing the scene in {surface_format:?}");
+    return surface_format;
+}
+
+/// Sample counts usable for both the scene format and `DEPTH_FORMAT`.
+pub fn supported_sample_counts(adapter: &wgpu::Adapter, device_features: wgpu::Features, format: wgpu::TextureFormat) -> Vec<u32> {
+    let color = adapter.get_texture_format_features(format).flags;
+    let depth = adapter.get_texture_format_features(DEPTH_FORMAT).flags;
+    // Without this feature only the counts WebGPU guarantees may be used.
+    let adapter_specific = device_features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    return color
+        .supported_sample_counts()
+        .into_iter()
+        .filter(|&n| depth.sample_count_supported(n) && (adapter_specific || n == 1 || n == 4))
+        .collect();
+}
+
+uniform_struct! {
+    pub(super) struct ToneMapUniforms {
+        curve: u32,
+        exposure: f32,
+        encode_srgb: u32,
+        pad: u32,
+    }
+}
+
+/// Where the scene is drawn before it reaches the surface.
+///
+/// Renderables draw into an HDR target (multisampled with MSAA, then resolved), which is tone
+/// mapped onto the surface, or onto an intermediate texture that FXAA copies over.
+pub struct RenderTargets {
+    settings: RenderSettings,
+    format: wgpu::TextureFormat,
+    surface_format: wgpu::TextureFormat,
+    size: (u32, u32),
+    /// Where renderables draw.
+    scene: Option<wgpu::TextureView>,
+    /// Single sampled copy of `scene` with MSAA.
+    resolved: Option<wgpu::TextureView>,
+    /// The tone mapped scene, waiting for FXAA.
+    ldr: Option<wgpu::TextureView>,
+    tone_map: ToneMapPass,
+    fxaa: Option<Fxaa>,
+}
+
+struct ToneMapPass {
+    pipeline: PipelineId,
+    bgl: CachedLayout,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+struct Fxaa {
+    pipeline: PipelineId,
+    bgl: CachedLayout,
+    sampler: wgpu::Sampler,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+fn color_target(device: &wgpu::Device, label: &str, size: (u32, u32), samples: u32, format: wgpu::TextureFormat) -> wgpu::TextureView {
+    let usage = if samples > 1 {
+        wgpu::TextureUsages::RENDER_ATTACHMENT
+    } else {
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d { width: size.0.max(1), height: size.1.max(1), depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: samples,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage,
+        view_formats: &[],
+    });
+    return texture.create_view(&wgpu::TextureViewDescriptor::default());
+}
+
+/// A pipeline drawing one screen covering triangle from `shader` into `format`.
+fn fullscreen_pipeline(
+    device: &wgpu::Device,
+    shaders: &ShaderRegistry,
+    pipelines: &PipelineCache,
+    label: &str,
+    shader: &str,
+    bgl: &CachedLayout,
+    format: wgpu::TextureFormat,
+) -> PipelineId {
+    return PipelineBuilder::new(label, shader)
+        .bind_groups(&[bgl])
+        .blend(wgpu::BlendState::REPLACE)
+        .draws_into(TargetState::single(format))
+        .build_in(device, shaders, pipelines);
+}
+
+/// A pass drawing one screen covering triangle over `view`.
+fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    view: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+    timestamp_writes: Option<wgpu::RenderPassTimestampWrites>,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+            depth_slice: None,
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+impl RenderTargets {
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderRegistry,
+        pipelines: &PipelineCache,
+        surface_format: wgpu::TextureFormat,
+        format: wgpu::TextureFormat,
+        settings: RenderSettings,
+    ) -> Self {
+        return RenderTargets {
+            settings,
+            format,
+            surface_format,
+            size: (0, 0),
+            scene: None,
+            resolved: None,
+            ldr: None,
+            tone_map: ToneMapPass::new(device, shaders, pipelines, surface_format),
+            fxaa: settings.uses_fxaa().then(|| Fxaa::new(device, shaders, pipelines, surface_format)),
+        };
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        return &self.settings;
+    }
+
+    /// Colour format renderables draw in.
+    pub fn format(&self) -> wgpu::TextureFormat {
+        return self.format;
+    }
+
+    /// Follow the surface size.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if self.size == (width, height) && self.scene.is_some() {
+            return;
+        }
+        let size = (width, height);
+        let samples = self.settings.msaa_samples;
+        self.size = size;
+        let scene = color_target(device, "sceneTarget", size, samples, self.format);
+        self.resolved = (samples > 1).then(|| color_target(device, "sceneResolved", size, 1, self.format));
+        self.tone_map.bind(device, self.resolved.as_ref().unwrap_or(&scene));
+        self.scene = Some(scene);
+        if let Some(fxaa) = &mut self.fxaa {
+            let ldr = color_target(device, "toneMapped", size, 1, self.surface_format);
+            fxaa.bind(device, &ldr);
+            self.ldr = Some(ldr);
+        }
+    }
+
+    /// The view renderables draw into; None until the first resize.
+    pub fn scene_view(&self) -> Option<&wgpu::TextureView> {
+        return self.scene.as_ref();
+    }
+
+    /// Get the scene onto the surface: resolve the samples, tone map, then run FXAA.
+    pub fn finish(
+        &self,
+        queue: &wgpu::Queue,
+        pipelines: &PipelineCache,
+        profiler: &Profiler,
+        encoder: &mut wgpu::CommandEncoder,
+        surface_view: &wgpu::TextureView,
+        tone: &ToneMapping,
+    ) {
+        let Some(scene) = &self.scene else {
+            return;
+        };
+        if let Some(resolved) = &self.resolved {
+            // A pass without draws still resolves at the end.
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("msaaResolvePass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: scene,
+                    resolve_target: Some(resolved),
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Discard },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: profiler.timestamp_writes(),
+            });
+        }
+
+        let uniforms = ToneMapUniforms {
+            curve: tone.operator as u32,
+            exposure: tone.exposure,
+            encode_srgb: !self.surface_format.is_srgb() as u32,
+            pad: 0,
+        };
+        queue.write_buffer(&self.tone_map.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        let tone_mapped = self.ldr.as_ref().unwrap_or(surface_view);
+        if let Some(bind_group) = &self.tone_map.bind_group {
+            fullscreen_pass(encoder, "toneMapPass", tone_mapped, &pipelines.get(&self.tone_map.pipeline), bind_group, profiler.timestamp_writes());
+        }
+        if let Some(fxaa) = &self.fxaa
+            && let Some(bind_group) = &fxaa.bind_group
+        {
+            fullscreen_pass(encoder, "fxaaPass", surface_view, &pipelines.get(&fxaa.pipeline), bind_group, profiler.timestamp_writes());
+        }
+    }
+}
+
+impl ToneMapPass {
+    fn new(device: &wgpu::Device, shaders: &ShaderRegistry, pipelines: &PipelineCache, format: wgpu::TextureFormat) -> Self {
+        let bgl = pipelines.get_or_create_bgl(device, &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ]);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("toneMapUniforms"),
+            size: std::mem::size_of::<ToneMapUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pipeline = fullscreen_pipeline(device, shaders, pipelines, "toneMapPipeline", "tonemap.wgsl", &bgl, format);
+        return ToneMapPass { pipeline, bgl, uniform_buffer, bind_group: None };
+    }
+
+    fn bind(&mut self, device: &wgpu::Device, view: &wgpu::TextureView) {
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("toneMapBg"),
+            layout: &self.bgl,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(view) },
+                wgpu::BindGroupEntry { binding: 1, resource: self.uniform_buffer.as_entire_binding() },
+            ],
+        }));
+    }
+}
+
+impl Fxaa {
+    fn new(device: &wgpu::Device, shaders: &ShaderRegistry, pipelines: &PipelineCache, format: wgpu::TextureFormat) -> Self {
+        let bgl = pipelines.get_or_create_bgl(device, &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ]);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("fxaaSampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let pipeline = fullscreen_pipeline(device, shaders, pipelines, "fxaaPipeline", "fxaa.wgsl", &bgl, format);
+        return Fxaa { pipeline, bgl, sampler, bind_group: None };
+    }
+
+    fn bind(&mut self, device: &wgpu::Device, view: &wgpu::TextureView) {
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("fxaaBg"),
+            layout: &self.bgl,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(view) },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+            ],
+        }));
+    }
+}
+
+#[test]
+fn settings_fall_back_to_what_is_supported() {
+    let asked = RenderSettings { msaa_samples: 8, fxaa: true, present_mode: PresentMode::Fifo };
+    assert_eq!(asked.validated(&[1, 2, 4, 8]).msaa_samples, 8);
+    assert_eq!(asked.validated(&[1, 4]).msaa_samples, 4);
+    assert!(!asked.validated(&[1, 4]).uses_fxaa());
+
+    // No MSAA at all: FXAA takes over, unless it is turned off too.
+    let fallback = asked.validated(&[1]);
+    assert_eq!(fallback.msaa_samples, 1);
+    assert!(fallback.uses_fxaa());
+    assert!(!RenderSettings { fxaa: false, ..asked }.validated(&[1]).uses_fxaa());
+    assert_eq!(RenderSettings { msaa_samples: 0, fxaa: false, present_mode: PresentMode::Fifo }.validated(&[]).msaa_samples, 1);
+    assert_eq!(asked.validated(&[1, 4]).multisample().count, 4);
+}
+
+#[test]
+fn post_passes_come_from_the_pipeline_cache() {
+    let (device, _) = wgpu::Device::noop(&Default::default());
+    let (shaders, pipelines) = (ShaderRegistry::new(), PipelineCache::default());
+    let settings = RenderSettings { msaa_samples: 1, fxaa: true, present_mode: PresentMode::Fifo };
+    let targets = RenderTargets::new(&device, &shaders, &pipelines, wgpu::TextureFormat::Bgra8UnormSrgb, HDR_FORMAT, settings);
+    assert_eq!(pipelines.len(), (2, 2));
+
+    // They keep drawing into the surface whatever the scene target is.
+    pipelines.refresh(&device, &shaders, TargetState { format: HDR_FORMAT, samples: 4 });
+    assert_eq!(pipelines.len(), (2, 2));
+    drop(targets);
+    pipelines.refresh(&device, &shaders, TargetState { format: HDR_FORMAT, samples: 4 });
+    assert_eq!(pipelines.len(), (0, 0));
+}
@@ -0,0 +1,104 @@
+//! Mapping the HDR scene to display colours. The curves here are the CPU twins of the ones in
+//! `tonemap.wgsl`; keep the two in step.
+
+/// The curve that brings scene radiance into [0, 1]. Defaults to `Clamp`, which leaves LDR
+/// content (imagery, labels, overlays) as authored; the curves darken it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    /// Clip at 1, i.e. what drawing straight to the surface did.
+    #[default]
+    Clamp,
+    /// `x / (1 + x)`.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+}
+
+impl ToneMapOperator {
+    pub const ALL: [ToneMapOperator; 3] = [ToneMapOperator::Clamp, ToneMapOperator::Reinhard, ToneMapOperator::Aces];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ToneMapOperator::Clamp => "clamp",
+            ToneMapOperator::Reinhard => "Reinhard",
+            ToneMapOperator::Aces => "ACES",
+        };
+    }
+
+    /// Map one linear channel value.
+    pub fn apply(&self, x: f32) -> f32 {
+        let x = x.max(0.);
+        let y = match self {
+            ToneMapOperator::Clamp => x,
+            ToneMapOperator::Reinhard => x / (1. + x),
+            ToneMapOperator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        };
+        return y.clamp(0., 1.);
+    }
+}
+
+/// How the scene is brought to the screen; changeable every frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Multiplies scene colours before the curve.
+    pub exposure: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        return ToneMapping { operator: Default::default(), exposure: 1. };
+    }
+}
+
+impl ToneMapping {
+    /// Display colour (linear, in [0, 1]) for a linear scene colour.
+    pub fn map(&self, rgb: [f32; 3]) -> [f32; 3] {
+        return rgb.map(|c| self.operator.apply(c * self.exposure));
+    }
+}
+
+/// The sRGB transfer function, for surfaces that don't encode by themselves.
+pub fn linear_to_srgb(x: f32) -> f32 {
+    let x = x.clamp(0., 1.);
+    if x <= 0.003_130_8 {
+        return 12.92 * x;
+    }
+    return 1.055 * x.powf(1. / 2.4) - 0.055;
+}
+
+#[test]
+fn curves_are_monotonic_and_bounded() {
+    for op in ToneMapOperator::ALL {
+        assert_eq!(op.apply(0.), 0., "{op:?}");
+        assert_eq!(op.apply(-1.), 0., "{op:?}");
+        let mut last = 0.;
+        for i in 1..1000 {
+            let y = op.apply(i as f32 * 0.02);
+            assert!(y >= last && y <= 1., "{op:?} at {i}");
+            last = y;
+        }
+    }
+    assert_eq!(ToneMapOperator::Clamp.apply(0.5), 0.5);
+    assert_eq!(ToneMapOperator::Clamp.apply(3.), 1.);
+    assert_eq!(ToneMapOperator::Reinhard.apply(1.), 0.5);
+    // ACES keeps a little contrast at the top but still rolls off: bright values don't clip
+    // right away.
+    assert!(ToneMapOperator::Aces.apply(1.) > 0.75 && ToneMapOperator::Aces.apply(1.) < 0.85);
+    assert!(ToneMapOperator::Aces.apply(4.) < 1.);
+    assert!(ToneMapOperator::Reinhard.apply(100.) < 1.);
+}
+
+#[test]
+fn exposure_and_srgb_encoding() {
+    // By default LDR colours come through unchanged.
+    assert_eq!(ToneMapping::default().map([0.2, 0.5, 1.]), [0.2, 0.5, 1.]);
+    let half = ToneMapping { operator: ToneMapOperator::Reinhard, exposure: 0.5 };
+    assert_eq!(half.map([2., 0., 4.]), [0.5, 0., 2. / 3.]);
+    assert_eq!(linear_to_srgb(0.), 0.);
+    assert!((linear_to_srgb(1.) - 1.).abs() < 1e-6);
+    // Middle grey (18%) sits a bit below the middle of the sRGB range.
+    assert!((linear_to_srgb(0.18) - 0.461).abs() < 1e-3);
+    // Continuous where the linear and power segments meet.
+    assert!((linear_to_srgb(0.003_130_8) - linear_to_srgb(0.003_130_9)).abs() < 1e-5);
+}
@@ -0,0 +1,50 @@
+// HDR scene to display colours. The curves match `tonemap.rs`.
+
+struct ToneMapUniforms {
+    // 0: clamp, 1: Reinhard, 2: ACES.
+    curve: u32,
+    exposure: f32,
+    // 1 if the target isn't sRGB, so the shader has to encode.
+    encode_srgb: u32,
+    pad: u32,
+};
+
+@group(0) @binding(0)
+var scene_tex: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> u: ToneMapUniforms;
+
+// One triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+fn tone_map(c: vec3<f32>) -> vec3<f32> {
+    let x = max(c, vec3<f32>(0.0));
+    var y = x;
+    if (u.curve == 1u) {
+        y = x / (1.0 + x);
+    } else if (u.curve == 2u) {
+        y = (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+    }
+    return clamp(y, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    let low = 12.92 * c;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(@builtin(position) frag: vec4<f32>) -> @location(0) vec4<f32> {
+    // Same size as the target, so read texels directly.
+    let hdr = textureLoad(scene_tex, vec2<i32>(frag.xy), 0);
+    var rgb = tone_map(hdr.rgb * u.exposure);
+    if (u.encode_srgb == 1u) {
+        rgb = linear_to_srgb(rgb);
+    }
+    return vec4<f32>(rgb, 1.0);
+}
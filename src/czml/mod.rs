@@ -0,0 +1,448 @@
+//! A reader for the subset of CZML (https://github.com/AnalyticalGraphicsInc/czml-writer/wiki)
+//! that maps onto wglobe: the document clock and entities with `availability`, `position`,
+//! `point`, `polyline`, `label` and `billboard`.
+//!
+//! Packets with the same `id` are merged, later properties replacing earlier ones. Unsupported
+//! properties are ignored.
+
+use std::collections::HashMap;
+
+use nalgebra::Vector3;
+use serde_json::Value;
+
+use crate::core::clock::parse_iso8601;
+use crate::core::{Clock, Geodetic};
+use crate::tracks::{Interpolation, Track, TrackSample};
+
+pub type Color = [f32; 4];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CzmlClock {
+    pub start: f64,
+    pub stop: f64,
+    pub current: f64,
+    pub multiplier: f64,
+}
+
+impl CzmlClock {
+    pub fn to_clock(&self) -> Clock {
+        let mut c = Clock::new(self.start, self.stop);
+        c.current = self.current;
+        c.rate = self.multiplier;
+        return c;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Position {
+    Constant(Vector3<f64>),
+    Sampled { track: Track, interpolation: Interpolation },
+}
+
+impl Position {
+    pub fn at(&self, t: f64) -> Option<Vector3<f64>> {
+        return match self {
+            Position::Constant(p) => Some(*p),
+            Position::Sampled { track, interpolation } => track.position_at(t, *interpolation).map(|(p, _)| p),
+        };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Point {
+    pub color: Color,
+    pub pixel_size: f32,
+    pub show: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Polyline {
+    /// ECEF meters.
+    pub positions: Vec<Vector3<f64>>,
+    pub color: Color,
+    pub width: f32,
+    pub show: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub text: String,
+    pub fill_color: Color,
+    pub show: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Billboard {
+    /// URI or data URI of the image.
+    pub image: String,
+    pub scale: f32,
+    pub color: Color,
+    pub show: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Entity {
+    pub id: String,
+    pub name: Option<String>,
+    /// Unions of the availability intervals, unix seconds. Empty means always available.
+    pub availability: Vec<(f64, f64)>,
+    pub position: Option<Position>,
+    pub point: Option<Point>,
+    pub polyline: Option<Polyline>,
+    pub label: Option<Label>,
+    pub billboard: Option<Billboard>,
+}
+
+impl Entity {
+    pub fn is_available(&self, t: f64) -> bool {
+        return self.availability.is_empty() || self.availability.iter().any(|&(a, b)| t >= a && t <= b);
+    }
+
+    pub fn position_at(&self, t: f64) -> Option<Vector3<f64>> {
+        if !self.is_available(t) {
+            return None;
+        }
+        return self.position.as_ref()?.at(t);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Document {
+    pub name: Option<String>,
+    pub clock: Option<CzmlClock>,
+    /// In order of first appearance.
+    pub entities: Vec<Entity>,
+}
+
+impl Document {
+    /// The span covered by the document clock, or else by entity availability and samples.
+    pub fn time_range(&self) -> Option<(f64, f64)> {
+        if let Some(c) = &self.clock {
+            return Some((c.start, c.stop));
+        }
+        return self
+            .entities
+            .iter()
+            .flat_map(|e| {
+                let sampled = match &e.position {
+                    Some(Position::Sampled { track, .. }) => track.time_range(),
+                    _ => None,
+                };
+                e.availability.iter().copied().chain(sampled)
+            })
+            .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)));
+    }
+
+    pub fn entity(&self, id: &str) -> Option<&Entity> {
+        return self.entities.iter().find(|e| e.id == id);
+    }
+}
+
+fn parse_interval(s: &str) -> anyhow::Result<(f64, f64)> {
+    let (a, b) = s.split_once('/').ok_or_else(|| anyhow::anyhow!("bad CZML interval '{s}'"))?;
+    return Ok((parse_iso8601(a)?, parse_iso8601(b)?));
+}
+
+fn numbers(v: &Value) -> anyhow::Result<Vec<f64>> {
+    let arr = v.as_array().ok_or_else(|| anyhow::anyhow!("expected an array, got {v}"))?;
+    return arr
+        .iter()
+        .map(|x| x.as_f64().ok_or_else(|| anyhow::anyhow!("expected a number, got {x}")))
+        .collect();
+}
+
+fn parse_color(v: &Value) -> anyhow::Result<Color> {
+    // Colors can be wrapped as {"color": {...}} or {"solidColor": {"color": {...}}}.
+    if let Some(inner) = v.get("solidColor").or_else(|| v.get("color")) {
+        return parse_color(inner);
+    }
+    if let Some(rgba) = v.get("rgba") {
+        let c = numbers(rgba)?;
+        anyhow::ensure!(c.len() == 4, "rgba needs 4 components");
+        return Ok([c[0] as f32 / 255., c[1] as f32 / 255., c[2] as f32 / 255., c[3] as f32 / 255.]);
+    }
+    if let Some(rgbaf) = v.get("rgbaf") {
+        let c = numbers(rgbaf)?;
+        anyhow::ensure!(c.len() == 4, "rgbaf needs 4 components");
+        return Ok([c[0] as f32, c[1] as f32, c[2] as f32, c[3] as f32]);
+    }
+    anyhow::bail!("unsupported CZML color {v}");
+}
+
+fn bool_or(v: &Value, key: &str, default: bool) -> bool {
+    return v.get(key).and_then(Value::as_bool).unwrap_or(default);
+}
+
+fn f32_or(v: &Value, key: &str, default: f32) -> f32 {
+    return v.get(key).and_then(Value::as_f64).map(|x| x as f32).unwrap_or(default);
+}
+
+fn color_or(v: &Value, key: &str, default: Color) -> anyhow::Result<Color> {
+    return v.get(key).map(parse_color).unwrap_or(Ok(default));
+}
+
+type ToEcef = fn(&[f64]) -> Vector3<f64>;
+
+// A list of (optional time, ECEF point) from one of the cartesian/cartographic encodings. Sampled
+// values have a leading time per point, either seconds from `epoch` or an ISO 8601 string.
+fn parse_points(v: &Value, sampled: bool, epoch: Option<f64>) -> anyhow::Result<Vec<(f64, Vector3<f64>)>> {
+    let (key, to_ecef): (&str, ToEcef) = if v.get("cartesian").is_some() {
+        ("cartesian", |c| Vector3::new(c[0], c[1], c[2]))
+    } else if v.get("cartographicDegrees").is_some() {
+        ("cartographicDegrees", |c| Geodetic::new(c[1], c[0], c[2]).to_ecef())
+    } else if v.get("cartographicRadians").is_some() {
+        ("cartographicRadians", |c| Geodetic::new(c[1].to_degrees(), c[0].to_degrees(), c[2]).to_ecef())
+    } else {
+        anyhow::bail!("unsupported CZML position {v}");
+    };
+
+    let arr = v[key].as_array().ok_or_else(|| anyhow::anyhow!("{key} must be an array"))?;
+    let stride = if sampled { 4 } else { 3 };
+    anyhow::ensure!(arr.len() % stride == 0, "{key} has {} values, not a multiple of {stride}", arr.len());
+
+    let mut out = vec![];
+    for chunk in arr.chunks(stride) {
+        let t = if sampled {
+            match &chunk[0] {
+                Value::String(s) => parse_iso8601(s)?,
+                x => {
+                    let epoch = epoch.ok_or_else(|| anyhow::anyhow!("sampled {key} without epoch"))?;
+                    epoch + x.as_f64().ok_or_else(|| anyhow::anyhow!("bad sample time {x}"))?
+                }
+            }
+        } else {
+            0.
+        };
+        let c = numbers(&Value::Array(chunk[stride - 3..].to_vec()))?;
+        out.push((t, to_ecef(&c)));
+    }
+    return Ok(out);
+}
+
+fn parse_position(v: &Value) -> anyhow::Result<Position> {
+    let epoch = v.get("epoch").and_then(Value::as_str).map(parse_iso8601).transpose()?;
+    let key = ["cartesian", "cartographicDegrees", "cartographicRadians"]
+        .into_iter()
+        .find(|k| v.get(k).is_some())
+        .ok_or_else(|| anyhow::anyhow!("unsupported CZML position {v}"))?;
+    let sampled = v[key].as_array().is_some_and(|a| a.len() > 3);
+
+    if !sampled {
+        return Ok(Position::Constant(parse_points(v, false, None)?[0].1));
+    }
+
+    let samples = parse_points(v, true, epoch)?
+        .into_iter()
+        .map(|(t, position)| TrackSample { t, position, orientation: None })
+        .collect();
+    let degree = v.get("interpolationDegree").and_then(Value::as_u64).unwrap_or(1) as usize;
+    let interpolation = match v.get("interpolationAlgorithm").and_then(Value::as_str).unwrap_or("LINEAR") {
+        "LAGRANGE" => Interpolation::Lagrange(degree),
+        "HERMITE" => Interpolation::Hermite,
+        _ => Interpolation::Linear,
+    };
+    return Ok(Position::Sampled { track: Track::new("", samples), interpolation });
+}
+
+fn merge_packet(e: &mut Entity, p: &Value) -> anyhow::Result<()> {
+    if let Some(n) = p.get("name").and_then(Value::as_str) {
+        e.name = Some(n.to_string());
+    }
+
+    match p.get("availability") {
+        Some(Value::String(s)) => e.availability = vec![parse_interval(s)?],
+        Some(Value::Array(a)) => {
+            e.availability = a
+                .iter()
+                .map(|s| parse_interval(s.as_str().unwrap_or("")))
+                .collect::<anyhow::Result<_>>()?
+        }
+        _ => {}
+    }
+
+    if let Some(pos) = p.get("position") {
+        match pos {
+            Value::Array(_) => log::warn!("CZML: interval-tagged position on '{}' is not supported", e.id),
+            _ => e.position = Some(parse_position(pos)?),
+        }
+    }
+
+    if let Some(v) = p.get("point") {
+        e.point = Some(Point {
+            color: color_or(v, "color", [1., 1., 1., 1.])?,
+            pixel_size: f32_or(v, "pixelSize", 1.),
+            show: bool_or(v, "show", true),
+        });
+    }
+
+    if let Some(v) = p.get("polyline") {
+        let positions = match v.get("positions") {
+            Some(ps) => parse_points(ps, false, None)?.into_iter().map(|(_, p)| p).collect(),
+            None => vec![],
+        };
+        e.polyline = Some(Polyline {
+            positions,
+            color: color_or(v, "material", [1., 1., 1., 1.])?,
+            width: f32_or(v, "width", 1.),
+            show: bool_or(v, "show", true),
+        });
+    }
+
+    if let Some(v) = p.get("label") {
+        let text = match v.get("text") {
+            Some(Value::String(s)) => s.clone(),
+            Some(t) => t.get("string").and_then(Value::as_str).unwrap_or("").to_string(),
+            None => String::new(),
+        };
+        e.label = Some(Label {
+            text,
+            fill_color: color_or(v, "fillColor", [1., 1., 1., 1.])?,
+            show: bool_or(v, "show", true),
+        });
+    }
+
+    if let Some(v) = p.get("billboard") {
+        let image = match v.get("image") {
+            Some(Value::String(s)) => s.clone(),
+            Some(i) => i.get("uri").and_then(Value::as_str).unwrap_or("").to_string(),
+            None => String::new(),
+        };
+        e.billboard = Some(Billboard {
+            image,
+            scale: f32_or(v, "scale", 1.),
+            color: color_or(v, "color", [1., 1., 1., 1.])?,
+            show: bool_or(v, "show", true),
+        });
+    }
+
+    return Ok(());
+}
+
+pub fn parse_czml(text: &str) -> anyhow::Result<Document> {
+    let v: Value = serde_json::from_str(text)?;
+    let packets = v.as_array().ok_or_else(|| anyhow::anyhow!("a CZML document is a JSON array of packets"))?;
+
+    let mut doc = Document::default();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for (i, p) in packets.iter().enumerate() {
+        let id = p.get("id").and_then(Value::as_str).map(str::to_string).unwrap_or_else(|| format!("packet{i}"));
+
+        if id == "document" {
+            doc.name = p.get("name").and_then(Value::as_str).map(str::to_string);
+            if let Some(c) = p.get("clock") {
+                let (start, stop) = match c.get("interval").and_then(Value::as_str) {
+                    Some(s) => parse_interval(s)?,
+                    None => anyhow::bail!("CZML document clock without interval"),
+                };
+                let current = c.get("currentTime").and_then(Value::as_str).map(parse_iso8601).transpose()?;
+                doc.clock = Some(CzmlClock {
+                    start,
+                    stop,
+                    current: current.unwrap_or(start),
+                    multiplier: c.get("multiplier").and_then(Value::as_f64).unwrap_or(1.),
+                });
+            }
+            continue;
+        }
+
+        if p.get("delete").and_then(Value::as_bool) == Some(true) {
+            if let Some(j) = index.remove(&id) {
+                doc.entities.remove(j);
+                index.values_mut().filter(|k| **k > j).for_each(|k| *k -= 1);
+            }
+            continue;
+        }
+
+        let j = *index.entry(id.clone()).or_insert_with(|| {
+            doc.entities.push(Entity { id: id.clone(), ..Default::default() });
+            doc.entities.len() - 1
+        });
+        merge_packet(&mut doc.entities[j], p).map_err(|e| anyhow::anyhow!("CZML packet '{id}': {e}"))?;
+    }
+
+    return Ok(doc);
+}
+
+#[cfg(test)]
+const FIXTURE: &str = r#"[
+  {"id": "document", "name": "fixture", "version": "1.0",
+   "clock": {"interval": "2012-03-15T10:00:00Z/2012-03-15T11:00:00Z",
+             "currentTime": "2012-03-15T10:30:00Z", "multiplier": 60, "range": "LOOP_STOP"}},
+  {"id": "station", "name": "Ground station",
+   "position": {"cartographicDegrees": [-75.0, 40.0, 100.0]},
+   "point": {"color": {"rgba": [255, 0, 0, 255]}, "pixelSize": 8},
+   "label": {"text": "GS-1", "fillColor": {"rgbaf": [0, 1, 0, 1]}}},
+  {"id": "plane", "availability": "2012-03-15T10:00:00Z/2012-03-15T10:20:00Z",
+   "position": {"epoch": "2012-03-15T10:00:00Z", "interpolationAlgorithm": "LAGRANGE", "interpolationDegree": 2,
+                "cartesian": [0, 7000000, 0, 0,  600, 0, 7000000, 0,  1200, -7000000, 0, 0]},
+   "billboard": {"image": "plane.png", "scale": 2}},
+  {"id": "route", "polyline": {"positions": {"cartographicDegrees": [0, 0, 0, 10, 0, 0, 10, 10, 0]},
+   "material": {"solidColor": {"color": {"rgba": [0, 0, 255, 128]}}}, "width": 3}},
+  {"id": "station", "point": {"pixelSize": 12}},
+  {"id": "gone", "point": {}},
+  {"id": "gone", "delete": true}
+]"#;
+
+#[test]
+fn czml_document_clock() {
+    let doc = parse_czml(FIXTURE).unwrap();
+    assert_eq!(doc.name.as_deref(), Some("fixture"));
+    let c = doc.clock.as_ref().unwrap();
+    assert_eq!(c.stop - c.start, 3600.);
+    assert_eq!(c.current - c.start, 1800.);
+    assert_eq!(c.to_clock().rate, 60.);
+    assert_eq!(doc.time_range(), Some((c.start, c.stop)));
+}
+
+#[test]
+fn czml_entities_and_merging() {
+    let doc = parse_czml(FIXTURE).unwrap();
+    let ids: Vec<&str> = doc.entities.iter().map(|e| e.id.as_str()).collect();
+    assert_eq!(ids, vec!["station", "plane", "route"]);
+
+    let st = doc.entity("station").unwrap();
+    assert_eq!(st.name.as_deref(), Some("Ground station"));
+    // The second packet replaced the point wholesale.
+    assert_eq!(st.point.as_ref().unwrap().pixel_size, 12.);
+    assert_eq!(st.label.as_ref().unwrap().text, "GS-1");
+    assert_eq!(st.label.as_ref().unwrap().fill_color, [0., 1., 0., 1.]);
+    let g = Geodetic::from_ecef(&st.position_at(0.).unwrap());
+    assert!((g.lat - 40.).abs() < 1e-9 && (g.lon + 75.).abs() < 1e-9 && (g.height - 100.).abs() < 1e-6);
+
+    let route = doc.entity("route").unwrap().polyline.as_ref().unwrap();
+    assert_eq!(route.positions.len(), 3);
+    assert_eq!(route.width, 3.);
+    assert!((route.color[3] - 128. / 255.).abs() < 1e-6);
+
+    assert_eq!(doc.entity("plane").unwrap().billboard.as_ref().unwrap().image, "plane.png");
+}
+
+#[test]
+fn czml_sampled_position_and_availability() {
+    let doc = parse_czml(FIXTURE).unwrap();
+    let t0 = parse_iso8601("2012-03-15T10:00:00Z").unwrap();
+    let plane = doc.entity("plane").unwrap();
+
+    let Some(Position::Sampled { interpolation, .. }) = &plane.position else {
+        panic!("expected sampled position");
+    };
+    assert_eq!(*interpolation, Interpolation::Lagrange(2));
+
+    let p = plane.position_at(t0 + 600.).unwrap();
+    assert!((p - Vector3::new(0., 7e6, 0.)).norm() < 1e-6);
+    // Through the three samples x is linear and y is a parabola.
+    let p = plane.position_at(t0 + 300.).unwrap();
+    assert!((p.x - 3.5e6).abs() < 1e-3 && (p.y - 5.25e6).abs() < 1e-3, "{p:?}");
+
+    // Outside availability even though the samples go on to 10:20.
+    assert!(plane.position_at(t0 + 1201.).is_none());
+    assert!(plane.is_available(t0 + 1200.));
+}
+
+#[test]
+fn czml_errors() {
+    assert!(parse_czml("{}").is_err());
+    assert!(parse_czml(r#"[{"id": "x", "position": {"cartesian": [1, 2]}}]"#).is_err());
+    assert!(parse_czml(r#"[{"id": "x", "position": {"cartesian": [0, 1, 2, 3]}}]"#).is_err());
+}
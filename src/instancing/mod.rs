@@ -0,0 +1,209 @@
+use std::ops::Range;
+
+use nalgebra::{Matrix4, Vector3};
+
+use crate::models::Primitive;
+
+/// One copy of an instanced mesh, laid out as the instanced mesh shader reads it.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Instance {
+    /// Rows of the affine transform from mesh space to meters from the mesh's centre, along the
+    /// ECEF axes.
+    pub transform: [[f32; 4]; 3],
+    /// Multiplies the mesh colour; alpha 0 hides the instance.
+    pub color: [u8; 4],
+    /// Caller's id for the object, e.g. for picking. Not used for drawing.
+    pub id: u32,
+}
+
+impl Instance {
+    /// An instance placed by a mesh space to ECEF transform (e.g. `renderables::placement`),
+    /// relative to `center`. The subtraction happens in f64, so instances keep millimeter
+    /// precision even though the globe is millions of meters across.
+    pub fn from_placement(center: &Vector3<f64>, placement: &Matrix4<f64>, color: [u8; 4], id: u32) -> Self {
+        let mut m = *placement;
+        for r in 0..3 {
+            m[(r, 3)] -= center[r];
+        }
+        let m = m.cast::<f32>();
+        let row = |r: usize| [m[(r, 0)], m[(r, 1)], m[(r, 2)], m[(r, 3)]];
+        return Instance { transform: [row(0), row(1), row(2)], color, id };
+    }
+
+    /// Where the instance's origin is, in meters from the centre.
+    pub fn offset(&self) -> Vector3<f32> {
+        return Vector3::new(self.transform[0][3], self.transform[1][3], self.transform[2][3]);
+    }
+}
+
+/// CPU copy of an instanced mesh's instances, remembering which ones changed so only those are
+/// uploaded.
+#[derive(Clone, Debug, Default)]
+pub struct InstanceSet {
+    instances: Vec<Instance>,
+    dirty: Option<Range<usize>>,
+}
+
+impl InstanceSet {
+    pub fn new(instances: Vec<Instance>) -> Self {
+        let dirty = (!instances.is_empty()).then_some(0..instances.len());
+        return InstanceSet { instances, dirty };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.instances.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.instances.is_empty();
+    }
+
+    pub fn as_slice(&self) -> &[Instance] {
+        return &self.instances;
+    }
+
+    pub fn get(&self, i: usize) -> Option<&Instance> {
+        return self.instances.get(i);
+    }
+
+    fn mark(&mut self, range: Range<usize>) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(d) => d.start.min(range.start)..d.end.max(range.end),
+            None => range,
+        });
+    }
+
+    pub fn set(&mut self, i: usize, instance: Instance) {
+        self.instances[i] = instance;
+        self.mark(i..i + 1);
+    }
+
+    /// Overwrite `instances.len()` instances starting at `start`, growing the set if needed.
+    pub fn set_range(&mut self, start: usize, instances: &[Instance]) {
+        let end = start + instances.len();
+        assert!(start <= self.instances.len(), "set_range starts past the end");
+        if end > self.instances.len() {
+            self.instances.resize(end, Instance::default());
+        }
+        self.instances[start..end].copy_from_slice(instances);
+        self.mark(start..end);
+    }
+
+    /// Add an instance, returning its index.
+    pub fn push(&mut self, instance: Instance) -> usize {
+        self.instances.push(instance);
+        let i = self.instances.len() - 1;
+        self.mark(i..i + 1);
+        return i;
+    }
+
+    /// Remove an instance by moving the last one into its place.
+    pub fn swap_remove(&mut self, i: usize) -> Instance {
+        let removed = self.instances.swap_remove(i);
+        if i < self.instances.len() {
+            self.mark(i..i + 1);
+        }
+        return removed;
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.dirty = None;
+    }
+
+    /// The instances changed since the last call, clipped to the current length.
+    pub fn take_dirty(&mut self) -> Option<Range<usize>> {
+        let len = self.instances.len();
+        return self.dirty.take().map(|d| d.start.min(len)..d.end.min(len)).filter(|d| !d.is_empty());
+    }
+}
+
+/// An axis aligned box centred on the origin with flat normals, `size` meters along x, y and z.
+pub fn box_mesh(size: [f32; 3]) -> Primitive {
+    let h = [size[0] / 2., size[1] / 2., size[2] / 2.];
+    let mut mesh = Primitive::default();
+    for axis in 0..3 {
+        for sign in [-1f32, 1.] {
+            let mut normal = [0.; 3];
+            normal[axis] = sign;
+            // Two directions spanning the face, ordered so the winding is counter-clockwise seen
+            // from outside.
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let (u, v) = if sign > 0. { (u, v) } else { (v, u) };
+            let base = mesh.positions.len() as u32;
+            for (su, sv) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)] {
+                let mut p = [0.; 3];
+                p[axis] = sign * h[axis];
+                p[u] = su * h[u];
+                p[v] = sv * h[v];
+                mesh.positions.push(p);
+                mesh.normals.push(normal);
+                mesh.uvs.push([(su + 1.) / 2., (sv + 1.) / 2.]);
+            }
+            mesh.indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+    }
+    return mesh;
+}
+
+#[test]
+fn instance_set_tracks_changes() {
+    let at = |x: f32| Instance { transform: [[1., 0., 0., x], [0., 1., 0., 0.], [0., 0., 1., 0.]], ..Default::default() };
+    let mut set = InstanceSet::new((0..10).map(|i| at(i as f32)).collect());
+    assert_eq!(set.take_dirty(), Some(0..10));
+    assert_eq!(set.take_dirty(), None);
+
+    set.set(7, at(70.));
+    set.set(3, at(30.));
+    assert_eq!(set.take_dirty(), Some(3..8));
+
+    set.set_range(9, &[at(90.), at(100.), at(110.)]);
+    assert_eq!(set.len(), 12);
+    assert_eq!(set.push(at(120.)), 12);
+    assert_eq!(set.take_dirty(), Some(9..13));
+
+    // Removing moves the last instance into the hole; removing the last one leaves nothing to
+    // upload.
+    assert_eq!(set.swap_remove(2).offset().x, 2.);
+    assert_eq!(set.get(2).unwrap().offset().x, 120.);
+    assert_eq!(set.take_dirty(), Some(2..3));
+    set.swap_remove(set.len() - 1);
+    assert_eq!(set.take_dirty(), None);
+
+    // Changes past a later truncation are dropped.
+    set.set(10, at(0.));
+    set.swap_remove(10);
+    set.swap_remove(9);
+    assert_eq!(set.len(), 9);
+    assert_eq!(set.take_dirty(), None);
+}
+
+#[test]
+fn instances_keep_precision_far_from_origin() {
+    use crate::core::Geodetic;
+    let center = Geodetic::new(48.85, 2.35, 0.).to_ecef();
+    let spot = center + Vector3::new(0.1234, -0.5678, 1000.0009);
+    let placement = Matrix4::new_translation(&spot) * Matrix4::new_scaling(2.);
+    let inst = Instance::from_placement(&center, &placement, [255; 4], 7);
+    let off = inst.offset().cast::<f64>();
+    assert!((off - (spot - center)).norm() < 1e-4, "{off}");
+    assert_eq!(inst.transform[0][0], 2.);
+    assert_eq!(std::mem::size_of::<Instance>(), 56);
+}
+
+#[test]
+fn box_faces_point_outwards() {
+    let mesh = box_mesh([2., 4., 6.]);
+    assert_eq!((mesh.positions.len(), mesh.indices.len()), (24, 36));
+    for tri in mesh.indices.chunks(3) {
+        let p = |i: u32| Vector3::from(mesh.positions[i as usize]);
+        let (a, b, c) = (p(tri[0]), p(tri[1]), p(tri[2]));
+        let n = (b - a).cross(&(c - a));
+        let centre = (a + b + c) / 3.;
+        assert!(n.dot(&centre) > 0., "triangle {tri:?} faces in");
+        assert!(n.normalize().dot(&Vector3::from(mesh.normals[tri[0] as usize])) > 0.99);
+    }
+    let max = mesh.positions.iter().map(|p| p[2]).fold(f32::MIN, f32::max);
+    assert_eq!(max, 3.);
+}
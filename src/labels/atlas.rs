@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+
+use crate::billboards::ShelfPacker;
+
+/// Size glyphs are rasterised at. Labels of other sizes scale the distance field.
+pub const BASE_SIZE: f32 = 32.;
+/// How far (in atlas pixels) the distance field reaches outside and inside the outlines.
+pub const SPREAD: usize = 4;
+
+/// Where a glyph is in the atlas and how to place it, in pixels at `BASE_SIZE`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GlyphInfo {
+    /// Top-left corner and size of the glyph's cell in the atlas, 0 sized for blank glyphs.
+    pub atlas_pos: [u32; 2],
+    pub atlas_size: [u32; 2],
+    /// Offset of the cell's top-left corner from the pen position on the baseline (y down).
+    pub bearing: [f32; 2],
+    pub advance: f32,
+}
+
+/// A single channel signed distance field atlas of glyphs from one font, filled on demand with
+/// shelf packing. 0.5 is on the outline, larger is inside.
+pub struct GlyphAtlas {
+    font: FontRef<'static>,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    glyphs: HashMap<char, GlyphInfo>,
+    packer: ShelfPacker,
+    /// Set when glyphs were added since the last `take_dirty`.
+    dirty: bool,
+    full: bool,
+}
+
+impl GlyphAtlas {
+    /// An atlas for the sans-serif font egui ships with.
+    pub fn new(width: u32, height: u32) -> Self {
+        let font = FontRef::try_from_slice(epaint_default_fonts::UBUNTU_LIGHT).expect("bundled font is valid");
+        return Self::with_font(font, width, height);
+    }
+
+    pub fn with_font(font: FontRef<'static>, width: u32, height: u32) -> Self {
+        return GlyphAtlas {
+            font,
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+            glyphs: HashMap::new(),
+            packer: ShelfPacker::new(width, height),
+            dirty: false,
+            full: false,
+        };
+    }
+
+    fn scaled(&self) -> ab_glyph::PxScaleFont<&FontRef<'static>> {
+        return self.font.as_scaled(PxScale::from(BASE_SIZE));
+    }
+
+    /// Baseline to baseline distance at `BASE_SIZE`.
+    pub fn line_height(&self) -> f32 {
+        let s = self.scaled();
+        return s.ascent() - s.descent() + s.line_gap();
+    }
+
+    /// Baseline to the top of the tallest glyphs at `BASE_SIZE`.
+    pub fn ascent(&self) -> f32 {
+        return self.scaled().ascent();
+    }
+
+    pub fn kern(&self, a: char, b: char) -> f32 {
+        let s = self.scaled();
+        return s.kern(self.font.glyph_id(a), self.font.glyph_id(b));
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&GlyphInfo> {
+        return self.glyphs.get(&c);
+    }
+
+    /// Whether the atlas changed since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        return std::mem::take(&mut self.dirty);
+    }
+
+    /// Make sure every character of `text` is in the atlas. Characters that no longer fit are
+    /// left out, and drawn as nothing.
+    pub fn ensure(&mut self, text: &str) {
+        for c in text.chars() {
+            if !self.glyphs.contains_key(&c) && !c.is_control()
+                && let Some(info) = self.rasterize(c)
+            {
+                self.glyphs.insert(c, info);
+            }
+        }
+    }
+
+    fn rasterize(&mut self, c: char) -> Option<GlyphInfo> {
+        let id = self.font.glyph_id(c);
+        let advance = self.scaled().h_advance(id);
+        let Some(outline) = self.font.outline_glyph(id.with_scale(BASE_SIZE)) else {
+            return Some(GlyphInfo { advance, ..Default::default() });
+        };
+
+        let bounds = outline.px_bounds();
+        let (gw, gh) = (bounds.width() as usize, bounds.height() as usize);
+        let (w, h) = (gw + 2 * SPREAD, gh + 2 * SPREAD);
+        let mut coverage = vec![0f32; w * h];
+        outline.draw(|x, y, v| {
+            coverage[(y as usize + SPREAD) * w + x as usize + SPREAD] = v;
+        });
+
+        let Some(pos) = self.packer.allocate(w as u32, h as u32) else {
+            if !self.full {
+                log::warn!("glyph atlas is full; some characters won't be drawn");
+                self.full = true;
+            }
+            return None;
+        };
+        let sdf = distance_field(&coverage, w, h);
+        for y in 0..h {
+            let row = (pos[1] as usize + y) * self.width as usize + pos[0] as usize;
+            self.pixels[row..row + w].copy_from_slice(&sdf[y * w..(y + 1) * w]);
+        }
+        self.dirty = true;
+        return Some(GlyphInfo {
+            atlas_pos: pos,
+            atlas_size: [w as u32, h as u32],
+            bearing: [bounds.min.x - SPREAD as f32, bounds.min.y - SPREAD as f32],
+            advance,
+        });
+    }
+}
+
+/// Turn coverage into a distance field by brute force search within `SPREAD` pixels; fine for
+/// glyph sized bitmaps.
+fn distance_field(coverage: &[f32], w: usize, h: usize) -> Vec<u8> {
+    let inside = |x: isize, y: isize| x >= 0 && y >= 0 && (x as usize) < w && (y as usize) < h && coverage[y as usize * w + x as usize] >= 0.5;
+    let r = SPREAD as isize;
+    let mut out = vec![0u8; w * h];
+    for y in 0..h as isize {
+        for x in 0..w as isize {
+            let me = inside(x, y);
+            let mut best = f32::INFINITY;
+            for dy in -r..=r {
+                for dx in -r..=r {
+                    if inside(x + dx, y + dy) != me {
+                        best = best.min((dx * dx + dy * dy) as f32);
+                    }
+                }
+            }
+            // Distances are measured to the other side's pixel centre; half a pixel puts the
+            // edge between them.
+            let d = (best.sqrt() - 0.5).min(SPREAD as f32);
+            let signed = if me { d } else { -d };
+            out[y as usize * w + x as usize] = ((0.5 + signed / (2. * SPREAD as f32)) * 255.).round().clamp(0., 255.) as u8;
+        }
+    }
+    return out;
+}
+
+#[test]
+fn atlas_rasterizes_glyphs() {
+    let mut atlas = GlyphAtlas::new(256, 256);
+    atlas.ensure("Hi l");
+    assert!(atlas.take_dirty());
+    assert!(!atlas.take_dirty());
+
+    let space = atlas.glyph(' ').unwrap();
+    assert_eq!(space.atlas_size, [0, 0]);
+    assert!(space.advance > 0.);
+
+    // 'l' is a tall stem sitting on the baseline, with padding all around that is well outside.
+    let l = *atlas.glyph('l').unwrap();
+    assert!(l.bearing[1] < -BASE_SIZE / 2. && l.bearing[1] + l.atlas_size[1] as f32 > 0.);
+    let at = |x: u32, y: u32| atlas.pixels[((l.atlas_pos[1] + y) * atlas.width + l.atlas_pos[0] + x) as usize];
+    let row: Vec<u8> = (0..l.atlas_size[0]).map(|x| at(x, l.atlas_size[1] / 2)).collect();
+    assert!(*row.iter().max().unwrap() > 128, "{row:?}");
+    assert_eq!(at(0, 0), 0);
+    assert!(row[0] < 32 && *row.last().unwrap() < 32, "{row:?}");
+
+    // Cells don't overlap.
+    let h = *atlas.glyph('H').unwrap();
+    let i = *atlas.glyph('i').unwrap();
+    assert!(h.atlas_pos[0] + h.atlas_size[0] <= i.atlas_pos[0] || h.atlas_pos[1] != i.atlas_pos[1]);
+
+    // A tiny atlas runs out of room without panicking.
+    let mut tiny = GlyphAtlas::new(32, 32);
+    tiny.ensure("W");
+    assert!(tiny.glyph('W').is_none());
+}
@@ -0,0 +1,86 @@
+use super::atlas::{GlyphAtlas, BASE_SIZE};
+
+/// One glyph of laid out text.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GlyphQuad {
+    /// Left, top, right, bottom in pixels from the top-left corner of the text box.
+    pub rect: [f32; 4],
+    /// The same corners in atlas texture coordinates.
+    pub uv: [f32; 4],
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextLayout {
+    pub quads: Vec<GlyphQuad>,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Lay out `text` at `size` pixels per em, one line per '\n'. Glyphs missing from the atlas
+/// (see `GlyphAtlas::ensure`) are skipped.
+pub fn layout_text(atlas: &GlyphAtlas, text: &str, size: f32) -> TextLayout {
+    let scale = size / BASE_SIZE;
+    let line_height = atlas.line_height() * scale;
+    let mut out = TextLayout::default();
+
+    for (line_no, line) in text.split('\n').enumerate() {
+        let baseline = atlas.ascent() * scale + line_no as f32 * line_height;
+        let mut pen = 0f32;
+        let mut prev: Option<char> = None;
+        for c in line.chars() {
+            let Some(g) = atlas.glyph(c) else {
+                continue;
+            };
+            if let Some(p) = prev {
+                pen += atlas.kern(p, c) * scale;
+            }
+            if g.atlas_size[0] > 0 {
+                let x0 = pen + g.bearing[0] * scale;
+                let y0 = baseline + g.bearing[1] * scale;
+                let (w, h) = (atlas.width as f32, atlas.height as f32);
+                out.quads.push(GlyphQuad {
+                    rect: [x0, y0, x0 + g.atlas_size[0] as f32 * scale, y0 + g.atlas_size[1] as f32 * scale],
+                    uv: [
+                        g.atlas_pos[0] as f32 / w,
+                        g.atlas_pos[1] as f32 / h,
+                        (g.atlas_pos[0] + g.atlas_size[0]) as f32 / w,
+                        (g.atlas_pos[1] + g.atlas_size[1]) as f32 / h,
+                    ],
+                });
+            }
+            pen += g.advance * scale;
+            prev = Some(c);
+        }
+        out.width = out.width.max(pen);
+        out.height = (line_no + 1) as f32 * line_height;
+    }
+    return out;
+}
+
+#[test]
+fn layout_measures_text() {
+    let mut atlas = GlyphAtlas::new(512, 512);
+    atlas.ensure("Base camp\nAlt 5364 m");
+
+    let one = layout_text(&atlas, "Base camp", 16.);
+    assert_eq!(one.quads.len(), 8);
+    let two = layout_text(&atlas, "Base camp\nAlt 5364 m", 16.);
+    assert!((two.height - 2. * one.height).abs() < 1e-3);
+    assert!(two.width > one.width);
+
+    // Doubling the size doubles everything.
+    let big = layout_text(&atlas, "Base camp", 32.);
+    assert!((big.width - 2. * one.width).abs() < 1e-3);
+    assert!((big.quads[0].rect[2] - 2. * one.quads[0].rect[2]).abs() < 1e-3);
+    assert_eq!(big.quads[0].uv, one.quads[0].uv);
+
+    // Glyphs go left to right and stay inside the line box, give or take the SDF padding.
+    for w in one.quads.windows(2) {
+        assert!(w[1].rect[0] > w[0].rect[0]);
+    }
+    let pad = super::atlas::SPREAD as f32 * 0.5;
+    assert!(one.quads.iter().all(|q| q.rect[1] >= -pad && q.rect[3] <= one.height + pad));
+
+    // Characters not in the atlas are skipped.
+    assert!(layout_text(&atlas, "QQ", 16.).quads.is_empty());
+}
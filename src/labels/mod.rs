@@ -0,0 +1,145 @@
+mod atlas;
+mod layout;
+
+pub use atlas::{GlyphAtlas, GlyphInfo, BASE_SIZE, SPREAD};
+pub use layout::{layout_text, GlyphQuad, TextLayout};
+
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::core::culling::EllipsoidalOccluder;
+
+/// Text anchored at a point on or above the globe.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    /// ECEF meters.
+    pub position: Vector3<f64>,
+    pub text: String,
+    /// Higher priority labels win when labels overlap.
+    pub priority: f32,
+    pub color: [f32; 4],
+    /// Pixels per em.
+    pub size: f32,
+    /// Offset of the text box's centre from the projected position, in pixels (y down).
+    pub pixel_offset: [f32; 2],
+}
+
+impl Label {
+    pub fn new(position: Vector3<f64>, text: &str) -> Self {
+        return Label { position, text: text.to_string(), priority: 0., color: [1.; 4], size: 16., pixel_offset: [0., -14.] };
+    }
+}
+
+/// What label placement needs to know about the camera.
+#[derive(Copy, Clone, Debug)]
+pub struct LabelView {
+    /// World (ECEF) to clip space.
+    pub view_proj: Matrix4<f64>,
+    /// Camera position in ECEF.
+    pub eye: Vector3<f64>,
+    /// Width and height in pixels.
+    pub viewport: [f32; 2],
+}
+
+/// A label that made it on screen.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PlacedLabel {
+    /// Index into the labels given to `place_labels`.
+    pub index: usize,
+    /// Left, top, right, bottom in pixels from the top-left of the viewport.
+    pub rect: [f32; 4],
+}
+
+/// Whether `p` can be seen from `eye` past the curve of the ellipsoid.
+pub fn above_horizon(eye: &Vector3<f64>, p: &Vector3<f64>) -> bool {
+    return EllipsoidalOccluder::new(eye).is_point_visible(p);
+}
+
+/// Project labels to the screen and pick a set that doesn't overlap: labels behind the camera,
+/// off screen or over the horizon are dropped, then the rest are taken greedily by priority
+/// (ties go to the earlier label). `sizes` holds each label's text box width and height.
+pub fn place_labels(labels: &[Label], sizes: &[[f32; 2]], view: &LabelView) -> Vec<PlacedLabel> {
+    let [vw, vh] = view.viewport;
+    let mut candidates = vec![];
+    for (index, (l, size)) in labels.iter().zip(sizes).enumerate() {
+        if l.text.is_empty() || !above_horizon(&view.eye, &l.position) {
+            continue;
+        }
+        let clip = view.view_proj * Vector4::new(l.position.x, l.position.y, l.position.z, 1.);
+        if clip.w <= 0. {
+            continue;
+        }
+        let x = ((clip.x / clip.w + 1.) / 2.) as f32 * vw + l.pixel_offset[0];
+        let y = ((1. - clip.y / clip.w) / 2.) as f32 * vh + l.pixel_offset[1];
+        let rect = [x - size[0] / 2., y - size[1] / 2., x + size[0] / 2., y + size[1] / 2.];
+        if rect[2] < 0. || rect[0] > vw || rect[3] < 0. || rect[1] > vh {
+            continue;
+        }
+        candidates.push(PlacedLabel { index, rect });
+    }
+
+    candidates.sort_by(|a, b| labels[b.index].priority.total_cmp(&labels[a.index].priority).then(a.index.cmp(&b.index)));
+    let overlaps = |a: &[f32; 4], b: &[f32; 4]| a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3];
+    let mut placed: Vec<PlacedLabel> = vec![];
+    for c in candidates {
+        if !placed.iter().any(|p| overlaps(&p.rect, &c.rect)) {
+            placed.push(c);
+        }
+    }
+    return placed;
+}
+
+#[cfg(test)]
+fn test_view(eye: Vector3<f64>) -> LabelView {
+    // Orthographic view down the x axis, 1 pixel per km over a 1000x1000 viewport: y to the
+    // right, z up.
+    let s = 1. / 500_000.;
+    #[rustfmt::skip]
+    let view_proj = Matrix4::new(
+        0., s, 0., 0.,
+        0., 0., s, 0.,
+        0., 0., 0., 0.,
+        0., 0., 0., 1.,
+    );
+    return LabelView { view_proj, eye, viewport: [1000., 1000.] };
+}
+
+#[test]
+fn horizon_culling() {
+    use crate::core::Geodetic;
+    let eye = Geodetic::new(0., 0., 1_000_000.).to_ecef();
+    assert!(above_horizon(&eye, &Geodetic::new(0., 0., 0.).to_ecef()));
+    assert!(above_horizon(&eye, &Geodetic::new(5., 5., 0.).to_ecef()));
+    assert!(!above_horizon(&eye, &Geodetic::new(0., 180., 0.).to_ecef()));
+    assert!(!above_horizon(&eye, &Geodetic::new(0., 40., 0.).to_ecef()));
+    // The same spot high up pokes over the horizon.
+    assert!(above_horizon(&eye, &Geodetic::new(0., 40., 5_000_000.).to_ecef()));
+}
+
+#[test]
+fn placement_culls_and_resolves_collisions() {
+    let eye = Vector3::new(10_000_000., 0., 0.);
+    let at = |y_km: f64, z_km: f64| Vector3::new(6_378_137., y_km * 1000., z_km * 1000.);
+    let mut labels = vec![
+        Label::new(at(0., 0.), "low"),
+        Label::new(at(10., 0.), "high"),
+        Label::new(at(200., 0.), "apart"),
+        // Far side of the earth, and off screen.
+        Label::new(Vector3::new(-6_378_137., 0., 0.), "hidden"),
+        Label::new(at(900., 0.), "offscreen"),
+    ];
+    labels[1].priority = 5.;
+    for l in &mut labels {
+        l.pixel_offset = [0., 0.];
+    }
+    let sizes = vec![[40., 16.]; labels.len()];
+    let placed = place_labels(&labels, &sizes, &test_view(eye));
+    let names: Vec<&str> = placed.iter().map(|p| labels[p.index].text.as_str()).collect();
+    assert_eq!(names, ["high", "apart"]);
+    // Screen y points down, ECEF z up.
+    assert_eq!(placed[1].rect, [680., 492., 720., 508.]);
+
+    // With equal priority, the first label wins.
+    labels[1].priority = 0.;
+    let placed = place_labels(&labels, &sizes, &test_view(eye));
+    assert_eq!(placed[0].index, 0);
+}
@@ -1,46 +1,129 @@
 use std::iter;
+use std::time::Instant;
 
-use winit::{
-    event::*,
-    event_loop::{ActiveEventLoop, EventLoop},
-    keyboard::KeyCode,
-};
+use winit::event_loop::EventLoop;
 
+pub mod billboards;
+pub mod config;
 pub mod core;
+pub mod czml;
+pub mod instancing;
+pub mod labels;
+pub mod models;
+pub mod orbits;
+pub mod pointclouds;
+pub mod polylines;
 pub mod renderables;
+pub mod tiles;
+pub mod tracks;
 
-use core::{AppObjects, RenderState, BaseApp, UserApp, Renderable, CameraPose, Scene};
+use config::Config;
+use core::{Action, AppObjects, CameraController, RenderState, BaseApp, UserApp, Renderable, Scene, Clock, Gui, InputState, LayerManager, DrawStats};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 
+/// Frames between timing reports in the log when headless.
+const HEADLESS_REPORT_FRAMES: u32 = 300;
+
+/// Bookmarks and the view string or file typed into the view window.
+#[derive(Default)]
+struct BookmarkUi {
+    bookmarks: core::Bookmarks,
+    /// Where bookmarks are saved when they change; they only last the session without it.
+    path: Option<std::path::PathBuf>,
+    name: String,
+    view: String,
+}
+
 #[derive(Default)]
 struct MyApp {
-    renderables: Vec<Box<dyn Renderable>>,
+    config: Config,
+    layers: LayerManager,
     scene: Option<Scene>,
+    camera: CameraController,
+    /// Where the "Fly to" form in the view window goes, and how long it takes in seconds.
+    fly_to: (core::Viewpoint, f64),
+    last_frame: Option<Instant>,
+    bookmarks: BookmarkUi,
+    frames: u32,
+    done: bool,
 }
 impl UserApp for MyApp {
-    fn render(&mut self, ao: &AppObjects) -> Result<(), wgpu::SurfaceError> {
-        ao.window.request_redraw();
-
+    fn render(&mut self, ao: &AppObjects, gui: &mut Gui, input: &InputState) -> Result<(), wgpu::SurfaceError> {
         // We can't render unless the surface is configured
         if !ao.is_surface_configured {
+            ao.request_redraw();
             return Ok(());
         }
 
-        // Initialize camera if necessary.
+        // Initialize camera and the initial layers if necessary. Layers may be removed later, so
+        // an empty list doesn't mean we haven't started yet.
         if self.scene.is_none() {
-            self.scene = Some(Scene::new(ao));
+            let scene = self.scene.insert(Scene::new(ao));
+            self.layers.add("shape", Box::new(crate::renderables::SimpleShape::new(ao, scene)));
+            let mut data_clock = None;
+            for path in &self.config.data {
+                match load_data_file(ao, scene, &self.config, path) {
+                    Ok((r, clock)) => {
+                        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("data");
+                        self.layers.add(name, r);
+                        data_clock = data_clock.or(clock);
+                    }
+                    Err(e) => log::error!("failed to load '{}': {e}", path.display()),
+                }
+            }
+
+            // Play back whatever time-dynamic data was loaded, preferring a clock that a file
+            // asked for explicitly.
+            if let Some(clock) = data_clock {
+                scene.clock = clock;
+            } else if let Some((start, stop)) = self.layers.time_range() {
+                scene.clock.set_range(start, stop);
+            }
+
+            if let Some(c) = &self.config.camera {
+                scene.cam.look_from(&core::Geodetic::new(c.lat, c.lon, c.height), c.heading, c.pitch);
+            }
+            if let Some(view) = &self.config.view {
+                view.apply(scene, &mut self.layers);
+            }
+            self.fly_to = (scene.cam.viewpoint(), 3.);
+            if let Some(path) = &self.config.bookmarks {
+                match core::Bookmarks::load(path) {
+                    Ok(b) => self.bookmarks.bookmarks = b,
+                    Err(e) => log::error!("{e:#}"),
+                }
+                self.bookmarks.path = Some(path.clone());
+            }
+            for (what, source) in [("imagery", &self.config.imagery), ("terrain", &self.config.terrain)] {
+                if let Some(source) = source {
+                    log::warn!("{what} isn't supported yet, ignoring '{source}'");
+                }
+            }
         }
 
-        if self.renderables.len() == 0 {
-            self.renderables.push(Box::new(crate::renderables::SimpleShape::new(ao, &self.scene.as_ref().unwrap())));
+        let scene = self.scene.as_mut().unwrap();
+        gui.run(&ao.window, |ctx| overlay_ui(ctx, ao, scene, &mut self.layers, &mut self.camera, &mut self.fly_to, &mut self.bookmarks));
+
+        if input.just_pressed(Action::TogglePlay) {
+            scene.clock.playing = !scene.clock.playing;
         }
+        // Long stalls, e.g. while loading, shouldn't throw the camera across the globe.
+        let now = Instant::now();
+        let dt = self.last_frame.replace(now).map_or(0., |t| (now - t).as_secs_f64().min(0.1));
+        self.camera.update(&mut scene.cam, input, dt);
+        self.layers.handle_input(ao, input);
 
-        self.scene.as_ref().unwrap().update_buffer(&ao);
+        scene.tick();
+        scene.update_buffer(ao);
+        self.layers.update(ao, scene);
 
+        // Acquiring and presenting block on the display, so they are timed apart from the work.
+        let start = Instant::now();
         let output = ao.surface.get_current_texture()?;
+        ao.profiler.record_wait("surface", start.elapsed());
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -52,56 +135,378 @@ impl UserApp for MyApp {
             });
 
         let mut rs = RenderState {
-            ao: &ao,
-            encoder: encoder,
+            ao,
+            encoder,
+            target_view: ao.targets.scene_view().unwrap_or(&view).clone(),
             surface_tex_view: Some(view),
-            scene: &self.scene.as_ref().unwrap(),
+            scene: self.scene.as_ref().unwrap(),
         };
 
-        {
-            for shape in &self.renderables {
-                shape.render(&mut rs);
+        self.layers.render(&mut rs);
+        let start = Instant::now();
+        ao.profiler.set_scope("post");
+        ao.targets.finish(&ao.queue, &ao.pipelines, &ao.profiler, &mut rs.encoder, rs.surface_tex_view.as_ref().unwrap(), &rs.scene.tone_mapping);
+        ao.profiler.record("post", start.elapsed(), DrawStats::default());
+
+        let start = Instant::now();
+        ao.profiler.set_scope("gui");
+        gui.paint(ao, &mut rs.encoder, rs.surface_tex_view.as_ref().unwrap());
+        ao.profiler.record("gui", start.elapsed(), DrawStats::default());
+
+        self.frames += 1;
+        let last_frame = self.frames == self.config.frames;
+        let capture = match &self.config.output {
+            Some(_) if last_frame && !ao.config.usage.contains(wgpu::TextureUsages::COPY_SRC) => {
+                log::error!("the surface can't be copied from, so the frame can't be saved");
+                None
             }
-        }
+            Some(path) if last_frame => Some((core::FrameCapture::copy(ao, &mut rs.encoder, &output.texture), path)),
+            _ => None,
+        };
 
+        ao.profiler.resolve(&mut rs.encoder);
         ao.queue.submit(iter::once(rs.encoder.finish()));
+        if let Some((capture, path)) = capture
+            && let Err(e) = capture.save(ao, path)
+        {
+            log::error!("failed to save the frame to '{}': {e}", path.display());
+        }
+        let start = Instant::now();
         output.present();
+        ao.profiler.record_wait("surface", start.elapsed());
+        self.done = self.config.exits_after_frames() && self.frames >= self.config.frames;
+        // Without a window to show the overlay in, timings go to the log.
+        if self.config.headless && (self.done || self.frames.is_multiple_of(HEADLESS_REPORT_FRAMES)) {
+            log::info!("{}", ao.profiler.timings().summary());
+        }
+
+        // Keep drawing while things move by themselves; input asks for frames anyway.
+        let playing = self.scene.as_ref().is_some_and(|s| s.clock.playing);
+        if playing || self.camera.animating(input) || self.layers.animating() || gui.needs_repaint() || (self.config.exits_after_frames() && !self.done) {
+            ao.request_redraw();
+        }
 
         Ok(())
     }
-    fn handle_key(&mut self, _ao: &AppObjects, event_loop: &ActiveEventLoop, key: KeyCode, pressed: bool) {
+    fn wants_exit(&self) -> bool {
+        return self.done;
+    }
+}
+
+// Timeline and clock controls along the bottom, camera readout and layer list in a side window.
+fn overlay_ui(
+    ctx: &egui::Context,
+    ao: &AppObjects,
+    scene: &mut Scene,
+    layers: &mut LayerManager,
+    camera: &mut CameraController,
+    fly_to: &mut (core::Viewpoint, f64),
+    bookmarks: &mut BookmarkUi,
+) {
+    egui::TopBottomPanel::bottom("timeline").show(ctx, |ui| {
+        let clock = &mut scene.clock;
+        ui.horizontal(|ui| {
+            if ui.button(if clock.playing { "Pause" } else { "Play" }).clicked() {
+                clock.playing = !clock.playing;
+            }
+            if ui.button("Reset").clicked() {
+                clock.seek(clock.start);
+            }
+            ui.label("rate");
+            ui.add(egui::DragValue::new(&mut clock.rate).speed(0.1).suffix("x"));
+            for r in [1., 60., 3600.] {
+                if ui.small_button(format!("{r}x")).clicked() {
+                    clock.rate = r;
+                }
+            }
+            ui.separator();
+            ui.monospace(core::clock::format_iso8601(clock.current));
+        });
+
+        let mut elapsed = clock.elapsed();
+        let span = (clock.stop - clock.start).max(0.);
+        ui.spacing_mut().slider_width = ui.available_width() - 80.;
+        let slider = egui::Slider::new(&mut elapsed, 0.0..=span).suffix(" s").show_value(true);
+        if ui.add(slider).changed() {
+            clock.seek(clock.start + elapsed);
+        }
+    });
+
+    performance_ui(ctx, ao);
+
+    egui::Window::new("View").default_pos([10., 10.]).show(ctx, |ui| {
+        let (at, heading, pitch, _) = scene.cam.geodetic_hpr();
+        egui::Grid::new("camera").show(ui, |ui| {
+            ui.label("lat");
+            ui.monospace(format!("{:.5}°", at.lat));
+            ui.end_row();
+            ui.label("lon");
+            ui.monospace(format!("{:.5}°", at.lon));
+            ui.end_row();
+            ui.label("height");
+            ui.monospace(format!("{:.1} m", at.height));
+            ui.end_row();
+            ui.label("heading");
+            ui.monospace(format!("{heading:.1}°"));
+            ui.end_row();
+            ui.label("pitch");
+            ui.monospace(format!("{pitch:.1}°"));
+            ui.end_row();
+        });
+
+        ui.collapsing("Fly to", |ui| {
+            let (to, duration) = fly_to;
+            egui::Grid::new("fly_to").show(ui, |ui| {
+                let fields = [
+                    ("lat", &mut to.at.lat, -90.0..=90.0, "°"),
+                    ("lon", &mut to.at.lon, -180.0..=180.0, "°"),
+                    ("height", &mut to.at.height, core::flight::MIN_HEIGHT..=1e8, " m"),
+                    ("heading", &mut to.heading, 0.0..=360.0, "°"),
+                    ("pitch", &mut to.pitch, -90.0..=90.0, "°"),
+                    ("duration", duration, 0.0..=60.0, " s"),
+                ];
+                for (name, value, range, suffix) in fields {
+                    ui.label(name);
+                    ui.add(egui::DragValue::new(value).range(range).suffix(suffix).speed(0.1));
+                    ui.end_row();
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Here").on_hover_text("fill in the current view").clicked() {
+                    *to = scene.cam.viewpoint();
+                }
+                if ui.button("Fly").clicked() {
+                    camera.fly_to(&scene.cam, to.at.lat, to.at.lon, to.at.height, to.heading, to.pitch, *duration);
+                }
+            });
+        });
+
+        ui.collapsing("Bookmarks", |ui| bookmarks_ui(ui, scene, layers, bookmarks));
 
-        let t = nalgebra::Isometry3::<f32>::translation(0.1, 0., 0.);
-        // self.cam.pose = self.cam.pose * t;
+        ui.horizontal(|ui| {
+            let tone = &mut scene.tone_mapping;
+            egui::ComboBox::from_id_salt("tone_mapping").selected_text(tone.operator.name()).show_ui(ui, |ui| {
+                for op in core::ToneMapOperator::ALL {
+                    ui.selectable_value(&mut tone.operator, op, op.name());
+                }
+            });
+            ui.add(egui::Slider::new(&mut tone.exposure, 0.1..=10.0).logarithmic(true).text("exposure"));
+        });
 
-        for r in &mut self.renderables {
-            r.handle_key(event_loop, key, pressed);
+        // Takes effect next frame, when the targets and pipelines are rebuilt.
+        let settings = *ao.settings();
+        let mut samples = settings.msaa_samples;
+        let name = |n: u32| if n > 1 { format!("{n}x MSAA") } else { "no MSAA".to_string() };
+        egui::ComboBox::from_id_salt("msaa").selected_text(name(samples)).show_ui(ui, |ui| {
+            for &n in ao.supported_sample_counts() {
+                ui.selectable_value(&mut samples, n, name(n));
+            }
+        });
+        if samples != settings.msaa_samples {
+            ao.request_render_settings(core::RenderSettings { msaa_samples: samples, ..settings });
         }
+
+        ui.separator();
+        // Top layer first, like most layer lists. Reordering waits until after the loop.
+        let mut raise = None;
+        let mut lower = None;
+        let mut remove = None;
+        for l in layers.iter_mut().rev() {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut l.visible, &l.name);
+                ui.add(egui::Slider::new(&mut l.opacity, 0.0..=1.0).show_value(false))
+                    .on_hover_text("opacity");
+                ui.checkbox(&mut l.input_enabled, "input");
+                if ui.small_button("⏶").clicked() {
+                    raise = Some(l.id());
+                }
+                if ui.small_button("⏷").clicked() {
+                    lower = Some(l.id());
+                }
+                if ui.small_button("✖").clicked() {
+                    remove = Some(l.id());
+                }
+            });
+        }
+        if let Some(id) = raise {
+            layers.raise(id);
+        }
+        if let Some(id) = lower {
+            layers.lower(id);
+        }
+        if let Some(id) = remove {
+            layers.remove(id);
+        }
+    });
+}
+
+// Going to, adding and removing bookmarks, and sharing the view as a string or JSON file.
+fn bookmarks_ui(ui: &mut egui::Ui, scene: &mut Scene, layers: &mut LayerManager, b: &mut BookmarkUi) {
+    let mut changed = false;
+    let mut remove = None;
+    for mark in b.bookmarks.iter() {
+        ui.horizontal(|ui| {
+            if ui.button(&mark.name).on_hover_text(mark.view.to_string()).clicked() {
+                mark.view.apply(scene, layers);
+            }
+            if ui.small_button("✖").clicked() {
+                remove = Some(mark.name.clone());
+            }
+        });
+    }
+    if let Some(name) = remove {
+        changed |= b.bookmarks.remove(&name).is_some();
     }
-    fn handle_mouse(&mut self, _ao: &AppObjects, _event_loop: &ActiveEventLoop, _state: ElementState, _button: MouseButton) {
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut b.name).hint_text("name").desired_width(120.));
+        if ui.add_enabled(!b.name.trim().is_empty(), egui::Button::new("Add")).clicked() {
+            b.bookmarks.add(b.name.trim(), core::ViewState::capture(scene, layers));
+            b.name.clear();
+            changed = true;
+        }
+    });
+    if changed && let Some(path) = &b.path && let Err(e) = b.bookmarks.save(path) {
+        log::error!("{e:#}");
     }
+
+    ui.separator();
+    ui.add(egui::TextEdit::singleline(&mut b.view).hint_text("view string or .json file"));
+    ui.horizontal(|ui| {
+        if ui.button("Go").clicked() {
+            match core::ViewState::parse_or_load(&b.view) {
+                Ok(view) => view.apply(scene, layers),
+                Err(e) => log::warn!("can't go to '{}': {e:#}", b.view),
+            }
+        }
+        if ui.button("Copy").on_hover_text("copy the current view as a string").clicked() {
+            ui.ctx().copy_text(core::ViewState::capture(scene, layers).to_string());
+        }
+        let file = std::path::Path::new(b.view.trim());
+        let json = file.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        if ui.add_enabled(json, egui::Button::new("Save")).on_hover_text("save the current view to the .json file").clicked() {
+            match core::ViewState::capture(scene, layers).save(file) {
+                Ok(()) => log::info!("saved the view to '{}'", file.display()),
+                Err(e) => log::error!("{e:#}"),
+            }
+        }
+    });
+}
+
+// Frame rate and timings, with a row per layer. GPU columns stay empty without timestamp queries.
+fn performance_ui(ctx: &egui::Context, ao: &AppObjects) {
+    egui::Window::new("Performance").default_pos([10., 300.]).default_open(false).show(ctx, |ui| {
+        let t = ao.profiler.timings();
+        let ms = |s: &core::profiler::RollingStats| match (s.avg(), s.p95()) {
+            (Some(avg), Some(p95)) => format!("{avg:.2} / {p95:.2}"),
+            _ => "-".to_string(),
+        };
+        let fps = t.interval.avg().filter(|&ms| ms > 0.).map_or("-".to_string(), |ms| format!("{:.0}", 1e3 / ms));
+        ui.monospace(format!("{fps} fps, frame {} ms (avg / p95)", ms(&t.interval)));
+        ui.monospace(format!("cpu {} ms, gpu {} ms", ms(&t.cpu), if ao.profiler.has_gpu_timing() { ms(&t.gpu) } else { "n/a".to_string() }));
+        ui.separator();
+        egui::Grid::new("scopes").striped(true).show(ui, |ui| {
+            for h in ["", "cpu ms", "gpu ms", "draws", "triangles", "tiles"] {
+                ui.label(h);
+            }
+            ui.end_row();
+            for s in &t.scopes {
+                ui.label(&s.name);
+                ui.monospace(ms(&s.cpu));
+                ui.monospace(ms(&s.gpu));
+                ui.monospace(s.stats.draw_calls.to_string());
+                ui.monospace(s.stats.triangles.to_string());
+                ui.monospace(s.stats.tiles.map_or(String::new(), |n| n.to_string()));
+                ui.end_row();
+            }
+        });
+    });
 }
 
+// Pick a renderable based on the file extension. Some formats also say how the clock should run.
+fn load_data_file(ao: &AppObjects, scene: &Scene, config: &Config, path: &std::path::Path) -> anyhow::Result<(Box<dyn Renderable>, Option<Clock>)> {
+    // 3D Tiles before the extension match, which would take tileset.json for satellites.
+    let location = path.to_str().unwrap_or("");
+    if path.is_dir() || path.file_name().is_some_and(|n| n == "tileset.json") || location.starts_with("http://") {
+        return Ok((Box::new(renderables::Tiles3d::open(ao, scene, location)?), None));
+    }
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match ext.as_str() {
+        "tle" | "txt" | "json" => {
+            let sats = orbits::load_elements(path)?
+                .iter()
+                .filter_map(|el| match orbits::Satellite::new(el) {
+                    Ok(s) => Some(s),
+                    Err(e) => {
+                        log::warn!("skipping {}: {e}", el.name);
+                        None
+                    }
+                })
+                .collect();
+            return Ok((Box::new(renderables::Satellites::new(ao, scene, sats)), None));
+        }
+        "gpx" => {
+            let tracks = tracks::parse_gpx(&std::fs::read_to_string(path)?)?;
+            return Ok((Box::new(renderables::Tracks::new(ao, scene, tracks)), None));
+        }
+        "csv" => {
+            let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("track");
+            let tracks = tracks::parse_csv(&std::fs::read_to_string(path)?, name, &config.tracks.csv)?;
+            return Ok((Box::new(renderables::Tracks::new(ao, scene, tracks)), None));
+        }
+        "czml" => {
+            let doc = czml::parse_czml(&std::fs::read_to_string(path)?)?;
+            let clock = doc.clock.as_ref().map(czml::CzmlClock::to_clock);
+            let dir = path.parent().map(std::path::Path::to_path_buf).unwrap_or_default();
+            return Ok((Box::new(renderables::CzmlEntities::new(ao, scene, doc, dir)), clock));
+        }
+        "gltf" | "glb" => {
+            let data = models::load_model(path)?;
+            let placement = models::ModelPlacement::load_for(path)?.unwrap_or_else(|| {
+                log::warn!("no {} next to '{}', putting it at 0, 0", models::ModelPlacement::sidecar_path(path).display(), path.display());
+                models::ModelPlacement { lat: 0., lon: 0., height: 0., heading: 0., pitch: 0., roll: 0., scale: 1. }
+            });
+            let at = core::Geodetic::new(placement.lat, placement.lon, placement.height);
+            let mut model = renderables::Model::new(ao, scene, data, at);
+            (model.heading, model.pitch, model.roll, model.scale) = (placement.heading, placement.pitch, placement.roll, placement.scale);
+            model.animation = (!model.data.animations.is_empty()).then_some(0);
+            return Ok((Box::new(model), None));
+        }
+        "las" => {
+            let data = pointclouds::load_point_cloud(path)?;
+            return Ok((Box::new(renderables::PointCloud::new(ao, scene, data)), None));
+        }
+        "laz" => anyhow::bail!("LAZ isn't supported yet; decompress '{}' to LAS with laszip", path.display()),
+        _ => anyhow::bail!("don't know how to load '{}'", path.display()),
+    }
+}
 
 pub fn run() -> anyhow::Result<()> {
     #[cfg(not(target_arch = "wasm32"))]
-    {
-        // env_logger::init();
-        let mut builder = env_logger::Builder::new();
-        builder.filter_level(log::LevelFilter::Trace);
-        builder.parse_env(env_logger::Env::new());
-        builder.init();
-    }
+    let config = Config::from_cli(clap::Parser::parse())?;
     #[cfg(target_arch = "wasm32")]
-    {
-        console_log::init_with_level(log::Level::Info).unwrap_throw();
-    }
+    let config = Config::default();
+
+    core::diagnostics::init_logging(config.log_level);
 
     let event_loop = EventLoop::with_user_event().build()?;
     let mut app = BaseApp::<MyApp>::new(
         #[cfg(target_arch = "wasm32")]
         &event_loop,
     );
+    app.render_settings.present_mode = config.window.present_mode;
+    let redraw = if config.window.on_demand { core::RedrawMode::OnDemand } else { core::RedrawMode::Continuous };
+    app.pacer = core::FramePacer::new(redraw, config.window.max_fps);
+    app.input = core::Input::new(config.bindings.clone());
+    // The canvas has its size on the web.
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        app.window_attributes = app
+            .window_attributes
+            .with_title("wglobe")
+            .with_inner_size(winit::dpi::PhysicalSize::new(config.window.width, config.window.height))
+            .with_visible(!config.headless);
+    }
+    app.uapp.config = config;
     event_loop.run_app(&mut app)?;
 
     Ok(())
@@ -0,0 +1,179 @@
+use nalgebra::{Quaternion, UnitQuaternion, Vector3, Vector4};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelProperty {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelInterpolation {
+    Step,
+    Linear,
+    /// Values come in (in-tangent, value, out-tangent) triples.
+    CubicSpline,
+}
+
+/// Keyframes for one property of one node. Values are stored as 4-vectors, with rotations as
+/// (x, y, z, w) quaternions and translations and scales padded with a zero.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub node: usize,
+    pub property: ChannelProperty,
+    pub interpolation: ChannelInterpolation,
+    pub times: Vec<f32>,
+    pub values: Vec<Vector4<f32>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    /// Seconds, the last keyframe time over all channels.
+    pub duration: f32,
+}
+
+impl Channel {
+    fn value(&self, k: usize) -> Vector4<f32> {
+        return match self.interpolation {
+            ChannelInterpolation::CubicSpline => self.values[3 * k + 1],
+            _ => self.values[k],
+        };
+    }
+
+    /// Value at `t`, held constant outside the keyframes.
+    pub fn sample(&self, t: f32) -> Vector4<f32> {
+        let n = self.times.len();
+        if t <= self.times[0] || n == 1 {
+            return self.value(0);
+        }
+        if t >= self.times[n - 1] {
+            return self.value(n - 1);
+        }
+        let k = self.times.partition_point(|&x| x <= t) - 1;
+        let dt = self.times[k + 1] - self.times[k];
+        let s = (t - self.times[k]) / dt;
+        let is_rotation = self.property == ChannelProperty::Rotation;
+
+        let v = match self.interpolation {
+            ChannelInterpolation::Step => self.value(k),
+            ChannelInterpolation::Linear if is_rotation => {
+                let q = |v: Vector4<f32>| UnitQuaternion::from_quaternion(Quaternion::from(v));
+                return q(self.value(k)).slerp(&q(self.value(k + 1)), s).into_inner().coords;
+            }
+            ChannelInterpolation::Linear => self.value(k).lerp(&self.value(k + 1), s),
+            ChannelInterpolation::CubicSpline => {
+                let (s2, s3) = (s * s, s * s * s);
+                let out_tangent = self.values[3 * k + 2];
+                let in_tangent = self.values[3 * (k + 1)];
+                self.value(k) * (2. * s3 - 3. * s2 + 1.)
+                    + out_tangent * dt * (s3 - 2. * s2 + s)
+                    + self.value(k + 1) * (-2. * s3 + 3. * s2)
+                    + in_tangent * dt * (s3 - s2)
+            }
+        };
+        if is_rotation {
+            return v.normalize();
+        }
+        return v;
+    }
+}
+
+impl Animation {
+    pub(super) fn from_gltf<'a, 's>(
+        anim: &gltf::Animation<'a>,
+        get_buffer: impl Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+    ) -> Self {
+        use gltf::animation::util::ReadOutputs;
+
+        let mut channels = vec![];
+        for ch in anim.channels() {
+            let reader = ch.reader(get_buffer.clone());
+            let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs()) else {
+                continue;
+            };
+            let v3 = |v: [f32; 3]| Vector4::new(v[0], v[1], v[2], 0.);
+            let (property, values): (ChannelProperty, Vec<Vector4<f32>>) = match outputs {
+                ReadOutputs::Translations(it) => (ChannelProperty::Translation, it.map(v3).collect()),
+                ReadOutputs::Scales(it) => (ChannelProperty::Scale, it.map(v3).collect()),
+                ReadOutputs::Rotations(it) => (ChannelProperty::Rotation, it.into_f32().map(Vector4::from).collect()),
+                ReadOutputs::MorphTargetWeights(_) => {
+                    log::warn!("gltf: morph target animation is not supported");
+                    continue;
+                }
+            };
+            let interpolation = match ch.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => ChannelInterpolation::Step,
+                gltf::animation::Interpolation::Linear => ChannelInterpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => ChannelInterpolation::CubicSpline,
+            };
+            let times: Vec<f32> = inputs.collect();
+            let per_key = if interpolation == ChannelInterpolation::CubicSpline { 3 } else { 1 };
+            if times.is_empty() || values.len() != times.len() * per_key {
+                log::warn!("gltf: skipping animation channel with {} times and {} values", times.len(), values.len());
+                continue;
+            }
+            channels.push(Channel { node: ch.target().node().index(), property, interpolation, times, values });
+        }
+
+        let duration = channels.iter().filter_map(|c| c.times.last().copied()).fold(0., f32::max);
+        return Animation { name: anim.name().unwrap_or_default().to_string(), channels, duration };
+    }
+
+    /// Overwrite the animated properties in per-node (translation, rotation, scale) at time `t`.
+    pub fn apply(&self, t: f32, trs: &mut [(Vector3<f32>, UnitQuaternion<f32>, Vector3<f32>)]) {
+        for ch in &self.channels {
+            let Some(node) = trs.get_mut(ch.node) else {
+                continue;
+            };
+            let v = ch.sample(t);
+            match ch.property {
+                ChannelProperty::Translation => node.0 = v.xyz(),
+                ChannelProperty::Rotation => node.1 = UnitQuaternion::from_quaternion(Quaternion::from(v)),
+                ChannelProperty::Scale => node.2 = v.xyz(),
+            }
+        }
+    }
+}
+
+#[test]
+fn step_linear_and_cubic_sampling() {
+    let mut ch = Channel {
+        node: 0,
+        property: ChannelProperty::Translation,
+        interpolation: ChannelInterpolation::Step,
+        times: vec![0., 1., 3.],
+        values: vec![Vector4::zeros(), Vector4::new(2., 0., 0., 0.), Vector4::new(4., 0., 0., 0.)],
+    };
+    assert_eq!(ch.sample(0.9).x, 0.);
+    assert_eq!(ch.sample(2.).x, 2.);
+    assert_eq!(ch.sample(9.).x, 4.);
+
+    ch.interpolation = ChannelInterpolation::Linear;
+    assert_eq!(ch.sample(0.5).x, 1.);
+    assert_eq!(ch.sample(2.).x, 3.);
+
+    // Hermite with tangents matching the slope of a straight line reproduces it.
+    ch.interpolation = ChannelInterpolation::CubicSpline;
+    ch.times = vec![0., 2.];
+    let slope = Vector4::new(1., 0., 0., 0.);
+    ch.values = vec![slope, Vector4::zeros(), slope, slope, Vector4::new(2., 0., 0., 0.), slope];
+    assert!((ch.sample(0.5).x - 0.5).abs() < 1e-6);
+    assert!((ch.sample(1.5).x - 1.5).abs() < 1e-6);
+}
+
+#[test]
+fn rotations_are_slerped() {
+    let q0 = UnitQuaternion::identity();
+    let q1 = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2);
+    let ch = Channel {
+        node: 0,
+        property: ChannelProperty::Rotation,
+        interpolation: ChannelInterpolation::Linear,
+        times: vec![0., 1.],
+        values: vec![q0.into_inner().coords, q1.into_inner().coords],
+    };
+    let q = UnitQuaternion::from_quaternion(Quaternion::from(ch.sample(0.5)));
+    assert!((q.angle() - std::f32::consts::FRAC_PI_4).abs() < 1e-5);
+}
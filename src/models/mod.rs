@@ -0,0 +1,412 @@
+mod animation;
+
+pub use animation::{Animation, Channel, ChannelProperty, ChannelInterpolation};
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use nalgebra::{Matrix4, Quaternion, UnitQuaternion, Vector3};
+
+/// A triangle list with one material. Normals are generated if the file has none.
+#[derive(Clone, Debug, Default)]
+pub struct Primitive {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// TEXCOORD_0, zeros if missing.
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    pub material: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub name: String,
+    pub primitives: Vec<Primitive>,
+}
+
+/// PBR metallic-roughness material. Only the base colour is used for drawing so far.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    pub base_color: [f32; 4],
+    /// Index into `ModelData::images`.
+    pub base_color_texture: Option<usize>,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        return Material {
+            name: String::new(),
+            base_color: [1.; 4],
+            base_color_texture: None,
+            metallic: 1.,
+            roughness: 1.,
+        };
+    }
+}
+
+/// An 8 bit sRGB RGBA image.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+/// A glTF 2.0 scene, flattened out of the file into plain data. Skins and morph targets are not
+/// supported and are ignored when loading.
+///
+/// Model space is glTF's: meters, +Y up, +Z forward.
+#[derive(Clone, Debug, Default)]
+pub struct ModelData {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub images: Vec<Image>,
+    pub nodes: Vec<Node>,
+    /// Root nodes of the default scene.
+    pub roots: Vec<usize>,
+    pub animations: Vec<Animation>,
+}
+
+/// Load a `.gltf` (with external or embedded buffers) or `.glb` file.
+pub fn load_model(path: &Path) -> anyhow::Result<ModelData> {
+    let (doc, buffers, images) = gltf::import(path).with_context(|| format!("reading {}", path.display()))?;
+    return ModelData::from_gltf(&doc, &buffers, &images);
+}
+
+/// Where a model goes on the globe, read from a JSON file next to it (see `sidecar_path`), as
+/// glTF itself has no georeference. Angles are in degrees.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPlacement {
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(default)]
+    pub height: f64,
+    #[serde(default)]
+    pub heading: f64,
+    #[serde(default)]
+    pub pitch: f64,
+    #[serde(default)]
+    pub roll: f64,
+    #[serde(default = "one")]
+    pub scale: f64,
+}
+
+fn one() -> f64 {
+    return 1.;
+}
+
+impl ModelPlacement {
+    /// `truck.placement.json` for `truck.glb`.
+    pub fn sidecar_path(model: &Path) -> PathBuf {
+        return model.with_extension("placement.json");
+    }
+
+    /// The placement for the model at `model`, or None if it has no sidecar file.
+    pub fn load_for(model: &Path) -> anyhow::Result<Option<Self>> {
+        let path = Self::sidecar_path(model);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path).with_context(|| format!("can't read '{}'", path.display()))?;
+        return serde_json::from_str(&text).map(Some).with_context(|| format!("can't parse '{}'", path.display()));
+    }
+}
+
+/// Parse a glTF model held in memory. External files can't be resolved, so buffers and images
+/// must be embedded (GLB or data URIs).
+pub fn parse_model(bytes: &[u8]) -> anyhow::Result<ModelData> {
+    let (doc, buffers, images) = gltf::import_slice(bytes)?;
+    return ModelData::from_gltf(&doc, &buffers, &images);
+}
+
+impl ModelData {
+    fn from_gltf(doc: &gltf::Document, buffers: &[gltf::buffer::Data], images: &[gltf::image::Data]) -> anyhow::Result<Self> {
+        let get_buffer = |b: gltf::Buffer| buffers.get(b.index()).map(|d| &d.0[..]);
+
+        let mut meshes = vec![];
+        for mesh in doc.meshes() {
+            let mut primitives = vec![];
+            for prim in mesh.primitives() {
+                if prim.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!("gltf: skipping {:?} primitive in mesh {}", prim.mode(), mesh.index());
+                    continue;
+                }
+                let reader = prim.reader(get_buffer);
+                let Some(positions) = reader.read_positions() else {
+                    continue;
+                };
+                let positions: Vec<[f32; 3]> = positions.collect();
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(i) => i.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+                if let Some(&bad) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+                    anyhow::bail!("gltf: index {bad} out of range in mesh {}", mesh.index());
+                }
+                let normals = match reader.read_normals() {
+                    Some(n) => n.collect(),
+                    None => vertex_normals(&positions, &indices),
+                };
+                let uvs = match reader.read_tex_coords(0) {
+                    Some(t) => t.into_f32().collect(),
+                    None => vec![[0., 0.]; positions.len()],
+                };
+                primitives.push(Primitive { positions, normals, uvs, indices, material: prim.material().index() });
+            }
+            meshes.push(Mesh { name: mesh.name().unwrap_or_default().to_string(), primitives });
+        }
+
+        let materials = doc
+            .materials()
+            .map(|m| {
+                let pbr = m.pbr_metallic_roughness();
+                Material {
+                    name: m.name().unwrap_or_default().to_string(),
+                    base_color: pbr.base_color_factor(),
+                    base_color_texture: pbr.base_color_texture().map(|t| t.texture().source().index()),
+                    metallic: pbr.metallic_factor(),
+                    roughness: pbr.roughness_factor(),
+                }
+            })
+            .collect();
+
+        let images = images.iter().map(to_rgba8).collect();
+
+        let nodes = doc
+            .nodes()
+            .map(|n| {
+                let (t, r, s) = n.transform().decomposed();
+                Node {
+                    name: n.name().unwrap_or_default().to_string(),
+                    children: n.children().map(|c| c.index()).collect(),
+                    mesh: n.mesh().map(|m| m.index()),
+                    translation: t.into(),
+                    rotation: UnitQuaternion::from_quaternion(Quaternion::new(r[3], r[0], r[1], r[2])),
+                    scale: s.into(),
+                }
+            })
+            .collect();
+
+        let roots = match doc.default_scene().or_else(|| doc.scenes().next()) {
+            Some(scene) => scene.nodes().map(|n| n.index()).collect(),
+            None => vec![],
+        };
+
+        let animations = doc
+            .animations()
+            .map(|a| Animation::from_gltf(&a, get_buffer))
+            .collect();
+
+        return Ok(ModelData { meshes, materials, images, nodes, roots, animations });
+    }
+
+    /// Model-space transform of every node, with `animation` (index, seconds) applied if given.
+    /// Nodes that aren't in the scene keep the identity.
+    pub fn node_transforms(&self, animation: Option<(usize, f32)>) -> Vec<Matrix4<f32>> {
+        let mut local: Vec<(Vector3<f32>, UnitQuaternion<f32>, Vector3<f32>)> =
+            self.nodes.iter().map(|n| (n.translation, n.rotation, n.scale)).collect();
+        if let Some(anim) = animation.and_then(|(i, t)| Some((self.animations.get(i)?, t))) {
+            anim.0.apply(anim.1, &mut local);
+        }
+
+        let mut out = vec![Matrix4::identity(); self.nodes.len()];
+        let mut stack: Vec<(usize, Matrix4<f32>)> = self.roots.iter().map(|&r| (r, Matrix4::identity())).collect();
+        while let Some((i, parent)) = stack.pop() {
+            let (t, r, s) = local[i];
+            let m = parent * Matrix4::new_translation(&t) * r.to_homogeneous() * Matrix4::new_nonuniform_scaling(&s);
+            out[i] = m;
+            stack.extend(self.nodes[i].children.iter().map(|&c| (c, m)));
+        }
+        return out;
+    }
+
+    /// Model-space bounding box of the scene at rest, or `None` if there's nothing to draw.
+    pub fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let transforms = self.node_transforms(None);
+        let mut out: Option<(Vector3<f32>, Vector3<f32>)> = None;
+        for (node, m) in self.nodes.iter().zip(&transforms) {
+            let Some(mesh) = node.mesh.and_then(|i| self.meshes.get(i)) else {
+                continue;
+            };
+            for p in mesh.primitives.iter().flat_map(|p| &p.positions) {
+                let p = m.transform_point(&(*p).into()).coords;
+                out = Some(match out {
+                    Some((lo, hi)) => (lo.inf(&p), hi.sup(&p)),
+                    None => (p, p),
+                });
+            }
+        }
+        return out;
+    }
+}
+
+fn vertex_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![Vector3::<f32>::zeros(); positions.len()];
+    for tri in indices.chunks_exact(3) {
+        let p = |i: u32| Vector3::from(positions[i as usize]);
+        // Area weighted, since the cross product isn't normalized.
+        let n = (p(tri[1]) - p(tri[0])).cross(&(p(tri[2]) - p(tri[0])));
+        for &i in tri {
+            normals[i as usize] += n;
+        }
+    }
+    return normals.iter().map(|n| n.try_normalize(1e-12).unwrap_or(Vector3::y()).into()).collect();
+}
+
+fn to_rgba8(image: &gltf::image::Data) -> Image {
+    use gltf::image::Format;
+    let px = &image.pixels;
+    let rgba = match image.format {
+        Format::R8 => px.iter().flat_map(|&r| [r, r, r, 255]).collect(),
+        Format::R8G8 => px.chunks_exact(2).flat_map(|c| [c[0], c[1], 0, 255]).collect(),
+        Format::R8G8B8 => px.chunks_exact(3).flat_map(|c| [c[0], c[1], c[2], 255]).collect(),
+        Format::R8G8B8A8 => px.clone(),
+        other => {
+            log::warn!("gltf: unsupported image format {other:?}, using white");
+            vec![255; (image.width * image.height * 4) as usize]
+        }
+    };
+    return Image { width: image.width, height: image.height, rgba };
+}
+
+/// Wrap a JSON document and binary chunk up as a GLB.
+#[cfg(test)]
+pub(crate) fn make_glb(json: &str, bin: &[u8]) -> Vec<u8> {
+    let pad = |mut v: Vec<u8>, with: u8| {
+        while !v.len().is_multiple_of(4) {
+            v.push(with);
+        }
+        v
+    };
+    let json = pad(json.as_bytes().to_vec(), b' ');
+    let bin = pad(bin.to_vec(), 0);
+    let total = 12 + 8 + json.len() + 8 + bin.len();
+
+    let mut out = vec![];
+    out.extend_from_slice(b"glTF");
+    out.extend_from_slice(&2u32.to_le_bytes());
+    out.extend_from_slice(&(total as u32).to_le_bytes());
+    out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"JSON");
+    out.extend_from_slice(&json);
+    out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"BIN\0");
+    out.extend_from_slice(&bin);
+    return out;
+}
+
+/// One triangle under a translated parent node, with a red material and a 2 s translation
+/// animation on the parent.
+#[cfg(test)]
+pub(crate) fn triangle_fixture() -> Vec<u8> {
+    let mut bin: Vec<u8> = vec![];
+    // Positions (36 bytes), indices (6 bytes + 2 padding), anim times (8), anim values (24).
+    for v in [0f32, 0., 0., 1., 0., 0., 0., 1., 0.] {
+        bin.extend_from_slice(&v.to_le_bytes());
+    }
+    for i in [0u16, 1, 2, 0] {
+        bin.extend_from_slice(&i.to_le_bytes());
+    }
+    for v in [0f32, 2., 0., 0., 0., 0., 4., 0.] {
+        bin.extend_from_slice(&v.to_le_bytes());
+    }
+
+    let json = r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0]}],
+        "nodes": [
+            {"name": "parent", "translation": [10, 0, 0], "children": [1]},
+            {"name": "tri", "mesh": 0, "rotation": [0, 0, 0.7071068, 0.7071068]}
+        ],
+        "meshes": [{"name": "triangle", "primitives": [{"attributes": {"POSITION": 0}, "indices": 1, "material": 0}]}],
+        "materials": [{"name": "red", "pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0.25}}],
+        "animations": [{
+            "name": "slide",
+            "channels": [{"sampler": 0, "target": {"node": 0, "path": "translation"}}],
+            "samplers": [{"input": 2, "output": 3, "interpolation": "LINEAR"}]
+        }],
+        "buffers": [{"byteLength": 76}],
+        "bufferViews": [
+            {"buffer": 0, "byteOffset": 0, "byteLength": 36},
+            {"buffer": 0, "byteOffset": 36, "byteLength": 6},
+            {"buffer": 0, "byteOffset": 44, "byteLength": 8},
+            {"buffer": 0, "byteOffset": 52, "byteLength": 24}
+        ],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]},
+            {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"},
+            {"bufferView": 2, "componentType": 5126, "count": 2, "type": "SCALAR", "min": [0], "max": [2]},
+            {"bufferView": 3, "componentType": 5126, "count": 2, "type": "VEC3"}
+        ]
+    }"#;
+    return make_glb(json, &bin);
+}
+
+#[test]
+fn parses_triangle_fixture() {
+    let m = parse_model(&triangle_fixture()).unwrap();
+    assert_eq!(m.meshes.len(), 1);
+    let prim = &m.meshes[0].primitives[0];
+    assert_eq!(prim.indices, [0, 1, 2]);
+    assert_eq!(prim.material, Some(0));
+    // Generated normals face +z for a counter-clockwise triangle in the xy plane.
+    assert!((Vector3::from(prim.normals[0]) - Vector3::z()).norm() < 1e-6);
+    assert_eq!(m.materials[0].base_color, [1., 0., 0., 1.]);
+    assert_eq!(m.materials[0].metallic, 0.25);
+    assert_eq!(m.roots, [0]);
+    assert_eq!(m.nodes[0].children, [1]);
+    assert_eq!(m.animations[0].duration, 2.);
+}
+
+#[test]
+fn node_hierarchy_and_animation() {
+    let m = parse_model(&triangle_fixture()).unwrap();
+
+    // The child is rotated 90 degrees about z, then moved 10 m along x by the parent.
+    let (lo, hi) = m.bounds().unwrap();
+    assert!((lo - Vector3::new(9., 0., 0.)).norm() < 1e-5, "{lo}");
+    assert!((hi - Vector3::new(10., 1., 0.)).norm() < 1e-5, "{hi}");
+
+    // Halfway through the animation the parent is at y = 2 and its own x offset is replaced.
+    let tf = m.node_transforms(Some((0, 1.)));
+    let p = tf[1].transform_point(&[1., 0., 0.].into());
+    assert!((p.coords - Vector3::new(0., 3., 0.)).norm() < 1e-5, "{p}");
+}
+
+#[test]
+fn converts_images_to_rgba() {
+    let img = gltf::image::Data { pixels: vec![1, 2, 3, 4, 5, 6], format: gltf::image::Format::R8G8B8, width: 2, height: 1 };
+    assert_eq!(to_rgba8(&img).rgba, [1, 2, 3, 255, 4, 5, 6, 255]);
+}
+
+#[test]
+fn placements_are_read_from_a_sidecar() {
+    let dir = std::env::temp_dir().join(format!("wglobe-placement-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let model = dir.join("truck.glb");
+    assert_eq!(ModelPlacement::sidecar_path(&model), dir.join("truck.placement.json"));
+    assert_eq!(ModelPlacement::load_for(&model).unwrap(), None);
+
+    std::fs::write(dir.join("truck.placement.json"), r#"{"lat": 46.5, "lon": 7.9, "height": 1200, "heading": 90}"#).unwrap();
+    let p = ModelPlacement::load_for(&model).unwrap().unwrap();
+    assert_eq!(p, ModelPlacement { lat: 46.5, lon: 7.9, height: 1200., heading: 90., pitch: 0., roll: 0., scale: 1. });
+
+    std::fs::write(dir.join("truck.placement.json"), r#"{"lat": 46.5}"#).unwrap();
+    assert!(ModelPlacement::load_for(&model).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,94 @@
+mod sdp4;
+mod sgp4;
+mod tle;
+
+pub use sgp4::Sgp4;
+pub use tle::{load_elements, parse_omm_json, parse_tle_file, Elements};
+
+use nalgebra::{Matrix3, Vector3};
+
+/// Earth rotation rate [rad/s].
+pub const OMEGA_EARTH: f64 = 7.292115146706979e-5;
+
+/// Greenwich mean sidereal time (IAU-82) in radians.
+pub fn gmst(jd_ut1: f64) -> f64 {
+    let tut1 = (jd_ut1 - 2451545.0) / 36525.0;
+    let secs = -6.2e-6 * tut1 * tut1 * tut1
+        + 0.093104 * tut1 * tut1
+        + (876600.0 * 3600.0 + 8640184.812866) * tut1
+        + 67310.54841;
+    return (secs.to_radians() / 240.0).rem_euclid(std::f64::consts::TAU);
+}
+
+/// Rotate a TEME state [km, km/s] into ECEF [m, m/s]. Polar motion is ignored, which costs a few
+/// tens of meters at most.
+pub fn teme_to_ecef(jd: f64, r: &Vector3<f64>, v: &Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let (s, c) = gmst(jd).sin_cos();
+    let rot = Matrix3::new(c, s, 0.0, -s, c, 0.0, 0.0, 0.0, 1.0);
+    let r_ecef = rot * r * 1000.0;
+    let w = Vector3::new(0.0, 0.0, OMEGA_EARTH);
+    let v_ecef = rot * v * 1000.0 - w.cross(&r_ecef);
+    return (r_ecef, v_ecef);
+}
+
+/// A named satellite that can be propagated to a scene time.
+#[derive(Clone, Debug)]
+pub struct Satellite {
+    pub name: String,
+    pub norad_id: u32,
+    pub sgp4: Sgp4,
+}
+
+impl Satellite {
+    pub fn new(el: &Elements) -> anyhow::Result<Self> {
+        return Ok(Satellite {
+            name: el.name.clone(),
+            norad_id: el.norad_id,
+            sgp4: Sgp4::new(el)?,
+        });
+    }
+
+    /// ECEF position [m] at a unix time.
+    pub fn position_ecef(&self, t: f64) -> anyhow::Result<Vector3<f64>> {
+        let jd = crate::core::clock::unix_to_jd(t);
+        let (r, v) = self.sgp4.propagate_jd(jd)?;
+        return Ok(teme_to_ecef(jd, &r, &v).0);
+    }
+}
+
+#[test]
+fn gmst_at_j2000() {
+    // 2000-01-01T12:00:00 UT1 is 280.46061837 degrees.
+    assert!((gmst(2451545.0).to_degrees() - 280.46061837).abs() < 1e-6);
+}
+
+#[test]
+fn teme_to_ecef_preserves_radius() {
+    let r = Vector3::new(7000.0, 100.0, 200.0);
+    let v = Vector3::new(0.0, 7.5, 0.0);
+    let (re, _) = teme_to_ecef(2458849.5, &r, &v);
+    assert!((re.norm() - r.norm() * 1000.0).abs() < 1e-6);
+    assert!((re.z - 200_000.0).abs() < 1e-6);
+}
+
+#[test]
+fn ground_track_stays_on_surface_and_orbit_is_closed() {
+    let el = parse_tle_file(
+        "1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927
+2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537",
+    )
+    .unwrap();
+    let sat = Satellite::new(&el[0]).unwrap();
+    let t = crate::core::clock::jd_to_unix(sat.sgp4.epoch_jd);
+
+    let p = sat.position_ecef(t).unwrap();
+    let g = crate::core::Geodetic::from_ecef(&p);
+    assert!(g.height > 300e3 && g.height < 450e3, "{g:?}");
+    assert!(g.lat.abs() <= 51.7);
+
+    // A full period in the inertial frame comes back (almost) to where it started.
+    let period = sat.sgp4.period_minutes();
+    let (r0, _) = sat.sgp4.propagate(0.).unwrap();
+    let (r1, _) = sat.sgp4.propagate(period).unwrap();
+    assert!((r0 - r1).norm() < 50.0, "{}", (r0 - r1).norm());
+}
@@ -0,0 +1,478 @@
+//! The deep-space (SDP4) part of SGP4: lunar and solar perturbations, and the geopotential
+//! resonance of 12 hour and geosynchronous orbits. Follows `dscom`, `dpper`, `dsinit` and `dspace`
+//! in Vallado et al., "Revisiting Spacetrack Report #3" (AIAA 2006-6753), in its "improved" mode.
+
+use std::f64::consts::PI;
+
+const TWOPI: f64 = 2.0 * PI;
+const X2O3: f64 = 2.0 / 3.0;
+
+const ZNS: f64 = 1.19459e-5;
+const ZES: f64 = 0.01675;
+const ZNL: f64 = 1.5835218e-4;
+const ZEL: f64 = 0.05490;
+/// Earth rotation rate [rad/min].
+const RPTIM: f64 = 4.3752690880113e-3;
+
+/// Mean elements as the propagator carries them: eccentricity, inclination, node, argument of
+/// perigee and mean anomaly [rad], and mean motion [rad/min].
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Mean {
+    pub e: f64,
+    pub incl: f64,
+    pub node: f64,
+    pub argp: f64,
+    pub m: f64,
+    pub n: f64,
+}
+
+/// Lunar-solar periodic coefficients for one body.
+#[derive(Clone, Debug, Default)]
+struct Periodics {
+    e2: f64,
+    e3: f64,
+    i2: f64,
+    i3: f64,
+    l2: f64,
+    l3: f64,
+    l4: f64,
+    gh2: f64,
+    gh3: f64,
+    gh4: f64,
+    h2: f64,
+    h3: f64,
+}
+
+/// What `dscom` works out for one body.
+#[derive(Clone, Debug, Default)]
+struct Body {
+    s: [f64; 7],
+    z1: f64,
+    z3: f64,
+    z11: f64,
+    z13: f64,
+    z21: f64,
+    z23: f64,
+    z31: f64,
+    z33: f64,
+    periodics: Periodics,
+}
+
+#[derive(Clone, Debug)]
+enum Resonance {
+    None,
+    /// Geosynchronous, one revolution a day.
+    Synchronous { del: [f64; 3] },
+    /// 12 hour orbits with e >= 0.5.
+    HalfDay { d: [f64; 10] },
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct DeepSpace {
+    sun: Periodics,
+    moon: Periodics,
+    zmos: f64,
+    zmol: f64,
+
+    dedt: f64,
+    didt: f64,
+    dmdt: f64,
+    dnodt: f64,
+    domdt: f64,
+
+    resonance: Resonance,
+    gsto: f64,
+    xfact: f64,
+    xlamo: f64,
+    argpo: f64,
+    argpdot: f64,
+    no_unkozai: f64,
+}
+
+/// `dscom`: the lunar and solar terms for the elements at epoch. `day` is days since 1950 Jan 0.0.
+fn dscom(day: f64, el: &Mean) -> (Body, Body, f64, f64) {
+    const C1SS: f64 = 2.9864797e-6;
+    const C1L: f64 = 4.7968065e-7;
+    const ZSINIS: f64 = 0.39785416;
+    const ZCOSIS: f64 = 0.91744867;
+    const ZCOSGS: f64 = 0.1945905;
+    const ZSINGS: f64 = -0.98088458;
+
+    let (snodm, cnodm) = el.node.sin_cos();
+    let (sinomm, cosomm) = el.argp.sin_cos();
+    let (sinim, cosim) = el.incl.sin_cos();
+    let emsq = el.e * el.e;
+    let betasq = 1.0 - emsq;
+    let rtemsq = betasq.sqrt();
+
+    let xnodce = (4.5236020 - 9.2422029e-4 * day) % TWOPI;
+    let (stem, ctem) = xnodce.sin_cos();
+    let zcosil = 0.91375164 - 0.03568096 * ctem;
+    let zsinil = (1.0 - zcosil * zcosil).sqrt();
+    let zsinhl = 0.089683511 * stem / zsinil;
+    let zcoshl = (1.0 - zsinhl * zsinhl).sqrt();
+    let gam = 5.8351514 + 0.0019443680 * day;
+    let zx = (0.39785416 * stem / zsinil).atan2(zcoshl * ctem + 0.91744867 * zsinhl * stem);
+    let zx = gam + zx - xnodce;
+    let (zsingl, zcosgl) = zx.sin_cos();
+
+    let body = |zcosg: f64, zsing: f64, zcosi: f64, zsini: f64, zcosh: f64, zsinh: f64, cc: f64| {
+        let a1 = zcosg * zcosh + zsing * zcosi * zsinh;
+        let a3 = -zsing * zcosh + zcosg * zcosi * zsinh;
+        let a7 = -zcosg * zsinh + zsing * zcosi * zcosh;
+        let a8 = zsing * zsini;
+        let a9 = zsing * zsinh + zcosg * zcosi * zcosh;
+        let a10 = zcosg * zsini;
+        let a2 = cosim * a7 + sinim * a8;
+        let a4 = cosim * a9 + sinim * a10;
+        let a5 = -sinim * a7 + cosim * a8;
+        let a6 = -sinim * a9 + cosim * a10;
+
+        let x1 = a1 * cosomm + a2 * sinomm;
+        let x2 = a3 * cosomm + a4 * sinomm;
+        let x3 = -a1 * sinomm + a2 * cosomm;
+        let x4 = -a3 * sinomm + a4 * cosomm;
+        let x5 = a5 * sinomm;
+        let x6 = a6 * sinomm;
+        let x7 = a5 * cosomm;
+        let x8 = a6 * cosomm;
+
+        let z31 = 12.0 * x1 * x1 - 3.0 * x3 * x3;
+        let z32 = 24.0 * x1 * x2 - 6.0 * x3 * x4;
+        let z33 = 12.0 * x2 * x2 - 3.0 * x4 * x4;
+        let mut z1 = 3.0 * (a1 * a1 + a2 * a2) + z31 * emsq;
+        let mut z2 = 6.0 * (a1 * a3 + a2 * a4) + z32 * emsq;
+        let mut z3 = 3.0 * (a3 * a3 + a4 * a4) + z33 * emsq;
+        let z11 = -6.0 * a1 * a5 + emsq * (-24.0 * x1 * x7 - 6.0 * x3 * x5);
+        let z12 = -6.0 * (a1 * a6 + a3 * a5) + emsq * (-24.0 * (x2 * x7 + x1 * x8) - 6.0 * (x3 * x6 + x4 * x5));
+        let z13 = -6.0 * a3 * a6 + emsq * (-24.0 * x2 * x8 - 6.0 * x4 * x6);
+        let z21 = 6.0 * a2 * a5 + emsq * (24.0 * x1 * x5 - 6.0 * x3 * x7);
+        let z22 = 6.0 * (a4 * a5 + a2 * a6) + emsq * (24.0 * (x2 * x5 + x1 * x6) - 6.0 * (x4 * x7 + x3 * x8));
+        let z23 = 6.0 * a4 * a6 + emsq * (24.0 * x2 * x6 - 6.0 * x4 * x8);
+        z1 = z1 + z1 + betasq * z31;
+        z2 = z2 + z2 + betasq * z32;
+        z3 = z3 + z3 + betasq * z33;
+        let s3 = cc / el.n;
+        let s2 = -0.5 * s3 / rtemsq;
+        let s4 = s3 * rtemsq;
+        let s1 = -15.0 * el.e * s4;
+        let s5 = x1 * x3 + x2 * x4;
+        let s6 = x2 * x3 + x1 * x4;
+        let s7 = x2 * x4 - x1 * x3;
+
+        let periodics = Periodics {
+            e2: 2.0 * s1 * s6,
+            e3: 2.0 * s1 * s7,
+            i2: 2.0 * s2 * z12,
+            i3: 2.0 * s2 * (z13 - z11),
+            l2: -2.0 * s3 * z2,
+            l3: -2.0 * s3 * (z3 - z1),
+            l4: 0.0,
+            gh2: 2.0 * s4 * z32,
+            gh3: 2.0 * s4 * (z33 - z31),
+            gh4: -18.0 * s4,
+            h2: -2.0 * s2 * z22,
+            h3: -2.0 * s2 * (z23 - z21),
+        };
+        return Body { s: [s1, s2, s3, s4, s5, s6, s7], z1, z3, z11, z13, z21, z23, z31, z33, periodics };
+    };
+
+    let mut sun = body(ZCOSGS, ZSINGS, ZCOSIS, ZSINIS, cnodm, snodm, C1SS);
+    let mut moon = body(
+        zcosgl,
+        zsingl,
+        zcosil,
+        zsinil,
+        zcoshl * cnodm + zsinhl * snodm,
+        snodm * zcoshl - cnodm * zsinhl,
+        C1L,
+    );
+    // The terms that depend on each body's eccentricity.
+    for (b, ze) in [(&mut sun, ZES), (&mut moon, ZEL)] {
+        b.periodics.l4 = -2.0 * b.s[2] * (-21.0 - 9.0 * emsq) * ze;
+        b.periodics.gh4 *= ze;
+    }
+
+    let zmol = (4.7199672 + 0.22997150 * day - gam) % TWOPI;
+    let zmos = (6.2565837 + 0.017201977 * day) % TWOPI;
+    return (sun, moon, zmos, zmol);
+}
+
+impl DeepSpace {
+    /// `dscom` and `dsinit`. `epoch` is the julian date of the elements, `gsto` the sidereal time
+    /// then; the rates are SGP4's secular ones.
+    pub fn new(epoch: f64, gsto: f64, el: &Mean, xke: f64, mdot: f64, argpdot: f64, nodedot: f64) -> Self {
+        let day = epoch - 2433281.5 + 18261.5;
+        let (sun, moon, zmos, zmol) = dscom(day, el);
+        let (sinim, cosim) = el.incl.sin_cos();
+        let emsq = el.e * el.e;
+
+        let nm = el.n;
+        let mut resonance = Resonance::None;
+        let synchronous = nm < 0.0052359877 && nm > 0.0034906585;
+        let half_day = (8.26e-3..=9.24e-3).contains(&nm) && el.e >= 0.5;
+
+        // Secular rates, from the sun and then the moon. Near equatorial orbits have no node.
+        let unless_equatorial = |shs: f64| match el.incl < 5.2359877e-2 || el.incl > PI - 5.2359877e-2 {
+            true => 0.0,
+            false => shs,
+        };
+        let (s, ss) = (&moon.s, &sun.s);
+        let ses = ss[0] * ZNS * ss[4];
+        let sis = ss[1] * ZNS * (sun.z11 + sun.z13);
+        let sls = -ZNS * ss[2] * (sun.z1 + sun.z3 - 14.0 - 6.0 * emsq);
+        let sghs = ss[3] * ZNS * (sun.z31 + sun.z33 - 6.0);
+        let mut shs = unless_equatorial(-ZNS * ss[1] * (sun.z21 + sun.z23));
+        if sinim != 0.0 {
+            shs /= sinim;
+        }
+        let sgs = sghs - cosim * shs;
+
+        let dedt = ses + s[0] * ZNL * s[4];
+        let didt = sis + s[1] * ZNL * (moon.z11 + moon.z13);
+        let dmdt = sls - ZNL * s[2] * (moon.z1 + moon.z3 - 14.0 - 6.0 * emsq);
+        let sghl = s[3] * ZNL * (moon.z31 + moon.z33 - 6.0);
+        let shll = unless_equatorial(-ZNL * s[1] * (moon.z21 + moon.z23));
+        let mut domdt = sgs + sghl;
+        let mut dnodt = shs;
+        if sinim != 0.0 {
+            domdt -= cosim / sinim * shll;
+            dnodt += shll / sinim;
+        }
+
+        let theta = gsto % TWOPI;
+        let aonv = (nm / xke).powf(X2O3);
+        let (mut xlamo, mut xfact) = (0.0, 0.0);
+        if half_day {
+            let cosisq = cosim * cosim;
+            let em = el.e;
+            let eoc = em * emsq;
+            let g201 = -0.306 - (em - 0.64) * 0.440;
+            let (g211, g310, g322, g410, g422, g520);
+            if em <= 0.65 {
+                g211 = 3.616 - 13.2470 * em + 16.2900 * emsq;
+                g310 = -19.302 + 117.3900 * em - 228.4190 * emsq + 156.5910 * eoc;
+                g322 = -18.9068 + 109.7927 * em - 214.6334 * emsq + 146.5816 * eoc;
+                g410 = -41.122 + 242.6940 * em - 471.0940 * emsq + 313.9530 * eoc;
+                g422 = -146.407 + 841.8800 * em - 1629.014 * emsq + 1083.4350 * eoc;
+                g520 = -532.114 + 3017.977 * em - 5740.032 * emsq + 3708.2760 * eoc;
+            } else {
+                g211 = -72.099 + 331.819 * em - 508.738 * emsq + 266.724 * eoc;
+                g310 = -346.844 + 1582.851 * em - 2415.925 * emsq + 1246.113 * eoc;
+                g322 = -342.585 + 1554.908 * em - 2366.899 * emsq + 1215.972 * eoc;
+                g410 = -1052.797 + 4758.686 * em - 7193.992 * emsq + 3651.957 * eoc;
+                g422 = -3581.690 + 16178.110 * em - 24462.770 * emsq + 12422.520 * eoc;
+                g520 = match em > 0.715 {
+                    true => -5149.66 + 29936.92 * em - 54087.36 * emsq + 31324.56 * eoc,
+                    false => 1464.74 - 4664.75 * em + 3763.64 * emsq,
+                };
+            }
+            let (g533, g521, g532) = match em < 0.7 {
+                true => (
+                    -919.22770 + 4988.6100 * em - 9064.7700 * emsq + 5542.21 * eoc,
+                    -822.71072 + 4568.6173 * em - 8491.4146 * emsq + 5337.524 * eoc,
+                    -853.66600 + 4690.2500 * em - 8624.7700 * emsq + 5341.4 * eoc,
+                ),
+                false => (
+                    -37995.780 + 161616.52 * em - 229838.20 * emsq + 109377.94 * eoc,
+                    -51752.104 + 218913.95 * em - 309468.16 * emsq + 146349.42 * eoc,
+                    -40023.880 + 170470.89 * em - 242699.48 * emsq + 115605.82 * eoc,
+                ),
+            };
+
+            let sini2 = sinim * sinim;
+            let f220 = 0.75 * (1.0 + 2.0 * cosim + cosisq);
+            let f221 = 1.5 * sini2;
+            let f321 = 1.875 * sinim * (1.0 - 2.0 * cosim - 3.0 * cosisq);
+            let f322 = -1.875 * sinim * (1.0 + 2.0 * cosim - 3.0 * cosisq);
+            let f441 = 35.0 * sini2 * f220;
+            let f442 = 39.3750 * sini2 * sini2;
+            let f522 = 9.84375
+                * sinim
+                * (sini2 * (1.0 - 2.0 * cosim - 5.0 * cosisq) + 0.33333333 * (-2.0 + 4.0 * cosim + 6.0 * cosisq));
+            let f523 = sinim
+                * (4.92187512 * sini2 * (-2.0 - 4.0 * cosim + 10.0 * cosisq)
+                    + 6.56250012 * (1.0 + 2.0 * cosim - 3.0 * cosisq));
+            let f542 = 29.53125 * sinim * (2.0 - 8.0 * cosim + cosisq * (-12.0 + 8.0 * cosim + 10.0 * cosisq));
+            let f543 = 29.53125 * sinim * (-2.0 - 8.0 * cosim + cosisq * (12.0 + 8.0 * cosim - 10.0 * cosisq));
+
+            let mut temp1 = 3.0 * nm * nm * aonv * aonv;
+            let temp = temp1 * 1.7891679e-6;
+            let (d2201, d2211) = (temp * f220 * g201, temp * f221 * g211);
+            temp1 *= aonv;
+            let temp = temp1 * 3.7393792e-7;
+            let (d3210, d3222) = (temp * f321 * g310, temp * f322 * g322);
+            temp1 *= aonv;
+            let temp = 2.0 * temp1 * 7.3636953e-9;
+            let (d4410, d4422) = (temp * f441 * g410, temp * f442 * g422);
+            temp1 *= aonv;
+            let temp = temp1 * 1.1428639e-7;
+            let (d5220, d5232) = (temp * f522 * g520, temp * f523 * g532);
+            let temp = 2.0 * temp1 * 2.1765803e-9;
+            let (d5421, d5433) = (temp * f542 * g521, temp * f543 * g533);
+            resonance = Resonance::HalfDay { d: [d2201, d2211, d3210, d3222, d4410, d4422, d5220, d5232, d5421, d5433] };
+            xlamo = (el.m + el.node + el.node - theta - theta) % TWOPI;
+            xfact = mdot + dmdt + 2.0 * (nodedot + dnodt - RPTIM) - el.n;
+        } else if synchronous {
+            let g200 = 1.0 + emsq * (-2.5 + 0.8125 * emsq);
+            let g310 = 1.0 + 2.0 * emsq;
+            let g300 = 1.0 + emsq * (-6.0 + 6.60937 * emsq);
+            let f220 = 0.75 * (1.0 + cosim) * (1.0 + cosim);
+            let f311 = 0.9375 * sinim * sinim * (1.0 + 3.0 * cosim) - 0.75 * (1.0 + cosim);
+            let f330 = 1.875 * (1.0 + cosim).powi(3);
+            let del1 = 3.0 * nm * nm * aonv * aonv;
+            let del2 = 2.0 * del1 * f220 * g200 * 1.7891679e-6;
+            let del3 = 3.0 * del1 * f330 * g300 * 2.2123015e-7 * aonv;
+            let del1 = del1 * f311 * g310 * 2.1460748e-6 * aonv;
+            resonance = Resonance::Synchronous { del: [del1, del2, del3] };
+            xlamo = (el.m + el.node + el.argp - theta) % TWOPI;
+            xfact = mdot + (argpdot + nodedot) - RPTIM + dmdt + domdt + dnodt - el.n;
+        }
+
+        return DeepSpace {
+            sun: sun.periodics,
+            moon: moon.periodics,
+            zmos,
+            zmol,
+            dedt,
+            didt,
+            dmdt,
+            dnodt,
+            domdt,
+            resonance,
+            gsto,
+            xfact,
+            xlamo,
+            argpo: el.argp,
+            argpdot,
+            no_unkozai: el.n,
+        };
+    }
+
+    /// `dspace`: the secular lunar-solar effects and resonance at `t` minutes from epoch, on
+    /// top of the near-earth secular elements in `el`. The resonance is integrated from epoch in
+    /// half day steps every call.
+    pub fn secular(&self, t: f64, el: &mut Mean) {
+        const FASX2: f64 = 0.13130908;
+        const FASX4: f64 = 2.8843198;
+        const FASX6: f64 = 0.37448087;
+        const G22: f64 = 5.7686396;
+        const G32: f64 = 0.95240898;
+        const G44: f64 = 1.8014998;
+        const G52: f64 = 1.0508330;
+        const G54: f64 = 4.4108898;
+        const STEPP: f64 = 720.0;
+        const STEP2: f64 = 259200.0;
+
+        let theta = (self.gsto + t * RPTIM) % TWOPI;
+        el.e += self.dedt * t;
+        el.incl += self.didt * t;
+        el.argp += self.domdt * t;
+        el.node += self.dnodt * t;
+        el.m += self.dmdt * t;
+        if let Resonance::None = self.resonance {
+            return;
+        }
+
+        // Mean longitude and motion, with their first and second derivatives.
+        let derivatives = |atime: f64, xli: f64, xni: f64| -> (f64, f64, f64) {
+            let xldot = xni + self.xfact;
+            let (xndt, xnddt) = match &self.resonance {
+                Resonance::None => unreachable!(),
+                Resonance::Synchronous { del } => (
+                    del[0] * (xli - FASX2).sin() + del[1] * (2.0 * (xli - FASX4)).sin() + del[2] * (3.0 * (xli - FASX6)).sin(),
+                    del[0] * (xli - FASX2).cos()
+                        + 2.0 * del[1] * (2.0 * (xli - FASX4)).cos()
+                        + 3.0 * del[2] * (3.0 * (xli - FASX6)).cos(),
+                ),
+                Resonance::HalfDay { d } => {
+                    let xomi = self.argpo + self.argpdot * atime;
+                    let x2omi = xomi + xomi;
+                    let x2li = xli + xli;
+                    let args = [
+                        x2omi + xli - G22,
+                        xli - G22,
+                        xomi + xli - G32,
+                        -xomi + xli - G32,
+                        x2omi + x2li - G44,
+                        x2li - G44,
+                        xomi + xli - G52,
+                        -xomi + xli - G52,
+                        xomi + x2li - G54,
+                        -xomi + x2li - G54,
+                    ];
+                    // The terms in 2 * xli change twice as fast.
+                    let factor = [1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0];
+                    let xndt = d.iter().zip(&args).map(|(d, a)| d * a.sin()).sum();
+                    let xnddt = d.iter().zip(&args).zip(&factor).map(|((d, a), f)| f * d * a.cos()).sum();
+                    (xndt, xnddt)
+                }
+            };
+            return (xldot, xndt, xnddt * xldot);
+        };
+
+        let delt = if t > 0.0 { STEPP } else { -STEPP };
+        let (mut atime, mut xli, mut xni) = (0.0, self.xlamo, self.no_unkozai);
+        let (xldot, xndt, xnddt) = loop {
+            let (xldot, xndt, xnddt) = derivatives(atime, xli, xni);
+            if (t - atime).abs() < STEPP {
+                break (xldot, xndt, xnddt);
+            }
+            xli += xldot * delt + xndt * STEP2;
+            xni += xndt * delt + xnddt * STEP2;
+            atime += delt;
+        };
+        let ft = t - atime;
+        el.n = xni + xndt * ft + xnddt * ft * ft * 0.5;
+        let xl = xli + xldot * ft + xndt * ft * ft * 0.5;
+        el.m = match self.resonance {
+            Resonance::Synchronous { .. } => xl - el.node - el.argp + theta,
+            _ => xl - 2.0 * el.node + 2.0 * theta,
+        };
+    }
+
+    /// `dpper`: the lunar-solar periodics at `t` minutes from epoch, applied to `el`.
+    pub fn periodic(&self, t: f64, el: &mut Mean) {
+        let terms = |p: &Periodics, zm: f64, ze: f64| {
+            let zf = zm + 2.0 * ze * zm.sin();
+            let sinzf = zf.sin();
+            let f2 = 0.5 * sinzf * sinzf - 0.25;
+            let f3 = -0.5 * sinzf * zf.cos();
+            return [
+                p.e2 * f2 + p.e3 * f3,
+                p.i2 * f2 + p.i3 * f3,
+                p.l2 * f2 + p.l3 * f3 + p.l4 * sinzf,
+                p.gh2 * f2 + p.gh3 * f3 + p.gh4 * sinzf,
+                p.h2 * f2 + p.h3 * f3,
+            ];
+        };
+        let s = terms(&self.sun, self.zmos + ZNS * t, ZES);
+        let l = terms(&self.moon, self.zmol + ZNL * t, ZEL);
+        let [pe, pinc, pl, mut pgh, mut ph] = [0, 1, 2, 3, 4].map(|i| s[i] + l[i]);
+
+        el.incl += pinc;
+        el.e += pe;
+        let (sinip, cosip) = el.incl.sin_cos();
+        if el.incl >= 0.2 {
+            ph /= sinip;
+            pgh -= cosip * ph;
+            el.argp += pgh;
+            el.node += ph;
+            el.m += pl;
+        } else {
+            // Lyddane's modification, for low inclinations.
+            let (sinop, cosop) = el.node.sin_cos();
+            let alfdp = sinip * sinop + ph * cosop + pinc * cosip * sinop;
+            let betdp = sinip * cosop - ph * sinop + pinc * cosip * cosop;
+            el.node %= TWOPI;
+            let xls = el.m + el.argp + cosip * el.node + pl + pgh - pinc * el.node * sinip;
+            let xnoh = el.node;
+            el.node = alfdp.atan2(betdp);
+            if (xnoh - el.node).abs() > PI {
+                el.node += if el.node < xnoh { TWOPI } else { -TWOPI };
+            }
+            el.m += pl;
+            el.argp = xls - el.m - cosip * el.node;
+        }
+    }
+}
@@ -0,0 +1,470 @@
+use std::f64::consts::PI;
+
+use nalgebra::Vector3;
+
+use super::sdp4::{DeepSpace, Mean};
+use super::{gmst, Elements};
+
+// WGS72 constants, as used to generate TLEs.
+const MU: f64 = 398600.8;
+const RE: f64 = 6378.135;
+const J2: f64 = 0.001082616;
+const J3: f64 = -0.00000253881;
+const J4: f64 = -0.00000165597;
+const J3OJ2: f64 = J3 / J2;
+const X2O3: f64 = 2.0 / 3.0;
+const TWOPI: f64 = 2.0 * PI;
+const MIN_PER_DAY: f64 = 1440.0;
+
+fn xke() -> f64 {
+    return 60.0 / (RE * RE * RE / MU).sqrt();
+}
+
+/// SGP4, following Vallado et al., "Revisiting Spacetrack Report #3" (AIAA 2006-6753). Orbits
+/// with a period of 225 minutes or more get the deep-space (SDP4) terms as well.
+#[derive(Clone, Debug)]
+pub struct Sgp4 {
+    pub epoch_jd: f64,
+
+    isimp: bool,
+    bstar: f64,
+    ecco: f64,
+    inclo: f64,
+    nodeo: f64,
+    argpo: f64,
+    mo: f64,
+    no_unkozai: f64,
+
+    aycof: f64,
+    con41: f64,
+    cc1: f64,
+    cc4: f64,
+    cc5: f64,
+    d2: f64,
+    d3: f64,
+    d4: f64,
+    delmo: f64,
+    eta: f64,
+    argpdot: f64,
+    omgcof: f64,
+    sinmao: f64,
+    t2cof: f64,
+    t3cof: f64,
+    t4cof: f64,
+    t5cof: f64,
+    x1mth2: f64,
+    x7thm1: f64,
+    mdot: f64,
+    nodedot: f64,
+    xlcof: f64,
+    xmcof: f64,
+    nodecf: f64,
+    deep: Option<Box<DeepSpace>>,
+}
+
+impl Sgp4 {
+    pub fn new(el: &Elements) -> anyhow::Result<Self> {
+        let xpdotp = MIN_PER_DAY / TWOPI;
+        let no_kozai = el.mean_motion / xpdotp;
+        let ecco = el.eccentricity;
+        let inclo = el.inclination.to_radians();
+        let xke = xke();
+
+        if !(0.0..1.0).contains(&ecco) || no_kozai <= 0.0 {
+            anyhow::bail!("SGP4: invalid elements for {} (e = {ecco}, n = {})", el.name, el.mean_motion);
+        }
+
+        // initl
+        let eccsq = ecco * ecco;
+        let omeosq = 1.0 - eccsq;
+        let rteosq = omeosq.sqrt();
+        let cosio = inclo.cos();
+        let cosio2 = cosio * cosio;
+
+        let ak = (xke / no_kozai).powf(X2O3);
+        let d1 = 0.75 * J2 * (3.0 * cosio2 - 1.0) / (rteosq * omeosq);
+        let mut del = d1 / (ak * ak);
+        let adel = ak * (1.0 - del * del - del * (1.0 / 3.0 + 134.0 * del * del / 81.0));
+        del = d1 / (adel * adel);
+        let no_unkozai = no_kozai / (1.0 + del);
+
+        let ao = (xke / no_unkozai).powf(X2O3);
+        let sinio = inclo.sin();
+        let po = ao * omeosq;
+        let con42 = 1.0 - 5.0 * cosio2;
+        let con41 = -con42 - cosio2 - cosio2;
+        let posq = po * po;
+        let rp = ao * (1.0 - ecco);
+
+        // sgp4init
+        let deep_space = TWOPI / no_unkozai >= 225.0;
+        let ss = 78.0 / RE + 1.0;
+        let qzms2t = ((120.0 - 78.0) / RE).powi(4);
+        let isimp = deep_space || rp < 220.0 / RE + 1.0;
+
+        let mut sfour = ss;
+        let mut qzms24 = qzms2t;
+        let perige = (rp - 1.0) * RE;
+        if perige < 156.0 {
+            sfour = if perige < 98.0 { 20.0 } else { perige - 78.0 };
+            qzms24 = ((120.0 - sfour) / RE).powi(4);
+            sfour = sfour / RE + 1.0;
+        }
+
+        let bstar = el.bstar;
+        let argpo = el.arg_perigee.to_radians();
+        let mo = el.mean_anomaly.to_radians();
+
+        let pinvsq = 1.0 / posq;
+        let tsi = 1.0 / (ao - sfour);
+        let eta = ao * ecco * tsi;
+        let etasq = eta * eta;
+        let eeta = ecco * eta;
+        let psisq = (1.0 - etasq).abs();
+        let coef = qzms24 * tsi.powi(4);
+        let coef1 = coef / psisq.powf(3.5);
+        let cc2 = coef1
+            * no_unkozai
+            * (ao * (1.0 + 1.5 * etasq + eeta * (4.0 + etasq))
+                + 0.375 * J2 * tsi / psisq * con41 * (8.0 + 3.0 * etasq * (8.0 + etasq)));
+        let cc1 = bstar * cc2;
+        let cc3 = if ecco > 1.0e-4 { -2.0 * coef * tsi * J3OJ2 * no_unkozai * sinio / ecco } else { 0.0 };
+        let x1mth2 = 1.0 - cosio2;
+        let cc4 = 2.0
+            * no_unkozai
+            * coef1
+            * ao
+            * omeosq
+            * (eta * (2.0 + 0.5 * etasq) + ecco * (0.5 + 2.0 * etasq)
+                - J2 * tsi / (ao * psisq)
+                    * (-3.0 * con41 * (1.0 - 2.0 * eeta + etasq * (1.5 - 0.5 * eeta))
+                        + 0.75 * x1mth2 * (2.0 * etasq - eeta * (1.0 + etasq)) * (2.0 * argpo).cos()));
+        let cc5 = 2.0 * coef1 * ao * omeosq * (1.0 + 2.75 * (etasq + eeta) + eeta * etasq);
+
+        let cosio4 = cosio2 * cosio2;
+        let temp1 = 1.5 * J2 * pinvsq * no_unkozai;
+        let temp2 = 0.5 * temp1 * J2 * pinvsq;
+        let temp3 = -0.46875 * J4 * pinvsq * pinvsq * no_unkozai;
+        let mdot = no_unkozai
+            + 0.5 * temp1 * rteosq * con41
+            + 0.0625 * temp2 * rteosq * (13.0 - 78.0 * cosio2 + 137.0 * cosio4);
+        let argpdot = -0.5 * temp1 * con42
+            + 0.0625 * temp2 * (7.0 - 114.0 * cosio2 + 395.0 * cosio4)
+            + temp3 * (3.0 - 36.0 * cosio2 + 49.0 * cosio4);
+        let xhdot1 = -temp1 * cosio;
+        let nodedot = xhdot1 + (0.5 * temp2 * (4.0 - 19.0 * cosio2) + 2.0 * temp3 * (3.0 - 7.0 * cosio2)) * cosio;
+        let omgcof = bstar * cc3 * argpo.cos();
+        let xmcof = if ecco > 1.0e-4 { -X2O3 * coef * bstar / eeta } else { 0.0 };
+        let nodecf = 3.5 * omeosq * xhdot1 * cc1;
+        let t2cof = 1.5 * cc1;
+        let xlcof = if (cosio + 1.0).abs() > 1.5e-12 {
+            -0.25 * J3OJ2 * sinio * (3.0 + 5.0 * cosio) / (1.0 + cosio)
+        } else {
+            -0.25 * J3OJ2 * sinio * (3.0 + 5.0 * cosio) / 1.5e-12
+        };
+        let aycof = -0.5 * J3OJ2 * sinio;
+        let delmo = (1.0 + eta * mo.cos()).powi(3);
+        let sinmao = mo.sin();
+        let x7thm1 = 7.0 * cosio2 - 1.0;
+        let nodeo = el.raan.to_radians();
+
+        let deep = deep_space.then(|| {
+            let mean = Mean { e: ecco, incl: inclo, node: nodeo, argp: argpo, m: mo, n: no_unkozai };
+            Box::new(DeepSpace::new(el.epoch_jd, gmst(el.epoch_jd), &mean, xke, mdot, argpdot, nodedot))
+        });
+
+        let (mut d2, mut d3, mut d4, mut t3cof, mut t4cof, mut t5cof) = (0., 0., 0., 0., 0., 0.);
+        if !isimp {
+            let cc1sq = cc1 * cc1;
+            d2 = 4.0 * ao * tsi * cc1sq;
+            let temp = d2 * tsi * cc1 / 3.0;
+            d3 = (17.0 * ao + sfour) * temp;
+            d4 = 0.5 * temp * ao * tsi * (221.0 * ao + 31.0 * sfour) * cc1;
+            t3cof = d2 + 2.0 * cc1sq;
+            t4cof = 0.25 * (3.0 * d3 + cc1 * (12.0 * d2 + 10.0 * cc1sq));
+            t5cof = 0.2 * (3.0 * d4 + 12.0 * cc1 * d3 + 6.0 * d2 * d2 + 15.0 * cc1sq * (2.0 * d2 + cc1sq));
+        }
+
+        return Ok(Sgp4 {
+            epoch_jd: el.epoch_jd,
+            isimp,
+            bstar,
+            ecco,
+            inclo,
+            nodeo,
+            argpo,
+            mo,
+            no_unkozai,
+            aycof,
+            con41,
+            cc1,
+            cc4,
+            cc5,
+            d2,
+            d3,
+            d4,
+            delmo,
+            eta,
+            argpdot,
+            omgcof,
+            sinmao,
+            t2cof,
+            t3cof,
+            t4cof,
+            t5cof,
+            x1mth2,
+            x7thm1,
+            mdot,
+            nodedot,
+            xlcof,
+            xmcof,
+            nodecf,
+            deep,
+        });
+    }
+
+    /// Orbital period at epoch in minutes.
+    pub fn period_minutes(&self) -> f64 {
+        return TWOPI / self.no_unkozai;
+    }
+
+    /// Position [km] and velocity [km/s] in TEME at `tsince` minutes from epoch.
+    pub fn propagate(&self, tsince: f64) -> anyhow::Result<(Vector3<f64>, Vector3<f64>)> {
+        let xke = xke();
+        let vkmpersec = RE * xke / 60.0;
+        let t = tsince;
+
+        let xmdf = self.mo + self.mdot * t;
+        let argpdf = self.argpo + self.argpdot * t;
+        let nodedf = self.nodeo + self.nodedot * t;
+        let mut argpm = argpdf;
+        let mut mm = xmdf;
+        let t2 = t * t;
+        let mut nodem = nodedf + self.nodecf * t2;
+        let mut tempa = 1.0 - self.cc1 * t;
+        let mut tempe = self.bstar * self.cc4 * t;
+        let mut templ = self.t2cof * t2;
+
+        if !self.isimp {
+            let delomg = self.omgcof * t;
+            let delm = self.xmcof * ((1.0 + self.eta * xmdf.cos()).powi(3) - self.delmo);
+            let temp = delomg + delm;
+            mm = xmdf + temp;
+            argpm = argpdf - temp;
+            let t3 = t2 * t;
+            let t4 = t3 * t;
+            tempa = tempa - self.d2 * t2 - self.d3 * t3 - self.d4 * t4;
+            tempe += self.bstar * self.cc5 * (mm.sin() - self.sinmao);
+            templ = templ + self.t3cof * t3 + t4 * (self.t4cof + t * self.t5cof);
+        }
+
+        let mut secular = Mean { e: self.ecco, incl: self.inclo, node: nodem, argp: argpm, m: mm, n: self.no_unkozai };
+        if let Some(deep) = &self.deep {
+            deep.secular(t, &mut secular);
+            if secular.n <= 0.0 {
+                anyhow::bail!("SGP4: mean motion < 0 at t = {t} min");
+            }
+        }
+        (nodem, argpm, mm) = (secular.node, secular.argp, secular.m);
+
+        let am = (xke / secular.n).powf(X2O3) * tempa * tempa;
+        let nm = xke / am.powf(1.5);
+        let mut em = secular.e - tempe;
+        if !(-0.001..1.0).contains(&em) {
+            anyhow::bail!("SGP4: eccentricity out of range ({em}) at t = {t} min");
+        }
+        em = em.max(1.0e-6);
+        mm += self.no_unkozai * templ;
+        let mut xlm = mm + argpm + nodem;
+        nodem %= TWOPI;
+        argpm %= TWOPI;
+        xlm %= TWOPI;
+        mm = (xlm - argpm - nodem) % TWOPI;
+
+        let (mut aycof, mut xlcof, mut con41, mut x1mth2, mut x7thm1) = (self.aycof, self.xlcof, self.con41, self.x1mth2, self.x7thm1);
+        let mut p = Mean { e: em, incl: secular.incl, node: nodem, argp: argpm, m: mm, n: nm };
+        if let Some(deep) = &self.deep {
+            // lunar-solar periodics, and the coefficients that depend on the inclination
+            deep.periodic(t, &mut p);
+            if p.incl < 0.0 {
+                p.incl = -p.incl;
+                p.node += PI;
+                p.argp -= PI;
+            }
+            if !(0.0..=1.0).contains(&p.e) {
+                anyhow::bail!("SGP4: eccentricity out of range ({}) at t = {t} min", p.e);
+            }
+            let (sinip, cosip) = p.incl.sin_cos();
+            aycof = -0.5 * J3OJ2 * sinip;
+            xlcof = -0.25 * J3OJ2 * sinip * (3.0 + 5.0 * cosip) / (1.0 + cosip).max(1.5e-12);
+            let cosisq = cosip * cosip;
+            con41 = 3.0 * cosisq - 1.0;
+            x1mth2 = 1.0 - cosisq;
+            x7thm1 = 7.0 * cosisq - 1.0;
+        }
+        let (sinip, cosip) = p.incl.sin_cos();
+        let (em, nodem, argpm, mm) = (p.e, p.node, p.argp, p.m);
+
+        // long period periodics
+        let axnl = em * argpm.cos();
+        let temp = 1.0 / (am * (1.0 - em * em));
+        let aynl = em * argpm.sin() + temp * aycof;
+        let xl = mm + argpm + nodem + temp * xlcof * axnl;
+
+        // solve kepler's equation
+        let u = (xl - nodem) % TWOPI;
+        let mut eo1 = u;
+        let mut tem5: f64 = 9999.9;
+        let mut ktr = 1;
+        let (mut sineo1, mut coseo1) = (0.0, 0.0);
+        while tem5.abs() >= 1.0e-12 && ktr <= 10 {
+            (sineo1, coseo1) = eo1.sin_cos();
+            tem5 = 1.0 - coseo1 * axnl - sineo1 * aynl;
+            tem5 = (u - aynl * coseo1 + axnl * sineo1 - eo1) / tem5;
+            if tem5.abs() >= 0.95 {
+                tem5 = 0.95f64.copysign(tem5);
+            }
+            eo1 += tem5;
+            ktr += 1;
+        }
+
+        // short period preliminary quantities
+        let ecose = axnl * coseo1 + aynl * sineo1;
+        let esine = axnl * sineo1 - aynl * coseo1;
+        let el2 = axnl * axnl + aynl * aynl;
+        let pl = am * (1.0 - el2);
+        if pl < 0.0 {
+            anyhow::bail!("SGP4: semi-latus rectum < 0 at t = {t} min");
+        }
+        let rl = am * (1.0 - ecose);
+        let rdotl = am.sqrt() * esine / rl;
+        let rvdotl = pl.sqrt() / rl;
+        let betal = (1.0 - el2).sqrt();
+        let temp = esine / (1.0 + betal);
+        let sinu = am / rl * (sineo1 - aynl - axnl * temp);
+        let cosu = am / rl * (coseo1 - axnl + aynl * temp);
+        let mut su = sinu.atan2(cosu);
+        let sin2u = (cosu + cosu) * sinu;
+        let cos2u = 1.0 - 2.0 * sinu * sinu;
+        let temp = 1.0 / pl;
+        let temp1 = 0.5 * J2 * temp;
+        let temp2 = temp1 * temp;
+
+        // update for short period periodics
+        let mrt = rl * (1.0 - 1.5 * temp2 * betal * con41) + 0.5 * temp1 * x1mth2 * cos2u;
+        su -= 0.25 * temp2 * x7thm1 * sin2u;
+        let xnode = nodem + 1.5 * temp2 * cosip * sin2u;
+        let xinc = p.incl + 1.5 * temp2 * cosip * sinip * cos2u;
+        let mvt = rdotl - nm * temp1 * x1mth2 * sin2u / xke;
+        let rvdot = rvdotl + nm * temp1 * (x1mth2 * cos2u + 1.5 * con41) / xke;
+
+        // orientation vectors
+        let (sinsu, cossu) = su.sin_cos();
+        let (snod, cnod) = xnode.sin_cos();
+        let (sini, cosi) = xinc.sin_cos();
+        let xmx = -snod * cosi;
+        let xmy = cnod * cosi;
+        let uu = Vector3::new(xmx * sinsu + cnod * cossu, xmy * sinsu + snod * cossu, sini * sinsu);
+        let vv = Vector3::new(xmx * cossu - cnod * sinsu, xmy * cossu - snod * sinsu, sini * cossu);
+
+        if mrt < 1.0 {
+            anyhow::bail!("SGP4: satellite has decayed at t = {t} min");
+        }
+
+        let r = uu * (mrt * RE);
+        let v = (uu * mvt + vv * rvdot) * vkmpersec;
+        return Ok((r, v));
+    }
+
+    /// Position [km] and velocity [km/s] in TEME at the given julian date.
+    pub fn propagate_jd(&self, jd: f64) -> anyhow::Result<(Vector3<f64>, Vector3<f64>)> {
+        return self.propagate((jd - self.epoch_jd) * MIN_PER_DAY);
+    }
+}
+
+// Vectors from Vallado's SGP4-VER.TLE / tcppver.out.
+#[cfg(test)]
+fn check_against(line1: &str, line2: &str, expected: &[[f64; 7]]) {
+    let el = Elements::from_tle("", line1, line2).unwrap();
+    let sgp4 = Sgp4::new(&el).unwrap();
+    for row in expected {
+        let (r, v) = sgp4.propagate(row[0]).unwrap();
+        let dr = (r - Vector3::new(row[1], row[2], row[3])).norm();
+        let dv = (v - Vector3::new(row[4], row[5], row[6])).norm();
+        assert!(dr < 1e-6, "t={} r={r:?} off by {dr} km", row[0]);
+        assert!(dv < 1e-8, "t={} v={v:?} off by {dv} km/s", row[0]);
+    }
+}
+
+#[test]
+fn sgp4_verification_00005() {
+    check_against(
+        "1 00005U 58002B   00179.78495062  .00000023  00000-0  28098-4 0  4753",
+        "2 00005  34.2682 348.7242 1859667 331.7664  19.3264 10.82419157413667",
+        &[
+            [0.0, 7022.46529266, -1400.08296755, 0.03995155, 1.893841015, 6.405893759, 4.534807250],
+            [360.0, -7154.03120202, -3783.17682504, -3536.19412294, 4.741887409, -4.151817765, -2.093935425],
+        ],
+    );
+}
+
+#[test]
+fn sgp4_verification_06251() {
+    check_against(
+        "1 06251U 62025E   06176.82412014  .00008885  00000-0  12808-3 0  3985",
+        "2 06251  58.0579  54.0425 0030035 139.1568 221.1854 15.56387291  6774",
+        &[
+            [0.0, 3988.31022699, 5498.96657235, 0.90055879, -3.290032738, 2.357652820, 6.496623475],
+            [120.0, -3935.69800083, 409.10980837, 5471.33577327, -3.374784183, -6.635211043, -1.942056221],
+            [240.0, -1675.12766915, -5683.30432352, -3286.21510937, 5.282496925, 1.508674259, -5.354872978],
+            [360.0, 4993.62642836, 2890.54969900, -3600.40145627, 0.347333429, 5.707031557, 5.070699638],
+        ],
+    );
+}
+
+#[test]
+fn sdp4_verification_04632() {
+    // Deep space without resonance, inclination just above where Lyddane's modification starts.
+    check_against(
+        "1 04632U 70093B   04031.91070959 -.00000084  00000-0  10000-3 0  9955",
+        "2 04632  11.4628 273.1101 1450506 207.6000 143.9350  1.20231981 44145",
+        &[[0.0, 2334.11450085, -41920.44035349, -0.03867437, 2.826321032, -0.065091664, 0.570936053]],
+    );
+}
+
+#[test]
+fn sdp4_verification_08195() {
+    // A Molniya orbit, in 12 hour resonance.
+    check_against(
+        "1 08195U 75081A   06176.33215444  .00000099  00000-0  11873-3 0   813",
+        "2 08195  64.1586 279.0717 6877146 264.7651  20.2257  2.00491383225656",
+        &[[0.0, 2349.89483350, -14785.93811562, 0.02119378, 2.721488096, -3.256811655, 4.498416672]],
+    );
+}
+
+#[test]
+fn sdp4_geosynchronous_stays_put() {
+    // 28626 from the verification set: geosynchronous and nearly equatorial, so both the
+    // synchronous resonance and Lyddane's modification are used.
+    let el = Elements::from_tle(
+        "",
+        "1 28626U 05008A   06176.46683397 -.00000205  00000-0  10000-3 0  2190",
+        "2 28626   0.0019 286.9433 0000335  13.7918  55.6504  1.00270176  4891",
+    )
+    .unwrap();
+    let sgp4 = Sgp4::new(&el).unwrap();
+    let longitude = |t: f64| {
+        let (r, _) = sgp4.propagate(t).unwrap();
+        assert!((r.norm() - 42164.0).abs() < 10.0, "t={t} r={}", r.norm());
+        // The sun and moon tilt the orbit by most of a degree a year.
+        assert!((r.z / r.norm()).asin().to_degrees().abs() < 0.1, "t={t} z={}", r.z);
+        return r.y.atan2(r.x).to_degrees();
+    };
+    // Node, perigee and mean anomaly add up to 356.39 degrees, in inertial space.
+    assert!((longitude(0.0) - (356.3855 - 360.0)).abs() < 0.1, "{}", longitude(0.0));
+    // A little over a revolution a day, with the resonance integrated over ten days.
+    for days in [1.0, -1.0, 10.0] {
+        let expected = longitude(0.0) + 0.00270176 * 360.0 * days;
+        assert!((longitude(days * MIN_PER_DAY) - expected).abs() < 0.5, "{days} days: {}", longitude(days * MIN_PER_DAY));
+    }
+}
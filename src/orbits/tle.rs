@@ -0,0 +1,235 @@
+use serde::Deserialize;
+
+use crate::core::clock::{civil_to_unix, parse_iso8601, unix_to_jd};
+
+/// Mean orbital elements as they appear in a TLE or OMM, in the units used there.
+#[derive(Clone, Debug, Default)]
+pub struct Elements {
+    pub name: String,
+    pub norad_id: u32,
+    pub epoch_jd: f64,
+
+    /// First derivative of mean motion divided by two [rev/day^2], as written in a TLE.
+    pub ndot: f64,
+    /// Second derivative of mean motion divided by six [rev/day^3].
+    pub nddot: f64,
+    /// Drag term [1/earth radii].
+    pub bstar: f64,
+
+    pub inclination: f64,
+    pub raan: f64,
+    pub eccentricity: f64,
+    pub arg_perigee: f64,
+    pub mean_anomaly: f64,
+    /// [rev/day]
+    pub mean_motion: f64,
+}
+
+fn field(line: &str, a: usize, b: usize) -> anyhow::Result<&str> {
+    return line
+        .get(a..b)
+        .map(str::trim)
+        .ok_or_else(|| anyhow::anyhow!("TLE line too short: '{line}'"));
+}
+
+fn float(line: &str, a: usize, b: usize) -> anyhow::Result<f64> {
+    let f = field(line, a, b)?;
+    return f.parse().map_err(|_| anyhow::anyhow!("bad TLE number '{f}' in '{line}'"));
+}
+
+// TLE "assumed decimal point" exponent notation, e.g. " 12345-3" == 0.12345e-3
+fn exp_float(line: &str, a: usize, b: usize) -> anyhow::Result<f64> {
+    let f = field(line, a, b)?.replace(' ', "");
+    if f.is_empty() {
+        return Ok(0.);
+    }
+    let (mant, exp) = match f[1..].rfind(['-', '+']) {
+        Some(i) => f.split_at(i + 1),
+        None => (f.as_str(), "0"),
+    };
+    let (sign, digits) = match mant.strip_prefix('-') {
+        Some(d) => (-1., d),
+        None => (1., mant.strip_prefix('+').unwrap_or(mant)),
+    };
+    let m: f64 = format!("0.{digits}").parse().map_err(|_| anyhow::anyhow!("bad TLE number '{f}'"))?;
+    let e: i32 = exp.parse().map_err(|_| anyhow::anyhow!("bad TLE exponent '{f}'"))?;
+    return Ok(sign * m * 10f64.powi(e));
+}
+
+fn checksum_ok(line: &str) -> bool {
+    let Some(expect) = line.chars().nth(68).and_then(|c| c.to_digit(10)) else {
+        return true;
+    };
+    let sum: u32 = line
+        .chars()
+        .take(68)
+        .map(|c| match c {
+            '-' => 1,
+            _ => c.to_digit(10).unwrap_or(0),
+        })
+        .sum();
+    return sum % 10 == expect;
+}
+
+impl Elements {
+    pub fn from_tle(name: &str, line1: &str, line2: &str) -> anyhow::Result<Self> {
+        if !line1.starts_with('1') || !line2.starts_with('2') {
+            anyhow::bail!("not a TLE pair:\n{line1}\n{line2}");
+        }
+        for l in [line1, line2] {
+            if !checksum_ok(l) {
+                log::warn!("TLE checksum mismatch: '{l}'");
+            }
+        }
+
+        let norad_id = field(line1, 2, 7)?.parse()?;
+        let yy: i64 = field(line1, 18, 20)?.parse()?;
+        let year = if yy < 57 { 2000 + yy } else { 1900 + yy };
+        let day = float(line1, 20, 32)?;
+        let epoch_jd = unix_to_jd(civil_to_unix(year, 1, 1, 0, 0, 0.)) + day - 1.;
+
+        return Ok(Elements {
+            name: name.trim().to_string(),
+            norad_id,
+            epoch_jd,
+            ndot: float(line1, 33, 43)?,
+            nddot: exp_float(line1, 44, 52)?,
+            bstar: exp_float(line1, 53, 61)?,
+            inclination: float(line2, 8, 16)?,
+            raan: float(line2, 17, 25)?,
+            eccentricity: format!("0.{}", field(line2, 26, 33)?).parse()?,
+            arg_perigee: float(line2, 34, 42)?,
+            mean_anomaly: float(line2, 43, 51)?,
+            mean_motion: float(line2, 52, 63)?,
+        });
+    }
+}
+
+/// Parse a file of two- or three-line element sets. Blank lines and `#` comments are skipped.
+pub fn parse_tle_file(text: &str) -> anyhow::Result<Vec<Elements>> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|l| l.trim_end())
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        .collect();
+
+    let mut out = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let (name, l1, l2) = if lines[i].starts_with("1 ") {
+            ("", lines[i], lines.get(i + 1))
+        } else {
+            let name = lines[i].strip_prefix("0 ").unwrap_or(lines[i]);
+            i += 1;
+            (name, *lines.get(i).unwrap_or(&""), lines.get(i + 1))
+        };
+        let Some(l2) = l2 else {
+            anyhow::bail!("truncated TLE after '{l1}'");
+        };
+        let mut el = Elements::from_tle(name, l1, l2)?;
+        if el.name.is_empty() {
+            el.name = el.norad_id.to_string();
+        }
+        out.push(el);
+        i += 2;
+    }
+    return Ok(out);
+}
+
+// The CCSDS OMM keys as used by e.g. CelesTrak's `FORMAT=json`.
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct OmmJson {
+    #[serde(default)]
+    OBJECT_NAME: String,
+    NORAD_CAT_ID: u32,
+    EPOCH: String,
+    MEAN_MOTION: f64,
+    ECCENTRICITY: f64,
+    INCLINATION: f64,
+    RA_OF_ASC_NODE: f64,
+    ARG_OF_PERICENTER: f64,
+    MEAN_ANOMALY: f64,
+    #[serde(default)]
+    BSTAR: f64,
+    #[serde(default)]
+    MEAN_MOTION_DOT: f64,
+    #[serde(default)]
+    MEAN_MOTION_DDOT: f64,
+}
+
+/// Parse OMM records in JSON form, either a single object or an array of them.
+pub fn parse_omm_json(text: &str) -> anyhow::Result<Vec<Elements>> {
+    let v: serde_json::Value = serde_json::from_str(text)?;
+    let records: Vec<OmmJson> = match v {
+        serde_json::Value::Array(_) => serde_json::from_value(v)?,
+        _ => vec![serde_json::from_value(v)?],
+    };
+
+    return records
+        .into_iter()
+        .map(|r| {
+            Ok(Elements {
+                name: r.OBJECT_NAME,
+                norad_id: r.NORAD_CAT_ID,
+                epoch_jd: unix_to_jd(parse_iso8601(&r.EPOCH)?),
+                ndot: r.MEAN_MOTION_DOT,
+                nddot: r.MEAN_MOTION_DDOT,
+                bstar: r.BSTAR,
+                inclination: r.INCLINATION,
+                raan: r.RA_OF_ASC_NODE,
+                eccentricity: r.ECCENTRICITY,
+                arg_perigee: r.ARG_OF_PERICENTER,
+                mean_anomaly: r.MEAN_ANOMALY,
+                mean_motion: r.MEAN_MOTION,
+            })
+        })
+        .collect();
+}
+
+/// Load a `.json` OMM file or a TLE file, depending on the extension.
+pub fn load_elements(path: &std::path::Path) -> anyhow::Result<Vec<Elements>> {
+    let text = std::fs::read_to_string(path)?;
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+        return parse_omm_json(&text);
+    }
+    return parse_tle_file(&text);
+}
+
+#[test]
+fn parse_iss_tle() {
+    let text = "ISS (ZARYA)
+1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927
+2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537
+";
+    let els = parse_tle_file(text).unwrap();
+    assert_eq!(els.len(), 1);
+    let e = &els[0];
+    assert_eq!(e.name, "ISS (ZARYA)");
+    assert_eq!(e.norad_id, 25544);
+    assert!((e.bstar - -0.11606e-4).abs() < 1e-15);
+    assert!((e.ndot - -0.00002182).abs() < 1e-15);
+    assert!((e.eccentricity - 0.0006703).abs() < 1e-15);
+    assert!((e.mean_motion - 15.72125391).abs() < 1e-12);
+    // 2008 day 264.51782528 -> 2008-09-20T12:25:40.1
+    assert!((e.epoch_jd - 2454730.01782528).abs() < 1e-8);
+    assert!(checksum_ok("1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927"));
+}
+
+#[test]
+fn parse_omm() {
+    let text = r#"[{"OBJECT_NAME":"ISS (ZARYA)","OBJECT_ID":"1998-067A","EPOCH":"2008-09-20T12:25:40.104192",
+        "MEAN_MOTION":15.72125391,"ECCENTRICITY":0.0006703,"INCLINATION":51.6416,"RA_OF_ASC_NODE":247.4627,
+        "ARG_OF_PERICENTER":130.536,"MEAN_ANOMALY":325.0288,"EPHEMERIS_TYPE":0,"CLASSIFICATION_TYPE":"U",
+        "NORAD_CAT_ID":25544,"ELEMENT_SET_NO":292,"REV_AT_EPOCH":56353,"BSTAR":-1.1606e-5,
+        "MEAN_MOTION_DOT":-2.182e-5,"MEAN_MOTION_DDOT":0}]"#;
+    let els = parse_omm_json(text).unwrap();
+    let tle = &parse_tle_file(
+        "1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927
+2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537",
+    )
+    .unwrap()[0];
+    assert_eq!(els[0].norad_id, tle.norad_id);
+    assert!((els[0].epoch_jd - tle.epoch_jd).abs() * 86400. < 1e-3);
+    assert_eq!(tle.name, "25544");
+}
@@ -0,0 +1,157 @@
+use nalgebra::Vector3;
+
+use crate::core::geo::WGS84_F;
+use crate::core::Geodetic;
+
+/// Coordinate reference systems point clouds can be reprojected from. Datums are all taken as
+/// WGS84 (NAD83 and ETRS89 are within a meter or two of it), and heights as ellipsoidal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Crs {
+    /// x = longitude, y = latitude in degrees, z = height.
+    Geographic,
+    /// Already ECEF.
+    Geocentric,
+    Utm { zone: u8, north: bool },
+}
+
+/// A `Crs` plus the length units its coordinates are in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CrsUnits {
+    pub crs: Crs,
+    /// Meters per horizontal unit (ignored for geographic coordinates).
+    pub horizontal: f64,
+    /// Meters per vertical unit.
+    pub vertical: f64,
+}
+
+pub const US_SURVEY_FOOT: f64 = 1200. / 3937.;
+pub const INTERNATIONAL_FOOT: f64 = 0.3048;
+
+impl Crs {
+    /// The CRS for an EPSG code, if it's one we can handle.
+    pub fn from_epsg(code: u32) -> Option<Crs> {
+        match code {
+            4326 | 4979 | 4269 | 4258 | 4937 => return Some(Crs::Geographic),
+            4978 | 4936 => return Some(Crs::Geocentric),
+            // WGS84 UTM north and south.
+            32601..=32660 => return Some(Crs::Utm { zone: (code - 32600) as u8, north: true }),
+            32701..=32760 => return Some(Crs::Utm { zone: (code - 32700) as u8, north: false }),
+            // NAD83 UTM 1N-23N, ETRS89 UTM 28N-38N.
+            26901..=26923 => return Some(Crs::Utm { zone: (code - 26900) as u8, north: true }),
+            25828..=25838 => return Some(Crs::Utm { zone: (code - 25800) as u8, north: true }),
+            _ => return None,
+        }
+    }
+
+    pub fn is_projected(&self) -> bool {
+        return matches!(self, Crs::Utm { .. });
+    }
+}
+
+impl CrsUnits {
+    pub fn meters(crs: Crs) -> Self {
+        return CrsUnits { crs, horizontal: 1., vertical: 1. };
+    }
+
+    pub fn to_ecef(&self, x: f64, y: f64, z: f64) -> Vector3<f64> {
+        let z = z * self.vertical;
+        match self.crs {
+            Crs::Geographic => return Geodetic::new(y, x, z).to_ecef(),
+            Crs::Geocentric => return Vector3::new(x * self.horizontal, y * self.horizontal, z),
+            Crs::Utm { zone, north } => {
+                let (lat, lon) = utm_to_geodetic(zone, north, x * self.horizontal, y * self.horizontal);
+                return Geodetic::new(lat, lon, z).to_ecef();
+            }
+        }
+    }
+}
+
+/// Inverse transverse Mercator on WGS84 with Krüger's series to third order in n, good to about
+/// a millimeter within a UTM zone. Returns latitude and longitude in degrees.
+pub fn utm_to_geodetic(zone: u8, north: bool, easting: f64, northing: f64) -> (f64, f64) {
+    const K0: f64 = 0.9996;
+    let n = WGS84_F / (2. - WGS84_F);
+    let (n2, n3) = (n * n, n * n * n);
+    let a = crate::core::geo::WGS84_A / (1. + n) * (1. + n2 / 4. + n2 * n2 / 64.);
+    let beta = [n / 2. - 2. / 3. * n2 + 37. / 96. * n3, n2 / 48. + n3 / 15., 17. / 480. * n3];
+    let delta = [2. * n - 2. / 3. * n2 - 2. * n3, 7. / 3. * n2 - 8. / 5. * n3, 56. / 15. * n3];
+
+    let false_northing = if north { 0. } else { 10_000_000. };
+    let xi = (northing - false_northing) / (K0 * a);
+    let eta = (easting - 500_000.) / (K0 * a);
+
+    let mut xi1 = xi;
+    let mut eta1 = eta;
+    for (j, b) in beta.iter().enumerate() {
+        let k = 2. * (j + 1) as f64;
+        xi1 -= b * (k * xi).sin() * (k * eta).cosh();
+        eta1 -= b * (k * xi).cos() * (k * eta).sinh();
+    }
+    let chi = (xi1.sin() / eta1.cosh()).asin();
+    let mut lat = chi;
+    for (j, d) in delta.iter().enumerate() {
+        lat += d * (2. * (j + 1) as f64 * chi).sin();
+    }
+    let lon0 = zone as f64 * 6. - 183.;
+    let lon = lon0 + eta1.sinh().atan2(xi1.cos()).to_degrees();
+    return (lat.to_degrees(), lon);
+}
+
+/// Pick the horizontal CRS out of OGC WKT (1 or 2) by its EPSG ids. Nested definitions carry
+/// their own ids (a projected CRS contains its geographic base), so projected codes win over
+/// geocentric and geographic ones.
+pub fn crs_from_wkt(wkt: &str) -> Option<Crs> {
+    let mut found: Vec<Crs> = vec![];
+    for pat in ["AUTHORITY[\"EPSG\",", "ID[\"EPSG\","] {
+        for (i, _) in wkt.match_indices(pat) {
+            let rest = wkt[i + pat.len()..].trim_start_matches(['"', ' ']);
+            let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+            if let Some(crs) = digits.parse().ok().and_then(Crs::from_epsg) {
+                found.push(crs);
+            }
+        }
+    }
+    return found
+        .iter()
+        .find(|c| c.is_projected())
+        .or(found.iter().find(|c| **c == Crs::Geocentric))
+        .or(found.first())
+        .copied();
+}
+
+#[test]
+fn utm_inverse() {
+    // On the central meridian the northing is the scaled meridian arc; integrate it independently.
+    let e2 = crate::core::geo::WGS84_E2;
+    let steps = 10_000;
+    let h = 45f64.to_radians() / steps as f64;
+    let m = |phi: f64| crate::core::geo::WGS84_A * (1. - e2) / (1. - e2 * phi.sin().powi(2)).powf(1.5);
+    let arc: f64 = (0..steps).map(|i| (m(i as f64 * h) + 4. * m((i as f64 + 0.5) * h) + m((i + 1) as f64 * h)) * h / 6.).sum();
+
+    let (lat, lon) = utm_to_geodetic(33, true, 500_000., 0.9996 * arc);
+    assert!((lat - 45.).abs() < 1e-8, "{lat}");
+    assert!((lon - 15.).abs() < 1e-12);
+
+    // Southern hemisphere, and symmetry about the central meridian.
+    let (lat_s, _) = utm_to_geodetic(33, false, 500_000., 10_000_000. - 0.9996 * arc);
+    assert!((lat_s + 45.).abs() < 1e-8);
+    let (lat_e, lon_e) = utm_to_geodetic(10, true, 600_000., 5_000_000.);
+    let (lat_w, lon_w) = utm_to_geodetic(10, true, 400_000., 5_000_000.);
+    assert!((lat_e - lat_w).abs() < 1e-12);
+    assert!((lon_e + lon_w + 2. * 123.).abs() < 1e-9, "{lon_e} {lon_w}");
+    // 100 km east at 45°N is about 1.27° of longitude.
+    assert!((lon_e + 123. - 1.27).abs() < 0.01, "{lon_e}");
+}
+
+#[test]
+fn crs_from_codes_and_wkt() {
+    assert_eq!(Crs::from_epsg(32633), Some(Crs::Utm { zone: 33, north: true }));
+    assert_eq!(Crs::from_epsg(32718), Some(Crs::Utm { zone: 18, north: false }));
+    assert_eq!(Crs::from_epsg(2056), None);
+    let wkt = r#"COMPD_CS["WGS 84 / UTM zone 33N + EGM96",PROJCS["WGS 84 / UTM zone 33N",GEOGCS["WGS 84",
+        AUTHORITY["EPSG","4326"]],PROJECTION["Transverse_Mercator"],AUTHORITY["EPSG","32633"]],
+        VERT_CS["EGM96 height",AUTHORITY["EPSG","5773"]]]"#;
+    assert_eq!(crs_from_wkt(wkt), Some(Crs::Utm { zone: 33, north: true }));
+    assert_eq!(crs_from_wkt(r#"GEOGCRS["WGS 84",ID["EPSG",4979]]"#), Some(Crs::Geographic));
+    assert_eq!(crs_from_wkt("LOCAL_CS[\"x\"]"), None);
+}
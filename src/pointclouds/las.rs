@@ -0,0 +1,340 @@
+use anyhow::Context;
+
+use super::crs::{crs_from_wkt, Crs, CrsUnits, INTERNATIONAL_FOOT, US_SURVEY_FOOT};
+
+/// Variable length record, from the header area or (LAS 1.4) after the points.
+#[derive(Clone, Debug)]
+pub struct Vlr {
+    pub user_id: String,
+    pub record_id: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LasHeader {
+    pub version: (u8, u8),
+    pub point_format: u8,
+    /// Set on LAZ files.
+    pub compressed: bool,
+    pub point_record_length: u16,
+    pub point_count: u64,
+    pub offset_to_points: u32,
+    pub scale: [f64; 3],
+    pub offset: [f64; 3],
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+    pub vlrs: Vec<Vlr>,
+}
+
+/// Points as stored, with coordinates scaled and offset but still in the file's CRS.
+#[derive(Clone, Debug, Default)]
+pub struct LasPoints {
+    pub positions: Vec<[f64; 3]>,
+    pub intensity: Vec<u16>,
+    pub classification: Vec<u8>,
+    /// Formats 2, 3, 5, 7, 8 and 10.
+    pub rgb: Option<Vec<[u16; 3]>>,
+}
+
+fn u16_at(b: &[u8], i: usize) -> u16 {
+    return u16::from_le_bytes(b[i..i + 2].try_into().unwrap());
+}
+
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    return u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+}
+
+fn u64_at(b: &[u8], i: usize) -> u64 {
+    return u64::from_le_bytes(b[i..i + 8].try_into().unwrap());
+}
+
+fn f64_at(b: &[u8], i: usize) -> f64 {
+    return f64::from_le_bytes(b[i..i + 8].try_into().unwrap());
+}
+
+fn i32_at(b: &[u8], i: usize) -> i32 {
+    return i32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+}
+
+/// Smallest record length of each point format, 0 to 10.
+const FORMAT_LENGTHS: [u16; 11] = [20, 28, 26, 34, 57, 63, 30, 36, 38, 59, 67];
+
+/// Where the RGB triple starts in a record, for formats that have one.
+fn rgb_offset(format: u8) -> Option<usize> {
+    match format {
+        2 => return Some(20),
+        3 | 5 => return Some(28),
+        7 | 8 | 10 => return Some(30),
+        _ => return None,
+    }
+}
+
+fn fixed_string(b: &[u8]) -> String {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    return String::from_utf8_lossy(&b[..end]).into_owned();
+}
+
+/// Parse the public header block and the VLRs (including LAS 1.4 extended VLRs) of a LAS file.
+pub fn parse_header(bytes: &[u8]) -> anyhow::Result<LasHeader> {
+    anyhow::ensure!(bytes.len() >= 227 && bytes.starts_with(b"LASF"), "not a LAS file");
+    let version = (bytes[24], bytes[25]);
+    anyhow::ensure!(version.0 == 1 && (0..=4).contains(&version.1), "unsupported LAS version {}.{}", version.0, version.1);
+    let header_size = u16_at(bytes, 94) as usize;
+    let offset_to_points = u32_at(bytes, 96);
+    let vlr_count = u32_at(bytes, 100);
+    let format_byte = bytes[104];
+
+    let triple = |i: usize| [f64_at(bytes, i), f64_at(bytes, i + 8), f64_at(bytes, i + 16)];
+    let mut header = LasHeader {
+        version,
+        // LAZ sets the top bits of the format byte.
+        point_format: format_byte & 0x3f,
+        compressed: format_byte & 0xc0 != 0,
+        point_record_length: u16_at(bytes, 105),
+        point_count: u32_at(bytes, 107) as u64,
+        offset_to_points,
+        scale: triple(131),
+        offset: triple(155),
+        max: [f64_at(bytes, 179), f64_at(bytes, 195), f64_at(bytes, 211)],
+        min: [f64_at(bytes, 187), f64_at(bytes, 203), f64_at(bytes, 219)],
+        vlrs: vec![],
+    };
+
+    let mut evlrs = None;
+    if version.1 >= 4 {
+        anyhow::ensure!(bytes.len() >= 375 && header_size >= 375, "LAS 1.4 header too short");
+        // The 64 bit count is authoritative; the legacy one is 0 for formats 6-10.
+        header.point_count = u64_at(bytes, 247);
+        evlrs = Some((u64_at(bytes, 235) as usize, u32_at(bytes, 243)));
+    }
+
+    let mut at = header_size;
+    for _ in 0..vlr_count {
+        anyhow::ensure!(bytes.len() >= at + 54, "VLR header past end of file");
+        let len = u16_at(bytes, at + 20) as usize;
+        let data = bytes.get(at + 54..at + 54 + len).context("VLR past end of file")?;
+        header.vlrs.push(Vlr { user_id: fixed_string(&bytes[at + 2..at + 18]), record_id: u16_at(bytes, at + 18), data: data.to_vec() });
+        at += 54 + len;
+    }
+    if let Some((mut at, count)) = evlrs.filter(|&(start, _)| start > 0) {
+        for _ in 0..count {
+            anyhow::ensure!(bytes.len() >= at + 60, "EVLR header past end of file");
+            let len = u64_at(bytes, at + 20) as usize;
+            let data = bytes.get(at + 60..at + 60 + len).context("EVLR past end of file")?;
+            header.vlrs.push(Vlr { user_id: fixed_string(&bytes[at + 2..at + 18]), record_id: u16_at(bytes, at + 18), data: data.to_vec() });
+            at += 60 + len;
+        }
+    }
+    return Ok(header);
+}
+
+impl LasHeader {
+    fn vlr(&self, user_id: &str, record_id: u16) -> Option<&Vlr> {
+        return self.vlrs.iter().find(|v| v.user_id == user_id && v.record_id == record_id);
+    }
+
+    /// The CRS from the WKT or GeoTIFF key VLRs, with units.
+    pub fn crs(&self) -> Option<CrsUnits> {
+        if let Some(v) = self.vlr("LASF_Projection", 2112) {
+            let wkt = String::from_utf8_lossy(&v.data);
+            let crs = crs_from_wkt(&wkt)?;
+            let feet = if wkt.contains("US survey foot") {
+                US_SURVEY_FOOT
+            } else if wkt.contains("\"foot\"") {
+                INTERNATIONAL_FOOT
+            } else {
+                1.
+            };
+            return Some(CrsUnits { crs, horizontal: feet, vertical: feet });
+        }
+
+        // GeoKeyDirectoryTag: a 4 entry header, then (key, location, count, value) entries.
+        let v = self.vlr("LASF_Projection", 34735)?;
+        let keys: Vec<u16> = v.data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        let mut out: Option<CrsUnits> = None;
+        let mut units = (1., None);
+        for k in keys.get(4..)?.chunks_exact(4) {
+            // Only values stored inline (location 0) are of interest.
+            if k[1] != 0 {
+                continue;
+            }
+            let unit = |code: u16| match code {
+                9002 => INTERNATIONAL_FOOT,
+                9003 => US_SURVEY_FOOT,
+                _ => 1.,
+            };
+            match k[0] {
+                // ProjectedCSTypeGeoKey, GeographicTypeGeoKey
+                3072 | 2048 => {
+                    if let Some(crs) = Crs::from_epsg(k[3] as u32)
+                        && out.is_none_or(|o| !o.crs.is_projected())
+                    {
+                        out = Some(CrsUnits::meters(crs));
+                    }
+                }
+                // GTModelTypeGeoKey: geocentric
+                1024 if k[3] == 3 => out = Some(CrsUnits::meters(Crs::Geocentric)),
+                // ProjLinearUnitsGeoKey, VerticalUnitsGeoKey
+                3076 => units.0 = unit(k[3]),
+                4099 => units.1 = Some(unit(k[3])),
+                _ => {}
+            }
+        }
+        let mut out = out?;
+        out.horizontal = units.0;
+        out.vertical = units.1.unwrap_or(units.0);
+        return Some(out);
+    }
+}
+
+/// Read all points of an uncompressed LAS file.
+pub fn read_las(bytes: &[u8]) -> anyhow::Result<(LasHeader, LasPoints)> {
+    let header = parse_header(bytes)?;
+    anyhow::ensure!(!header.compressed, "LAZ (compressed) point data is not supported; decompress it with laszip first");
+    let format = header.point_format;
+    let min_length = *FORMAT_LENGTHS.get(format as usize).with_context(|| format!("unknown point format {format}"))?;
+    let stride = header.point_record_length as usize;
+    anyhow::ensure!(stride >= min_length as usize, "point records of {stride} bytes are too short for format {format}");
+
+    let start = header.offset_to_points as usize;
+    let count = usize::try_from(header.point_count).ok();
+    let end = count.and_then(|c| c.checked_mul(stride)).and_then(|len| len.checked_add(start));
+    let data = end.and_then(|end| bytes.get(start..end)).context("point data past end of file")?;
+    let count = data.len() / stride;
+
+    let mut points = LasPoints {
+        positions: Vec::with_capacity(count),
+        intensity: Vec::with_capacity(count),
+        classification: Vec::with_capacity(count),
+        rgb: rgb_offset(format).map(|_| Vec::with_capacity(count)),
+    };
+    let (s, o) = (header.scale, header.offset);
+    for r in data.chunks_exact(stride) {
+        points.positions.push([
+            i32_at(r, 0) as f64 * s[0] + o[0],
+            i32_at(r, 4) as f64 * s[1] + o[1],
+            i32_at(r, 8) as f64 * s[2] + o[2],
+        ]);
+        points.intensity.push(u16_at(r, 12));
+        // Formats 0-5 pack the class into 5 bits next to the synthetic/keypoint/withheld flags.
+        points.classification.push(if format < 6 { r[15] & 0x1f } else { r[16] });
+        if let (Some(rgb), Some(at)) = (&mut points.rgb, rgb_offset(format)) {
+            rgb.push([u16_at(r, at), u16_at(r, at + 2), u16_at(r, at + 4)]);
+        }
+    }
+    return Ok((header, points));
+}
+
+/// A LAS file with the given points, each `(x, y, z, intensity, class, rgb)` in file units. Only
+/// fills in what `read_las` looks at.
+#[cfg(test)]
+pub(crate) fn make_las(minor: u8, format: u8, scale: f64, vlrs: &[(&str, u16, Vec<u8>)], points: &[([f64; 3], u16, u8, [u16; 3])]) -> Vec<u8> {
+    let header_size: usize = match minor {
+        4 => 375,
+        3 => 235,
+        _ => 227,
+    };
+    let stride = FORMAT_LENGTHS[format as usize] as usize;
+    let vlr_bytes: usize = vlrs.iter().map(|v| 54 + v.2.len()).sum();
+    let mut out = vec![0u8; header_size];
+    out[..4].copy_from_slice(b"LASF");
+    out[24] = 1;
+    out[25] = minor;
+    out[94..96].copy_from_slice(&(header_size as u16).to_le_bytes());
+    out[96..100].copy_from_slice(&((header_size + vlr_bytes) as u32).to_le_bytes());
+    out[100..104].copy_from_slice(&(vlrs.len() as u32).to_le_bytes());
+    out[104] = format;
+    out[105..107].copy_from_slice(&(stride as u16).to_le_bytes());
+    if format < 6 {
+        out[107..111].copy_from_slice(&(points.len() as u32).to_le_bytes());
+    }
+    for i in 0..3 {
+        out[131 + 8 * i..139 + 8 * i].copy_from_slice(&scale.to_le_bytes());
+    }
+    if minor >= 4 {
+        out[247..255].copy_from_slice(&(points.len() as u64).to_le_bytes());
+    }
+    for (user, id, data) in vlrs {
+        let mut h = [0u8; 54];
+        h[2..2 + user.len()].copy_from_slice(user.as_bytes());
+        h[18..20].copy_from_slice(&id.to_le_bytes());
+        h[20..22].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        out.extend_from_slice(&h);
+        out.extend_from_slice(data);
+    }
+    for (p, intensity, class, rgb) in points {
+        let mut r = vec![0u8; stride];
+        for i in 0..3 {
+            r[4 * i..4 * i + 4].copy_from_slice(&((p[i] / scale).round() as i32).to_le_bytes());
+        }
+        r[12..14].copy_from_slice(&intensity.to_le_bytes());
+        r[if format < 6 { 15 } else { 16 }] = *class;
+        if let Some(at) = rgb_offset(format) {
+            for i in 0..3 {
+                r[at + 2 * i..at + 2 * i + 2].copy_from_slice(&rgb[i].to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&r);
+    }
+    return out;
+}
+
+/// GeoTIFF keys for a projected CRS given by EPSG code, with linear units.
+#[cfg(test)]
+pub(crate) fn geokeys(epsg: u16, units: u16) -> Vec<u8> {
+    let keys: [u16; 12] = [1, 1, 0, 2, 3072, 0, 1, epsg, 3076, 0, 1, units];
+    return keys.iter().flat_map(|k| k.to_le_bytes()).collect();
+}
+
+#[test]
+fn reads_las_12_with_geokeys() {
+    let points = [([500_000., 5_000_000., 12.5], 300, 2, [65535, 0, 256]), ([500_010.25, 5_000_001., 13.], 40, 6, [0, 0, 0])];
+    let bytes = make_las(2, 3, 0.01, &[("LASF_Projection", 34735, geokeys(32610, 9001))], &points);
+    let (h, p) = read_las(&bytes).unwrap();
+    assert_eq!((h.version, h.point_format, h.point_count, h.compressed), ((1, 2), 3, 2, false));
+    assert_eq!(h.vlrs.len(), 1);
+    assert_eq!(h.crs(), Some(CrsUnits::meters(Crs::Utm { zone: 10, north: true })));
+    assert_eq!(p.positions[1], [500_010.25, 5_000_001., 13.]);
+    assert_eq!(p.intensity, [300, 40]);
+    assert_eq!(p.classification, [2, 6]);
+    assert_eq!(p.rgb.unwrap()[0], [65535, 0, 256]);
+
+    let feet = make_las(2, 0, 0.01, &[("LASF_Projection", 34735, geokeys(32610, 9003))], &points);
+    let crs = parse_header(&feet).unwrap().crs().unwrap();
+    assert_eq!((crs.horizontal, crs.vertical), (US_SURVEY_FOOT, US_SURVEY_FOOT));
+}
+
+#[test]
+fn geocentric_feet_scale_each_axis_once() {
+    // GTModelTypeGeoKey geocentric, with US survey feet as the linear unit.
+    let keys: [u16; 12] = [1, 1, 0, 2, 1024, 0, 1, 3, 3076, 0, 1, 9003];
+    let keys = keys.iter().flat_map(|k| k.to_le_bytes()).collect();
+    let bytes = make_las(2, 0, 0.01, &[("LASF_Projection", 34735, keys)], &[([0., 0., 0.], 0, 0, [0, 0, 0])]);
+    let crs = parse_header(&bytes).unwrap().crs().unwrap();
+    assert_eq!((crs.crs, crs.horizontal, crs.vertical), (Crs::Geocentric, US_SURVEY_FOOT, US_SURVEY_FOOT));
+    let p = crs.to_ecef(3937., -3937., 39370.);
+    assert!((p - nalgebra::Vector3::new(1200., -1200., 12000.)).norm() < 1e-9, "{p:?}");
+}
+
+#[test]
+fn reads_las_14_with_wkt() {
+    let wkt = br#"PROJCS["WGS 84 / UTM zone 33N",GEOGCS["WGS 84",AUTHORITY["EPSG","4326"]],AUTHORITY["EPSG","32633"]]"#;
+    let points = [([1., 2., 3.], 7, 200, [0, 0, 0])];
+    let bytes = make_las(4, 6, 0.001, &[("LASF_Projection", 2112, wkt.to_vec())], &points);
+    let (h, p) = read_las(&bytes).unwrap();
+    assert_eq!((h.version, h.point_format, h.point_count), ((1, 4), 6, 1));
+    assert_eq!(h.crs(), Some(CrsUnits::meters(Crs::Utm { zone: 33, north: true })));
+    // Formats 6+ have a full byte for the class, and no RGB.
+    assert_eq!(p.classification, [200]);
+    assert!(p.rgb.is_none());
+
+    let mut laz = bytes.clone();
+    laz[104] |= 0x80;
+    assert!(read_las(&laz).unwrap_err().to_string().contains("LAZ"));
+    assert!(read_las(&bytes[..bytes.len() - 1]).is_err());
+
+    // A point count whose size overflows is an error, not a panic.
+    let mut huge = bytes.clone();
+    huge[247..255].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(read_las(&huge).unwrap_err().to_string().contains("past end"));
+}
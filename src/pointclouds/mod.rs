@@ -0,0 +1,164 @@
+mod crs;
+mod las;
+mod octree;
+
+pub use crs::{crs_from_wkt, utm_to_geodetic, Crs, CrsUnits};
+pub use las::{parse_header, read_las, LasHeader, LasPoints, Vlr};
+pub use octree::{Octree, OctreeNode};
+
+use std::path::Path;
+
+use anyhow::Context;
+use nalgebra::Vector3;
+
+use crate::core::Geodetic;
+
+/// How points are coloured.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// The file's colours, or intensity if it has none.
+    #[default]
+    Rgb,
+    Intensity,
+    /// A ramp over height above the ellipsoid, from the lowest to the highest point.
+    Elevation,
+    /// ASPRS standard classes.
+    Classification,
+}
+
+impl ColorMode {
+    pub const ALL: [ColorMode; 4] = [ColorMode::Rgb, ColorMode::Intensity, ColorMode::Elevation, ColorMode::Classification];
+
+    pub fn next(self) -> Self {
+        let i = ColorMode::ALL.iter().position(|m| *m == self).unwrap();
+        return ColorMode::ALL[(i + 1) % ColorMode::ALL.len()];
+    }
+}
+
+/// A point cloud in ECEF with its LOD octree.
+#[derive(Clone, Debug, Default)]
+pub struct PointCloudData {
+    pub positions: Vec<Vector3<f64>>,
+    /// Height above the ellipsoid, for the elevation ramp.
+    pub heights: Vec<f32>,
+    pub intensity: Vec<u16>,
+    pub classification: Vec<u8>,
+    pub rgb: Option<Vec<[u8; 3]>>,
+    pub octree: Octree,
+}
+
+/// Points per side of the grid each octree node samples.
+const OCTREE_GRID: u32 = 64;
+const OCTREE_LEAF_SIZE: usize = 20_000;
+
+pub fn load_point_cloud(path: &Path) -> anyhow::Result<PointCloudData> {
+    let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    return PointCloudData::from_las(&bytes, None);
+}
+
+impl PointCloudData {
+    /// Reproject a LAS file to ECEF. `crs` overrides whatever the file says.
+    pub fn from_las(bytes: &[u8], crs: Option<CrsUnits>) -> anyhow::Result<Self> {
+        let (header, points) = read_las(bytes)?;
+        let crs = crs
+            .or_else(|| header.crs())
+            .context("no supported CRS in the LAS header (geographic, geocentric or UTM by EPSG code)")?;
+        log::info!("{} points, LAS {}.{} format {}, {:?}", points.positions.len(), header.version.0, header.version.1, header.point_format, crs);
+
+        let positions: Vec<Vector3<f64>> = points.positions.iter().map(|p| crs.to_ecef(p[0], p[1], p[2])).collect();
+        let heights = positions.iter().map(|p| Geodetic::from_ecef(p).height as f32).collect();
+        // Plenty of files store 8 bit colours in the 16 bit fields.
+        let rgb = points.rgb.map(|rgb| {
+            let shift = if rgb.iter().flatten().any(|&c| c > 255) { 8 } else { 0 };
+            rgb.iter().map(|c| c.map(|v| (v >> shift) as u8)).collect()
+        });
+        let octree = Octree::build(&positions, OCTREE_GRID, OCTREE_LEAF_SIZE);
+        return Ok(PointCloudData { positions, heights, intensity: points.intensity, classification: points.classification, rgb, octree });
+    }
+
+    /// One RGBA colour per point, in point order.
+    pub fn colors(&self, mode: ColorMode) -> Vec<[u8; 4]> {
+        match (mode, &self.rgb) {
+            (ColorMode::Rgb, Some(rgb)) => return rgb.iter().map(|c| [c[0], c[1], c[2], 255]).collect(),
+            (ColorMode::Rgb | ColorMode::Intensity, _) => {
+                let max = self.intensity.iter().copied().max().unwrap_or(0).max(1) as f32;
+                return self
+                    .intensity
+                    .iter()
+                    .map(|&i| {
+                        // A gamma lift, since intensities cluster at the dark end.
+                        let v = ((i as f32 / max).sqrt() * 255.) as u8;
+                        [v, v, v, 255]
+                    })
+                    .collect();
+            }
+            (ColorMode::Elevation, _) => {
+                let lo = self.heights.iter().copied().fold(f32::INFINITY, f32::min);
+                let hi = self.heights.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let range = (hi - lo).max(1e-3);
+                return self.heights.iter().map(|&h| elevation_ramp((h - lo) / range)).collect();
+            }
+            (ColorMode::Classification, _) => return self.classification.iter().map(|&c| class_color(c)).collect(),
+        }
+    }
+}
+
+/// Blue through green and yellow to red, for `t` in [0, 1].
+pub fn elevation_ramp(t: f32) -> [u8; 4] {
+    const STOPS: [[f32; 3]; 5] = [[0.1, 0.2, 0.8], [0.1, 0.7, 0.9], [0.2, 0.8, 0.2], [0.95, 0.9, 0.2], [0.85, 0.15, 0.1]];
+    let x = t.clamp(0., 1.) * (STOPS.len() - 1) as f32;
+    let i = (x as usize).min(STOPS.len() - 2);
+    let f = x - i as f32;
+    let c = |k: usize| ((STOPS[i][k] * (1. - f) + STOPS[i + 1][k] * f) * 255.) as u8;
+    return [c(0), c(1), c(2), 255];
+}
+
+/// Colours for the ASPRS standard classes (LAS 1.4 table 17); others are grey.
+pub fn class_color(class: u8) -> [u8; 4] {
+    let rgb = match class {
+        0 | 1 => [170, 170, 170],  // never classified, unclassified
+        2 => [166, 118, 60],       // ground
+        3 => [144, 238, 144],      // low vegetation
+        4 => [50, 180, 50],        // medium vegetation
+        5 => [0, 110, 0],          // high vegetation
+        6 => [230, 70, 50],        // building
+        7 => [255, 0, 255],        // low point (noise)
+        9 => [40, 120, 230],       // water
+        10 => [120, 80, 40],       // rail
+        11 => [80, 80, 80],        // road surface
+        13..=16 => [250, 200, 0],  // wires and transmission towers
+        17 => [150, 150, 220],     // bridge deck
+        18 => [255, 0, 160],       // high noise
+        _ => [128, 128, 128],
+    };
+    return [rgb[0], rgb[1], rgb[2], 255];
+}
+
+#[test]
+fn las_fixture_to_ecef_and_colors() {
+    let points = [
+        ([500_000., 0., 0.], 100, 2, [255, 0, 0]),
+        ([500_000., 0., 10.], 400, 6, [0, 255, 0]),
+        ([500_001., 0., 20.], 0, 9, [0, 0, 255]),
+    ];
+    let bytes = las::make_las(2, 2, 0.001, &[("LASF_Projection", 34735, las::geokeys(32631, 9001))], &points);
+    let cloud = PointCloudData::from_las(&bytes, None).unwrap();
+
+    // Easting 500 km on the equator in zone 31 is 3°E, at the given height.
+    let g = Geodetic::from_ecef(&cloud.positions[1]);
+    assert!(g.lat.abs() < 1e-9 && (g.lon - 3.).abs() < 1e-9 && (g.height - 10.).abs() < 1e-6, "{g:?}");
+    assert!(((cloud.positions[2] - cloud.positions[0]).norm() - (1f64 + 400.).sqrt()).abs() < 1e-3);
+    assert_eq!(cloud.octree.order.len(), 3);
+
+    assert_eq!(cloud.colors(ColorMode::Rgb)[1], [0, 255, 0, 255]);
+    assert_eq!(cloud.colors(ColorMode::Intensity).iter().map(|c| c[0]).collect::<Vec<_>>(), [127, 255, 0]);
+    assert_eq!(cloud.colors(ColorMode::Elevation)[0], elevation_ramp(0.));
+    assert_eq!(cloud.colors(ColorMode::Elevation)[2], elevation_ramp(1.));
+    assert_eq!(cloud.colors(ColorMode::Classification)[2], class_color(9));
+    assert_eq!(ColorMode::Classification.next(), ColorMode::Rgb);
+
+    // Without a CRS in the file one has to be given.
+    let bare = las::make_las(2, 0, 0.001, &[], &points);
+    assert!(PointCloudData::from_las(&bare, None).is_err());
+    assert!(PointCloudData::from_las(&bare, Some(CrsUnits::meters(Crs::Geocentric))).is_ok());
+}
@@ -0,0 +1,186 @@
+use std::collections::{BinaryHeap, HashSet};
+
+use nalgebra::Vector3;
+
+/// Deeper than this, whatever is left stays in one node (duplicate points would otherwise
+/// recurse forever).
+const MAX_DEPTH: u32 = 24;
+
+#[derive(Clone, Debug)]
+pub struct OctreeNode {
+    pub center: Vector3<f64>,
+    pub half_size: f64,
+    /// This node's points are `Octree::order[start..end]`.
+    pub start: u32,
+    pub end: u32,
+    pub children: Vec<u32>,
+    /// Typical distance between this node's points, in meters.
+    pub spacing: f64,
+}
+
+/// A level-of-detail octree in the style of Potree: every node keeps an evenly spread sample of
+/// the points in its cube (one per grid cell) and passes the rest down to its children. Drawing
+/// a node together with all its ancestors gives the full density of that region.
+#[derive(Clone, Debug, Default)]
+pub struct Octree {
+    pub nodes: Vec<OctreeNode>,
+    /// Point indices grouped by node, so each node's points are contiguous.
+    pub order: Vec<u32>,
+}
+
+impl Octree {
+    /// `grid` cells per cube side pick each node's sample; nodes with at most `leaf_size` points
+    /// keep them all.
+    pub fn build(positions: &[Vector3<f64>], grid: u32, leaf_size: usize) -> Self {
+        let mut tree = Octree::default();
+        if positions.is_empty() {
+            return tree;
+        }
+        let (mut lo, mut hi) = (positions[0], positions[0]);
+        for p in positions {
+            lo = lo.inf(p);
+            hi = hi.sup(p);
+        }
+        let center = (lo + hi) / 2.;
+        let half_size = (hi - lo).max() / 2. * 1.001 + 1e-6;
+        tree.build_node(positions, (0..positions.len() as u32).collect(), center, half_size, grid.max(1), leaf_size, 0);
+        return tree;
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_node(
+        &mut self,
+        positions: &[Vector3<f64>],
+        indices: Vec<u32>,
+        center: Vector3<f64>,
+        half_size: f64,
+        grid: u32,
+        leaf_size: usize,
+        depth: u32,
+    ) -> u32 {
+        let id = self.nodes.len() as u32;
+        let cell = 2. * half_size / grid as f64;
+        let start = self.order.len() as u32;
+        let mut octants: [Vec<u32>; 8] = Default::default();
+
+        if indices.len() <= leaf_size || depth >= MAX_DEPTH {
+            self.order.extend(&indices);
+        } else {
+            let mut taken = HashSet::new();
+            let corner = center.add_scalar(-half_size);
+            for i in indices {
+                let p = positions[i as usize];
+                let c = (p - corner) / cell;
+                let key = (c.x.max(0.) as u32, c.y.max(0.) as u32, c.z.max(0.) as u32);
+                if taken.insert(key) {
+                    self.order.push(i);
+                } else {
+                    let o = (p.x >= center.x) as usize | ((p.y >= center.y) as usize) << 1 | ((p.z >= center.z) as usize) << 2;
+                    octants[o].push(i);
+                }
+            }
+        }
+
+        self.nodes.push(OctreeNode { center, half_size, start, end: self.order.len() as u32, children: vec![], spacing: cell });
+        for (o, idx) in octants.into_iter().enumerate() {
+            if idx.is_empty() {
+                continue;
+            }
+            let h = half_size / 2.;
+            let sign = |bit: usize| if o >> bit & 1 == 1 { h } else { -h };
+            let c = center + Vector3::new(sign(0), sign(1), sign(2));
+            let child = self.build_node(positions, idx, c, h, grid, leaf_size, depth + 1);
+            self.nodes[id as usize].children.push(child);
+        }
+        return id;
+    }
+
+    /// Distance from `p` to the node's cube, 0 inside.
+    fn distance(&self, node: &OctreeNode, p: &Vector3<f64>) -> f64 {
+        let d = (p - node.center).abs().add_scalar(-node.half_size);
+        return d.sup(&Vector3::zeros()).norm();
+    }
+
+    /// Nodes to draw: refine while a node's point spacing covers more than `max_spacing_px`
+    /// pixels, most coarse-looking first, until `budget` points are picked.
+    pub fn select(&self, eye: &Vector3<f64>, screen_height: f64, sse_denominator: f64, max_spacing_px: f64, budget: usize) -> Vec<u32> {
+        let mut out = vec![];
+        if self.nodes.is_empty() {
+            return out;
+        }
+        let spacing_px = |n: &OctreeNode| n.spacing * screen_height / (self.distance(n, eye).max(1e-3) * sse_denominator);
+        // Non-negative f64s order the same as their bit patterns.
+        let mut queue = BinaryHeap::from([(spacing_px(&self.nodes[0]).to_bits(), 0u32)]);
+        let mut total = 0;
+        while let Some((px, id)) = queue.pop() {
+            let node = &self.nodes[id as usize];
+            let count = (node.end - node.start) as usize;
+            if total + count > budget {
+                continue;
+            }
+            total += count;
+            out.push(id);
+            if f64::from_bits(px) > max_spacing_px {
+                for &c in &node.children {
+                    queue.push((spacing_px(&self.nodes[c as usize]).to_bits(), c));
+                }
+            }
+        }
+        return out;
+    }
+}
+
+#[cfg(test)]
+fn grid_cloud(n: usize) -> Vec<Vector3<f64>> {
+    let mut out = vec![];
+    for i in 0..n {
+        for j in 0..n {
+            out.push(Vector3::new(i as f64, j as f64, ((i * j) % 7) as f64 * 0.1));
+        }
+    }
+    return out;
+}
+
+#[test]
+fn octree_partitions_points() {
+    let points = grid_cloud(40);
+    let tree = Octree::build(&points, 8, 50);
+    assert!(tree.nodes.len() > 8);
+
+    let mut seen = tree.order.clone();
+    seen.sort();
+    assert_eq!(seen, (0..points.len() as u32).collect::<Vec<_>>());
+
+    for n in &tree.nodes {
+        assert!(n.end >= n.start);
+        for &i in &tree.order[n.start as usize..n.end as usize] {
+            let d = (points[i as usize] - n.center).abs();
+            assert!(d.max() <= n.half_size + 1e-9);
+        }
+        for &c in &n.children {
+            assert!((tree.nodes[c as usize].half_size - n.half_size / 2.).abs() < 1e-12);
+        }
+    }
+    // The root holds one point per occupied cell of its 8x8x8 grid; the cloud is nearly flat, so
+    // that's one or two layers of 8x8 cells.
+    let root = &tree.nodes[0];
+    assert!((64..=128).contains(&(root.end - root.start)));
+
+    // Lots of identical points don't recurse forever.
+    let same = vec![Vector3::new(1., 2., 3.); 1000];
+    let tree = Octree::build(&same, 4, 10);
+    assert_eq!(tree.order.len(), 1000);
+}
+
+#[test]
+fn octree_selection_refines_near_the_eye() {
+    let points = grid_cloud(40);
+    let tree = Octree::build(&points, 8, 50);
+    let far = tree.select(&Vector3::new(0., 0., 1e6), 1000., 1., 2., usize::MAX);
+    assert_eq!(far, [0]);
+    let near = tree.select(&Vector3::new(5., 5., 2.), 1000., 1., 2., usize::MAX);
+    assert_eq!(near.len(), tree.nodes.len());
+    let budget = tree.select(&Vector3::new(5., 5., 2.), 1000., 1., 2., 500);
+    let picked: u32 = budget.iter().map(|&i| tree.nodes[i as usize].end - tree.nodes[i as usize].start).sum();
+    assert!(picked <= 500 && budget.len() > 1 && budget[0] == 0);
+}
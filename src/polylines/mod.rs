@@ -0,0 +1,291 @@
+use std::ops::Range;
+
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::core::flight::slerp;
+use crate::core::Geodetic;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LineJoin {
+    /// Sharp corners, falling back to round where the miter would be longer than
+    /// `MITER_LIMIT` half widths.
+    #[default]
+    Miter,
+    Round,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LineCap {
+    /// Stop at the end point.
+    #[default]
+    Butt,
+    /// Carry on for half the width.
+    Square,
+    Round,
+}
+
+/// Alternating drawn and skipped lengths along the line, in pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Dash {
+    pub dash: f32,
+    pub gap: f32,
+}
+
+pub const MITER_LIMIT: f32 = 4.;
+
+/// A line through ECEF points, drawn a fixed number of pixels wide.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polyline {
+    /// ECEF meters.
+    pub positions: Vec<Vector3<f64>>,
+    /// Joins the last point back to the first.
+    pub closed: bool,
+    /// Pixels.
+    pub width: f32,
+    pub color: [f32; 4],
+    /// Pixels on each side, outside `width`.
+    pub outline_width: f32,
+    pub outline_color: [f32; 4],
+    pub join: LineJoin,
+    pub cap: LineCap,
+    pub dash: Option<Dash>,
+}
+
+impl Polyline {
+    pub fn new(positions: Vec<Vector3<f64>>) -> Self {
+        return Polyline {
+            positions,
+            closed: false,
+            width: 2.,
+            color: [1.; 4],
+            outline_width: 0.,
+            outline_color: [0., 0., 0., 1.],
+            join: LineJoin::default(),
+            cap: LineCap::default(),
+            dash: None,
+        };
+    }
+}
+
+pub const HAS_PREV: u32 = 1;
+pub const HAS_NEXT: u32 = 2;
+pub const JOIN_ROUND: u32 = 4;
+pub const CAP_SQUARE: u32 = 8;
+pub const CAP_ROUND: u32 = 16;
+
+/// One line segment with its neighbours, as the polyline shader takes it. Where there is no
+/// neighbour (see `flags`), `prev` and `next` repeat the segment's own end points.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Segment {
+    pub prev: [f32; 3],
+    pub width: f32,
+    pub p0: [f32; 3],
+    pub outline_width: f32,
+    pub p1: [f32; 3],
+    pub flags: u32,
+    pub next: [f32; 3],
+    pub pad: f32,
+    pub color: [f32; 4],
+    pub outline_color: [f32; 4],
+    /// Dash and gap lengths in pixels, gap 0 for solid lines.
+    pub dash: [f32; 2],
+}
+
+fn f32x3(p: &Vector3<f64>) -> [f32; 3] {
+    return [p.x as f32, p.y as f32, p.z as f32];
+}
+
+/// Positions with repeated points dropped (and, for closed lines, a last point equal to the
+/// first), since zero length segments have no direction.
+fn distinct_points(line: &Polyline) -> Vec<Vector3<f64>> {
+    let mut points: Vec<Vector3<f64>> = vec![];
+    for p in &line.positions {
+        if points.last() != Some(p) {
+            points.push(*p);
+        }
+    }
+    if line.closed && points.len() > 2 && points.first() == points.last() {
+        points.pop();
+    }
+    return points;
+}
+
+/// Segments of every line, and the range of segments belonging to each line.
+pub fn build_segments(lines: &[Polyline]) -> (Vec<Segment>, Vec<Range<u32>>) {
+    let mut segments = vec![];
+    let mut ranges = vec![];
+    for line in lines {
+        let start = segments.len() as u32;
+        let points = distinct_points(line);
+        let n = points.len();
+        let closed = line.closed && n > 2;
+        let count = if closed { n } else { n.saturating_sub(1) };
+
+        let mut style = match line.join {
+            LineJoin::Miter => 0,
+            LineJoin::Round => JOIN_ROUND,
+        };
+        style |= match line.cap {
+            LineCap::Butt => 0,
+            LineCap::Square => CAP_SQUARE,
+            LineCap::Round => CAP_ROUND,
+        };
+        let dash = line.dash.map_or([0., 0.], |d| [d.dash, d.gap]);
+
+        for i in 0..count {
+            let (i0, i1) = (i, (i + 1) % n);
+            let prev = if closed || i > 0 { Some((i0 + n - 1) % n) } else { None };
+            let next = if closed || i + 2 < n { Some((i1 + 1) % n) } else { None };
+            let mut flags = style;
+            if prev.is_some() {
+                flags |= HAS_PREV;
+            }
+            if next.is_some() {
+                flags |= HAS_NEXT;
+            }
+            segments.push(Segment {
+                prev: f32x3(&points[prev.unwrap_or(i0)]),
+                width: line.width,
+                p0: f32x3(&points[i0]),
+                outline_width: line.outline_width,
+                p1: f32x3(&points[i1]),
+                flags,
+                next: f32x3(&points[next.unwrap_or(i1)]),
+                pad: 0.,
+                color: line.color,
+                outline_color: line.outline_color,
+                dash,
+            });
+        }
+        ranges.push(start..segments.len() as u32);
+    }
+    return (segments, ranges);
+}
+
+/// For each segment, the on-screen length of the line before it in pixels, which is where the
+/// dash pattern picks up. Segments with an end behind the camera add nothing. Lines without
+/// dashes get zeros.
+pub fn dash_offsets(lines: &[Polyline], view_proj: &Matrix4<f64>, viewport: [f32; 2]) -> Vec<f32> {
+    let to_px = |p: &Vector3<f64>| {
+        let c = view_proj * Vector4::new(p.x, p.y, p.z, 1.);
+        return (c.w > 0.).then(|| {
+            nalgebra::Vector2::new(c.x / c.w * 0.5 * viewport[0] as f64, c.y / c.w * 0.5 * viewport[1] as f64)
+        });
+    };
+
+    let mut out = vec![];
+    for line in lines {
+        let points = distinct_points(line);
+        let n = points.len();
+        let count = if line.closed && n > 2 { n } else { n.saturating_sub(1) };
+        if line.dash.is_none() {
+            out.extend(std::iter::repeat_n(0., count));
+            continue;
+        }
+        let screen: Vec<_> = points.iter().map(to_px).collect();
+        let mut total = 0.;
+        for i in 0..count {
+            out.push(total as f32);
+            if let (Some(a), Some(b)) = (screen[i], screen[(i + 1) % n]) {
+                total += (b - a).norm();
+            }
+        }
+    }
+    return out;
+}
+
+/// Points along the shortest way over the ellipsoid between each pair of `points`, no more than
+/// about `max_segment` meters apart. Heights are interpolated linearly.
+pub fn geodesic_path(points: &[Vector3<f64>], max_segment: f64) -> Vec<Vector3<f64>> {
+    let mut out = vec![];
+    for w in points.windows(2) {
+        let (a, b) = (Geodetic::from_ecef(&w[0]), Geodetic::from_ecef(&w[1]));
+        // Slerp the surface normals ("n-vectors") and put the point back on the ellipsoid.
+        let n_vector = |g: &Geodetic| {
+            let (lat, lon) = (g.lat.to_radians(), g.lon.to_radians());
+            return Vector3::new(lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin());
+        };
+        let (na, nb) = (n_vector(&a), n_vector(&b));
+        let steps = ((w[1] - w[0]).norm() / max_segment).ceil().max(1.) as usize;
+        out.push(w[0]);
+        for k in 1..steps {
+            let t = k as f64 / steps as f64;
+            let n = slerp(&na, &nb, t);
+            let lat = n.z.clamp(-1., 1.).asin().to_degrees();
+            let lon = n.y.atan2(n.x).to_degrees();
+            out.push(Geodetic::new(lat, lon, a.height + (b.height - a.height) * t).to_ecef());
+        }
+    }
+    out.extend(points.last());
+    return out;
+}
+
+#[test]
+fn segments_link_neighbours() {
+    let p = |x: f64| Vector3::new(x, 0., 0.);
+    let mut open = Polyline::new(vec![p(0.), p(1.), p(1.), p(2.), p(3.)]);
+    open.join = LineJoin::Round;
+    open.cap = LineCap::Square;
+    let mut closed = Polyline::new(vec![p(10.), p(11.), p(12.), p(10.)]);
+    closed.closed = true;
+    closed.dash = Some(Dash { dash: 6., gap: 2. });
+    let lonely = Polyline::new(vec![p(5.), p(5.)]);
+
+    let (segs, ranges) = build_segments(&[open, lonely, closed]);
+    assert_eq!(ranges, [0..3, 3..3, 3..6]);
+
+    // The repeated point is dropped; the ends have no neighbours and repeat their own points.
+    assert_eq!(segs[0].flags, JOIN_ROUND | CAP_SQUARE | HAS_NEXT);
+    assert_eq!((segs[0].prev, segs[0].p0, segs[0].p1, segs[0].next), ([0.; 3], [0.; 3], [1., 0., 0.], [2., 0., 0.]));
+    assert_eq!(segs[1].flags, JOIN_ROUND | CAP_SQUARE | HAS_PREV | HAS_NEXT);
+    assert_eq!(segs[2].flags, JOIN_ROUND | CAP_SQUARE | HAS_PREV);
+    assert_eq!((segs[2].prev[0], segs[2].next[0]), (1., 3.));
+
+    // A closed line wraps around, and its repeated closing point isn't a segment of its own.
+    let c: Vec<[f32; 4]> = segs[3..].iter().map(|s| [s.prev[0], s.p0[0], s.p1[0], s.next[0]]).collect();
+    assert_eq!(c, [[12., 10., 11., 12.], [10., 11., 12., 10.], [11., 12., 10., 11.]]);
+    assert!(segs[3..].iter().all(|s| s.flags == HAS_PREV | HAS_NEXT && s.dash == [6., 2.]));
+}
+
+#[test]
+fn dash_offsets_accumulate_screen_length() {
+    // 1 pixel per meter: clip x = x / 500 over a 1000 pixel wide viewport.
+    let s = 1. / 500.;
+    let view_proj = Matrix4::new_nonuniform_scaling(&Vector3::new(s, s, 0.));
+    let p = |x: f64, y: f64| Vector3::new(x, y, 0.);
+    let mut dashed = Polyline::new(vec![p(0., 0.), p(30., 0.), p(30., 40.), p(0., 0.)]);
+    dashed.dash = Some(Dash { dash: 4., gap: 4. });
+    let solid = Polyline::new(vec![p(0., 0.), p(1., 0.), p(2., 0.)]);
+
+    let offsets = dash_offsets(&[solid, dashed.clone()], &view_proj, [1000., 1000.]);
+    assert_eq!(offsets, [0., 0., 0., 30., 70.]);
+
+    dashed.closed = true;
+    assert_eq!(dash_offsets(&[dashed], &view_proj, [1000., 1000.]), [0., 30., 70.]);
+}
+
+#[test]
+fn geodesic_follows_the_surface() {
+    let a = Geodetic::new(0., 0., 0.).to_ecef();
+    let b = Geodetic::new(0., 90., 1000.).to_ecef();
+    let path = geodesic_path(&[a, b], 100_000.);
+    // The chord is ~9000 km, so about 90 pieces.
+    assert_eq!(path.len(), 92);
+    assert_eq!((path[0], path[91]), (a, b));
+    let mid = Geodetic::from_ecef(&path[path.len() / 2 - 1]);
+    assert!(mid.lat.abs() < 1e-9, "{mid:?}");
+    assert!(mid.height > 400. && mid.height < 600., "{mid:?}");
+    for w in path.windows(2) {
+        assert!((w[1] - w[0]).norm() < 120_000.);
+    }
+    // High latitude crossing goes over the pole side, not along the parallel.
+    let path = geodesic_path(&[Geodetic::new(60., 0., 0.).to_ecef(), Geodetic::new(60., 180., 0.).to_ecef()], 50_000.);
+    let top = path.iter().map(|p| Geodetic::from_ecef(p).lat).fold(0., f64::max);
+    assert!(top > 89.9, "{top}");
+    // Antipodes have no single shortest way, but still get one.
+    let path = geodesic_path(&[Geodetic::new(10., 20., 0.).to_ecef(), Geodetic::new(-10., -160., 0.).to_ecef()], 500_000.);
+    assert!(path.len() > 20 && path.iter().all(|p| p.iter().all(|x| x.is_finite())));
+    let top = path.iter().map(|p| Geodetic::from_ecef(p).lat).fold(0., f64::max);
+    assert!(top > 85., "{top}");
+}
@@ -0,0 +1,267 @@
+use crate::billboards::{Billboard, IconAtlas, IconId};
+use crate::core::{AppObjects, Bounds, DrawStats, PipelineBuilder, PipelineId, RenderState, Renderable, Scene};
+use crate::core::shaders::uniforms::uniform_struct;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct BillboardInstance {
+    position: [f32; 3],
+    rotation: f32,
+    size: [f32; 2],
+    pixel_offset: [f32; 2],
+    color: [f32; 4],
+    uv: [f32; 4],
+    scale_by_distance: [f32; 4],
+}
+
+uniform_struct! {
+    pub(super) struct BillboardUniforms {
+        viewport: [f32; 2],
+        opacity: f32,
+        pad1: f32,
+        eye: [f32; 4],
+    }
+}
+
+const ATLAS_SIZE: u32 = 1024;
+
+/// Owns an icon atlas and draws billboards from it, one instance per billboard. Used by
+/// `Billboards` and by other renderables that have icons to show.
+pub(super) struct BillboardRenderer {
+    pub atlas: IconAtlas,
+    texture: wgpu::Texture,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: PipelineId,
+    instance_buffer: Option<wgpu::Buffer>,
+    num_instances: u32,
+}
+
+impl BillboardRenderer {
+    pub fn new(ao: &AppObjects, scene: &Scene) -> Self {
+        let texture = ao.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("iconAtlas"),
+            size: wgpu::Extent3d { width: ATLAS_SIZE, height: ATLAS_SIZE, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = ao.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("iconSampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("billboardUniforms"),
+            size: std::mem::size_of::<BillboardUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ]);
+        let bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("billboardBg"),
+            layout: &bgl,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&view) },
+                wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Sampler(&sampler) },
+            ],
+        });
+
+        const ATTRS: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
+            0 => Float32x3, 1 => Float32, 2 => Float32x2, 3 => Float32x2, 4 => Float32x4, 5 => Float32x4, 6 => Float32x4
+        ];
+        let pipeline = PipelineBuilder::new("billboardPipeline", "billboards.wgsl")
+            .bind_groups(&[&scene.bind_group_layout, &bgl])
+            // One quad (6 vertices) per billboard instance.
+            .vertex_buffer(wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<BillboardInstance>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &ATTRS,
+            })
+            .build(ao);
+
+        return Self {
+            atlas: IconAtlas::new(ATLAS_SIZE, ATLAS_SIZE),
+            texture,
+            uniform_buffer,
+            bind_group,
+            pipeline,
+            instance_buffer: None,
+            num_instances: 0,
+        };
+    }
+
+    /// Upload any new icons and this frame's instances. Billboards with icons not in the atlas
+    /// are skipped.
+    pub fn update(&mut self, ao: &AppObjects, scene: &Scene, billboards: &[Billboard], opacity: f32) {
+        if self.atlas.take_dirty() {
+            ao.queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &self.atlas.pixels,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.atlas.width * 4),
+                    rows_per_image: Some(self.atlas.height),
+                },
+                wgpu::Extent3d { width: self.atlas.width, height: self.atlas.height, depth_or_array_layers: 1 },
+            );
+        }
+
+        let eye = scene.cam.position().cast::<f32>();
+        let uniforms = BillboardUniforms {
+            viewport: [ao.config.width as f32, ao.config.height as f32],
+            opacity,
+            pad1: 0.,
+            eye: [eye.x, eye.y, eye.z, 1.],
+        };
+        ao.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+
+        let instances: Vec<BillboardInstance> = billboards
+            .iter()
+            .filter(|b| b.show)
+            .filter_map(|b| {
+                let (info, uv) = (self.atlas.icon(b.icon)?, self.atlas.uv(b.icon)?);
+                let s = b.scale_by_distance.map_or([0., 1., 0., 1.], |s| [s.near, s.near_value, s.far, s.far_value]);
+                return Some(BillboardInstance {
+                    position: [b.position.x as f32, b.position.y as f32, b.position.z as f32],
+                    rotation: b.rotation,
+                    size: [info.size[0] as f32 * b.scale, info.size[1] as f32 * b.scale],
+                    pixel_offset: b.pixel_offset,
+                    color: b.color,
+                    uv,
+                    scale_by_distance: s,
+                });
+            })
+            .collect();
+
+        let bytes: &[u8] = bytemuck::cast_slice(&instances);
+        if self.instance_buffer.as_ref().is_none_or(|b| b.size() < bytes.len() as u64) {
+            self.instance_buffer = Some(ao.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("billboardInstances"),
+                size: (bytes.len() as u64).next_power_of_two().max(4096),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        ao.queue.write_buffer(self.instance_buffer.as_ref().unwrap(), 0, bytes);
+        self.num_instances = instances.len() as u32;
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, ao: &AppObjects, scene: &Scene) {
+        let Some(ib) = self.instance_buffer.as_ref().filter(|_| self.num_instances > 0) else {
+            return;
+        };
+        render_pass.set_pipeline(&ao.pipelines.get(&self.pipeline));
+        render_pass.set_bind_group(0, &scene.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, ib.slice(..));
+        render_pass.draw(0..6, 0..self.num_instances);
+    }
+
+    pub fn stats(&self) -> DrawStats {
+        if self.num_instances == 0 {
+            return DrawStats::default();
+        }
+        return DrawStats { draw_calls: 1, triangles: 2 * self.num_instances as u64, tiles: None };
+    }
+}
+
+/// Screen-aligned icons at ECEF positions, all drawn with one instanced draw call. Add icons
+/// to the atlas with `add_icon_png`, then push `Billboard`s that refer to them. Billboards over
+/// the horizon are hidden.
+pub struct Billboards {
+    pub billboards: Vec<Billboard>,
+    opacity: f32,
+    renderer: BillboardRenderer,
+}
+
+impl Billboards {
+    pub fn new(ao: &AppObjects, scene: &Scene) -> Self {
+        return Self { billboards: vec![], opacity: 1., renderer: BillboardRenderer::new(ao, scene) };
+    }
+
+    /// Add a PNG icon to the atlas under `name`; adding a name again returns the same icon.
+    pub fn add_icon_png(&mut self, name: &str, bytes: &[u8]) -> anyhow::Result<IconId> {
+        return self.renderer.atlas.add_png(name, bytes);
+    }
+
+    pub fn icon(&self, name: &str) -> Option<IconId> {
+        return self.renderer.atlas.id(name);
+    }
+}
+
+impl Renderable for Billboards {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        self.renderer.update(ao, scene, &self.billboards, self.opacity);
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("billboardPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+        self.renderer.draw(&mut render_pass, rs.ao, rs.scene);
+    }
+
+    fn stats(&self) -> DrawStats {
+        return self.renderer.stats();
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        return Bounds::from_points(self.billboards.iter().map(|b| &b.position));
+    }
+}
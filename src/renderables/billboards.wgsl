@@ -0,0 +1,87 @@
+#include "scene.wgsl"
+#include "geo.wgsl"
+
+// Vertex shader
+
+struct BillboardInput {
+    @location(0) position: vec3<f32>,
+    // Radians, counter-clockwise on screen
+    @location(1) rotation: f32,
+    // Pixels, before scaling by distance
+    @location(2) size: vec2<f32>,
+    // Pixels, y down
+    @location(3) pixel_offset: vec2<f32>,
+    @location(4) color: vec4<f32>,
+    // Left, top, right, bottom in the atlas
+    @location(5) uv: vec4<f32>,
+    // near, near value, far, far value
+    @location(6) scale_by_distance: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct BillboardUniforms {
+    viewport: vec2<f32>,
+    opacity: f32,
+    pad1: f32,
+    // Camera position in ECEF, w unused
+    eye: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> billboards: BillboardUniforms;
+@group(1) @binding(1)
+var atlas: texture_2d<f32>;
+@group(1) @binding(2)
+var atlas_sampler: sampler;
+
+fn near_far(s: vec4<f32>, distance: f32) -> f32 {
+    if (s.z <= s.x) {
+        return s.y;
+    }
+    let t = clamp((distance - s.x) / (s.z - s.x), 0.0, 1.0);
+    return mix(s.y, s.w, t);
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    b: BillboardInput,
+) -> VertexOutput {
+    // Corners of the icon, y up.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5), vec2<f32>(0.5, -0.5), vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5), vec2<f32>(0.5, 0.5), vec2<f32>(-0.5, 0.5),
+    );
+    let corner = corners[vertex_index];
+
+    var out: VertexOutput;
+    let clip = scene.proj * scene.mv * vec4<f32>(b.position, 1.0);
+    if (clip.w <= 0.0 || !above_horizon(billboards.eye.xyz, b.position)) {
+        // Outside the clip volume, so the triangles are dropped.
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+
+    let size = b.size * near_far(b.scale_by_distance, distance(billboards.eye.xyz, b.position));
+    let c = cos(b.rotation);
+    let s = sin(b.rotation);
+    let local = corner * size;
+    let px = vec2<f32>(c * local.x - s * local.y, s * local.x + c * local.y)
+        + vec2<f32>(b.pixel_offset.x, -b.pixel_offset.y);
+    out.clip_position = clip + vec4<f32>(px * 2.0 / billboards.viewport * clip.w, 0.0, 0.0);
+    out.uv = vec2<f32>(mix(b.uv.x, b.uv.z, corner.x + 0.5), mix(b.uv.w, b.uv.y, corner.y + 0.5));
+    out.color = vec4<f32>(b.color.rgb, b.color.a * billboards.opacity);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(atlas, atlas_sampler, in.uv) * in.color;
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::billboards::{read_image_uri, Billboard, IconId};
+use crate::core::{AppObjects, DrawStats, PipelineId, RenderState, Renderable, Scene};
+use crate::czml::Document;
+use crate::labels::Label;
+use crate::polylines::{geodesic_path, Polyline};
+
+use super::billboards::BillboardRenderer;
+use super::labels::LabelRenderer;
+use super::polylines::PolylineRenderer;
+use super::tracks::{make_pipeline, Vertex};
+
+/// Draws the entities of a CZML document at the scene clock time.
+///
+/// Points are drawn as markers at the entity position, billboards as their image (or a marker if
+/// the image can't be loaded) and labels as text above it. Polylines are drawn at their pixel
+/// width along geodesics. Entities outside their availability are hidden.
+pub struct CzmlEntities {
+    pub document: Document,
+    /// Where the document was read from; relative image paths are resolved against it.
+    pub dir: PathBuf,
+    opacity: f32,
+
+    point_pipeline: PipelineId,
+    marker_buffer: wgpu::Buffer,
+    num_markers: u32,
+    polylines: PolylineRenderer,
+    /// Entity index of each line in `polylines`.
+    polyline_entities: Vec<usize>,
+    labels: LabelRenderer,
+    billboards: BillboardRenderer,
+    /// Icon of each entity's billboard, if it has one that loaded.
+    billboard_icons: Vec<Option<IconId>>,
+}
+
+/// Longest straight piece of a polyline; CZML lines follow geodesics by default.
+const GEODESIC_SEGMENT: f64 = 100_000.;
+
+impl CzmlEntities {
+    pub fn new(ao: &AppObjects, scene: &Scene, document: Document, dir: PathBuf) -> Self {
+        let marker_buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("czmlMarkers"),
+            size: (document.entities.len().max(1) * std::mem::size_of::<Vertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut polylines = PolylineRenderer::new(ao, scene);
+        let mut polyline_entities = vec![];
+        let mut lines = vec![];
+        for (i, e) in document.entities.iter().enumerate() {
+            if let Some(pl) = &e.polyline {
+                let mut line = Polyline::new(geodesic_path(&pl.positions, GEODESIC_SEGMENT));
+                line.width = pl.width;
+                line.color = pl.color;
+                lines.push(line);
+                polyline_entities.push(i);
+            }
+        }
+        polylines.set_lines(ao, lines);
+
+        let mut billboards = BillboardRenderer::new(ao, scene);
+        // Each image is read once, here, whether it loads or not; None for those that didn't.
+        let mut icons: HashMap<&str, Option<IconId>> = HashMap::new();
+        let billboard_icons = document
+            .entities
+            .iter()
+            .map(|e| {
+                let uri = e.billboard.as_ref()?.image.as_str();
+                return *icons.entry(uri).or_insert_with(|| {
+                    let icon = read_image_uri(uri, &dir).and_then(|bytes| billboards.atlas.add_png(uri, &bytes));
+                    return icon.inspect_err(|err| log::warn!("czml billboard '{}': {err}", e.id)).ok();
+                });
+            })
+            .collect();
+
+        return Self {
+            document,
+            dir,
+            opacity: 1.,
+            point_pipeline: make_pipeline(ao, scene, wgpu::PrimitiveTopology::PointList),
+            marker_buffer,
+            num_markers: 0,
+            polylines,
+            polyline_entities,
+            labels: LabelRenderer::new(ao),
+            billboards,
+            billboard_icons,
+        };
+    }
+
+    fn build_vertices(&self, t: f64) -> (Vec<Vertex>, Vec<Label>, Vec<Billboard>) {
+        let mut markers = vec![];
+        let mut labels = vec![];
+        let mut billboards = vec![];
+        let fade = |c: [f32; 4]| [c[0], c[1], c[2], c[3] * self.opacity];
+
+        for (e, icon) in self.document.entities.iter().zip(&self.billboard_icons).filter(|(e, _)| e.is_available(t)) {
+            let position = e.position_at(t);
+            let color = match (&e.point, &e.billboard, icon) {
+                (Some(p), _, _) if p.show => Some(p.color),
+                (_, Some(b), Some(icon)) if b.show => {
+                    if let Some(p) = position {
+                        billboards.push(Billboard { scale: b.scale, color: b.color, ..Billboard::new(p, *icon) });
+                    }
+                    None
+                }
+                (_, Some(b), None) if b.show => Some(b.color),
+                _ => None,
+            };
+            if let (Some(color), Some(p)) = (color, position) {
+                markers.push(Vertex::new(&p, fade(color)));
+            }
+            if let (Some(l), Some(p)) = (e.label.as_ref().filter(|l| l.show), position) {
+                let mut label = Label::new(p, &l.text);
+                label.color = l.fill_color;
+                labels.push(label);
+            }
+        }
+        return (markers, labels, billboards);
+    }
+}
+
+impl Renderable for CzmlEntities {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        let t = scene.clock.current;
+        let (markers, labels, billboards) = self.build_vertices(t);
+        ao.queue.write_buffer(&self.marker_buffer, 0, bytemuck::cast_slice(&markers));
+        self.num_markers = markers.len() as u32;
+        for (visible, &i) in self.polylines.visible.iter_mut().zip(&self.polyline_entities) {
+            let e = &self.document.entities[i];
+            *visible = e.is_available(t) && e.polyline.as_ref().is_some_and(|pl| pl.show);
+        }
+        self.polylines.update(ao, scene, self.opacity);
+        self.billboards.update(ao, scene, &billboards, self.opacity);
+        self.labels.update(ao, scene, &labels, self.opacity);
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("czmlPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+
+        self.polylines.draw(&mut render_pass, rs.ao, rs.scene);
+
+        render_pass.set_bind_group(0, &rs.scene.bind_group, &[]);
+        if self.num_markers > 0 {
+            render_pass.set_pipeline(&rs.ao.pipelines.get(&self.point_pipeline));
+            render_pass.set_vertex_buffer(0, self.marker_buffer.slice(..));
+            render_pass.draw(0..self.num_markers, 0..1);
+        }
+
+        self.billboards.draw(&mut render_pass, rs.ao, rs.scene);
+        self.labels.draw(&mut render_pass, rs.ao);
+    }
+
+    fn stats(&self) -> DrawStats {
+        let markers = DrawStats { draw_calls: (self.num_markers > 0) as u32, ..Default::default() };
+        return self.polylines.stats() + markers + self.billboards.stats() + self.labels.stats();
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    fn time_range(&self) -> Option<(f64, f64)> {
+        return self.document.time_range();
+    }
+}
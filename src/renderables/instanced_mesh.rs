@@ -0,0 +1,224 @@
+use nalgebra::{Matrix4, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::model::{light_at, DepthTarget};
+use crate::core::culling::BoundingSphere;
+use crate::core::{AppObjects, Bounds, DrawStats, Geodetic, PipelineBuilder, PipelineId, RenderState, Renderable, Scene};
+use crate::core::shaders::uniforms::uniform_struct;
+use crate::instancing::{Instance, InstanceSet};
+use crate::models::Primitive;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct MeshVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+}
+
+uniform_struct! {
+    pub(super) struct MeshUniforms {
+        center_mv: [f32; 16],
+        light: [f32; 4],
+    }
+}
+
+/// Many copies of one mesh (traffic, sensors, trees), drawn with a single instanced draw call.
+///
+/// Instances are placed relative to `center` (see `Instance::from_placement`) so they keep their
+/// precision anywhere on the globe. Change them through `instances`; only the instances that
+/// changed are uploaded on the next update. Like `Model`, the mesh has a depth buffer of its own.
+pub struct InstancedMesh {
+    /// ECEF meters.
+    pub center: Vector3<f64>,
+    pub instances: InstanceSet,
+    opacity: f32,
+
+    pipeline: PipelineId,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    instance_buffer: Option<wgpu::Buffer>,
+    /// Instances `instance_buffer` has room for.
+    capacity: usize,
+    /// Instances to draw, as of the last update.
+    num_instances: u32,
+    /// Distance from the mesh's origin to its farthest vertex.
+    mesh_radius: f64,
+    depth: DepthTarget,
+}
+
+impl InstancedMesh {
+    pub fn new(ao: &AppObjects, scene: &Scene, mesh: &Primitive, center: Vector3<f64>, instances: Vec<Instance>) -> Self {
+        let vertices: Vec<MeshVertex> = mesh
+            .positions
+            .iter()
+            .zip(&mesh.normals)
+            .map(|(&position, &normal)| MeshVertex { position, normal })
+            .collect();
+        let vertex_buffer = ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("instancedMeshVertices"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("instancedMeshIndices"),
+            contents: bytemuck::cast_slice(&mesh.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let uniform_buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("instancedMeshUniforms"),
+            size: std::mem::size_of::<MeshUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }]);
+        let bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("instancedMeshBg"),
+            layout: &bgl,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() }],
+        });
+
+        const VERTEX_ATTRS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+        // The id at the end of `Instance` isn't read by the shader.
+        const INSTANCE_ATTRS: [wgpu::VertexAttribute; 4] =
+            wgpu::vertex_attr_array![2 => Float32x4, 3 => Float32x4, 4 => Float32x4, 5 => Unorm8x4];
+        let pipeline = PipelineBuilder::new("instancedMeshPipeline", "instanced_mesh.wgsl")
+            .bind_groups(&[&scene.bind_group_layout, &bgl])
+            .vertex_buffer(wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<MeshVertex>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &VERTEX_ATTRS,
+            })
+            .vertex_buffer(wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &INSTANCE_ATTRS,
+            })
+            .depth()
+            .build(ao);
+
+        return Self {
+            center,
+            instances: InstanceSet::new(instances),
+            opacity: 1.,
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            num_indices: mesh.indices.len() as u32,
+            uniform_buffer,
+            bind_group,
+            instance_buffer: None,
+            capacity: 0,
+            num_instances: 0,
+            mesh_radius: mesh.positions.iter().map(|p| Vector3::from(*p).norm() as f64).fold(0., f64::max),
+            depth: Default::default(),
+        };
+    }
+
+    /// Upload changed instances, reallocating (and uploading everything) when they outgrow the
+    /// buffer.
+    fn upload_instances(&mut self, ao: &AppObjects) {
+        let dirty = self.instances.take_dirty();
+        let all = self.instances.as_slice();
+        if all.len() > self.capacity {
+            self.capacity = all.len().next_power_of_two();
+            let buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("instancedMeshInstances"),
+                size: (self.capacity * std::mem::size_of::<Instance>()) as u64,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            ao.queue.write_buffer(&buffer, 0, bytemuck::cast_slice(all));
+            self.instance_buffer = Some(buffer);
+        } else if let (Some(range), Some(buffer)) = (dirty, &self.instance_buffer) {
+            let offset = (range.start * std::mem::size_of::<Instance>()) as u64;
+            ao.queue.write_buffer(buffer, offset, bytemuck::cast_slice(&all[range]));
+        }
+        self.num_instances = all.len() as u32;
+    }
+}
+
+impl Renderable for InstancedMesh {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        self.depth.update(ao);
+        self.upload_instances(ao);
+
+        let center_mv = scene.cam.pose.to_matrix() * Matrix4::new_translation(&self.center);
+        let light = light_at(&Geodetic::from_ecef(&self.center));
+        let uniforms = MeshUniforms {
+            center_mv: center_mv.cast::<f32>().as_slice().try_into().unwrap(),
+            light: [light.x as f32, light.y as f32, light.z as f32, self.opacity],
+        };
+        ao.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let (Some(depth), Some(instances)) = (self.depth.attachment(), &self.instance_buffer) else {
+            return;
+        };
+        if self.num_instances == 0 {
+            return;
+        }
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("instancedMeshPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(depth),
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+
+        render_pass.set_pipeline(&rs.ao.pipelines.get(&self.pipeline));
+        render_pass.set_bind_group(0, &rs.scene.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instances.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..self.num_instances);
+    }
+
+    fn stats(&self) -> DrawStats {
+        if self.num_instances == 0 {
+            return DrawStats::default();
+        }
+        return DrawStats { draw_calls: 1, triangles: (self.num_indices / 3) as u64 * self.num_instances as u64, tiles: None };
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        // Instances may be scaled; the norm of the linear part bounds how far they stretch the
+        // mesh.
+        let radius = self
+            .instances
+            .as_slice()
+            .iter()
+            .map(|inst| {
+                let scale = inst.transform.iter().map(|r| Vector3::new(r[0], r[1], r[2]).norm_squared()).sum::<f32>().sqrt();
+                inst.offset().norm() as f64 + scale as f64 * self.mesh_radius
+            })
+            .reduce(f64::max)?;
+        return Some(Bounds::from_sphere(BoundingSphere { center: self.center, radius }));
+    }
+}
@@ -0,0 +1,61 @@
+// Many copies of one mesh, each with its own transform and colour.
+
+#include "scene.wgsl"
+#include "lighting.wgsl"
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    // Rows of the mesh space to (meters from the centre) transform
+    @location(2) row0: vec4<f32>,
+    @location(3) row1: vec4<f32>,
+    @location(4) row2: vec4<f32>,
+    @location(5) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct MeshUniforms {
+    // View transform with the translation to the mesh's centre folded in (in f64, on the CPU),
+    // so instance positions stay small.
+    center_mv: mat4x4<f32>,
+    // xyz: direction towards the light in world space, w: layer opacity.
+    light: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> mesh: MeshUniforms;
+
+@vertex
+fn vs_main(
+    v: VertexInput,
+    inst: InstanceInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let p = vec4<f32>(v.position, 1.0);
+    let world = vec3<f32>(dot(inst.row0, p), dot(inst.row1, p), dot(inst.row2, p));
+    // Fine for rotations and uniform scales, which is what instances are.
+    let n = vec4<f32>(v.normal, 0.0);
+    out.normal = vec3<f32>(dot(inst.row0, n), dot(inst.row1, n), dot(inst.row2, n));
+    out.color = inst.color;
+    out.clip_position = scene.proj * mesh.center_mv * vec4<f32>(world, 1.0);
+    if (inst.color.a <= 0.0) {
+        // Hidden: outside the clip volume, so the triangles are dropped.
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+    }
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(shade(in.color.rgb, in.normal, mesh.light.xyz), in.color.a * mesh.light.w);
+}
@@ -0,0 +1,249 @@
+use crate::core::{AppObjects, Bounds, DrawStats, PipelineBuilder, PipelineId, RenderState, Renderable, Scene};
+use crate::core::shaders::uniforms::uniform_struct;
+use crate::labels::{layout_text, place_labels, GlyphAtlas, Label, LabelView};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct LabelVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+impl LabelVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRS: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LabelVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRS,
+        }
+    }
+}
+
+uniform_struct! {
+    pub(super) struct LabelUniforms {
+        viewport: [f32; 2],
+        pad1: f32,
+        pad2: f32,
+    }
+}
+
+const ATLAS_SIZE: u32 = 1024;
+
+/// Lays out, places and draws labels with a shared glyph atlas. Used by `Labels` and by other
+/// renderables that have text to show.
+pub(super) struct LabelRenderer {
+    atlas: GlyphAtlas,
+    texture: wgpu::Texture,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: PipelineId,
+    vertex_buffer: Option<wgpu::Buffer>,
+    num_vertices: u32,
+}
+
+impl LabelRenderer {
+    pub fn new(ao: &AppObjects) -> Self {
+        let texture = ao.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("glyphAtlas"),
+            size: wgpu::Extent3d { width: ATLAS_SIZE, height: ATLAS_SIZE, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = ao.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("glyphSampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("labelUniforms"),
+            size: std::mem::size_of::<LabelUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ]);
+        let bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("labelBg"),
+            layout: &bgl,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&view) },
+                wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Sampler(&sampler) },
+            ],
+        });
+
+        let pipeline = PipelineBuilder::new("labelPipeline", "labels.wgsl")
+            .bind_groups(&[&bgl])
+            .vertex_buffer(LabelVertex::desc())
+            .build(ao);
+
+        return Self {
+            atlas: GlyphAtlas::new(ATLAS_SIZE, ATLAS_SIZE),
+            texture,
+            uniform_buffer,
+            bind_group,
+            pipeline,
+            vertex_buffer: None,
+            num_vertices: 0,
+        };
+    }
+
+    /// Place `labels` for the current camera and build their glyph quads.
+    pub fn update(&mut self, ao: &AppObjects, scene: &Scene, labels: &[Label], opacity: f32) {
+        for l in labels {
+            self.atlas.ensure(&l.text);
+        }
+        if self.atlas.take_dirty() {
+            ao.queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &self.atlas.pixels,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.atlas.width),
+                    rows_per_image: Some(self.atlas.height),
+                },
+                wgpu::Extent3d { width: self.atlas.width, height: self.atlas.height, depth_or_array_layers: 1 },
+            );
+        }
+
+        let viewport = [ao.config.width as f32, ao.config.height as f32];
+        ao.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&LabelUniforms { viewport, ..Default::default() }));
+
+        let layouts: Vec<_> = labels.iter().map(|l| layout_text(&self.atlas, &l.text, l.size)).collect();
+        let sizes: Vec<[f32; 2]> = layouts.iter().map(|t| [t.width, t.height]).collect();
+        let view = LabelView { view_proj: scene.cam.view_projection(), eye: scene.cam.position(), viewport };
+
+        let mut vertices = vec![];
+        for placed in place_labels(labels, &sizes, &view) {
+            let label = &labels[placed.index];
+            let color = [label.color[0], label.color[1], label.color[2], label.color[3] * opacity];
+            let [x, y, _, _] = placed.rect;
+            for q in &layouts[placed.index].quads {
+                let v = |i: usize, j: usize| LabelVertex { position: [x + q.rect[i], y + q.rect[j]], uv: [q.uv[i], q.uv[j]], color };
+                vertices.extend([v(0, 1), v(2, 1), v(2, 3), v(0, 1), v(2, 3), v(0, 3)]);
+            }
+        }
+
+        let bytes: &[u8] = bytemuck::cast_slice(&vertices);
+        if self.vertex_buffer.as_ref().is_none_or(|b| b.size() < bytes.len() as u64) {
+            self.vertex_buffer = Some(ao.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("labelVertices"),
+                size: (bytes.len() as u64).next_power_of_two().max(4096),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        ao.queue.write_buffer(self.vertex_buffer.as_ref().unwrap(), 0, bytes);
+        self.num_vertices = vertices.len() as u32;
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, ao: &AppObjects) {
+        let Some(vb) = self.vertex_buffer.as_ref().filter(|_| self.num_vertices > 0) else {
+            return;
+        };
+        render_pass.set_pipeline(&ao.pipelines.get(&self.pipeline));
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vb.slice(..));
+        render_pass.draw(0..self.num_vertices, 0..1);
+    }
+
+    pub fn stats(&self) -> DrawStats {
+        if self.num_vertices == 0 {
+            return DrawStats::default();
+        }
+        return DrawStats { draw_calls: 1, triangles: self.num_vertices as u64 / 3, tiles: None };
+    }
+}
+
+/// Text labels anchored at ECEF positions, drawn on top of everything else in the layer stack
+/// below them. Labels over the horizon are hidden, and where labels overlap only the highest
+/// priority one is shown.
+pub struct Labels {
+    pub labels: Vec<Label>,
+    opacity: f32,
+    renderer: LabelRenderer,
+}
+
+impl Labels {
+    pub fn new(ao: &AppObjects, labels: Vec<Label>) -> Self {
+        return Self { labels, opacity: 1., renderer: LabelRenderer::new(ao) };
+    }
+}
+
+impl Renderable for Labels {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        self.renderer.update(ao, scene, &self.labels, self.opacity);
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("labelPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+        self.renderer.draw(&mut render_pass, rs.ao);
+    }
+
+    fn stats(&self) -> DrawStats {
+        return self.renderer.stats();
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        return Bounds::from_points(self.labels.iter().map(|l| &l.position));
+    }
+}
@@ -0,0 +1,53 @@
+// Vertex shader
+
+struct VertexInput {
+    // Pixels from the top-left of the viewport
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct LabelUniforms {
+    viewport: vec2<f32>,
+    pad1: f32,
+    pad2: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> labels: LabelUniforms;
+@group(0) @binding(1)
+var atlas: texture_2d<f32>;
+@group(0) @binding(2)
+var atlas_sampler: sampler;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    let ndc = in.position / labels.viewport * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+// Fragment shader
+
+// Distance field values of the glyph outline and of the dark halo around it.
+const EDGE: f32 = 0.5;
+const HALO: f32 = 0.3;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let d = textureSample(atlas, atlas_sampler, in.uv).r;
+    // About one pixel of antialiasing at any scale.
+    let w = max(fwidth(d) * 0.7, 1e-3);
+    let fill = smoothstep(EDGE - w, EDGE + w, d);
+    let halo = smoothstep(HALO - w, HALO + w, d);
+    return vec4<f32>(in.color.rgb * fill, in.color.a * halo);
+}
@@ -1,4 +1,37 @@
-
+mod billboards;
+mod czml;
+mod instanced_mesh;
+mod labels;
+mod model;
+mod point_cloud;
+mod polylines;
+mod satellites;
 mod simple_shape;
+mod tiles;
+mod tracks;
 
+pub use billboards::Billboards;
+pub use czml::CzmlEntities;
+pub use instanced_mesh::InstancedMesh;
+pub use labels::Labels;
+pub use model::{placement, Model};
+pub use point_cloud::PointCloud;
+pub use polylines::Polylines;
+pub use satellites::Satellites;
 pub use simple_shape::SimpleShape;
+pub use tiles::Tiles3d;
+pub use tracks::Tracks;
+
+#[test]
+fn uniforms_match_shaders() {
+    use crate::core::shaders::uniforms::assert_matches_shader;
+    assert_matches_shader::<billboards::BillboardUniforms>("billboards.wgsl");
+    assert_matches_shader::<instanced_mesh::MeshUniforms>("instanced_mesh.wgsl");
+    assert_matches_shader::<labels::LabelUniforms>("labels.wgsl");
+    assert_matches_shader::<model::NodeUniforms>("model.wgsl");
+    assert_matches_shader::<model::MaterialUniforms>("model.wgsl");
+    assert_matches_shader::<point_cloud::CloudUniforms>("point_cloud.wgsl");
+    assert_matches_shader::<polylines::PolylineUniforms>("polylines.wgsl");
+    assert_matches_shader::<simple_shape::ShapeUniforms>("simple_shape.wgsl");
+    assert_matches_shader::<tracks::DrawUniforms>("tracks.wgsl");
+}
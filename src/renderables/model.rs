@@ -0,0 +1,494 @@
+use nalgebra::{Matrix3, Matrix4, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::core::geo::hpr_to_ecef;
+use crate::core::culling::BoundingSphere;
+use crate::core::{AppObjects, Bounds, CachedLayout, DrawStats, Geodetic, PipelineBuilder, PipelineId, RenderState, Renderable, Scene, DEPTH_FORMAT};
+use crate::core::shaders::uniforms::uniform_struct;
+use crate::models::{Material, ModelData};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct Vertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    uv: [f32; 2],
+}
+
+impl Vertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRS: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x2];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRS,
+        }
+    }
+}
+
+uniform_struct! {
+    pub(super) struct NodeUniforms {
+        model: [f32; 16],
+        normal: [f32; 16],
+        light: [f32; 4],
+    }
+}
+
+uniform_struct! {
+    pub(super) struct MaterialUniforms {
+        base_color: [f32; 4],
+        metallic_roughness: [f32; 4],
+    }
+}
+
+struct GpuPrimitive {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    material: Option<usize>,
+}
+
+/// glTF model space (+Y up, +Z forward, +X left) to the body frame of `geo::hpr_to_ecef`
+/// (x forward, y left, z up).
+fn gltf_to_body() -> Matrix3<f64> {
+    return Matrix3::new(
+        0., 0., 1., //
+        1., 0., 0., //
+        0., 1., 0., //
+    );
+}
+
+/// Model space to ECEF for a model standing at `at`, turned by heading/pitch/roll in degrees and
+/// uniformly scaled.
+pub fn placement(at: &Geodetic, heading: f64, pitch: f64, roll: f64, scale: f64) -> Matrix4<f64> {
+    let rot = hpr_to_ecef(at, heading, pitch, roll).to_rotation_matrix().into_inner() * gltf_to_body() * scale;
+    return Matrix4::new_translation(&at.to_ecef()) * rot.to_homogeneous();
+}
+
+/// Pipeline and layouts for drawing `ModelData`, shared by everything that draws models.
+pub(super) struct ModelPipeline {
+    pipeline: PipelineId,
+    node_bgl: CachedLayout,
+    material_bgl: CachedLayout,
+    // One sampler for everything; per-texture sampler settings in the file are ignored.
+    sampler: wgpu::Sampler,
+    white: wgpu::TextureView,
+}
+
+impl ModelPipeline {
+    pub fn new(ao: &AppObjects, scene: &Scene) -> Self {
+        let node_bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<NodeUniforms>() as u64),
+                },
+                count: None,
+            }]);
+
+        let material_bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ]);
+
+        let sampler = ao.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("modelSampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let pipeline = PipelineBuilder::new("modelPipeline", "model.wgsl")
+            .bind_groups(&[&scene.bind_group_layout, &node_bgl, &material_bgl])
+            .vertex_buffer(Vertex::desc())
+            .depth()
+            .build(ao);
+
+        let white = upload_image(ao, 1, 1, &[255; 4]);
+        return Self { pipeline, node_bgl, material_bgl, sampler, white };
+    }
+
+    pub fn set(&self, render_pass: &mut wgpu::RenderPass, ao: &AppObjects, scene: &Scene) {
+        render_pass.set_pipeline(&ao.pipelines.get(&self.pipeline));
+        render_pass.set_bind_group(0, &scene.bind_group, &[]);
+    }
+
+    fn make_material(&self, ao: &AppObjects, m: &Material, images: &[wgpu::TextureView]) -> wgpu::BindGroup {
+        let uniforms = MaterialUniforms { base_color: m.base_color, metallic_roughness: [m.metallic, m.roughness, 0., 0.] };
+        let buffer = ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("modelMaterial"),
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let view = m.base_color_texture.and_then(|i| images.get(i)).unwrap_or(&self.white);
+        return ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("modelMaterialBg"),
+            layout: &self.material_bgl,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(view) },
+                wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+            ],
+        });
+    }
+}
+
+fn upload_image(ao: &AppObjects, width: u32, height: u32, rgba: &[u8]) -> wgpu::TextureView {
+    let texture = ao.device.create_texture_with_data(
+        &ao.queue,
+        &wgpu::TextureDescriptor {
+            label: Some("modelTexture"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        rgba,
+    );
+    return texture.create_view(&wgpu::TextureViewDescriptor::default());
+}
+
+/// GPU copy of one `ModelData`, drawn `instances` times with different placements.
+pub(super) struct ModelMeshes {
+    instances: usize,
+    node_count: usize,
+    node_stride: u64,
+    node_buffer: wgpu::Buffer,
+    node_bind_group: wgpu::BindGroup,
+    material_bind_groups: Vec<wgpu::BindGroup>,
+    default_material: wgpu::BindGroup,
+    meshes: Vec<Vec<GpuPrimitive>>,
+    /// Nodes reachable from the scene roots that have a mesh, with that mesh.
+    drawn_nodes: Vec<(usize, usize)>,
+}
+
+impl ModelMeshes {
+    pub fn new(ao: &AppObjects, mp: &ModelPipeline, data: &ModelData, instances: usize) -> Self {
+        let node_size = std::mem::size_of::<NodeUniforms>() as u64;
+        let align = ao.device.limits().min_uniform_buffer_offset_alignment as u64;
+        let node_stride = node_size.div_ceil(align) * align;
+        let node_count = data.nodes.len();
+
+        let node_buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("modelNodes"),
+            size: node_stride * (node_count * instances).max(1) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let node_bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("modelNodeBg"),
+            layout: &mp.node_bgl,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &node_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(node_size),
+                }),
+            }],
+        });
+
+        let images: Vec<wgpu::TextureView> = data
+            .images
+            .iter()
+            .map(|im| if im.width > 0 && im.height > 0 { upload_image(ao, im.width, im.height, &im.rgba) } else { mp.white.clone() })
+            .collect();
+        let material_bind_groups = data.materials.iter().map(|m| mp.make_material(ao, m, &images)).collect();
+        let default_material = mp.make_material(ao, &Default::default(), &images);
+
+        let meshes = data
+            .meshes
+            .iter()
+            .map(|mesh| {
+                mesh.primitives
+                    .iter()
+                    .map(|p| {
+                        let vertices: Vec<Vertex> = (0..p.positions.len())
+                            .map(|i| Vertex { position: p.positions[i], normal: p.normals[i], uv: p.uvs[i] })
+                            .collect();
+                        GpuPrimitive {
+                            vertex_buffer: ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                                label: Some("modelVertices"),
+                                contents: bytemuck::cast_slice(&vertices),
+                                usage: wgpu::BufferUsages::VERTEX,
+                            }),
+                            index_buffer: ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                                label: Some("modelIndices"),
+                                contents: bytemuck::cast_slice(&p.indices),
+                                usage: wgpu::BufferUsages::INDEX,
+                            }),
+                            num_indices: p.indices.len() as u32,
+                            material: p.material,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut drawn_nodes = vec![];
+        let mut stack = data.roots.clone();
+        while let Some(i) = stack.pop() {
+            if let Some(m) = data.nodes[i].mesh {
+                drawn_nodes.push((i, m));
+            }
+            stack.extend(&data.nodes[i].children);
+        }
+
+        return Self {
+            instances,
+            node_count,
+            node_stride,
+            node_buffer,
+            node_bind_group,
+            material_bind_groups,
+            default_material,
+            meshes,
+            drawn_nodes,
+        };
+    }
+
+    /// Write node transforms for each instance placement (model space to ECEF).
+    pub fn update(
+        &self,
+        ao: &AppObjects,
+        data: &ModelData,
+        animation: Option<(usize, f32)>,
+        placements: &[Matrix4<f64>],
+        light: &Vector3<f64>,
+        opacity: f32,
+    ) {
+        let transforms = data.node_transforms(animation);
+        let mut bytes = vec![0u8; self.node_buffer.size() as usize];
+        for (k, place) in placements.iter().take(self.instances).enumerate() {
+            for (i, tf) in transforms.iter().enumerate() {
+                let model = place * tf.cast::<f64>();
+                let normal = model.fixed_view::<3, 3>(0, 0).try_inverse().unwrap_or_else(Matrix3::identity).transpose();
+                let uniforms = NodeUniforms {
+                    model: model.cast::<f32>().as_slice().try_into().unwrap(),
+                    normal: normal.to_homogeneous().cast::<f32>().as_slice().try_into().unwrap(),
+                    light: [light.x as f32, light.y as f32, light.z as f32, opacity],
+                };
+                let at = (k * self.node_count + i) * self.node_stride as usize;
+                bytes[at..at + std::mem::size_of::<NodeUniforms>()].copy_from_slice(bytemuck::bytes_of(&uniforms));
+            }
+        }
+        ao.queue.write_buffer(&self.node_buffer, 0, &bytes);
+    }
+
+    /// Draw all instances. The caller sets the pipeline with `ModelPipeline::set` first.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        for k in 0..self.instances {
+            for &(i, m) in &self.drawn_nodes {
+                let offset = ((k * self.node_count + i) as u64 * self.node_stride) as u32;
+                render_pass.set_bind_group(1, &self.node_bind_group, &[offset]);
+                for p in self.meshes.get(m).into_iter().flatten() {
+                    let material = p.material.and_then(|m| self.material_bind_groups.get(m)).unwrap_or(&self.default_material);
+                    render_pass.set_bind_group(2, material, &[]);
+                    render_pass.set_vertex_buffer(0, p.vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(p.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.draw_indexed(0..p.num_indices, 0, 0..1);
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> DrawStats {
+        let primitives = self.drawn_nodes.iter().flat_map(|&(_, m)| self.meshes.get(m).into_iter().flatten());
+        let (draws, triangles) = primitives.fold((0, 0), |(d, t), p| (d + 1, t + p.num_indices as u64 / 3));
+        return DrawStats { draw_calls: draws * self.instances as u32, triangles: triangles * self.instances as u64, tiles: None };
+    }
+}
+
+/// A depth buffer that follows the surface size, for renderables that need one of their own.
+#[derive(Default)]
+pub(super) struct DepthTarget {
+    view: Option<wgpu::TextureView>,
+    size: (u32, u32),
+    samples: u32,
+}
+
+impl DepthTarget {
+    pub fn update(&mut self, ao: &AppObjects) {
+        let size = (ao.config.width, ao.config.height);
+        let samples = ao.settings().msaa_samples;
+        if self.view.is_some() && self.size == size && self.samples == samples {
+            return;
+        }
+        let texture = ao.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("modelDepth"),
+            size: wgpu::Extent3d { width: size.0.max(1), height: size.1.max(1), depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: samples,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        self.view = Some(texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        self.size = size;
+        self.samples = samples;
+    }
+
+    /// Cleared at the start of the pass and thrown away at the end.
+    pub fn attachment(&self) -> Option<wgpu::RenderPassDepthStencilAttachment<'_>> {
+        return Some(wgpu::RenderPassDepthStencilAttachment {
+            view: self.view.as_ref()?,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: wgpu::StoreOp::Discard,
+            }),
+            stencil_ops: None,
+        });
+    }
+}
+
+/// A glTF model placed on the globe. Animation `animation` (if any) loops against the scene clock.
+///
+/// Each model draws with its own depth buffer, so it occludes itself properly but not other
+/// layers. Vertices end up in ECEF as f32, which is fine for vehicles and buildings seen from
+/// more than a few meters away.
+pub struct Model {
+    pub data: ModelData,
+    pub position: Geodetic,
+    pub heading: f64,
+    pub pitch: f64,
+    pub roll: f64,
+    pub scale: f64,
+    pub animation: Option<usize>,
+    opacity: f32,
+    /// Distance from the model's origin to the farthest corner of its bounding box at rest.
+    radius: f64,
+
+    pipeline: ModelPipeline,
+    meshes: ModelMeshes,
+    depth: DepthTarget,
+}
+
+impl Model {
+    pub fn new(ao: &AppObjects, scene: &Scene, data: ModelData, position: Geodetic) -> Self {
+        let pipeline = ModelPipeline::new(ao, scene);
+        let meshes = ModelMeshes::new(ao, &pipeline, &data, 1);
+        let radius = data.bounds().map_or(0., |(lo, hi)| lo.abs().sup(&hi.abs()).norm() as f64);
+        return Self {
+            data,
+            position,
+            heading: 0.,
+            pitch: 0.,
+            roll: 0.,
+            scale: 1.,
+            animation: None,
+            opacity: 1.,
+            radius,
+            pipeline,
+            meshes,
+            depth: Default::default(),
+        };
+    }
+}
+
+/// Direction the models are lit from: high in the sky, a bit to the north east.
+pub(super) fn light_at(at: &Geodetic) -> Vector3<f64> {
+    return at.enu_to_ecef() * Vector3::new(0.3, 0.4, 1.).normalize();
+}
+
+impl Renderable for Model {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        self.depth.update(ao);
+
+        let anim = self.animation.and_then(|i| {
+            let duration = self.data.animations.get(i)?.duration;
+            Some((i, if duration > 0. { (scene.clock.elapsed() as f32).rem_euclid(duration) } else { 0. }))
+        });
+        let place = placement(&self.position, self.heading, self.pitch, self.roll, self.scale);
+        self.meshes.update(ao, &self.data, anim, &[place], &light_at(&self.position), self.opacity);
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let Some(depth) = self.depth.attachment() else {
+            return;
+        };
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("modelPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(depth),
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+
+        self.pipeline.set(&mut render_pass, rs.ao, rs.scene);
+        self.meshes.draw(&mut render_pass);
+    }
+
+    fn stats(&self) -> DrawStats {
+        return self.meshes.stats();
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        // Animations can move parts out of the rest pose's bounds; a model seldom animates that
+        // far, so allow for double.
+        let radius = 2. * self.radius * self.scale;
+        return Some(Bounds::from_sphere(BoundingSphere { center: self.position.to_ecef(), radius }));
+    }
+}
+
+#[test]
+fn placement_uses_gltf_axes() {
+    let at = Geodetic::new(30., 40., 100.);
+    let m = placement(&at, 90., 0., 0., 2.);
+    let enu = at.enu_to_ecef();
+    let origin = m.transform_point(&nalgebra::Point3::origin()).coords;
+    assert!((origin - at.to_ecef()).norm() < 1e-6);
+
+    // Model forward (+Z) points east at heading 90, model up (+Y) points up, and scale applies.
+    let fwd = m.transform_vector(&Vector3::z());
+    let up = m.transform_vector(&Vector3::y());
+    assert!((fwd - enu.column(0) * 2.).norm() < 1e-9, "{fwd}");
+    assert!((up - enu.column(2) * 2.).norm() < 1e-9, "{up}");
+    // +X is the model's left, so north when facing east.
+    assert!((m.transform_vector(&Vector3::x()) - enu.column(1) * 2.).norm() < 1e-9);
+}
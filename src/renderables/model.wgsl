@@ -0,0 +1,59 @@
+// glTF models: base colour (factor times texture) with simple Lambert shading.
+
+#include "scene.wgsl"
+#include "lighting.wgsl"
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+};
+
+struct NodeUniforms {
+    // Model space to world (ECEF).
+    model: mat4x4<f32>,
+    normal: mat4x4<f32>,
+    // xyz: direction towards the light in world space, w: layer opacity.
+    light: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> node: NodeUniforms;
+
+struct MaterialUniforms {
+    base_color: vec4<f32>,
+    // x: metallic, y: roughness. Not used for shading yet.
+    metallic_roughness: vec4<f32>,
+};
+
+@group(2) @binding(0)
+var<uniform> material: MaterialUniforms;
+@group(2) @binding(1)
+var base_color_texture: texture_2d<f32>;
+@group(2) @binding(2)
+var base_color_sampler: sampler;
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.normal = (node.normal * vec4<f32>(model.normal, 0.0)).xyz;
+    out.uv = model.uv;
+    out.clip_position = scene.proj * scene.mv * node.model * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let base = material.base_color * textureSample(base_color_texture, base_color_sampler, in.uv);
+    return vec4<f32>(shade(base.rgb, in.normal, node.light.xyz), base.a * node.light.w);
+}
@@ -0,0 +1,233 @@
+use nalgebra::{Matrix4, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::model::DepthTarget;
+use crate::core::culling::BoundingSphere;
+use crate::core::{Action, AppObjects, Bounds, DrawStats, InputState, PipelineBuilder, PipelineId, RenderState, Renderable, Scene};
+use crate::core::shaders::uniforms::uniform_struct;
+use crate::pointclouds::{ColorMode, PointCloudData};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointVertex {
+    position: [f32; 3],
+    spacing: f32,
+}
+
+uniform_struct! {
+    pub(super) struct CloudUniforms {
+        center_mv: [f32; 16],
+        viewport: [f32; 2],
+        min_size: f32,
+        max_size: f32,
+        size_scale: f32,
+        opacity: f32,
+        pad1: f32,
+        pad2: f32,
+    }
+}
+
+/// A lidar point cloud, drawn as round screen-facing sprites with octree LOD.
+///
+/// Each frame the octree nodes whose point spacing is coarse on screen are refined, up to
+/// `point_budget` points. Points are sized by their node's spacing in pixels, clamped to
+/// `min_point_size..max_point_size`, so sparse levels still look solid. `C` cycles the colour
+/// mode. Points are stored relative to the octree root's centre, like `InstancedMesh`, so they
+/// keep their precision in f32.
+pub struct PointCloud {
+    pub data: PointCloudData,
+    pub color_mode: ColorMode,
+    pub point_budget: usize,
+    /// Keep refining while points are further apart than this many pixels.
+    pub max_spacing_px: f64,
+    pub min_point_size: f32,
+    pub max_point_size: f32,
+    opacity: f32,
+    /// Where the point buffer's origin is, in ECEF.
+    center: Vector3<f64>,
+
+    pipeline: PipelineId,
+    point_buffer: wgpu::Buffer,
+    color_buffer: wgpu::Buffer,
+    uploaded_mode: ColorMode,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    depth: DepthTarget,
+    /// Point ranges (in octree order) to draw this frame.
+    draws: Vec<std::ops::Range<u32>>,
+}
+
+impl PointCloud {
+    pub fn new(ao: &AppObjects, scene: &Scene, data: PointCloudData) -> Self {
+        // Points in octree order, so every node draws one contiguous range.
+        let center = data.octree.nodes.first().map_or(Vector3::zeros(), |root| root.center);
+        let mut vertices = vec![PointVertex::default(); data.octree.order.len()];
+        for n in &data.octree.nodes {
+            for k in n.start..n.end {
+                let p = data.positions[data.octree.order[k as usize] as usize] - center;
+                vertices[k as usize] = PointVertex { position: [p.x as f32, p.y as f32, p.z as f32], spacing: n.spacing as f32 };
+            }
+        }
+        let point_buffer = ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("pointCloudPoints"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let color_mode = ColorMode::default();
+        let color_buffer = ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("pointCloudColors"),
+            contents: bytemuck::cast_slice(&ordered_colors(&data, color_mode)),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pointCloudUniforms"),
+            size: std::mem::size_of::<CloudUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }]);
+        let bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("pointCloudBg"),
+            layout: &bgl,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() }],
+        });
+
+        const POINT_ATTRS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32];
+        const COLOR_ATTRS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![2 => Unorm8x4];
+        let pipeline = PipelineBuilder::new("pointCloudPipeline", "point_cloud.wgsl")
+            .bind_groups(&[&scene.bind_group_layout, &bgl])
+            // One sprite (6 vertices) per point instance.
+            .vertex_buffer(wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<PointVertex>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &POINT_ATTRS,
+            })
+            .vertex_buffer(wgpu::VertexBufferLayout {
+                array_stride: 4,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &COLOR_ATTRS,
+            })
+            .depth()
+            .build(ao);
+
+        return Self {
+            data,
+            color_mode,
+            point_budget: 3_000_000,
+            max_spacing_px: 2.,
+            min_point_size: 1.,
+            max_point_size: 8.,
+            opacity: 1.,
+            center,
+            pipeline,
+            point_buffer,
+            color_buffer,
+            uploaded_mode: color_mode,
+            uniform_buffer,
+            bind_group,
+            depth: Default::default(),
+            draws: vec![],
+        };
+    }
+}
+
+/// Colours for `mode`, in the octree order the point buffer uses.
+fn ordered_colors(data: &PointCloudData, mode: ColorMode) -> Vec<[u8; 4]> {
+    let colors = data.colors(mode);
+    return data.octree.order.iter().map(|&i| colors[i as usize]).collect();
+}
+
+impl Renderable for PointCloud {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        self.depth.update(ao);
+
+        if self.uploaded_mode != self.color_mode {
+            ao.queue.write_buffer(&self.color_buffer, 0, bytemuck::cast_slice(&ordered_colors(&self.data, self.color_mode)));
+            self.uploaded_mode = self.color_mode;
+        }
+
+        let center_mv = scene.cam.pose.to_matrix() * Matrix4::new_translation(&self.center);
+        let uniforms = CloudUniforms {
+            center_mv: center_mv.cast::<f32>().as_slice().try_into().unwrap(),
+            viewport: [ao.config.width as f32, ao.config.height as f32],
+            min_size: self.min_point_size,
+            max_size: self.max_point_size,
+            size_scale: 1.,
+            opacity: self.opacity,
+            ..Default::default()
+        };
+        ao.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+
+        let octree = &self.data.octree;
+        let nodes = octree.select(
+            &scene.cam.position(),
+            ao.config.height as f64,
+            scene.cam.intrin.sse_denominator(),
+            self.max_spacing_px,
+            self.point_budget,
+        );
+        self.draws = nodes.iter().map(|&i| octree.nodes[i as usize].start..octree.nodes[i as usize].end).collect();
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let Some(depth) = self.depth.attachment() else {
+            return;
+        };
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("pointCloudPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(depth),
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+
+        render_pass.set_pipeline(&rs.ao.pipelines.get(&self.pipeline));
+        render_pass.set_bind_group(0, &rs.scene.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.point_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.color_buffer.slice(..));
+        for range in &self.draws {
+            render_pass.draw(0..6, range.clone());
+        }
+    }
+
+    fn stats(&self) -> DrawStats {
+        // Each point is a quad.
+        let points: u64 = self.draws.iter().map(|r| r.len() as u64).sum();
+        return DrawStats { draw_calls: self.draws.len() as u32, triangles: 2 * points, tiles: None };
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let root = self.data.octree.nodes.first()?;
+        return Some(Bounds::from_sphere(BoundingSphere { center: root.center, radius: root.half_size * 3f64.sqrt() }));
+    }
+
+    fn handle_input(&mut self, _ao: &AppObjects, input: &InputState) {
+        if input.just_pressed(Action::CycleColours) {
+            self.color_mode = self.color_mode.next();
+            log::info!("point cloud colours: {:?}", self.color_mode);
+        }
+    }
+}
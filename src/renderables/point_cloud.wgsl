@@ -0,0 +1,67 @@
+#include "scene.wgsl"
+
+// Vertex shader
+
+struct PointInput {
+    // Relative to the octree root's centre
+    @location(0) position: vec3<f32>,
+    // Point spacing of the octree node the point belongs to, in meters
+    @location(1) spacing: f32,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) corner: vec2<f32>,
+};
+
+struct CloudUniforms {
+    // View transform with the translation to the octree root's centre folded in (in f64, on the
+    // CPU), so point positions stay small.
+    center_mv: mat4x4<f32>,
+    viewport: vec2<f32>,
+    min_size: f32,
+    max_size: f32,
+    size_scale: f32,
+    opacity: f32,
+    pad1: f32,
+    pad2: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> cloud: CloudUniforms;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    point: PointInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    let clip = scene.proj * cloud.center_mv * vec4<f32>(point.position, 1.0);
+    // The node spacing projected to pixels, so sparse (coarse) nodes draw bigger points and
+    // leave no holes.
+    let w = max(clip.w, 1e-6);
+    let size = clamp(point.spacing * cloud.size_scale * scene.proj[1][1] / w * 0.5 * cloud.viewport.y, cloud.min_size, cloud.max_size);
+
+    var out: VertexOutput;
+    out.clip_position = clip + vec4<f32>(corner * size / cloud.viewport * clip.w, 0.0, 0.0);
+    out.color = vec4<f32>(point.color.rgb, point.color.a * cloud.opacity);
+    out.corner = corner;
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (dot(in.corner, in.corner) > 1.0) {
+        discard;
+    }
+    return in.color;
+}
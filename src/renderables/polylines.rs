@@ -0,0 +1,224 @@
+use std::ops::Range;
+
+use crate::core::{AppObjects, Bounds, DrawStats, PipelineBuilder, PipelineId, RenderState, Renderable, Scene};
+use crate::core::shaders::uniforms::uniform_struct;
+use crate::polylines::{build_segments, dash_offsets, Polyline, Segment};
+
+uniform_struct! {
+    pub(super) struct PolylineUniforms {
+        viewport: [f32; 2],
+        opacity: f32,
+        pad1: f32,
+    }
+}
+
+/// Draws polylines as screen-space quads, one instance per segment. Used by `Polylines` and by
+/// other renderables with lines to draw.
+pub(super) struct PolylineRenderer {
+    lines: Vec<Polyline>,
+    /// Whether to draw each line; all true after `set_lines`.
+    pub visible: Vec<bool>,
+    pipeline: PipelineId,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    segment_buffer: Option<wgpu::Buffer>,
+    dash_buffer: Option<wgpu::Buffer>,
+    /// Segments of each line.
+    ranges: Vec<Range<u32>>,
+}
+
+impl PolylineRenderer {
+    pub fn new(ao: &AppObjects, scene: &Scene) -> Self {
+        let uniform_buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("polylineUniforms"),
+            size: std::mem::size_of::<PolylineUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }]);
+        let bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("polylineBg"),
+            layout: &bgl,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() }],
+        });
+
+        let attr = |shader_location, format, offset| wgpu::VertexAttribute { format, offset, shader_location };
+        use wgpu::VertexFormat::{Float32, Float32x2, Float32x3, Float32x4, Uint32};
+        let segment_attrs = [
+            attr(0, Float32x3, 0),
+            attr(1, Float32, 12),
+            attr(2, Float32x3, 16),
+            attr(3, Float32, 28),
+            attr(4, Float32x3, 32),
+            attr(5, Uint32, 44),
+            attr(6, Float32x3, 48),
+            attr(7, Float32x4, 64),
+            attr(8, Float32x4, 80),
+            attr(9, Float32x2, 96),
+        ];
+        let dash_attrs = [attr(10, Float32, 0)];
+        let pipeline = PipelineBuilder::new("polylinePipeline", "polylines.wgsl")
+            .bind_groups(&[&scene.bind_group_layout, &bgl])
+            // One quad (6 vertices) per segment instance.
+            .vertex_buffer(wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<Segment>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &segment_attrs,
+            })
+            .vertex_buffer(wgpu::VertexBufferLayout {
+                array_stride: 4,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &dash_attrs,
+            })
+            .build(ao);
+
+        return Self {
+            lines: vec![],
+            visible: vec![],
+            pipeline,
+            uniform_buffer,
+            bind_group,
+            segment_buffer: None,
+            dash_buffer: None,
+            ranges: vec![],
+        };
+    }
+
+    pub fn lines(&self) -> &[Polyline] {
+        return &self.lines;
+    }
+
+    /// Replace the lines and rebuild the segment buffer.
+    pub fn set_lines(&mut self, ao: &AppObjects, lines: Vec<Polyline>) {
+        let (segments, ranges) = build_segments(&lines);
+        let vertex_buffer = |label, size: usize| {
+            return ao.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size.max(4) as u64,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+        };
+        let segment_buffer = vertex_buffer("polylineSegments", segments.len() * std::mem::size_of::<Segment>());
+        ao.queue.write_buffer(&segment_buffer, 0, bytemuck::cast_slice(&segments));
+        let dash_buffer = vertex_buffer("polylineDashOffsets", segments.len() * 4);
+        ao.queue.write_buffer(&dash_buffer, 0, bytemuck::cast_slice(&vec![0f32; segments.len()]));
+
+        self.segment_buffer = Some(segment_buffer);
+        self.dash_buffer = Some(dash_buffer);
+        self.visible = vec![true; lines.len()];
+        self.ranges = ranges;
+        self.lines = lines;
+    }
+
+    pub fn update(&mut self, ao: &AppObjects, scene: &Scene, opacity: f32) {
+        let viewport = [ao.config.width as f32, ao.config.height as f32];
+        let uniforms = PolylineUniforms { viewport, opacity, pad1: 0. };
+        ao.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+
+        // Dashes follow the line's length on screen, which changes as the camera moves.
+        if let Some(dash_buffer) = &self.dash_buffer
+            && self.lines.iter().any(|l| l.dash.is_some())
+        {
+            let offsets = dash_offsets(&self.lines, &scene.cam.view_projection(), viewport);
+            ao.queue.write_buffer(dash_buffer, 0, bytemuck::cast_slice(&offsets));
+        }
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, ao: &AppObjects, scene: &Scene) {
+        let (Some(segments), Some(dashes)) = (&self.segment_buffer, &self.dash_buffer) else {
+            return;
+        };
+        render_pass.set_pipeline(&ao.pipelines.get(&self.pipeline));
+        render_pass.set_bind_group(0, &scene.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, segments.slice(..));
+        render_pass.set_vertex_buffer(1, dashes.slice(..));
+        for range in self.drawn_ranges() {
+            render_pass.draw(0..6, range.clone());
+        }
+    }
+
+    fn drawn_ranges(&self) -> impl Iterator<Item = &Range<u32>> {
+        return self.ranges.iter().zip(&self.visible).filter(|(r, v)| **v && !r.is_empty()).map(|(r, _)| r);
+    }
+
+    pub fn stats(&self) -> DrawStats {
+        if self.segment_buffer.is_none() {
+            return DrawStats::default();
+        }
+        // A quad per segment.
+        let segments: u64 = self.drawn_ranges().map(|r| r.len() as u64).sum();
+        return DrawStats { draw_calls: self.drawn_ranges().count() as u32, triangles: 2 * segments, tiles: None };
+    }
+}
+
+/// Lines through ECEF points drawn a set number of pixels wide, with joins, caps, dashes and
+/// outlines (see `polylines::Polyline`). Use `polylines::geodesic_path` for lines that should
+/// follow the curve of the globe.
+pub struct Polylines {
+    opacity: f32,
+    renderer: PolylineRenderer,
+}
+
+impl Polylines {
+    pub fn new(ao: &AppObjects, scene: &Scene, lines: Vec<Polyline>) -> Self {
+        let mut renderer = PolylineRenderer::new(ao, scene);
+        renderer.set_lines(ao, lines);
+        return Self { opacity: 1., renderer };
+    }
+
+    pub fn lines(&self) -> &[Polyline] {
+        return self.renderer.lines();
+    }
+
+    pub fn set_lines(&mut self, ao: &AppObjects, lines: Vec<Polyline>) {
+        self.renderer.set_lines(ao, lines);
+    }
+}
+
+impl Renderable for Polylines {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        self.renderer.update(ao, scene, self.opacity);
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("polylinePass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+        self.renderer.draw(&mut render_pass, rs.ao, rs.scene);
+    }
+
+    fn stats(&self) -> DrawStats {
+        return self.renderer.stats();
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        return Bounds::from_points(self.lines().iter().flat_map(|l| &l.positions));
+    }
+}
@@ -0,0 +1,172 @@
+#include "scene.wgsl"
+
+// Vertex shader
+
+// Flags, see `polylines::Segment`.
+const HAS_PREV: u32 = 1u;
+const HAS_NEXT: u32 = 2u;
+const JOIN_ROUND: u32 = 4u;
+const CAP_SQUARE: u32 = 8u;
+const CAP_ROUND: u32 = 16u;
+const MITER_LIMIT: f32 = 4.0;
+
+struct SegmentInput {
+    @location(0) prev: vec3<f32>,
+    // Pixels
+    @location(1) width: f32,
+    @location(2) p0: vec3<f32>,
+    @location(3) outline_width: f32,
+    @location(4) p1: vec3<f32>,
+    @location(5) flags: u32,
+    @location(6) next: vec3<f32>,
+    @location(7) color: vec4<f32>,
+    @location(8) outline_color: vec4<f32>,
+    // Dash and gap lengths in pixels
+    @location(9) dash: vec2<f32>,
+    // Screen length of the line before this segment, in pixels
+    @location(10) dash_offset: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Pixels along the segment from p0, and across it from the centre line
+    @location(0) @interpolate(linear) line_coord: vec2<f32>,
+    // Segment length, half the width, half the width with outline, round start (1/0) and end
+    @location(1) @interpolate(flat) shape: vec4<f32>,
+    @location(2) @interpolate(flat) round_ends: vec2<f32>,
+    @location(3) @interpolate(flat) color: vec4<f32>,
+    @location(4) @interpolate(flat) outline_color: vec4<f32>,
+    // Dash, gap and offset
+    @location(5) @interpolate(flat) dash: vec3<f32>,
+};
+
+struct PolylineUniforms {
+    viewport: vec2<f32>,
+    opacity: f32,
+    pad1: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> lines: PolylineUniforms;
+
+fn to_clip(p: vec3<f32>) -> vec4<f32> {
+    return scene.proj * scene.mv * vec4<f32>(p, 1.0);
+}
+
+fn to_px(c: vec4<f32>) -> vec2<f32> {
+    return c.xy / c.w * 0.5 * lines.viewport;
+}
+
+fn perp(v: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(-v.y, v.x);
+}
+
+// Where the corner of the segment quad on `side` goes at an end point, relative to it, in pixels.
+// `dir` points away from the segment and `out` is the direction to the neighbouring point, if
+// there is one. z is 1 when the end is round and must be trimmed in the fragment shader.
+fn end_offset(dir: vec2<f32>, out: vec2<f32>, has_neighbour: bool, neighbour_ok: bool, flags: u32, side: f32, extent: f32) -> vec3<f32> {
+    let n = perp(dir) * side;
+    if (has_neighbour && neighbour_ok) {
+        if ((flags & JOIN_ROUND) == 0u) {
+            // Miter: the corner lies on the bisector of the two segments.
+            let bisector = normalize(dir + out);
+            let miter = perp(bisector) * side;
+            let k = 1.0 / dot(miter, n);
+            if (dot(dir, out) > -0.999 && k > 0.0 && k <= MITER_LIMIT) {
+                return vec3<f32>(miter * extent * k, 0.0);
+            }
+        }
+        return vec3<f32>(n * extent + dir * extent, 1.0);
+    }
+    if (has_neighbour || (flags & CAP_ROUND) != 0u) {
+        return vec3<f32>(n * extent + dir * extent, 1.0);
+    }
+    if ((flags & CAP_SQUARE) != 0u) {
+        return vec3<f32>(n * extent + dir * extent, 0.0);
+    }
+    return vec3<f32>(n * extent, 0.0);
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    seg: SegmentInput,
+) -> VertexOutput {
+    // (end, side) of each corner of the quad.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    var out: VertexOutput;
+    let c0 = to_clip(seg.p0);
+    let c1 = to_clip(seg.p1);
+    let s0 = to_px(c0);
+    let s1 = to_px(c1);
+    if (c0.w <= 0.0 || c1.w <= 0.0 || distance(s0, s1) < 1e-4) {
+        // Outside the clip volume, so the triangles are dropped.
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+    let dir = normalize(s1 - s0);
+    let half_width = seg.width * 0.5;
+    let half_outer = half_width + seg.outline_width;
+    // One more pixel for antialiasing.
+    let extent = half_outer + 1.0;
+
+    var offset: vec3<f32>;
+    var clip: vec4<f32>;
+    var base: vec2<f32>;
+    let cp = to_clip(seg.prev);
+    let cn = to_clip(seg.next);
+    let start = end_offset(-dir, normalize(to_px(cp) - s0), (seg.flags & HAS_PREV) != 0u, cp.w > 0.0 && distance(to_px(cp), s0) > 1e-4, seg.flags, -corner.y, extent);
+    let end = end_offset(dir, normalize(to_px(cn) - s1), (seg.flags & HAS_NEXT) != 0u, cn.w > 0.0 && distance(to_px(cn), s1) > 1e-4, seg.flags, corner.y, extent);
+    if (corner.x == 0.0) {
+        offset = start;
+        clip = c0;
+        base = s0;
+    } else {
+        offset = end;
+        clip = c1;
+        base = s1;
+    }
+
+    out.clip_position = clip + vec4<f32>(offset.xy * 2.0 / lines.viewport * clip.w, 0.0, 0.0);
+    let rel = base + offset.xy - s0;
+    out.line_coord = vec2<f32>(dot(rel, dir), dot(rel, perp(dir)));
+    out.shape = vec4<f32>(distance(s0, s1), half_width, half_outer, 0.0);
+    out.round_ends = vec2<f32>(start.z, end.z);
+    out.color = seg.color;
+    out.outline_color = seg.outline_color;
+    out.dash = vec3<f32>(seg.dash, seg.dash_offset);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let along = in.line_coord.x;
+    let len = in.shape.x;
+    // Distance from the centre line, or from the end point past a round end.
+    var d = abs(in.line_coord.y);
+    if (along < 0.0 && in.round_ends.x > 0.5) {
+        d = length(in.line_coord);
+    } else if (along > len && in.round_ends.y > 0.5) {
+        d = length(vec2<f32>(along - len, in.line_coord.y));
+    }
+
+    if (in.dash.y > 0.0) {
+        let period = in.dash.x + in.dash.y;
+        let phase = in.dash.z + clamp(along, 0.0, len);
+        if (phase - floor(phase / period) * period > in.dash.x) {
+            discard;
+        }
+    }
+
+    let coverage = clamp(in.shape.z - d + 0.5, 0.0, 1.0);
+    let fill = clamp(in.shape.y - d + 0.5, 0.0, 1.0);
+    let color = mix(in.outline_color, in.color, fill);
+    return vec4<f32>(color.rgb, color.a * coverage * lines.opacity);
+}
@@ -0,0 +1,226 @@
+use std::collections::HashSet;
+
+use nalgebra::{Matrix3, Vector3};
+
+use super::simple_shape::ShapeBindings;
+use crate::core::clock::unix_to_jd;
+use crate::core::{AppObjects, DrawStats, Geodetic, PipelineBuilder, PipelineId, RenderState, Renderable, Scene};
+use crate::orbits::{gmst, teme_to_ecef, Satellite};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct Vertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl Vertex {
+    fn new(p: &Vector3<f64>, color: [f32; 3]) -> Self {
+        return Vertex { position: [p.x as f32, p.y as f32, p.z as f32], color };
+    }
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRS,
+        }
+    }
+}
+
+const MARKER_COLOR: [f32; 3] = [1.0, 0.9, 0.2];
+const ORBIT_COLOR: [f32; 3] = [0.3, 0.8, 1.0];
+const GROUND_TRACK_COLOR: [f32; 3] = [0.9, 0.4, 0.2];
+
+/// Satellite markers, orbit paths and ground tracks, propagated with SGP4 to the scene clock.
+///
+/// The orbit is drawn over one period in the inertial frame, rotated to the current earth
+/// orientation, so it shows up as a closed ellipse. The ground track is the sub-satellite point
+/// over half a period either side of now. Nothing is recomputed while the clock stands still
+/// and the settings are unchanged.
+pub struct Satellites {
+    pub satellites: Vec<Satellite>,
+    pub show_orbits: bool,
+    pub show_ground_tracks: bool,
+    pub samples_per_orbit: usize,
+    opacity: f32,
+
+    bindings: ShapeBindings,
+    point_pipeline: PipelineId,
+    line_pipeline: PipelineId,
+    marker_buffer: Option<wgpu::Buffer>,
+    line_buffer: Option<wgpu::Buffer>,
+    num_markers: u32,
+    num_line_verts: u32,
+    /// Clock time, satellite count and settings the buffers were built for.
+    built_for: Option<(f64, usize, bool, bool, usize)>,
+    /// NORAD ids of the satellites that didn't propagate last time, so each failure is
+    /// logged once rather than on every rebuild.
+    failing: HashSet<u32>,
+}
+
+fn make_pipeline(ao: &AppObjects, scene: &Scene, bindings: &ShapeBindings, topology: wgpu::PrimitiveTopology) -> PipelineId {
+    // Same vertex format and uniforms as SimpleShape.
+    return PipelineBuilder::new("satellitesPipeline", "simple_shape.wgsl")
+        .bind_groups(&[&scene.bind_group_layout, &bindings.bgl])
+        .vertex_buffer(Vertex::desc())
+        .topology(topology)
+        .build(ao);
+}
+
+impl Satellites {
+    pub fn new(ao: &AppObjects, scene: &Scene, satellites: Vec<Satellite>) -> Self {
+        let bindings = ShapeBindings::new(ao);
+        return Self {
+            satellites,
+            show_orbits: true,
+            show_ground_tracks: true,
+            samples_per_orbit: 180,
+            opacity: 1.,
+            point_pipeline: make_pipeline(ao, scene, &bindings, wgpu::PrimitiveTopology::PointList),
+            line_pipeline: make_pipeline(ao, scene, &bindings, wgpu::PrimitiveTopology::LineList),
+            bindings,
+            marker_buffer: None,
+            line_buffer: None,
+            num_markers: 0,
+            num_line_verts: 0,
+            built_for: None,
+            failing: HashSet::new(),
+        };
+    }
+
+    fn push_strip(out: &mut Vec<Vertex>, pts: &[Vector3<f64>], color: [f32; 3]) {
+        for w in pts.windows(2) {
+            out.push(Vertex::new(&w[0], color));
+            out.push(Vertex::new(&w[1], color));
+        }
+    }
+
+    fn build_vertices(&mut self, t: f64) -> (Vec<Vertex>, Vec<Vertex>) {
+        let mut markers = Vec::with_capacity(self.satellites.len());
+        let mut lines = vec![];
+        let n = self.samples_per_orbit.max(2);
+
+        let (s, c) = gmst(unix_to_jd(t)).sin_cos();
+        let teme_to_ecef_now = Matrix3::new(c, s, 0.0, -s, c, 0.0, 0.0, 0.0, 1.0);
+
+        for sat in &self.satellites {
+            match sat.position_ecef(t) {
+                Ok(p) => {
+                    markers.push(Vertex::new(&p, MARKER_COLOR));
+                    self.failing.remove(&sat.norad_id);
+                }
+                Err(e) => {
+                    if self.failing.insert(sat.norad_id) {
+                        log::warn!("{}: {e}", sat.name);
+                    }
+                    continue;
+                }
+            }
+
+            let period = sat.sgp4.period_minutes() * 60.;
+            let sample_times = (0..n).map(|i| t - period / 2. + period * i as f64 / (n - 1) as f64);
+
+            if self.show_orbits {
+                let pts: Vec<Vector3<f64>> = sample_times
+                    .clone()
+                    .filter_map(|ti| sat.sgp4.propagate_jd(unix_to_jd(ti)).ok())
+                    .map(|(r, _)| teme_to_ecef_now * r * 1000.)
+                    .collect();
+                Self::push_strip(&mut lines, &pts, ORBIT_COLOR);
+            }
+
+            if self.show_ground_tracks {
+                let pts: Vec<Vector3<f64>> = sample_times
+                    .filter_map(|ti| {
+                        let jd = unix_to_jd(ti);
+                        let (r, v) = sat.sgp4.propagate_jd(jd).ok()?;
+                        let mut g = Geodetic::from_ecef(&teme_to_ecef(jd, &r, &v).0);
+                        g.height = 0.;
+                        Some(g.to_ecef())
+                    })
+                    .collect();
+                Self::push_strip(&mut lines, &pts, GROUND_TRACK_COLOR);
+            }
+        }
+
+        return (markers, lines);
+    }
+}
+
+/// Write `bytes` to `buffer`, replacing it with a bigger one first if they don't fit.
+fn upload(ao: &AppObjects, buffer: &mut Option<wgpu::Buffer>, label: &str, bytes: &[u8]) {
+    if buffer.as_ref().is_none_or(|b| b.size() < bytes.len() as u64) {
+        *buffer = Some(ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (bytes.len() as u64).next_power_of_two().max(1024),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+    }
+    ao.queue.write_buffer(buffer.as_ref().unwrap(), 0, bytes);
+}
+
+impl Renderable for Satellites {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        self.bindings.write(ao, self.opacity);
+        let key = (scene.clock.current, self.satellites.len(), self.show_orbits, self.show_ground_tracks, self.samples_per_orbit);
+        if self.built_for == Some(key) {
+            return;
+        }
+        self.built_for = Some(key);
+
+        let (markers, lines) = self.build_vertices(scene.clock.current);
+        upload(ao, &mut self.marker_buffer, "satelliteMarkers", bytemuck::cast_slice(&markers));
+        upload(ao, &mut self.line_buffer, "satelliteLines", bytemuck::cast_slice(&lines));
+        self.num_markers = markers.len() as u32;
+        self.num_line_verts = lines.len() as u32;
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("satellitesPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+
+        render_pass.set_bind_group(0, &rs.scene.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bindings.bind_group, &[]);
+
+        if self.num_line_verts > 0
+            && let Some(lines) = &self.line_buffer
+        {
+            render_pass.set_pipeline(&rs.ao.pipelines.get(&self.line_pipeline));
+            render_pass.set_vertex_buffer(0, lines.slice(..));
+            render_pass.draw(0..self.num_line_verts, 0..1);
+        }
+
+        if self.num_markers > 0
+            && let Some(markers) = &self.marker_buffer
+        {
+            render_pass.set_pipeline(&rs.ao.pipelines.get(&self.point_pipeline));
+            render_pass.set_vertex_buffer(0, markers.slice(..));
+            render_pass.draw(0..self.num_markers, 0..1);
+        }
+    }
+
+    fn stats(&self) -> DrawStats {
+        let draws = (self.num_line_verts > 0) as u32 + (self.num_markers > 0) as u32;
+        return DrawStats { draw_calls: draws, ..Default::default() };
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+}
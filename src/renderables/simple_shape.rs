@@ -1,8 +1,7 @@
-use log::info;
-
 use wgpu::util::DeviceExt;
 
-use crate::core::{AppObjects, RenderState, Renderable, Scene};
+use crate::core::{AppObjects, CachedLayout, DrawStats, PipelineBuilder, PipelineId, RenderState, Renderable, Scene};
+use crate::core::shaders::uniforms::uniform_struct;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -57,73 +56,68 @@ const VERTICES: &[Vertex] = &[
 
 const INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4, /* padding */ 0];
 
+uniform_struct! {
+    pub(super) struct ShapeUniforms {
+        opacity: f32,
+        pad1: f32,
+        pad2: f32,
+        pad3: f32,
+    }
+}
+
+/// The per-layer uniforms of `simple_shape.wgsl`, which `Satellites` draws with too.
+pub(super) struct ShapeBindings {
+    pub bgl: CachedLayout,
+    pub bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+}
+
+impl ShapeBindings {
+    pub fn new(ao: &AppObjects) -> Self {
+        let buffer = ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("shapeUniforms"),
+            contents: bytemuck::bytes_of(&ShapeUniforms { opacity: 1., ..Default::default() }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bgl = ao.pipelines.get_or_create_bgl(&ao.device, &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }]);
+        let bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shapeBg"),
+            layout: &bgl,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+        });
+        return ShapeBindings { bgl, bind_group, buffer };
+    }
+
+    pub fn write(&self, ao: &AppObjects, opacity: f32) {
+        ao.queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&ShapeUniforms { opacity, ..Default::default() }));
+    }
+}
+
 pub struct SimpleShape {
-    render_pipeline: wgpu::RenderPipeline,
+    render_pipeline: PipelineId,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     num_indices: u32,
+    bindings: ShapeBindings,
+    opacity: f32,
 }
 impl SimpleShape {
     pub fn new(ao: &AppObjects, scene: &Scene) -> Self {
-        let shader = ao.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("simple_shape.wgsl").into()),
-        });
-
-        let render_pipeline_layout =
-            ao.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&scene.bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = ao.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[Vertex::desc()],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: ao.config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
-                // or Features::POLYGON_MODE_POINT
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            // If the pipeline will be used with a multiview render pass, this
-            // indicates how many array layers the attachments will have.
-            multiview: None,
-            // Useful for optimizing shader compilation on Android
-            cache: None,
-        });
+        let bindings = ShapeBindings::new(ao);
+        let render_pipeline = PipelineBuilder::new("simpleShapePipeline", "simple_shape.wgsl")
+            .bind_groups(&[&scene.bind_group_layout, &bindings.bgl])
+            .vertex_buffer(Vertex::desc())
+            .cull_back_faces()
+            .build(ao);
 
         let vertex_buffer = ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
@@ -138,41 +132,47 @@ impl SimpleShape {
         let num_indices = INDICES.len() as u32;
 
         return Self {
-            render_pipeline, vertex_buffer, index_buffer, num_indices
+            render_pipeline, vertex_buffer, index_buffer, num_indices, bindings, opacity: 1.
         }
     }
 }
 
 impl Renderable for SimpleShape {
-    fn render(self: &Self, rs: &mut RenderState) {
-        info!("render");
+    fn update(&mut self, ao: &AppObjects, _scene: &Scene) {
+        self.bindings.write(ao, self.opacity);
+    }
 
+    fn render(self: &Self, rs: &mut RenderState) {
         let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &rs.surface_tex_view.as_ref().unwrap(),
+                view: &rs.target_view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }),
+                    load: wgpu::LoadOp::Load,
                     store: wgpu::StoreOp::Store,
                 },
                 depth_slice: None,
             })],
             depth_stencil_attachment: None,
             occlusion_query_set: None,
-            timestamp_writes: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
         });
 
-        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_pipeline(&rs.ao.pipelines.get(&self.render_pipeline));
         render_pass.set_bind_group(0, &rs.scene.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bindings.bind_group, &[]);
         render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
         render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
     }
+
+    fn stats(&self) -> DrawStats {
+        return DrawStats { draw_calls: 1, triangles: self.num_indices as u64 / 3, tiles: None };
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
 }
 
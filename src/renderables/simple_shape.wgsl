@@ -1,3 +1,5 @@
+#include "scene.wgsl"
+
 // Vertex shader
 
 struct VertexInput {
@@ -10,17 +12,15 @@ struct VertexOutput {
     @location(0) color: vec3<f32>,
 };
 
-struct LoweredScene {
-    mv: mat4x4<f32>,
-    proj: mat4x4<f32>,
-    time: f32,
+struct ShapeUniforms {
+    opacity: f32,
     pad1: f32,
     pad2: f32,
     pad3: f32,
 };
 
-@group(0) @binding(0)
-var<uniform> scene: LoweredScene;
+@group(1) @binding(0)
+var<uniform> shape: ShapeUniforms;
 
 @vertex
 fn vs_main(
@@ -36,5 +36,5 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+    return vec4<f32>(in.color, shape.opacity);
 }
@@ -0,0 +1,379 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::Context;
+use nalgebra::{Matrix4, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::model::{light_at, DepthTarget, ModelMeshes, ModelPipeline};
+use super::tracks::{draw_layout, make_relative_pipeline, DrawUniforms, Vertex};
+use crate::core::{AppObjects, CachedLayout, DrawStats, Geodetic, PipelineId, RenderState, Renderable, Scene};
+use crate::models::ModelData;
+use crate::tiles::{
+    open_source, parse_content, parse_subtree, resolve_uri, ContentState, Loader, Subtree, TileContent, TileId,
+    TileSource, Tileset, ViewState,
+};
+
+/// Most fetches running at once.
+const MAX_IN_FLIGHT: usize = 8;
+/// Subtree fetches tried before giving up on one.
+const SUBTREE_ATTEMPTS: u32 = 4;
+
+/// Frames to wait before trying a subtree again after `failures` failed fetches, or None to
+/// give up: about one second, doubling each time at 60 fps.
+fn subtree_retry_delay(failures: u32) -> Option<u64> {
+    return (failures < SUBTREE_ATTEMPTS).then(|| 60 << (failures - 1));
+}
+
+enum LoadKey {
+    Content(TileId),
+    Subtree(TileId),
+}
+
+enum Loaded {
+    Content(TileContent),
+    Subtree(Subtree),
+}
+
+enum GpuContent {
+    Model { data: ModelData, meshes: Box<ModelMeshes>, instances: Vec<Matrix4<f64>> },
+    /// Vertices are relative to `center`, in ECEF, and kept so the buffer can be rewritten when
+    /// the opacity changes.
+    Points {
+        buffer: wgpu::Buffer,
+        vertices: Vec<Vertex>,
+        opacity: f32,
+        center: Vector3<f64>,
+        uniform_buffer: wgpu::Buffer,
+        bind_group: wgpu::BindGroup,
+    },
+}
+
+struct Resident {
+    content: GpuContent,
+    last_used: u64,
+}
+
+/// An OGC 3D Tiles tileset, streamed from a directory or a local HTTP server as the camera moves.
+///
+/// Tiles are refined until their geometric error is at most `max_screen_space_error` pixels on
+/// screen. Content is fetched and decoded on a few worker threads and uploaded when it arrives;
+/// the least recently drawn tiles are dropped once more than `max_resident_tiles` are loaded.
+pub struct Tiles3d {
+    pub tileset: Tileset,
+    pub max_screen_space_error: f64,
+    pub max_resident_tiles: usize,
+    opacity: f32,
+
+    source: Arc<dyn TileSource>,
+    loader: Loader<LoadKey, anyhow::Result<Loaded>>,
+    pending_subtrees: HashSet<TileId>,
+    /// Subtrees that failed to load: how often, and the frame to try again from (never once
+    /// they've failed `SUBTREE_ATTEMPTS` times).
+    failed_subtrees: HashMap<TileId, (u32, u64)>,
+    resident: HashMap<TileId, Resident>,
+    selected: Vec<TileId>,
+    frame: u64,
+
+    model_pipeline: ModelPipeline,
+    point_pipeline: PipelineId,
+    point_layout: CachedLayout,
+    depth: DepthTarget,
+}
+
+impl Tiles3d {
+    /// Open a tileset: a directory with a tileset.json, the path of a tileset JSON file, or an
+    /// http:// URL of one.
+    pub fn open(ao: &AppObjects, scene: &Scene, location: &str) -> anyhow::Result<Self> {
+        let (source, uri) = open_source(location)?;
+        let json = source.fetch(&uri)?;
+        let tileset = Tileset::parse(std::str::from_utf8(&json).context("tileset is not UTF-8")?, &uri)?;
+        log::info!("{location}: {} tiles in the top level tileset", tileset.tiles.len());
+        let point_layout = draw_layout(ao);
+
+        return Ok(Self {
+            tileset,
+            max_screen_space_error: 16.,
+            max_resident_tiles: 512,
+            opacity: 1.,
+            source,
+            loader: Loader::new(4),
+            pending_subtrees: HashSet::new(),
+            failed_subtrees: HashMap::new(),
+            resident: HashMap::new(),
+            selected: vec![],
+            frame: 0,
+            model_pipeline: ModelPipeline::new(ao, scene),
+            point_pipeline: make_relative_pipeline(ao, scene, &point_layout, wgpu::PrimitiveTopology::PointList),
+            point_layout,
+            depth: Default::default(),
+        });
+    }
+
+    fn request_content(&mut self, id: TileId) {
+        let Some(uri) = self.tileset.tiles[id].content_uri.clone() else {
+            return;
+        };
+        self.tileset.tiles[id].content = ContentState::Loading;
+        let source = self.source.clone();
+        self.loader.request(LoadKey::Content(id), move || {
+            let bytes = source.fetch(&uri)?;
+            let content = parse_content(&bytes, |u| source.fetch(&resolve_uri(&uri, u)))
+                .with_context(|| format!("decoding {uri}"))?;
+            return Ok(Loaded::Content(content));
+        });
+    }
+
+    fn request_subtree(&mut self, id: TileId, uri: String) {
+        let Some(it) = self.tileset.tiles[id].implicit else {
+            return;
+        };
+        self.pending_subtrees.insert(id);
+        let source = self.source.clone();
+        self.loader.request(LoadKey::Subtree(id), move || {
+            let bytes = source.fetch(&uri)?;
+            let subtree = parse_subtree(&bytes, it.coord, |u| source.fetch(&resolve_uri(&uri, u)))
+                .with_context(|| format!("decoding {uri}"))?;
+            return Ok(Loaded::Subtree(subtree));
+        });
+    }
+
+    fn finish(&mut self, ao: &AppObjects, key: LoadKey, result: anyhow::Result<Loaded>) {
+        match (key, result) {
+            (LoadKey::Subtree(id), Ok(Loaded::Subtree(subtree))) => {
+                self.pending_subtrees.remove(&id);
+                self.failed_subtrees.remove(&id);
+                self.tileset.add_subtree(id, subtree);
+            }
+            (LoadKey::Subtree(id), Err(e)) => {
+                self.pending_subtrees.remove(&id);
+                let failures = self.failed_subtrees.get(&id).map_or(0, |f| f.0) + 1;
+                let retry_at = match subtree_retry_delay(failures) {
+                    Some(delay) => {
+                        log::warn!("tile {id}: {e:#}; trying again in {delay} frames");
+                        self.frame + delay
+                    }
+                    None => {
+                        log::warn!("tile {id}: {e:#}; giving up after {failures} tries");
+                        u64::MAX
+                    }
+                };
+                self.failed_subtrees.insert(id, (failures, retry_at));
+            }
+            (LoadKey::Content(id), Ok(Loaded::Content(content))) => {
+                self.tileset.tiles[id].content = ContentState::Ready;
+                let gpu = match content {
+                    TileContent::Model { model, instances } => {
+                        let meshes = Box::new(ModelMeshes::new(ao, &self.model_pipeline, &model, instances.len()));
+                        GpuContent::Model { data: model, meshes, instances }
+                    }
+                    TileContent::Points { positions, colors } => {
+                        // ECEF positions lose centimetres in f32, so they are kept relative to the tile.
+                        let tile = &self.tileset.tiles[id];
+                        let center = tile.volume.bounding_sphere().0;
+                        let vertices: Vec<Vertex> = positions
+                            .iter()
+                            .zip(&colors)
+                            .map(|(p, c)| Vertex::new(&(tile.transform.transform_point(&(*p).into()).coords - center), *c))
+                            .collect();
+                        let buffer = ao.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("tilePoints"),
+                            contents: bytemuck::cast_slice(&vertices),
+                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        });
+                        let uniform_buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+                            label: Some("tilePointUniforms"),
+                            size: std::mem::size_of::<DrawUniforms>() as u64,
+                            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        });
+                        let bind_group = ao.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                            label: Some("tilePointBg"),
+                            layout: &self.point_layout,
+                            entries: &[wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() }],
+                        });
+                        GpuContent::Points { buffer, vertices, opacity: 1., center, uniform_buffer, bind_group }
+                    }
+                    TileContent::Tileset(json) => {
+                        let uri = self.tileset.tiles[id].content_uri.clone().unwrap_or_default();
+                        if let Err(e) = self.tileset.graft(id, &json, &uri) {
+                            log::warn!("{uri}: {e:#}");
+                        }
+                        // The tile itself has nothing to draw.
+                        self.tileset.tiles[id].content = ContentState::None;
+                        return;
+                    }
+                };
+                self.resident.insert(id, Resident { content: gpu, last_used: self.frame });
+            }
+            (LoadKey::Content(id), Err(e)) => {
+                log::warn!("tile {id}: {e:#}");
+                self.tileset.tiles[id].content = ContentState::Failed;
+            }
+            (_, Ok(_)) => unreachable!("load result doesn't match its key"),
+        }
+    }
+
+    /// Drop the least recently drawn tiles beyond `max_resident_tiles`, never ones drawn this frame.
+    fn evict(&mut self) {
+        if self.resident.len() <= self.max_resident_tiles {
+            return;
+        }
+        let mut by_age: Vec<(u64, TileId)> = self
+            .resident
+            .iter()
+            .filter(|(_, r)| r.last_used < self.frame)
+            .map(|(&id, r)| (r.last_used, id))
+            .collect();
+        by_age.sort();
+        let excess = self.resident.len() - self.max_resident_tiles;
+        for &(_, id) in by_age.iter().take(excess) {
+            self.resident.remove(&id);
+            self.tileset.tiles[id].content = ContentState::Unloaded;
+        }
+    }
+}
+
+impl Renderable for Tiles3d {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        self.depth.update(ao);
+        self.frame += 1;
+
+        for (key, result) in self.loader.poll() {
+            self.finish(ao, key, result);
+        }
+
+        let view = ViewState {
+            position: scene.cam.position(),
+            screen_height: ao.config.height as f64,
+            sse_denominator: scene.cam.intrin.sse_denominator(),
+        };
+        let selection = self.tileset.select(&view, self.max_screen_space_error);
+
+        for id in selection.expand {
+            match self.tileset.needs_subtree(id) {
+                Some(uri) => {
+                    let waiting = self.failed_subtrees.get(&id).is_some_and(|&(_, at)| self.frame < at);
+                    if !waiting && !self.pending_subtrees.contains(&id) && self.loader.in_flight() < MAX_IN_FLIGHT {
+                        self.request_subtree(id, uri);
+                    }
+                }
+                None => {
+                    self.tileset.expand(id);
+                }
+            }
+        }
+        for id in selection.load {
+            if self.loader.in_flight() >= MAX_IN_FLIGHT {
+                break;
+            }
+            self.request_content(id);
+        }
+
+        for &id in &selection.render {
+            let Some(r) = self.resident.get_mut(&id) else {
+                continue;
+            };
+            r.last_used = self.frame;
+            let tile = &self.tileset.tiles[id];
+            match &mut r.content {
+                GpuContent::Model { data, meshes, instances } => {
+                    let placements: Vec<Matrix4<f64>> = instances.iter().map(|i| tile.transform * i).collect();
+                    let light = light_at(&Geodetic::from_ecef(&tile.volume.bounding_sphere().0));
+                    meshes.update(ao, data, None, &placements, &light, self.opacity);
+                }
+                GpuContent::Points { buffer, vertices, opacity, center, uniform_buffer, .. } => {
+                    let center_mv = scene.cam.pose.to_matrix() * Matrix4::new_translation(center);
+                    ao.queue.write_buffer(uniform_buffer, 0, bytemuck::bytes_of(&DrawUniforms::new(&center_mv)));
+                    if *opacity != self.opacity {
+                        *opacity = self.opacity;
+                        for v in vertices.iter_mut() {
+                            v.color[3] = self.opacity;
+                        }
+                        ao.queue.write_buffer(buffer, 0, bytemuck::cast_slice(vertices));
+                    }
+                }
+            }
+        }
+        self.selected = selection.render;
+        self.evict();
+    }
+
+    fn animating(&self) -> bool {
+        return self.loader.in_flight() > 0;
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let Some(depth) = self.depth.attachment() else {
+            return;
+        };
+        let color_attachment = || wgpu::RenderPassColorAttachment {
+            view: &rs.target_view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Store,
+            },
+            depth_slice: None,
+        };
+
+        let resident: Vec<&GpuContent> =
+            self.selected.iter().filter_map(|id| self.resident.get(id)).map(|r| &r.content).collect();
+
+        {
+            let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("tilesModelPass"),
+                color_attachments: &[Some(color_attachment())],
+                depth_stencil_attachment: Some(depth),
+                occlusion_query_set: None,
+                timestamp_writes: rs.ao.profiler.timestamp_writes(),
+            });
+            self.model_pipeline.set(&mut render_pass, rs.ao, rs.scene);
+            for c in &resident {
+                if let GpuContent::Model { meshes, .. } = c {
+                    meshes.draw(&mut render_pass);
+                }
+            }
+        }
+
+        // Points go through the tracks pipeline, which has no depth test.
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("tilesPointPass"),
+            color_attachments: &[Some(color_attachment())],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+        render_pass.set_bind_group(0, &rs.scene.bind_group, &[]);
+        render_pass.set_pipeline(&rs.ao.pipelines.get(&self.point_pipeline));
+        for c in &resident {
+            if let GpuContent::Points { buffer, vertices, bind_group, .. } = c {
+                render_pass.set_bind_group(1, bind_group, &[]);
+                render_pass.set_vertex_buffer(0, buffer.slice(..));
+                render_pass.draw(0..vertices.len() as u32, 0..1);
+            }
+        }
+    }
+
+    fn stats(&self) -> DrawStats {
+        let mut stats = DrawStats { tiles: Some(0), ..Default::default() };
+        for content in self.selected.iter().filter_map(|id| self.resident.get(id)).map(|r| &r.content) {
+            stats += match content {
+                GpuContent::Model { meshes, .. } => meshes.stats(),
+                GpuContent::Points { .. } => DrawStats { draw_calls: 1, ..Default::default() },
+            };
+            stats.tiles = stats.tiles.map(|n| n + 1);
+        }
+        return stats;
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+}
+
+#[test]
+fn failed_subtrees_back_off_then_give_up() {
+    let delays: Vec<Option<u64>> = (1..=SUBTREE_ATTEMPTS).map(subtree_retry_delay).collect();
+    assert_eq!(delays, [Some(60), Some(120), Some(240), None]);
+}
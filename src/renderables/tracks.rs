@@ -0,0 +1,220 @@
+use nalgebra::Vector3;
+
+use crate::core::shaders::uniforms::uniform_struct;
+use crate::core::{AppObjects, Bounds, CachedLayout, DrawStats, PipelineBuilder, PipelineId, RenderState, Renderable, Scene};
+use crate::tracks::{Interpolation, Track};
+
+// Shared with the CZML renderable, which draws with the same shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub(super) struct Vertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+    /// Time relative to the clock start, and seconds to fade over (0 for no fade).
+    pub time: [f32; 2],
+}
+
+impl Vertex {
+    pub fn new(p: &Vector3<f64>, color: [f32; 4]) -> Self {
+        return Vertex { position: [p.x as f32, p.y as f32, p.z as f32], color, time: [0., 0.] };
+    }
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRS: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4, 2 => Float32x2];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRS,
+        }
+    }
+}
+
+uniform_struct! {
+    /// For vertices relative to a centre: the centre's model view matrix.
+    pub(super) struct DrawUniforms {
+        center_mv: [f32; 16],
+    }
+}
+
+impl DrawUniforms {
+    pub fn new(center_mv: &nalgebra::Matrix4<f64>) -> Self {
+        return DrawUniforms { center_mv: center_mv.cast::<f32>().as_slice().try_into().unwrap() };
+    }
+}
+
+const PALETTE: [[f32; 3]; 6] = [
+    [1.0, 0.4, 0.2],
+    [0.2, 0.8, 1.0],
+    [0.6, 1.0, 0.3],
+    [1.0, 0.3, 0.8],
+    [1.0, 0.9, 0.2],
+    [0.7, 0.5, 1.0],
+];
+
+/// Plays back recorded tracks at the scene clock time: a marker at the interpolated position and a
+/// trail behind it that fades out over `trail_seconds`. The fade is done in the shader against
+/// `LoweredScene::time`, so trail vertices carry their time relative to the clock start.
+pub struct Tracks {
+    pub tracks: Vec<Track>,
+    pub interpolation: Interpolation,
+    pub trail_seconds: f64,
+    opacity: f32,
+
+    point_pipeline: PipelineId,
+    line_pipeline: PipelineId,
+    marker_buffer: wgpu::Buffer,
+    line_buffer: Option<wgpu::Buffer>,
+    num_markers: u32,
+    num_line_verts: u32,
+}
+
+pub(super) fn make_pipeline(ao: &AppObjects, scene: &Scene, topology: wgpu::PrimitiveTopology) -> PipelineId {
+    return PipelineBuilder::new("tracksPipeline", "tracks.wgsl")
+        .bind_groups(&[&scene.bind_group_layout])
+        .vertex_buffer(Vertex::desc())
+        .topology(topology)
+        .build(ao);
+}
+
+/// The shader's layout for `DrawUniforms`.
+pub(super) fn draw_layout(ao: &AppObjects) -> CachedLayout {
+    return ao.pipelines.get_or_create_bgl(&ao.device, &[wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+        count: None,
+    }]);
+}
+
+/// Like `make_pipeline`, for vertices relative to a centre given per draw in group 1.
+pub(super) fn make_relative_pipeline(ao: &AppObjects, scene: &Scene, draw_layout: &CachedLayout, topology: wgpu::PrimitiveTopology) -> PipelineId {
+    return PipelineBuilder::new("tracksRelativePipeline", "tracks.wgsl")
+        .define("RELATIVE", "")
+        .bind_groups(&[&scene.bind_group_layout, draw_layout])
+        .vertex_buffer(Vertex::desc())
+        .topology(topology)
+        .build(ao);
+}
+
+impl Tracks {
+    pub fn new(ao: &AppObjects, scene: &Scene, tracks: Vec<Track>) -> Self {
+        let marker_buffer = ao.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("trackMarkers"),
+            size: (tracks.len().max(1) * std::mem::size_of::<Vertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        return Self {
+            tracks,
+            interpolation: Interpolation::Hermite,
+            trail_seconds: 60.,
+            opacity: 1.,
+            point_pipeline: make_pipeline(ao, scene, wgpu::PrimitiveTopology::PointList),
+            line_pipeline: make_pipeline(ao, scene, wgpu::PrimitiveTopology::LineList),
+            marker_buffer,
+            line_buffer: None,
+            num_markers: 0,
+            num_line_verts: 0,
+        };
+    }
+
+    fn build_vertices(&self, t: f64, t_ref: f64) -> (Vec<Vertex>, Vec<Vertex>) {
+        let vert = |p: &Vector3<f64>, c: [f32; 3], ti: f64, fade: f64| Vertex {
+            position: [p.x as f32, p.y as f32, p.z as f32],
+            color: [c[0], c[1], c[2], self.opacity],
+            time: [(ti - t_ref) as f32, fade as f32],
+        };
+
+        let mut markers = vec![];
+        let mut lines = vec![];
+        for (i, track) in self.tracks.iter().enumerate() {
+            let color = PALETTE[i % PALETTE.len()];
+            let Some((p, _)) = track.position_at(t, self.interpolation) else {
+                continue;
+            };
+            markers.push(vert(&p, color, t, 0.));
+
+            let trail = track.trail(t, self.trail_seconds, self.interpolation);
+            for w in trail.windows(2) {
+                lines.push(vert(&w[0].1, color, w[0].0, self.trail_seconds));
+                lines.push(vert(&w[1].1, color, w[1].0, self.trail_seconds));
+            }
+        }
+        return (markers, lines);
+    }
+}
+
+impl Renderable for Tracks {
+    fn update(&mut self, ao: &AppObjects, scene: &Scene) {
+        let (markers, lines) = self.build_vertices(scene.clock.current, scene.clock.start);
+        ao.queue.write_buffer(&self.marker_buffer, 0, bytemuck::cast_slice(&markers));
+        self.num_markers = markers.len() as u32;
+
+        let bytes: &[u8] = bytemuck::cast_slice(&lines);
+        if self.line_buffer.as_ref().is_none_or(|b| b.size() < bytes.len() as u64) {
+            self.line_buffer = Some(ao.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("trackTrails"),
+                size: (bytes.len() as u64).next_power_of_two().max(1024),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        ao.queue.write_buffer(self.line_buffer.as_ref().unwrap(), 0, bytes);
+        self.num_line_verts = lines.len() as u32;
+    }
+
+    fn render(self: &Self, rs: &mut RenderState) {
+        let mut render_pass = rs.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("tracksPass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &rs.target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: rs.ao.profiler.timestamp_writes(),
+        });
+
+        render_pass.set_bind_group(0, &rs.scene.bind_group, &[]);
+
+        if let (Some(lb), true) = (&self.line_buffer, self.num_line_verts > 0) {
+            render_pass.set_pipeline(&rs.ao.pipelines.get(&self.line_pipeline));
+            render_pass.set_vertex_buffer(0, lb.slice(..));
+            render_pass.draw(0..self.num_line_verts, 0..1);
+        }
+
+        if self.num_markers > 0 {
+            render_pass.set_pipeline(&rs.ao.pipelines.get(&self.point_pipeline));
+            render_pass.set_vertex_buffer(0, self.marker_buffer.slice(..));
+            render_pass.draw(0..self.num_markers, 0..1);
+        }
+    }
+
+    fn stats(&self) -> DrawStats {
+        let draws = (self.num_line_verts > 0) as u32 + (self.num_markers > 0) as u32;
+        return DrawStats { draw_calls: draws, ..Default::default() };
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        return Bounds::from_points(self.tracks.iter().flat_map(|t| &t.samples).map(|s| &s.position));
+    }
+
+    fn time_range(&self) -> Option<(f64, f64)> {
+        return self
+            .tracks
+            .iter()
+            .filter_map(Track::time_range)
+            .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)));
+    }
+}
@@ -0,0 +1,50 @@
+#include "scene.wgsl"
+
+// With RELATIVE, positions are relative to a centre and `draw.center_mv` takes them to view space.
+struct DrawUniforms {
+    center_mv: mat4x4<f32>,
+};
+
+#ifdef RELATIVE
+@group(1) @binding(0)
+var<uniform> draw: DrawUniforms;
+#endif
+
+// Vertex shader
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+    // x: sample time relative to the clock start, y: seconds to fade over (0 for no fade)
+    @location(2) time: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    var fade = 1.0;
+    if (model.time.y > 0.0) {
+        fade = clamp(1.0 - (scene.time - model.time.x) / model.time.y, 0.0, 1.0);
+    }
+    out.color = vec4<f32>(model.color.rgb, model.color.a * fade);
+#ifdef RELATIVE
+    out.clip_position = scene.proj * draw.center_mv * vec4<f32>(model.position, 1.0);
+#else
+    out.clip_position = scene.proj * scene.mv * vec4<f32>(model.position, 1.0);
+#endif
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
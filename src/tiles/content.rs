@@ -0,0 +1,276 @@
+use anyhow::Context;
+use nalgebra::{Matrix3, Matrix4, Vector3};
+
+use crate::core::Geodetic;
+use crate::models::{parse_model, ModelData};
+
+/// Decoded tile content, in the tile's coordinate system (before the tile transform).
+pub enum TileContent {
+    /// A glTF model drawn once per instance transform. b3dm and glTF tiles have one instance.
+    Model { model: ModelData, instances: Vec<Matrix4<f64>> },
+    Points { positions: Vec<Vector3<f64>>, colors: Vec<[f32; 4]> },
+    /// An external tileset.json, to be grafted under the tile.
+    Tileset(String),
+}
+
+/// 3D Tiles are z-up, glTF is y-up.
+pub fn y_up_to_z_up() -> Matrix4<f64> {
+    return Matrix3::new(
+        1., 0., 0., //
+        0., 0., -1., //
+        0., 1., 0., //
+    )
+    .to_homogeneous();
+}
+
+/// Decode tile content by its magic bytes: b3dm, i3dm, pnts, GLB or JSON (glTF or an external
+/// tileset). `fetch` resolves URIs relative to the tile, for i3dm that reference their glTF.
+pub fn parse_content(bytes: &[u8], fetch: impl Fn(&str) -> anyhow::Result<Vec<u8>>) -> anyhow::Result<TileContent> {
+    match bytes.get(0..4) {
+        Some(b"b3dm") => return parse_b3dm(bytes),
+        Some(b"i3dm") => return parse_i3dm(bytes, fetch),
+        Some(b"pnts") => return parse_pnts(bytes),
+        Some(b"glTF") => {
+            return Ok(TileContent::Model { model: parse_model(bytes)?, instances: vec![y_up_to_z_up()] });
+        }
+        Some(b"cmpt") => anyhow::bail!("composite (cmpt) tiles are not supported"),
+        _ => {}
+    }
+    let v: serde_json::Value = serde_json::from_slice(bytes).context("unknown tile content")?;
+    if v.get("root").is_some() {
+        return Ok(TileContent::Tileset(String::from_utf8_lossy(bytes).into_owned()));
+    }
+    return Ok(TileContent::Model { model: parse_model(bytes)?, instances: vec![y_up_to_z_up()] });
+}
+
+/// The feature table of b3dm/i3dm/pnts: a JSON header with global values inline and
+/// per-feature values as `{"byteOffset": n}` into the binary part.
+struct FeatureTable<'a> {
+    json: serde_json::Value,
+    bin: &'a [u8],
+}
+
+impl<'a> FeatureTable<'a> {
+    fn new(json: &[u8], bin: &'a [u8]) -> anyhow::Result<Self> {
+        let json = if json.iter().all(|b| b.is_ascii_whitespace()) {
+            serde_json::Value::Object(Default::default())
+        } else {
+            serde_json::from_slice(json).context("feature table json")?
+        };
+        return Ok(FeatureTable { json, bin });
+    }
+
+    fn has(&self, name: &str) -> bool {
+        return self.json.get(name).is_some();
+    }
+
+    fn global_u32(&self, name: &str) -> Option<u32> {
+        return self.json.get(name)?.as_u64().map(|v| v as u32);
+    }
+
+    /// A global value given inline or in the binary part as `n` numbers of type `T`.
+    fn global_f64s<T: bytemuck::Pod + Into<f64>>(&self, name: &str, n: usize) -> Option<Vec<f64>> {
+        let v = self.json.get(name)?;
+        if let Some(arr) = v.as_array() {
+            return arr.iter().map(|x| x.as_f64()).collect::<Option<Vec<f64>>>().filter(|a| a.len() == n);
+        }
+        return Some(self.binary::<T>(name, n).ok()?.into_iter().map(Into::into).collect());
+    }
+
+    fn vec3(&self, name: &str) -> Option<Vector3<f64>> {
+        return self.global_f64s::<f32>(name, 3).map(|v| Vector3::new(v[0], v[1], v[2]));
+    }
+
+    /// `count` values of type `T` from the binary part.
+    fn binary<T: bytemuck::Pod>(&self, name: &str, count: usize) -> anyhow::Result<Vec<T>> {
+        let offset = self
+            .json
+            .get(name)
+            .and_then(|v| v.get("byteOffset"))
+            .and_then(|v| v.as_u64())
+            .with_context(|| format!("feature table {name} has no byteOffset"))? as usize;
+        let size = std::mem::size_of::<T>();
+        let bytes = self
+            .bin
+            .get(offset..offset + count * size)
+            .with_context(|| format!("feature table {name} out of range"))?;
+        return Ok(bytes.chunks_exact(size).map(bytemuck::pod_read_unaligned).collect());
+    }
+
+    fn positions(&self, count: usize) -> anyhow::Result<Vec<Vector3<f64>>> {
+        if self.has("POSITION") {
+            let p: Vec<[f32; 3]> = self.binary("POSITION", count)?;
+            return Ok(p.iter().map(|p| Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64)).collect());
+        }
+        if self.has("POSITION_QUANTIZED") {
+            let q: Vec<[u16; 3]> = self.binary("POSITION_QUANTIZED", count)?;
+            let offset = self.vec3("QUANTIZED_VOLUME_OFFSET").context("POSITION_QUANTIZED without QUANTIZED_VOLUME_OFFSET")?;
+            let scale = self.vec3("QUANTIZED_VOLUME_SCALE").context("POSITION_QUANTIZED without QUANTIZED_VOLUME_SCALE")?;
+            return Ok(q
+                .iter()
+                .map(|q| offset + Vector3::new(q[0] as f64, q[1] as f64, q[2] as f64).component_mul(&scale) / 65535.)
+                .collect());
+        }
+        anyhow::bail!("feature table has no POSITION or POSITION_QUANTIZED");
+    }
+}
+
+/// Split a b3dm/i3dm/pnts file into feature table and payload after the header.
+fn split_tables(bytes: &[u8], header_len: usize) -> anyhow::Result<(FeatureTable<'_>, &[u8])> {
+    anyhow::ensure!(bytes.len() >= header_len, "tile header too short");
+    let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap()) as usize;
+    let byte_length = u32_at(8).min(bytes.len());
+    let (ft_json, ft_bin, bt_json, bt_bin) = (u32_at(12), u32_at(16), u32_at(20), u32_at(24));
+    let ft_start = header_len;
+    let body = ft_start + ft_json + ft_bin + bt_json + bt_bin;
+    anyhow::ensure!(body <= byte_length, "tile tables run past the end of the file");
+    let ft = FeatureTable::new(&bytes[ft_start..ft_start + ft_json], &bytes[ft_start + ft_json..ft_start + ft_json + ft_bin])?;
+    return Ok((ft, &bytes[body..byte_length]));
+}
+
+fn parse_b3dm(bytes: &[u8]) -> anyhow::Result<TileContent> {
+    let (ft, glb) = split_tables(bytes, 28)?;
+    let rtc = ft.vec3("RTC_CENTER").unwrap_or_default();
+    let model = parse_model(glb).context("b3dm glTF")?;
+    return Ok(TileContent::Model { model, instances: vec![Matrix4::new_translation(&rtc) * y_up_to_z_up()] });
+}
+
+fn parse_i3dm(bytes: &[u8], fetch: impl Fn(&str) -> anyhow::Result<Vec<u8>>) -> anyhow::Result<TileContent> {
+    let (ft, payload) = split_tables(bytes, 32)?;
+    let gltf_format = u32::from_le_bytes(bytes[28..32].try_into().unwrap());
+    let model = if gltf_format == 0 {
+        let uri = String::from_utf8_lossy(payload);
+        parse_model(&fetch(uri.trim_end_matches(['\0', ' ']))?)?
+    } else {
+        parse_model(payload)?
+    };
+
+    let n = ft.global_u32("INSTANCES_LENGTH").context("i3dm without INSTANCES_LENGTH")? as usize;
+    let rtc = ft.vec3("RTC_CENTER").unwrap_or_default();
+    let positions = ft.positions(n)?;
+    let ups: Option<Vec<[f32; 3]>> = ft.has("NORMAL_UP").then(|| ft.binary("NORMAL_UP", n)).transpose()?;
+    let rights: Option<Vec<[f32; 3]>> = ft.has("NORMAL_RIGHT").then(|| ft.binary("NORMAL_RIGHT", n)).transpose()?;
+    let scales: Option<Vec<f32>> = ft.has("SCALE").then(|| ft.binary("SCALE", n)).transpose()?;
+    let scales3: Option<Vec<[f32; 3]>> = ft.has("SCALE_NON_UNIFORM").then(|| ft.binary("SCALE_NON_UNIFORM", n)).transpose()?;
+    let east_north_up = ft.json.get("EAST_NORTH_UP").and_then(|v| v.as_bool()).unwrap_or(false);
+
+    let v3 = |a: [f32; 3]| Vector3::new(a[0] as f64, a[1] as f64, a[2] as f64);
+    let mut instances = vec![];
+    for i in 0..n {
+        let p = rtc + positions[i];
+        let rot = match (&ups, &rights) {
+            (Some(u), Some(r)) => {
+                let (up, right) = (v3(u[i]), v3(r[i]));
+                Matrix3::from_columns(&[right, up, right.cross(&up)])
+            }
+            _ if east_north_up => Geodetic::from_ecef(&p).enu_to_ecef(),
+            _ => Matrix3::identity(),
+        };
+        let mut scale = Vector3::repeat(1.);
+        if let Some(s) = &scales {
+            scale *= s[i] as f64;
+        }
+        if let Some(s) = &scales3 {
+            scale = scale.component_mul(&v3(s[i]));
+        }
+        instances.push(
+            Matrix4::new_translation(&p) * rot.to_homogeneous() * Matrix4::new_nonuniform_scaling(&scale) * y_up_to_z_up(),
+        );
+    }
+    return Ok(TileContent::Model { model, instances });
+}
+
+fn parse_pnts(bytes: &[u8]) -> anyhow::Result<TileContent> {
+    let (ft, _) = split_tables(bytes, 28)?;
+    let n = ft.global_u32("POINTS_LENGTH").context("pnts without POINTS_LENGTH")? as usize;
+    let rtc = ft.vec3("RTC_CENTER").unwrap_or_default();
+    let positions = ft.positions(n)?.into_iter().map(|p| p + rtc).collect();
+
+    let unorm = |c: u8| c as f32 / 255.;
+    let colors = if ft.has("RGBA") {
+        ft.binary::<[u8; 4]>("RGBA", n)?.iter().map(|c| c.map(unorm)).collect()
+    } else if ft.has("RGB") {
+        ft.binary::<[u8; 3]>("RGB", n)?.iter().map(|c| [unorm(c[0]), unorm(c[1]), unorm(c[2]), 1.]).collect()
+    } else if ft.has("RGB565") {
+        ft.binary::<u16>("RGB565", n)?
+            .iter()
+            .map(|&c| [(c >> 11) as f32 / 31., ((c >> 5) & 63) as f32 / 63., (c & 31) as f32 / 31., 1.])
+            .collect()
+    } else {
+        let c = ft.global_f64s::<u8>("CONSTANT_RGBA", 4).map(|v| [v[0], v[1], v[2], v[3]].map(|x| x as f32 / 255.));
+        vec![c.unwrap_or([1., 1., 1., 1.]); n]
+    };
+    return Ok(TileContent::Points { positions, colors });
+}
+
+/// Wrap feature table JSON/binary and a payload up with a b3dm/i3dm/pnts header.
+#[cfg(test)]
+pub(crate) fn make_tile(magic: &[u8; 4], ft_json: &str, ft_bin: &[u8], extra_header: Option<u32>, payload: &[u8]) -> Vec<u8> {
+    let mut json = ft_json.as_bytes().to_vec();
+    let header_len = if extra_header.is_some() { 32 } else { 28 };
+    while !(header_len + json.len()).is_multiple_of(8) {
+        json.push(b' ');
+    }
+    let total = header_len + json.len() + ft_bin.len() + payload.len();
+    let mut out = magic.to_vec();
+    for v in [1, total, json.len(), ft_bin.len(), 0, 0] {
+        out.extend_from_slice(&(v as u32).to_le_bytes());
+    }
+    if let Some(v) = extra_header {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    out.extend_from_slice(&json);
+    out.extend_from_slice(ft_bin);
+    out.extend_from_slice(payload);
+    return out;
+}
+
+#[test]
+fn parses_pnts_with_quantized_positions_and_rgb() {
+    let mut bin = vec![];
+    for v in [0u16, 0, 0, 65535, 65535, 0] {
+        bin.extend_from_slice(&v.to_le_bytes());
+    }
+    let rgb_offset = bin.len();
+    bin.extend_from_slice(&[255, 0, 0, 0, 255, 0]);
+    let json = format!(
+        r#"{{"POINTS_LENGTH": 2, "RTC_CENTER": [1000, 0, 0], "QUANTIZED_VOLUME_OFFSET": [-1, -1, -1],
+            "QUANTIZED_VOLUME_SCALE": [2, 2, 2], "POSITION_QUANTIZED": {{"byteOffset": 0}}, "RGB": {{"byteOffset": {rgb_offset}}}}}"#
+    );
+    let bytes = make_tile(b"pnts", &json, &bin, None, &[]);
+    let Ok(TileContent::Points { positions, colors }) = parse_content(&bytes, |_| anyhow::bail!("")) else {
+        panic!("not points");
+    };
+    assert_eq!(positions, [Vector3::new(999., -1., -1.), Vector3::new(1001., 1., -1.)]);
+    assert_eq!(colors, [[1., 0., 0., 1.], [0., 1., 0., 1.]]);
+}
+
+#[test]
+fn parses_b3dm_and_i3dm() {
+    let glb = crate::models::triangle_fixture();
+    let bytes = make_tile(b"b3dm", r#"{"BATCH_LENGTH": 0, "RTC_CENTER": [5, 6, 7]}"#, &[], None, &glb);
+    let Ok(TileContent::Model { model, instances }) = parse_content(&bytes, |_| anyhow::bail!("")) else {
+        panic!("not a model");
+    };
+    assert_eq!(model.meshes.len(), 1);
+    // glTF +Y becomes +Z, then the RTC offset applies.
+    let p = instances[0].transform_point(&[0., 1., 0.].into());
+    assert!((p.coords - Vector3::new(5., 6., 8.)).norm() < 1e-12);
+
+    let mut bin = vec![];
+    for v in [10f32, 0., 0., 20., 0., 0.] {
+        bin.extend_from_slice(&v.to_le_bytes());
+    }
+    let bytes = make_tile(b"i3dm", r#"{"INSTANCES_LENGTH": 2, "POSITION": {"byteOffset": 0}}"#, &bin, Some(1), &glb);
+    let Ok(TileContent::Model { instances, .. }) = parse_content(&bytes, |_| anyhow::bail!("")) else {
+        panic!("not a model");
+    };
+    assert_eq!(instances.len(), 2);
+    assert!((instances[1].transform_point(&[0., 0., 0.].into()).coords - Vector3::new(20., 0., 0.)).norm() < 1e-12);
+}
+
+#[test]
+fn external_tileset_content() {
+    let json = br#"{"asset": {"version": "1.1"}, "geometricError": 1, "root": {"boundingVolume": {"sphere": [0,0,0,1]}, "geometricError": 0}}"#;
+    assert!(matches!(parse_content(json, |_| anyhow::bail!("")), Ok(TileContent::Tileset(_))));
+}
@@ -0,0 +1,229 @@
+use anyhow::Context;
+
+use super::tileset::SubdivisionScheme;
+
+/// Address of a tile in an implicit tileset. `z` is always 0 for quadtrees.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TileCoord {
+    pub level: u32,
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+impl TileCoord {
+    pub fn children(&self, scheme: SubdivisionScheme) -> Vec<TileCoord> {
+        let zs: &[u32] = if scheme == SubdivisionScheme::Octree { &[0, 1] } else { &[0] };
+        let mut out = vec![];
+        for &dz in zs {
+            for dy in 0..2 {
+                for dx in 0..2 {
+                    out.push(TileCoord { level: self.level + 1, x: 2 * self.x + dx, y: 2 * self.y + dy, z: 2 * self.z + dz });
+                }
+            }
+        }
+        return out;
+    }
+
+    /// This tile's coordinates relative to an ancestor, as (level, x, y, z).
+    fn relative_to(&self, ancestor: &TileCoord) -> (u32, u32, u32, u32) {
+        let l = self.level - ancestor.level;
+        return (l, self.x - (ancestor.x << l), self.y - (ancestor.y << l), self.z - (ancestor.z << l));
+    }
+
+    /// Fill in `{level}`, `{x}`, `{y}` and `{z}` in a URI template.
+    pub fn expand_template(&self, template: &str) -> String {
+        return template
+            .replace("{level}", &self.level.to_string())
+            .replace("{x}", &self.x.to_string())
+            .replace("{y}", &self.y.to_string())
+            .replace("{z}", &self.z.to_string());
+    }
+}
+
+fn spread_bits(v: u32, stride: u32) -> u64 {
+    let mut out = 0u64;
+    for i in 0..(64 / stride).min(32) {
+        out |= (((v >> i) & 1) as u64) << (i * stride);
+    }
+    return out;
+}
+
+/// Morton (Z-order) index, x in the lowest bit.
+pub fn morton_index(scheme: SubdivisionScheme, x: u32, y: u32, z: u32) -> u64 {
+    match scheme {
+        SubdivisionScheme::Quadtree => return spread_bits(x, 2) | (spread_bits(y, 2) << 1),
+        SubdivisionScheme::Octree => return spread_bits(x, 3) | (spread_bits(y, 3) << 1) | (spread_bits(z, 3) << 2),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Availability {
+    Constant(bool),
+    /// One bit per entry, least significant bit first.
+    Bits(Vec<u8>),
+}
+
+impl Availability {
+    pub fn get(&self, i: u64) -> bool {
+        match self {
+            Availability::Constant(c) => return *c,
+            Availability::Bits(b) => return b.get((i / 8) as usize).is_some_and(|byte| byte >> (i % 8) & 1 == 1),
+        }
+    }
+}
+
+/// Availability data for one subtree of an implicit tileset.
+#[derive(Clone, Debug)]
+pub struct Subtree {
+    pub root: TileCoord,
+    pub tile: Availability,
+    /// Availability of the first content. Missing means no content.
+    pub content: Availability,
+    pub child_subtree: Availability,
+}
+
+impl Subtree {
+    /// Index of a tile in the tile and content availability bitstreams.
+    fn tile_index(&self, scheme: SubdivisionScheme, c: &TileCoord) -> u64 {
+        let (l, x, y, z) = c.relative_to(&self.root);
+        let n: u64 = if scheme == SubdivisionScheme::Octree { 8 } else { 4 };
+        return (n.pow(l) - 1) / (n - 1) + morton_index(scheme, x, y, z);
+    }
+
+    pub fn tile_available(&self, scheme: SubdivisionScheme, c: &TileCoord) -> bool {
+        return self.tile.get(self.tile_index(scheme, c));
+    }
+
+    pub fn content_available(&self, scheme: SubdivisionScheme, c: &TileCoord) -> bool {
+        return self.content.get(self.tile_index(scheme, c));
+    }
+
+    /// Whether the subtree rooted at `c`, which is one level below this subtree's bottom, exists.
+    pub fn child_subtree_available(&self, scheme: SubdivisionScheme, c: &TileCoord) -> bool {
+        let (_, x, y, z) = c.relative_to(&self.root);
+        return self.child_subtree.get(morton_index(scheme, x, y, z));
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SubtreeJson {
+    #[serde(default)]
+    buffers: Vec<BufferJson>,
+    #[serde(default)]
+    buffer_views: Vec<BufferViewJson>,
+    tile_availability: AvailabilityJson,
+    #[serde(default)]
+    content_availability: Vec<AvailabilityJson>,
+    child_subtree_availability: AvailabilityJson,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferJson {
+    uri: Option<String>,
+    byte_length: usize,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferViewJson {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+}
+
+#[derive(serde::Deserialize)]
+struct AvailabilityJson {
+    constant: Option<u8>,
+    bitstream: Option<usize>,
+}
+
+/// Parse a `.subtree` file (binary with a JSON header) or a JSON subtree. External buffers are
+/// read with `fetch`, which gets the buffer URI as written in the file.
+pub fn parse_subtree(bytes: &[u8], root: TileCoord, fetch: impl Fn(&str) -> anyhow::Result<Vec<u8>>) -> anyhow::Result<Subtree> {
+    let (json, bin): (&[u8], &[u8]) = if bytes.starts_with(b"subt") {
+        anyhow::ensure!(bytes.len() >= 24, "subtree header too short");
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap()) as usize;
+        let (json_len, bin_len) = (u64_at(8), u64_at(16));
+        anyhow::ensure!(bytes.len() >= 24 + json_len + bin_len, "subtree truncated");
+        (&bytes[24..24 + json_len], &bytes[24 + json_len..24 + json_len + bin_len])
+    } else {
+        (bytes, &[])
+    };
+    let sj: SubtreeJson = serde_json::from_slice(json).context("subtree json")?;
+
+    let mut buffers = vec![];
+    for b in &sj.buffers {
+        let data = match &b.uri {
+            Some(uri) => fetch(uri)?,
+            None => bin.to_vec(),
+        };
+        anyhow::ensure!(data.len() >= b.byte_length, "subtree buffer shorter than its byteLength");
+        buffers.push(data);
+    }
+
+    let availability = |a: &AvailabilityJson| -> anyhow::Result<Availability> {
+        if let Some(c) = a.constant {
+            return Ok(Availability::Constant(c != 0));
+        }
+        let view = a.bitstream.and_then(|i| sj.buffer_views.get(i)).context("availability without constant or bitstream")?;
+        let buf = buffers.get(view.buffer).context("bad subtree buffer index")?;
+        let bits = buf.get(view.byte_offset..view.byte_offset + view.byte_length).context("subtree buffer view out of range")?;
+        return Ok(Availability::Bits(bits.to_vec()));
+    };
+
+    return Ok(Subtree {
+        root,
+        tile: availability(&sj.tile_availability)?,
+        content: match sj.content_availability.first() {
+            Some(a) => availability(a)?,
+            None => Availability::Constant(false),
+        },
+        child_subtree: availability(&sj.child_subtree_availability)?,
+    });
+}
+
+#[test]
+fn morton_and_template() {
+    assert_eq!(morton_index(SubdivisionScheme::Quadtree, 1, 0, 0), 1);
+    assert_eq!(morton_index(SubdivisionScheme::Quadtree, 0, 1, 0), 2);
+    assert_eq!(morton_index(SubdivisionScheme::Quadtree, 3, 1, 0), 7);
+    assert_eq!(morton_index(SubdivisionScheme::Octree, 1, 1, 1), 7);
+    assert_eq!(morton_index(SubdivisionScheme::Octree, 2, 0, 0), 8);
+    let c = TileCoord { level: 2, x: 3, y: 1, z: 0 };
+    assert_eq!(c.expand_template("content/{level}/{x}/{y}.glb"), "content/2/3/1.glb");
+    assert_eq!(c.children(SubdivisionScheme::Quadtree)[3], TileCoord { level: 3, x: 7, y: 3, z: 0 });
+}
+
+#[test]
+fn binary_subtree_availability() {
+    // Two levels of a quadtree: the root and children 0 and 3 are available, only child 3 has
+    // content, and every child subtree exists.
+    let json = br#"{
+        "buffers": [{"byteLength": 2}],
+        "bufferViews": [{"buffer": 0, "byteOffset": 0, "byteLength": 1}, {"buffer": 0, "byteOffset": 1, "byteLength": 1}],
+        "tileAvailability": {"bitstream": 0},
+        "contentAvailability": [{"bitstream": 1}],
+        "childSubtreeAvailability": {"constant": 1}
+    }"#;
+    let bin = [0b0001_0011u8, 0b0001_0000];
+    let mut bytes = b"subt".to_vec();
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(&(json.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&(bin.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(json);
+    bytes.extend_from_slice(&bin);
+
+    let root = TileCoord::default();
+    let st = parse_subtree(&bytes, root, |_| anyhow::bail!("no external buffers")).unwrap();
+    let q = SubdivisionScheme::Quadtree;
+    let kids = root.children(q);
+    assert!(st.tile_available(q, &root));
+    assert_eq!(kids.iter().map(|c| st.tile_available(q, c)).collect::<Vec<_>>(), [true, false, false, true]);
+    assert_eq!(kids.iter().map(|c| st.content_available(q, c)).collect::<Vec<_>>(), [false, false, false, true]);
+    assert!(!st.content_available(q, &root));
+    assert!(st.child_subtree_available(q, &TileCoord { level: 2, x: 3, y: 3, z: 0 }));
+}
@@ -0,0 +1,454 @@
+mod content;
+mod implicit;
+mod source;
+mod tileset;
+
+pub use content::{parse_content, y_up_to_z_up, TileContent};
+pub use implicit::{parse_subtree, Availability, Subtree, TileCoord};
+pub use source::{open_source, resolve_uri, DirSource, HttpSource, Loader, TileSource};
+pub use tileset::{BoundingVolume, Refine, SubdivisionScheme, TileJson, TilesetJson};
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use nalgebra::{Matrix4, Vector3};
+
+pub type TileId = usize;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ContentState {
+    /// The tile has no content of its own.
+    None,
+    Unloaded,
+    Loading,
+    Ready,
+    /// Loading failed; treated like `None` from then on.
+    Failed,
+}
+
+/// Where an implicit tile sits in its implicit tileset.
+#[derive(Copy, Clone, Debug)]
+pub struct ImplicitTile {
+    /// Index into `Tileset::schemes`.
+    pub scheme: usize,
+    pub coord: TileCoord,
+    /// Root of the subtree whose availability covers this tile.
+    pub subtree_root: TileCoord,
+}
+
+/// The `implicitTiling` of one tile, with its URI templates resolved.
+#[derive(Clone, Debug)]
+pub struct ImplicitScheme {
+    pub subdivision: SubdivisionScheme,
+    pub subtree_levels: u32,
+    pub available_levels: u32,
+    pub subtree_template: String,
+    pub content_template: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Tile {
+    pub parent: Option<TileId>,
+    pub children: Vec<TileId>,
+    /// In ECEF, with `transform` applied.
+    pub volume: BoundingVolume,
+    pub geometric_error: f64,
+    pub refine: Refine,
+    /// Tile space to ECEF, including all ancestors' transforms.
+    pub transform: Matrix4<f64>,
+    /// Resolved against the source root. 1.1 tiles with several contents only use the first.
+    pub content_uri: Option<String>,
+    pub content: ContentState,
+    pub implicit: Option<ImplicitTile>,
+    /// For implicit tiles, whether the children have been created yet.
+    pub expanded: bool,
+}
+
+/// A 3D Tiles tileset as a flat tree of tiles, root first. Implicit tilesets and external
+/// tilesets grow the tree as their subtrees and JSON files are loaded.
+#[derive(Clone, Debug, Default)]
+pub struct Tileset {
+    pub tiles: Vec<Tile>,
+    pub schemes: Vec<ImplicitScheme>,
+    subtrees: HashMap<(usize, TileCoord), Subtree>,
+}
+
+/// What the camera sees, as far as screen-space error is concerned.
+#[derive(Copy, Clone, Debug)]
+pub struct ViewState {
+    /// Camera position in ECEF.
+    pub position: Vector3<f64>,
+    /// Viewport height in pixels.
+    pub screen_height: f64,
+    /// 2 tan(fovy / 2).
+    pub sse_denominator: f64,
+}
+
+impl ViewState {
+    /// Geometric error of `tile` projected to pixels at the distance of its bounding volume.
+    pub fn screen_space_error(&self, tile: &Tile) -> f64 {
+        let distance = tile.volume.distance(&self.position).max(1e-3);
+        return tile.geometric_error * self.screen_height / (distance * self.sse_denominator);
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selection {
+    /// Tiles to draw this frame.
+    pub render: Vec<TileId>,
+    /// Tiles whose content should be loaded, most urgent (largest screen-space error) first.
+    pub load: Vec<TileId>,
+    /// Implicit tiles that want their subtree loaded or their children created.
+    pub expand: Vec<TileId>,
+}
+
+impl Tileset {
+    /// Parse a tileset.json found at `uri` (relative to the source root).
+    pub fn parse(json: &str, uri: &str) -> anyhow::Result<Self> {
+        let mut ts = Tileset::default();
+        ts.graft_json(None, json, uri)?;
+        return Ok(ts);
+    }
+
+    /// Hang an external tileset, the content of tile `parent`, under it.
+    pub fn graft(&mut self, parent: TileId, json: &str, uri: &str) -> anyhow::Result<TileId> {
+        return self.graft_json(Some(parent), json, uri);
+    }
+
+    fn graft_json(&mut self, parent: Option<TileId>, json: &str, uri: &str) -> anyhow::Result<TileId> {
+        let ts: TilesetJson = serde_json::from_str(json).with_context(|| format!("parsing {uri}"))?;
+        if !ts.asset.version.starts_with('1') && !ts.asset.version.starts_with("0.") {
+            log::warn!("{uri}: unexpected 3D Tiles version {}", ts.asset.version);
+        }
+        let (transform, refine) = match parent {
+            Some(p) => (self.tiles[p].transform, self.tiles[p].refine),
+            None => (Matrix4::identity(), Refine::Replace),
+        };
+        return self.add_tile(&ts.root, parent, &transform, refine, uri);
+    }
+
+    fn add_tile(
+        &mut self,
+        json: &TileJson,
+        parent: Option<TileId>,
+        parent_transform: &Matrix4<f64>,
+        parent_refine: Refine,
+        base_uri: &str,
+    ) -> anyhow::Result<TileId> {
+        let transform = match json.transform {
+            Some(t) => parent_transform * Matrix4::from_column_slice(&t),
+            None => *parent_transform,
+        };
+        if json.contents.len() > 1 {
+            log::warn!("{base_uri}: only the first of {} tile contents is used", json.contents.len());
+        }
+        let content_uri = json.content.as_ref().or(json.contents.first()).map(|c| resolve_uri(base_uri, &c.uri));
+
+        let mut tile = Tile {
+            parent,
+            children: vec![],
+            volume: BoundingVolume::from_json(&json.bounding_volume, &transform)?,
+            geometric_error: json.geometric_error,
+            refine: json.refine.unwrap_or(parent_refine),
+            transform,
+            content: if content_uri.is_some() { ContentState::Unloaded } else { ContentState::None },
+            content_uri,
+            implicit: None,
+            expanded: false,
+        };
+
+        if let Some(it) = &json.implicit_tiling {
+            self.schemes.push(ImplicitScheme {
+                subdivision: it.subdivision_scheme,
+                subtree_levels: it.subtree_levels.max(1),
+                available_levels: it.available_levels,
+                subtree_template: resolve_uri(base_uri, &it.subtrees.uri),
+                content_template: tile.content_uri.take(),
+            });
+            // Content availability comes with the subtree.
+            tile.content = ContentState::None;
+            let root = TileCoord::default();
+            tile.implicit = Some(ImplicitTile { scheme: self.schemes.len() - 1, coord: root, subtree_root: root });
+        }
+
+        let id = self.tiles.len();
+        self.tiles.push(tile);
+        if let Some(p) = parent {
+            self.tiles[p].children.push(id);
+        }
+        let refine = self.tiles[id].refine;
+        for c in &json.children {
+            self.add_tile(c, Some(id), &transform, refine, base_uri)?;
+        }
+        return Ok(id);
+    }
+
+    /// URI of the subtree file tile `id` needs before its content and children are known, if it
+    /// is the root of a subtree that hasn't been loaded.
+    pub fn needs_subtree(&self, id: TileId) -> Option<String> {
+        let it = self.tiles[id].implicit?;
+        if it.coord != it.subtree_root || self.subtrees.contains_key(&(it.scheme, it.coord)) {
+            return None;
+        }
+        return Some(it.coord.expand_template(&self.schemes[it.scheme].subtree_template));
+    }
+
+    /// Store the subtree rooted at implicit tile `id`, which tells whether it has content.
+    pub fn add_subtree(&mut self, id: TileId, subtree: Subtree) {
+        let Some(it) = self.tiles[id].implicit else {
+            return;
+        };
+        let scheme = &self.schemes[it.scheme];
+        if let Some(t) = &scheme.content_template
+            && subtree.content_available(scheme.subdivision, &it.coord)
+        {
+            self.tiles[id].content_uri = Some(it.coord.expand_template(t));
+            self.tiles[id].content = ContentState::Unloaded;
+        }
+        self.subtrees.insert((it.scheme, it.coord), subtree);
+    }
+
+    /// Create the available children of implicit tile `id`. Returns false if that has to wait for
+    /// a subtree (see `needs_subtree`).
+    pub fn expand(&mut self, id: TileId) -> bool {
+        let tile = &self.tiles[id];
+        let Some(it) = tile.implicit else {
+            return true;
+        };
+        if tile.expanded {
+            return true;
+        }
+        let Some(subtree) = self.subtrees.get(&(it.scheme, it.subtree_root)) else {
+            return false;
+        };
+        let scheme = &self.schemes[it.scheme];
+        let octree = scheme.subdivision == SubdivisionScheme::Octree;
+
+        let mut new_tiles = vec![];
+        if it.coord.level + 1 < scheme.available_levels {
+            let local_level = it.coord.level - it.subtree_root.level;
+            for c in it.coord.children(scheme.subdivision) {
+                // Children on the next subtree's top level are roots of their own subtrees.
+                let (available, subtree_root, has_content) = if local_level + 1 < scheme.subtree_levels {
+                    let a = subtree.tile_available(scheme.subdivision, &c);
+                    (a, it.subtree_root, a && subtree.content_available(scheme.subdivision, &c))
+                } else {
+                    (subtree.child_subtree_available(scheme.subdivision, &c), c, false)
+                };
+                if !available {
+                    continue;
+                }
+                let content_uri = scheme.content_template.as_ref().filter(|_| has_content).map(|t| c.expand_template(t));
+                new_tiles.push(Tile {
+                    parent: Some(id),
+                    children: vec![],
+                    volume: tile.volume.subdivide(c.x & 1, c.y & 1, c.z & 1, octree),
+                    geometric_error: tile.geometric_error / 2.,
+                    refine: tile.refine,
+                    transform: tile.transform,
+                    content: if content_uri.is_some() { ContentState::Unloaded } else { ContentState::None },
+                    content_uri,
+                    implicit: Some(ImplicitTile { scheme: it.scheme, coord: c, subtree_root }),
+                    expanded: false,
+                });
+            }
+        }
+
+        for t in new_tiles {
+            let child = self.tiles.len();
+            self.tiles.push(t);
+            self.tiles[id].children.push(child);
+        }
+        self.tiles[id].expanded = true;
+        return true;
+    }
+
+    /// Pick tiles to draw, load and expand for this view, refining until the screen-space error
+    /// is at most `max_sse` pixels. Where REPLACE children aren't all loaded yet, their parent is
+    /// drawn instead, so there are no holes while streaming.
+    pub fn select(&self, view: &ViewState, max_sse: f64) -> Selection {
+        let mut sel = Selection::default();
+        let mut load = vec![];
+        if !self.tiles.is_empty() {
+            self.visit(0, view, max_sse, &mut sel, &mut load);
+        }
+        load.sort_by(|a: &(TileId, f64), b| b.1.total_cmp(&a.1));
+        sel.load = load.into_iter().map(|(id, _)| id).collect();
+        return sel;
+    }
+
+    /// Returns whether the tile's area is fully covered by what ended up in `sel.render`.
+    fn visit(&self, id: TileId, view: &ViewState, max_sse: f64, sel: &mut Selection, load: &mut Vec<(TileId, f64)>) -> bool {
+        let tile = &self.tiles[id];
+        let sse = view.screen_space_error(tile);
+        let refine = sse > max_sse;
+
+        if tile.content == ContentState::Unloaded {
+            load.push((id, sse));
+        }
+        if tile.implicit.is_some() && !tile.expanded && (refine || self.needs_subtree(id).is_some()) {
+            sel.expand.push(id);
+        }
+
+        let ready = tile.content == ContentState::Ready;
+        let empty = matches!(tile.content, ContentState::None | ContentState::Failed);
+        if !refine || tile.children.is_empty() {
+            if ready {
+                sel.render.push(id);
+            }
+            return ready || empty;
+        }
+
+        match tile.refine {
+            Refine::Add => {
+                if ready {
+                    sel.render.push(id);
+                }
+                let mut all = true;
+                for &c in &tile.children {
+                    all &= self.visit(c, view, max_sse, sel, load);
+                }
+                return (ready || empty) && all;
+            }
+            Refine::Replace => {
+                let mark = sel.render.len();
+                let mut all = true;
+                for &c in &tile.children {
+                    all &= self.visit(c, view, max_sse, sel, load);
+                }
+                if all {
+                    return true;
+                }
+                if ready {
+                    sel.render.truncate(mark);
+                    sel.render.push(id);
+                    return true;
+                }
+                return false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+const SYNTHETIC: &str = r#"{
+    "asset": {"version": "1.1"},
+    "geometricError": 500,
+    "root": {
+        "boundingVolume": {"sphere": [0, 0, 0, 100]},
+        "geometricError": 100,
+        "refine": "REPLACE",
+        "content": {"uri": "root.b3dm"},
+        "children": [
+            {"boundingVolume": {"sphere": [-50, 0, 0, 50]}, "geometricError": 10, "content": {"uri": "tiles/a.pnts"}},
+            {"boundingVolume": {"sphere": [50, 0, 0, 50]}, "geometricError": 10, "content": {"uri": "../b.glb"},
+             "transform": [1,0,0,0, 0,1,0,0, 0,0,1,0, 0,0,1000,1]}
+        ]
+    }
+}"#;
+
+#[cfg(test)]
+fn view_at(x: f64) -> ViewState {
+    return ViewState { position: Vector3::new(x, 0., 0.), screen_height: 1000., sse_denominator: 1. };
+}
+
+#[test]
+fn parses_synthetic_tileset() {
+    let ts = Tileset::parse(SYNTHETIC, "city/tileset.json").unwrap();
+    assert_eq!(ts.tiles.len(), 3);
+    assert_eq!(ts.tiles[0].children, [1, 2]);
+    assert_eq!(ts.tiles[0].content_uri.as_deref(), Some("city/root.b3dm"));
+    assert_eq!(ts.tiles[1].content_uri.as_deref(), Some("city/tiles/a.pnts"));
+    assert_eq!(ts.tiles[2].content_uri.as_deref(), Some("b.glb"));
+    assert_eq!(ts.tiles[2].refine, Refine::Replace);
+    // The child transform moves its sphere.
+    assert_eq!(ts.tiles[2].volume, BoundingVolume::Sphere { center: Vector3::new(50., 0., 1000.), radius: 50. });
+}
+
+#[test]
+fn replace_refinement_waits_for_children() {
+    let mut ts = Tileset::parse(SYNTHETIC, "tileset.json").unwrap();
+    ts.tiles[2].volume = BoundingVolume::Sphere { center: Vector3::new(50., 0., 0.), radius: 50. };
+
+    // Far away only the root is wanted.
+    let sel = ts.select(&view_at(1e6), 16.);
+    assert_eq!((sel.render.as_slice(), sel.load.as_slice()), (&[][..], &[0][..]));
+    ts.tiles[0].content = ContentState::Ready;
+    assert_eq!(ts.select(&view_at(1e6), 16.).render, [0]);
+
+    // Close up the children are wanted, and the root stands in until they are all there.
+    let sel = ts.select(&view_at(200.), 16.);
+    assert_eq!(sel.render, [0]);
+    assert_eq!(sel.load, [2, 1]);
+    ts.tiles[1].content = ContentState::Ready;
+    assert_eq!(ts.select(&view_at(200.), 16.).render, [0]);
+    ts.tiles[2].content = ContentState::Failed;
+    assert_eq!(ts.select(&view_at(200.), 16.).render, [1]);
+}
+
+#[test]
+fn add_refinement_draws_parent_and_children() {
+    let mut ts = Tileset::parse(&SYNTHETIC.replace("REPLACE", "ADD"), "tileset.json").unwrap();
+    for t in &mut ts.tiles {
+        t.content = ContentState::Ready;
+    }
+    assert_eq!(ts.select(&view_at(200.), 16.).render, [0, 1, 2]);
+    assert_eq!(ts.select(&view_at(1e6), 16.).render, [0]);
+}
+
+#[test]
+fn external_tileset_is_grafted() {
+    let mut ts = Tileset::parse(SYNTHETIC, "tileset.json").unwrap();
+    let ext = r#"{"asset": {"version": "1.0"}, "geometricError": 10,
+        "root": {"boundingVolume": {"sphere": [0, 0, 0, 10]}, "geometricError": 1, "content": {"uri": "leaf.b3dm"}}}"#;
+    let id = ts.graft(1, ext, "tiles/ext/tileset.json").unwrap();
+    assert_eq!(ts.tiles[1].children, [id]);
+    assert_eq!(ts.tiles[id].content_uri.as_deref(), Some("tiles/ext/leaf.b3dm"));
+}
+
+#[test]
+fn implicit_quadtree_expands_from_subtrees() {
+    let json = r#"{
+        "asset": {"version": "1.1"},
+        "geometricError": 1000,
+        "root": {
+            "boundingVolume": {"region": [0, 0, 0.01, 0.01, 0, 100]},
+            "geometricError": 800,
+            "refine": "REPLACE",
+            "content": {"uri": "content/{level}/{x}/{y}.glb"},
+            "implicitTiling": {"subdivisionScheme": "QUADTREE", "subtreeLevels": 2, "availableLevels": 4,
+                               "subtrees": {"uri": "subtrees/{level}/{x}/{y}.json"}}
+        }
+    }"#;
+    let mut ts = Tileset::parse(json, "tileset.json").unwrap();
+    assert_eq!(ts.tiles[0].content, ContentState::None);
+    assert_eq!(ts.needs_subtree(0).as_deref(), Some("subtrees/0/0/0.json"));
+    assert!(!ts.expand(0));
+
+    // The view wants the subtree even before refining, to learn about the root's content.
+    let view = ViewState { position: crate::core::Geodetic::new(0.3, 0.3, 1e7).to_ecef(), screen_height: 1000., sse_denominator: 1. };
+    assert_eq!(ts.select(&view, 16.).expand, [0]);
+
+    let everything = Subtree {
+        root: TileCoord::default(),
+        tile: Availability::Constant(true),
+        content: Availability::Constant(true),
+        child_subtree: Availability::Bits(vec![0b0000_0001]),
+    };
+    ts.add_subtree(0, everything);
+    assert_eq!(ts.tiles[0].content_uri.as_deref(), Some("content/0/0/0.glb"));
+    assert!(ts.expand(0));
+    assert_eq!(ts.tiles[0].children.len(), 4);
+    let c = ts.tiles[0].children[3];
+    assert_eq!(ts.tiles[c].content_uri.as_deref(), Some("content/1/1/1.glb"));
+    assert_eq!(ts.tiles[c].geometric_error, 400.);
+
+    // Level 2 starts new subtrees; only the first child subtree of the root subtree exists.
+    let first = ts.tiles[0].children[0];
+    assert!(ts.expand(first));
+    let grandkids = ts.tiles[first].children.clone();
+    assert_eq!(grandkids.len(), 1);
+    assert_eq!(ts.tiles[grandkids[0]].content, ContentState::None);
+    assert_eq!(ts.needs_subtree(grandkids[0]).as_deref(), Some("subtrees/2/0/0.json"));
+    assert!(!ts.expand(grandkids[0]));
+}
@@ -0,0 +1,223 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+
+/// Where tileset files come from. URIs are relative to the source root, already resolved against
+/// whatever referenced them (see `resolve_uri`).
+pub trait TileSource: Send + Sync {
+    fn fetch(&self, uri: &str) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Files under a local directory.
+pub struct DirSource {
+    pub root: PathBuf,
+}
+
+impl TileSource for DirSource {
+    fn fetch(&self, uri: &str) -> anyhow::Result<Vec<u8>> {
+        let path = self.root.join(uri.split(['?', '#']).next().unwrap_or(uri));
+        return std::fs::read(&path).with_context(|| format!("reading {}", path.display()));
+    }
+}
+
+/// Plain `http://` on a local server. One connection per request, no TLS, no redirects; enough
+/// for `python -m http.server` and friends standing in for a tile server.
+pub struct HttpSource {
+    pub host: String,
+    pub port: u16,
+    /// Path prefix, starting and ending with '/'.
+    pub base: String,
+}
+
+impl HttpSource {
+    /// Split `http://host[:port]/dir/tileset.json` into a source for `http://host:port/dir/` and
+    /// the file name.
+    pub fn from_url(url: &str) -> anyhow::Result<(Self, String)> {
+        let rest = url.strip_prefix("http://").with_context(|| format!("not an http:// URL: {url}"))?;
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((h, p)) => (h, p.parse().with_context(|| format!("bad port in {url}"))?),
+            None => (authority, 80),
+        };
+        let path = if path.is_empty() { "/" } else { path };
+        let (dir, file) = path.rsplit_once('/').unwrap();
+        return Ok((HttpSource { host: host.to_string(), port, base: format!("{dir}/") }, file.to_string()));
+    }
+}
+
+impl TileSource for HttpSource {
+    fn fetch(&self, uri: &str) -> anyhow::Result<Vec<u8>> {
+        let mut stream = std::net::TcpStream::connect((self.host.as_str(), self.port))
+            .with_context(|| format!("connecting to {}:{}", self.host, self.port))?;
+        // HTTP/1.0 so the server closes the connection and doesn't send chunked bodies.
+        let request = format!("GET {}{} HTTP/1.0\r\nHost: {}\r\n\r\n", self.base, uri, self.host);
+        stream.write_all(request.as_bytes())?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+
+        let header_end = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .context("malformed HTTP response")?;
+        let head = String::from_utf8_lossy(&response[..header_end]);
+        let status = head.split_whitespace().nth(1).unwrap_or("");
+        anyhow::ensure!(status == "200", "GET {}{}: {}", self.base, uri, head.lines().next().unwrap_or(""));
+        return Ok(response.split_off(header_end + 4));
+    }
+}
+
+/// Open `location` (a directory path, path to a tileset.json, or an http:// URL) as a source and
+/// return it with the URI of the tileset JSON in it.
+pub fn open_source(location: &str) -> anyhow::Result<(Arc<dyn TileSource>, String)> {
+    if location.starts_with("http://") {
+        let (src, file) = HttpSource::from_url(location)?;
+        return Ok((Arc::new(src), file));
+    }
+    let path = PathBuf::from(location);
+    if path.is_dir() {
+        return Ok((Arc::new(DirSource { root: path }), "tileset.json".to_string()));
+    }
+    let root = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let file = path.file_name().and_then(|f| f.to_str()).context("bad tileset path")?.to_string();
+    return Ok((Arc::new(DirSource { root }), file));
+}
+
+/// Resolve `uri` against the file `base` it was found in, like a browser would for relative
+/// links. Both are relative to the source root.
+pub fn resolve_uri(base: &str, uri: &str) -> String {
+    if uri.contains("://") {
+        return uri.to_string();
+    }
+    let mut parts: Vec<&str> = if uri.starts_with('/') {
+        vec![]
+    } else {
+        base.rsplit_once('/').map(|(dir, _)| dir.split('/').collect()).unwrap_or_default()
+    };
+    for p in uri.split('/') {
+        match p {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            p => parts.push(p),
+        }
+    }
+    return parts.into_iter().filter(|p| !p.is_empty()).collect::<Vec<_>>().join("/");
+}
+
+type Job<T> = Box<dyn FnOnce() -> T + Send>;
+
+/// A small pool of threads running load jobs, with results collected by polling.
+///
+/// Dropping the loader closes the job queue; workers finish their current job and exit on their
+/// own, so a slow fetch doesn't hold up closing a layer.
+pub struct Loader<K, T> {
+    jobs: Sender<(K, Job<T>)>,
+    results: Receiver<(K, T)>,
+    in_flight: usize,
+}
+
+impl<K: Send + 'static, T: Send + 'static> Loader<K, T> {
+    pub fn new(threads: usize) -> Self {
+        let (jobs, job_rx) = channel::<(K, Job<T>)>();
+        let (result_tx, results) = channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        for _ in 0..threads.max(1) {
+            let job_rx = job_rx.clone();
+            let result_tx = result_tx.clone();
+            std::thread::spawn(move || {
+                loop {
+                    // Only hold the lock while waiting for a job, not while running it.
+                    let next = job_rx.lock().unwrap().recv();
+                    let Ok((key, job)) = next else {
+                        return;
+                    };
+                    if result_tx.send((key, job())).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        return Loader { jobs, results, in_flight: 0 };
+    }
+
+    pub fn request(&mut self, key: K, job: impl FnOnce() -> T + Send + 'static) {
+        if self.jobs.send((key, Box::new(job))).is_ok() {
+            self.in_flight += 1;
+        }
+    }
+
+    /// Jobs requested but not yet returned by `poll`.
+    pub fn in_flight(&self) -> usize {
+        return self.in_flight;
+    }
+
+    /// Finished jobs, without waiting.
+    pub fn poll(&mut self) -> Vec<(K, T)> {
+        let out: Vec<(K, T)> = self.results.try_iter().collect();
+        self.in_flight -= out.len();
+        return out;
+    }
+}
+
+#[test]
+fn uri_resolution() {
+    assert_eq!(resolve_uri("tileset.json", "tiles/0.b3dm"), "tiles/0.b3dm");
+    assert_eq!(resolve_uri("a/b/tileset.json", "../c/0.pnts"), "a/c/0.pnts");
+    assert_eq!(resolve_uri("a/tileset.json", "./x.glb"), "a/x.glb");
+    assert_eq!(resolve_uri("a/tileset.json", "/root.json"), "root.json");
+    assert_eq!(resolve_uri("a/tileset.json", "http://h/x"), "http://h/x");
+}
+
+#[test]
+fn dir_and_http_sources() {
+    let dir = std::env::temp_dir().join(format!("wglobe-tiles-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub/a.bin"), b"hello").unwrap();
+    let (src, file) = open_source(dir.join("tileset.json").to_str().unwrap()).unwrap();
+    assert_eq!(file, "tileset.json");
+    assert_eq!(src.fetch("sub/a.bin?v=2").unwrap(), b"hello");
+    assert!(src.fetch("missing").is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // A one-shot server standing in for a tile server.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        // Read the whole request, or closing the connection with unread data resets it.
+        let mut req = vec![];
+        let mut buf = [0u8; 256];
+        while !req.ends_with(b"\r\n\r\n") {
+            let n = conn.read(&mut buf).unwrap();
+            assert!(n > 0, "request ended early");
+            req.extend_from_slice(&buf[..n]);
+        }
+        let line = String::from_utf8_lossy(&req).lines().next().unwrap().to_string();
+        conn.write_all(b"HTTP/1.0 200 OK\r\nContent-Length: 3\r\n\r\nabc").unwrap();
+        line
+    });
+    let (src, file) = HttpSource::from_url(&format!("http://127.0.0.1:{port}/city/tileset.json")).unwrap();
+    assert_eq!(file, "tileset.json");
+    assert_eq!(src.fetch("t/1.b3dm").unwrap(), b"abc");
+    assert_eq!(server.join().unwrap(), "GET /city/t/1.b3dm HTTP/1.0");
+}
+
+#[test]
+fn loader_runs_jobs() {
+    let mut loader = Loader::new(2);
+    for i in 0..5 {
+        loader.request(i, move || i * 10);
+    }
+    let mut got = vec![];
+    while got.len() < 5 {
+        got.extend(loader.poll());
+        std::thread::yield_now();
+    }
+    got.sort();
+    assert_eq!(got, [(0, 0), (1, 10), (2, 20), (3, 30), (4, 40)]);
+    assert_eq!(loader.in_flight(), 0);
+}
@@ -0,0 +1,248 @@
+use nalgebra::{Matrix3, Matrix4, Vector3};
+
+use crate::core::Geodetic;
+
+// tileset.json as it is on disk. Only what we use is parsed; unknown properties are ignored.
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TilesetJson {
+    pub asset: AssetJson,
+    #[serde(default)]
+    pub geometric_error: f64,
+    pub root: TileJson,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct AssetJson {
+    pub version: String,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Refine {
+    Add,
+    #[default]
+    Replace,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TileJson {
+    pub bounding_volume: BoundingVolumeJson,
+    pub geometric_error: f64,
+    /// Inherited from the parent if missing.
+    pub refine: Option<Refine>,
+    pub content: Option<ContentJson>,
+    /// 3D Tiles 1.1 multiple contents.
+    #[serde(default)]
+    pub contents: Vec<ContentJson>,
+    #[serde(default)]
+    pub children: Vec<TileJson>,
+    /// Column major, like glTF.
+    pub transform: Option<[f64; 16]>,
+    pub implicit_tiling: Option<ImplicitTilingJson>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ContentJson {
+    /// Called `url` before 1.0.
+    #[serde(alias = "url")]
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct BoundingVolumeJson {
+    #[serde(rename = "box")]
+    pub obb: Option<[f64; 12]>,
+    pub region: Option<[f64; 6]>,
+    pub sphere: Option<[f64; 4]>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SubdivisionScheme {
+    Quadtree,
+    Octree,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImplicitTilingJson {
+    pub subdivision_scheme: SubdivisionScheme,
+    pub subtree_levels: u32,
+    pub available_levels: u32,
+    pub subtrees: ContentJson,
+}
+
+/// A tile's bounding volume in ECEF.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BoundingVolume {
+    /// Oriented box; the columns of `half_axes` are the box's half extents.
+    Box { center: Vector3<f64>, half_axes: Matrix3<f64> },
+    /// Geodetic region in radians and meters above the ellipsoid.
+    Region { west: f64, south: f64, east: f64, north: f64, min_height: f64, max_height: f64 },
+    Sphere { center: Vector3<f64>, radius: f64 },
+}
+
+impl BoundingVolume {
+    /// Box and sphere volumes are moved by the tile transform; regions are always in geodetic
+    /// coordinates.
+    pub fn from_json(json: &BoundingVolumeJson, transform: &Matrix4<f64>) -> anyhow::Result<Self> {
+        let lin = transform.fixed_view::<3, 3>(0, 0).into_owned();
+        let apply = |p: Vector3<f64>| transform.transform_point(&p.into()).coords;
+        if let Some(b) = json.obb {
+            let half_axes = Matrix3::new(b[3], b[6], b[9], b[4], b[7], b[10], b[5], b[8], b[11]);
+            return Ok(BoundingVolume::Box { center: apply(Vector3::new(b[0], b[1], b[2])), half_axes: lin * half_axes });
+        }
+        if let Some(r) = json.region {
+            return Ok(BoundingVolume::Region {
+                west: r[0],
+                south: r[1],
+                east: r[2],
+                north: r[3],
+                min_height: r[4],
+                max_height: r[5],
+            });
+        }
+        if let Some(s) = json.sphere {
+            // Scale the radius by the largest axis scale of the transform.
+            let scale = (0..3).map(|i| lin.column(i).norm()).fold(0., f64::max);
+            return Ok(BoundingVolume::Sphere { center: apply(Vector3::new(s[0], s[1], s[2])), radius: s[3] * scale });
+        }
+        anyhow::bail!("tile has no box, region or sphere bounding volume");
+    }
+
+    /// Distance from `p` to the volume, 0 inside.
+    pub fn distance(&self, p: &Vector3<f64>) -> f64 {
+        match self {
+            BoundingVolume::Sphere { center, radius } => {
+                return ((p - center).norm() - radius).max(0.);
+            }
+            BoundingVolume::Box { center, half_axes } => {
+                let d = p - center;
+                let mut out = 0.;
+                for i in 0..3 {
+                    let axis = half_axes.column(i);
+                    let len = axis.norm();
+                    if len == 0. {
+                        out += d.dot(&axis).powi(2);
+                        continue;
+                    }
+                    let excess = (d.dot(&axis) / len).abs() - len;
+                    out += excess.max(0.).powi(2);
+                }
+                return f64::sqrt(out);
+            }
+            BoundingVolume::Region { west, south, east, north, min_height, max_height } => {
+                // Clamp to the region in geodetic coordinates; good enough for tiles that aren't
+                // continent sized.
+                let g = Geodetic::from_ecef(p);
+                let (lat, lon) = (g.lat.to_radians(), g.lon.to_radians());
+                let lon = if east >= west {
+                    lon.clamp(*west, *east)
+                } else if lon >= *west || lon <= *east {
+                    lon
+                } else if (lon - east).abs() < (west - lon).abs() {
+                    // Crossing the antimeridian: clamp to the nearer edge.
+                    *east
+                } else {
+                    *west
+                };
+                let nearest = Geodetic::new(
+                    lat.clamp(*south, *north).to_degrees(),
+                    lon.to_degrees(),
+                    g.height.clamp(*min_height, *max_height),
+                );
+                return (p - nearest.to_ecef()).norm();
+            }
+        }
+    }
+
+    /// Center and radius of a sphere containing the volume.
+    pub fn bounding_sphere(&self) -> (Vector3<f64>, f64) {
+        match self {
+            BoundingVolume::Sphere { center, radius } => return (*center, *radius),
+            BoundingVolume::Box { center, half_axes } => {
+                let r = (half_axes.column(0).norm_squared() + half_axes.column(1).norm_squared() + half_axes.column(2).norm_squared()).sqrt();
+                return (*center, r);
+            }
+            BoundingVolume::Region { west, south, east, north, min_height, max_height } => {
+                let east = if east < west { east + std::f64::consts::TAU } else { *east };
+                let mut pts = vec![];
+                for i in 0..=2 {
+                    for j in 0..=2 {
+                        for h in [min_height, max_height] {
+                            let lat = south + (north - south) * i as f64 / 2.;
+                            let lon = west + (east - west) * j as f64 / 2.;
+                            pts.push(Geodetic::new(lat.to_degrees(), lon.to_degrees(), *h).to_ecef());
+                        }
+                    }
+                }
+                let center = pts.iter().sum::<Vector3<f64>>() / pts.len() as f64;
+                let r = pts.iter().map(|p| (p - center).norm()).fold(0., f64::max);
+                return (center, r);
+            }
+        }
+    }
+
+    /// The volume of child `(x, y, z)` (each 0 or 1) of an implicit tile. `z` is only used for
+    /// octrees.
+    pub fn subdivide(&self, x: u32, y: u32, z: u32, octree: bool) -> Self {
+        let half = |lo: f64, hi: f64, i: u32| {
+            let mid = (lo + hi) / 2.;
+            if i == 0 { (lo, mid) } else { (mid, hi) }
+        };
+        match self {
+            BoundingVolume::Region { west, south, east, north, min_height, max_height } => {
+                let (w, e) = half(*west, *east, x);
+                let (s, n) = half(*south, *north, y);
+                let (lo, hi) = if octree { half(*min_height, *max_height, z) } else { (*min_height, *max_height) };
+                return BoundingVolume::Region { west: w, south: s, east: e, north: n, min_height: lo, max_height: hi };
+            }
+            BoundingVolume::Box { center, half_axes } => {
+                let sign = |i: u32| if i == 0 { -0.5 } else { 0.5 };
+                let (ax, ay, az) = (half_axes.column(0), half_axes.column(1), half_axes.column(2));
+                let mut c = center + ax * sign(x) + ay * sign(y);
+                let mut h = Matrix3::from_columns(&[ax * 0.5, ay * 0.5, az.into_owned()]);
+                if octree {
+                    c += az * sign(z);
+                    h.set_column(2, &(az * 0.5));
+                }
+                return BoundingVolume::Box { center: c, half_axes: h };
+            }
+            // Implicit tiling only allows boxes and regions.
+            BoundingVolume::Sphere { .. } => return self.clone(),
+        }
+    }
+}
+
+#[test]
+fn box_and_sphere_distances() {
+    let json: BoundingVolumeJson = serde_json::from_str(r#"{"box": [0,0,0, 10,0,0, 0,5,0, 0,0,1]}"#).unwrap();
+    let t = Matrix4::new_translation(&Vector3::new(100., 0., 0.));
+    let b = BoundingVolume::from_json(&json, &t).unwrap();
+    assert_eq!(b.distance(&Vector3::new(105., 4., 0.)), 0.);
+    assert!((b.distance(&Vector3::new(120., 0., 0.)) - 10.).abs() < 1e-9);
+    assert!((b.distance(&Vector3::new(100., 8., 5.)) - 5.).abs() < 1e-9);
+
+    let json: BoundingVolumeJson = serde_json::from_str(r#"{"sphere": [0,0,0, 2]}"#).unwrap();
+    let s = BoundingVolume::from_json(&json, &Matrix4::new_scaling(3.)).unwrap();
+    assert!((s.distance(&Vector3::new(10., 0., 0.)) - 4.).abs() < 1e-9);
+}
+
+#[test]
+fn region_distance_and_subdivision() {
+    let d = 1f64.to_radians();
+    let r = BoundingVolume::Region { west: 0., south: 0., east: d, north: d, min_height: 0., max_height: 100. };
+    assert!(r.distance(&Geodetic::new(0.5, 0.5, 50.).to_ecef()) < 1e-6);
+    assert!((r.distance(&Geodetic::new(0.5, 0.5, 1100.).to_ecef()) - 1000.).abs() < 1e-3);
+    let (c, radius) = r.bounding_sphere();
+    assert!(radius > 70_000. && radius < 90_000., "{radius}");
+    assert!((Geodetic::from_ecef(&c).lat - 0.5).abs() < 0.01);
+
+    let BoundingVolume::Region { west, south, east, north, .. } = r.subdivide(1, 0, 0, false) else {
+        panic!();
+    };
+    assert_eq!((west, south, east, north), (d / 2., 0., d, d / 2.));
+}
@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use super::{Hpr, Track, TrackSample};
+use crate::core::clock::parse_iso8601;
+use crate::core::Geodetic;
+
+/// A column picked by header name or by zero-based index.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum CsvColumn {
+    Index(usize),
+    Name(String),
+}
+
+impl From<&str> for CsvColumn {
+    fn from(s: &str) -> Self {
+        return CsvColumn::Name(s.to_string());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvTimeFormat {
+    #[default]
+    Iso8601,
+    UnixSeconds,
+    UnixMillis,
+}
+
+/// Describes how to pull a track out of a CSV file. Latitude/longitude are in degrees, height in
+/// meters above the ellipsoid. Rows are grouped into one track per distinct `id` value.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvSchema {
+    pub delimiter: char,
+    pub has_header: bool,
+    pub time_format: CsvTimeFormat,
+
+    pub time: CsvColumn,
+    pub lat: CsvColumn,
+    pub lon: CsvColumn,
+    pub height: Option<CsvColumn>,
+    pub heading: Option<CsvColumn>,
+    pub pitch: Option<CsvColumn>,
+    pub roll: Option<CsvColumn>,
+    pub id: Option<CsvColumn>,
+}
+
+impl Default for CsvSchema {
+    fn default() -> Self {
+        return CsvSchema {
+            delimiter: ',',
+            has_header: true,
+            time_format: CsvTimeFormat::Iso8601,
+            time: "time".into(),
+            lat: "lat".into(),
+            lon: "lon".into(),
+            height: Some("height".into()),
+            heading: None,
+            pitch: None,
+            roll: None,
+            id: None,
+        };
+    }
+}
+
+fn resolve(col: &CsvColumn, headers: &[String]) -> anyhow::Result<usize> {
+    return match col {
+        CsvColumn::Index(i) => Ok(*i),
+        CsvColumn::Name(n) => headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(n))
+            .ok_or_else(|| anyhow::anyhow!("CSV has no column '{n}' (have {headers:?})")),
+    };
+}
+
+/// Parse CSV text into tracks. Optional columns that are missing from the header are ignored.
+pub fn parse_csv(text: &str, name: &str, schema: &CsvSchema) -> anyhow::Result<Vec<Track>> {
+    let mut rdr = ::csv::ReaderBuilder::new()
+        .delimiter(schema.delimiter as u8)
+        .has_headers(schema.has_header)
+        .flexible(true)
+        .trim(::csv::Trim::All)
+        .from_reader(text.as_bytes());
+
+    let headers: Vec<String> = if schema.has_header {
+        rdr.headers()?.iter().map(str::to_string).collect()
+    } else {
+        vec![]
+    };
+    let optional = |c: &Option<CsvColumn>| c.as_ref().and_then(|c| resolve(c, &headers).ok());
+
+    let time = resolve(&schema.time, &headers)?;
+    let lat = resolve(&schema.lat, &headers)?;
+    let lon = resolve(&schema.lon, &headers)?;
+    let height = optional(&schema.height);
+    let (heading, pitch, roll) = (optional(&schema.heading), optional(&schema.pitch), optional(&schema.roll));
+    let id = optional(&schema.id);
+
+    let mut groups: BTreeMap<String, Vec<TrackSample>> = BTreeMap::new();
+    for (row, rec) in rdr.records().enumerate() {
+        let rec = rec?;
+        let get = |i: usize| -> anyhow::Result<&str> {
+            rec.get(i).ok_or_else(|| anyhow::anyhow!("CSV row {} has no column {i}", row + 1))
+        };
+        let num = |i: usize| -> anyhow::Result<f64> {
+            let s = get(i)?;
+            s.parse().map_err(|_| anyhow::anyhow!("CSV row {}: bad number '{s}'", row + 1))
+        };
+        let opt_num = |i: Option<usize>| -> anyhow::Result<Option<f64>> {
+            return match i.map(get).transpose()? {
+                Some(s) if !s.is_empty() => Ok(Some(num(i.unwrap())?)),
+                _ => Ok(None),
+            };
+        };
+
+        let t = match schema.time_format {
+            CsvTimeFormat::Iso8601 => parse_iso8601(get(time)?)?,
+            CsvTimeFormat::UnixSeconds => num(time)?,
+            CsvTimeFormat::UnixMillis => num(time)? / 1000.,
+        };
+        let g = Geodetic::new(num(lat)?, num(lon)?, opt_num(height)?.unwrap_or(0.));
+        let orientation = opt_num(heading)?.map(|h| -> anyhow::Result<Hpr> {
+            Ok(Hpr { heading: h, pitch: opt_num(pitch)?.unwrap_or(0.), roll: opt_num(roll)?.unwrap_or(0.) })
+        });
+
+        let key = match id {
+            Some(i) => get(i)?.to_string(),
+            None => name.to_string(),
+        };
+        groups.entry(key).or_default().push(TrackSample {
+            t,
+            position: g.to_ecef(),
+            orientation: orientation.transpose()?,
+        });
+    }
+
+    return Ok(groups.into_iter().map(|(k, v)| Track::new(&k, v)).collect());
+}
+
+#[test]
+fn csv_default_schema() {
+    let text = "time,lat,lon,height
+2020-01-01T00:00:10Z, 1.0, 2.0, 30
+2020-01-01T00:00:00Z, 1.5, 2.5, 10
+";
+    let tracks = parse_csv(text, "veh", &CsvSchema::default()).unwrap();
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(tracks[0].name, "veh");
+    let s = &tracks[0].samples;
+    assert_eq!(s.len(), 2);
+    assert_eq!(s[0].t, 1577836800.);
+    let g = Geodetic::from_ecef(&s[0].position);
+    assert!((g.lat - 1.5).abs() < 1e-9 && (g.height - 10.).abs() < 1e-6);
+}
+
+#[test]
+fn csv_custom_schema_with_ids_and_attitude() {
+    let text = "1000;a;10;20;90;5\n2000;b;11;21;180;0\n3000;a;12;22;;\n";
+    let schema = CsvSchema {
+        delimiter: ';',
+        has_header: false,
+        time_format: CsvTimeFormat::UnixMillis,
+        time: CsvColumn::Index(0),
+        id: Some(CsvColumn::Index(1)),
+        lat: CsvColumn::Index(2),
+        lon: CsvColumn::Index(3),
+        height: None,
+        heading: Some(CsvColumn::Index(4)),
+        pitch: Some(CsvColumn::Index(5)),
+        roll: None,
+    };
+    let tracks = parse_csv(text, "x", &schema).unwrap();
+    assert_eq!(tracks.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+    assert_eq!(tracks[0].samples.len(), 2);
+    assert_eq!(tracks[0].samples[1].t, 3.);
+    assert_eq!(tracks[0].samples[0].orientation, Some(Hpr { heading: 90., pitch: 5., roll: 0. }));
+    assert_eq!(tracks[0].samples[1].orientation, None);
+
+    assert!(parse_csv("t,x\n1,2\n", "x", &CsvSchema::default()).is_err());
+}
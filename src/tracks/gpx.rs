@@ -0,0 +1,70 @@
+use super::{Track, TrackSample};
+use crate::core::clock::parse_iso8601;
+use crate::core::Geodetic;
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    return node.children().find(|c| c.has_tag_name(name)).and_then(|c| c.text()).map(str::trim);
+}
+
+/// Parse the `<trk>`s of a GPX 1.0/1.1 document, one `Track` per `<trk>` with its segments joined.
+/// Points without a `<time>` can't be played back and are skipped.
+pub fn parse_gpx(text: &str) -> anyhow::Result<Vec<Track>> {
+    let doc = roxmltree::Document::parse(text)?;
+    let mut out = vec![];
+
+    for (i, trk) in doc.descendants().filter(|n| n.has_tag_name("trk")).enumerate() {
+        let name = child_text(trk, "name").map(str::to_string).unwrap_or_else(|| format!("track {}", i + 1));
+
+        let mut samples = vec![];
+        for pt in trk.descendants().filter(|n| n.has_tag_name("trkpt")) {
+            let attr = |a: &str| -> anyhow::Result<f64> {
+                let v = pt.attribute(a).ok_or_else(|| anyhow::anyhow!("trkpt without {a}"))?;
+                Ok(v.trim().parse()?)
+            };
+            let Some(time) = child_text(pt, "time") else {
+                continue;
+            };
+            let height = child_text(pt, "ele").map(str::parse::<f64>).transpose()?.unwrap_or(0.);
+            samples.push(TrackSample {
+                t: parse_iso8601(time)?,
+                position: Geodetic::new(attr("lat")?, attr("lon")?, height).to_ecef(),
+                orientation: None,
+            });
+        }
+
+        if samples.is_empty() {
+            log::warn!("GPX track '{name}' has no timestamped points");
+            continue;
+        }
+        out.push(Track::new(&name, samples));
+    }
+
+    return Ok(out);
+}
+
+#[test]
+fn gpx_tracks() {
+    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk>
+    <name>Morning ride</name>
+    <trkseg>
+      <trkpt lat="47.6" lon="-122.3"><ele>12.5</ele><time>2021-06-01T08:00:00Z</time></trkpt>
+      <trkpt lat="47.61" lon="-122.31"><time>2021-06-01T08:00:05Z</time></trkpt>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="47.62" lon="-122.32"><ele>20</ele><time>2021-06-01T08:00:10Z</time></trkpt>
+      <trkpt lat="47.63" lon="-122.33"><ele>20</ele></trkpt>
+    </trkseg>
+  </trk>
+  <trk><trkseg><trkpt lat="0" lon="0"><time>2021-06-01T00:00:00Z</time></trkpt></trkseg></trk>
+</gpx>"#;
+    let tracks = parse_gpx(text).unwrap();
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[0].name, "Morning ride");
+    assert_eq!(tracks[1].name, "track 2");
+    assert_eq!(tracks[0].samples.len(), 3);
+    assert_eq!(tracks[0].time_range().unwrap().1 - tracks[0].time_range().unwrap().0, 10.);
+    let g = Geodetic::from_ecef(&tracks[0].samples[0].position);
+    assert!((g.height - 12.5).abs() < 1e-6);
+}
@@ -0,0 +1,293 @@
+mod csv;
+mod gpx;
+
+pub use self::csv::{parse_csv, CsvColumn, CsvSchema, CsvTimeFormat};
+pub use gpx::parse_gpx;
+
+use nalgebra::{UnitQuaternion, Vector3};
+
+use crate::core::geo::{ecef_to_hpr, hpr_to_ecef};
+use crate::core::Geodetic;
+
+/// Heading/pitch/roll in degrees relative to the local ENU frame, see `geo::hpr_to_ecef`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Hpr {
+    pub heading: f64,
+    pub pitch: f64,
+    pub roll: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackSample {
+    /// Unix seconds.
+    pub t: f64,
+    /// ECEF meters.
+    pub position: Vector3<f64>,
+    /// If the source has no attitude, orientation is derived from the direction of travel.
+    pub orientation: Option<Hpr>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    Linear,
+    /// Cubic Hermite with finite-difference (Catmull-Rom style) tangents.
+    #[default]
+    Hermite,
+    /// Lagrange polynomial of the given degree through the nearest samples, as used by CZML.
+    Lagrange(usize),
+}
+
+/// Interpolated state of a track at some time.
+#[derive(Clone, Debug)]
+pub struct TrackState {
+    pub position: Vector3<f64>,
+    /// ECEF m/s
+    pub velocity: Vector3<f64>,
+    /// Body (x forward, y left, z up) to ECEF.
+    pub orientation: UnitQuaternion<f64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Track {
+    pub name: String,
+    /// Sorted by time, no duplicate times.
+    pub samples: Vec<TrackSample>,
+}
+
+impl Track {
+    pub fn new(name: &str, mut samples: Vec<TrackSample>) -> Self {
+        samples.sort_by(|a, b| a.t.total_cmp(&b.t));
+        samples.dedup_by(|b, a| a.t == b.t);
+        return Track { name: name.to_string(), samples };
+    }
+
+    pub fn time_range(&self) -> Option<(f64, f64)> {
+        return Some((self.samples.first()?.t, self.samples.last()?.t));
+    }
+
+    // Index i such that samples[i].t <= t < samples[i+1].t, clamped to the last segment.
+    fn segment(&self, t: f64) -> usize {
+        let i = self.samples.partition_point(|s| s.t <= t);
+        return i.saturating_sub(1).min(self.samples.len() - 2);
+    }
+
+    fn tangent(&self, i: usize) -> Vector3<f64> {
+        let s = &self.samples;
+        let slope = |a: usize, b: usize| (s[b].position - s[a].position) / (s[b].t - s[a].t);
+        if i == 0 {
+            return slope(0, 1);
+        }
+        if i == s.len() - 1 {
+            return slope(i - 1, i);
+        }
+        return (slope(i - 1, i) + slope(i, i + 1)) * 0.5;
+    }
+
+    /// Position and velocity at `t`, or `None` outside the track's time range.
+    pub fn position_at(&self, t: f64, interp: Interpolation) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let (t0, t1) = self.time_range()?;
+        if t < t0 || t > t1 {
+            return None;
+        }
+        if self.samples.len() == 1 {
+            return Some((self.samples[0].position, Vector3::zeros()));
+        }
+
+        let i = self.segment(t);
+        let (a, b) = (&self.samples[i], &self.samples[i + 1]);
+        let h = b.t - a.t;
+        let s = (t - a.t) / h;
+
+        match interp {
+            Interpolation::Linear => {
+                let v = (b.position - a.position) / h;
+                return Some((a.position + v * (t - a.t), v));
+            }
+            Interpolation::Hermite => {
+                let (m0, m1) = (self.tangent(i) * h, self.tangent(i + 1) * h);
+                let (s2, s3) = (s * s, s * s * s);
+                let p = a.position * (2. * s3 - 3. * s2 + 1.)
+                    + m0 * (s3 - 2. * s2 + s)
+                    + b.position * (-2. * s3 + 3. * s2)
+                    + m1 * (s3 - s2);
+                let dp = a.position * (6. * s2 - 6. * s)
+                    + m0 * (3. * s2 - 4. * s + 1.)
+                    + b.position * (-6. * s2 + 6. * s)
+                    + m1 * (3. * s2 - 2. * s);
+                return Some((p, dp / h));
+            }
+            Interpolation::Lagrange(degree) => {
+                let n = (degree + 1).clamp(2, self.samples.len());
+                // Window of n samples centered on the segment, shifted to stay in range.
+                let first = (i + 1).saturating_sub(n / 2).min(self.samples.len() - n);
+                let window = &self.samples[first..first + n];
+                let p = lagrange(window, t);
+                // The polynomial is smooth, so a central difference is plenty for velocity.
+                let dt = 1e-3 * h;
+                let v = (lagrange(window, t + dt) - lagrange(window, t - dt)) / (2. * dt);
+                return Some((p, v));
+            }
+        }
+    }
+
+    /// Full interpolated state at `t`, or `None` outside the track's time range.
+    pub fn state_at(&self, t: f64, interp: Interpolation) -> Option<TrackState> {
+        let (position, velocity) = self.position_at(t, interp)?;
+        let here = Geodetic::from_ecef(&position);
+
+        let sampled = if self.samples.len() > 1 {
+            let i = self.segment(t);
+            let (a, b) = (&self.samples[i], &self.samples[i + 1]);
+            match (a.orientation, b.orientation) {
+                (Some(ha), Some(hb)) => {
+                    let qa = hpr_to_ecef(&Geodetic::from_ecef(&a.position), ha.heading, ha.pitch, ha.roll);
+                    let qb = hpr_to_ecef(&Geodetic::from_ecef(&b.position), hb.heading, hb.pitch, hb.roll);
+                    Some(qa.slerp(&qb, ((t - a.t) / (b.t - a.t)).clamp(0., 1.)))
+                }
+                _ => None,
+            }
+        } else {
+            self.samples[0].orientation.map(|o| hpr_to_ecef(&here, o.heading, o.pitch, o.roll))
+        };
+
+        let orientation = sampled.unwrap_or_else(|| {
+            let v = here.enu_to_ecef().transpose() * velocity;
+            let heading = v.x.atan2(v.y).to_degrees();
+            let pitch = if v.norm() > 1e-6 { v.z.atan2(v.xy().norm()).to_degrees() } else { 0. };
+            hpr_to_ecef(&here, heading, pitch, 0.)
+        });
+
+        return Some(TrackState { position, velocity, orientation });
+    }
+
+    /// Heading/pitch/roll at `t`, see `state_at`.
+    pub fn hpr_at(&self, t: f64, interp: Interpolation) -> Option<Hpr> {
+        let st = self.state_at(t, interp)?;
+        let (heading, pitch, roll) = ecef_to_hpr(&Geodetic::from_ecef(&st.position), &st.orientation);
+        return Some(Hpr { heading, pitch, roll });
+    }
+
+    /// Positions from `t - duration` up to `t` (clamped to the track), as (time, position) pairs
+    /// that include the recorded samples in between and interpolated end points.
+    pub fn trail(&self, t: f64, duration: f64, interp: Interpolation) -> Vec<(f64, Vector3<f64>)> {
+        let Some((t0, t1)) = self.time_range() else {
+            return vec![];
+        };
+        let (from, to) = ((t - duration).max(t0), t.min(t1));
+        if from > to {
+            return vec![];
+        }
+
+        let mut out = vec![];
+        out.extend(self.position_at(from, interp).map(|(p, _)| (from, p)));
+        for s in self.samples.iter().filter(|s| s.t > from && s.t < to) {
+            out.push((s.t, s.position));
+        }
+        if to > from {
+            out.extend(self.position_at(to, interp).map(|(p, _)| (to, p)));
+        }
+        return out;
+    }
+}
+
+fn lagrange(samples: &[TrackSample], t: f64) -> Vector3<f64> {
+    let mut out = Vector3::zeros();
+    for (j, sj) in samples.iter().enumerate() {
+        let w: f64 = samples
+            .iter()
+            .enumerate()
+            .filter(|(k, _)| *k != j)
+            .map(|(_, sk)| (t - sk.t) / (sj.t - sk.t))
+            .product();
+        out += sj.position * w;
+    }
+    return out;
+}
+
+#[cfg(test)]
+fn straight_track() -> Track {
+    // Eastward along the equator at 100 m/s, with one sample out of order.
+    let g = |t: f64| Geodetic::new(0.0, t * 100.0 / 111_320.0, 1000.0).to_ecef();
+    return Track::new(
+        "t",
+        [0., 10., 30., 20., 40.]
+            .iter()
+            .map(|&t| TrackSample { t, position: g(t), orientation: None })
+            .collect(),
+    );
+}
+
+#[test]
+fn linear_interpolation_hits_samples_and_midpoints() {
+    let tr = straight_track();
+    assert_eq!(tr.time_range(), Some((0., 40.)));
+    let (p, v) = tr.position_at(15., Interpolation::Linear).unwrap();
+    let mid = (tr.samples[1].position + tr.samples[2].position) / 2.;
+    assert!((p - mid).norm() < 1e-9);
+    assert!((v.norm() - 100.).abs() < 0.1);
+    assert!(tr.position_at(-1., Interpolation::Linear).is_none());
+    assert!(tr.position_at(40.5, Interpolation::Linear).is_none());
+    assert!((tr.position_at(40., Interpolation::Linear).unwrap().0 - tr.samples[4].position).norm() < 1e-9);
+}
+
+#[test]
+fn hermite_passes_through_samples_and_is_smooth() {
+    let tr = straight_track();
+    for s in &tr.samples {
+        let (p, _) = tr.position_at(s.t, Interpolation::Hermite).unwrap();
+        assert!((p - s.position).norm() < 1e-6);
+    }
+    // Velocity is continuous across a sample.
+    let (_, va) = tr.position_at(20. - 1e-6, Interpolation::Hermite).unwrap();
+    let (_, vb) = tr.position_at(20. + 1e-6, Interpolation::Hermite).unwrap();
+    assert!((va - vb).norm() < 1e-3);
+}
+
+#[test]
+fn lagrange_reproduces_polynomials() {
+    // A cubic in each coordinate is reproduced exactly by degree >= 3.
+    let f = |t: f64| Vector3::new(t * t * t - 2. * t, 5. * t * t, 7. - t);
+    let tr = Track::new(
+        "l",
+        [0., 1., 2.5, 3., 4., 6., 7.]
+            .iter()
+            .map(|&t| TrackSample { t, position: f(t), orientation: None })
+            .collect(),
+    );
+    for &t in &[0., 0.5, 2.7, 5.9, 7.] {
+        let (p, v) = tr.position_at(t, Interpolation::Lagrange(3)).unwrap();
+        assert!((p - f(t)).norm() < 1e-9, "t={t}");
+        assert!((v - Vector3::new(3. * t * t - 2., 10. * t, -1.)).norm() < 1e-4, "t={t}");
+    }
+    // Degree is clamped to what the track has.
+    assert!(tr.position_at(1.5, Interpolation::Lagrange(20)).is_some());
+}
+
+#[test]
+fn orientation_from_travel_and_from_samples() {
+    let tr = straight_track();
+    let hpr = tr.hpr_at(12., Interpolation::Linear).unwrap();
+    assert!((hpr.heading - 90.).abs() < 0.01 && hpr.pitch.abs() < 0.01, "{hpr:?}");
+
+    let p = Geodetic::new(10., 10., 0.).to_ecef();
+    let tr = Track::new(
+        "o",
+        vec![
+            TrackSample { t: 0., position: p, orientation: Some(Hpr { heading: 10., pitch: 0., roll: 0. }) },
+            TrackSample { t: 1., position: p, orientation: Some(Hpr { heading: 30., pitch: 0., roll: 0. }) },
+        ],
+    );
+    let hpr = tr.hpr_at(0.5, Interpolation::Linear).unwrap();
+    assert!((hpr.heading - 20.).abs() < 1e-6, "{hpr:?}");
+}
+
+#[test]
+fn trail_is_clamped_and_ordered() {
+    let tr = straight_track();
+    let trail = tr.trail(25., 12., Interpolation::Linear);
+    let times: Vec<f64> = trail.iter().map(|(t, _)| *t).collect();
+    assert_eq!(times, vec![13., 20., 25.]);
+    assert_eq!(tr.trail(5., 100., Interpolation::Linear).first().unwrap().0, 0.);
+    assert!(tr.trail(-10., 5., Interpolation::Linear).is_empty());
+}